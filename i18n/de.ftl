@@ -0,0 +1,50 @@
+# Deutsche Meldungen
+
+after-help-env-heading = UMGEBUNGSVARIABLEN:
+after-help-config =
+    Pfad zur TOML-Konfigurationsdatei (z. B. /etc/btrsnap.toml) oder eine
+    durch Doppelpunkte getrennte Liste, zusammengeführt wie mehrere --config.
+    Wenn gesetzt, laufen Befehle wie `btrsnap create` ohne --config.
+
+need-root = Fehler: Für BTRFS-Operationen mit sudo oder als root ausführen
+
+confirm-suffix = [j/N]
+confirm-yes = j, ja, y, yes
+aborted = Abgebrochen
+aborted-unchanged = Abgebrochen, { $path } bleibt unverändert
+confirm-append-fstab = Diese Einträge an { $path } anhängen?
+confirm-boot-root = { $snapshot } als Wurzeldateisystem booten?
+confirm-replace = { $live } durch { $snapshot } ersetzen?
+
+report-subvolume = SUBVOLUME
+report-created = ERSTELLT
+report-deleted = GELÖSCHT
+report-retries = VERSUCHE
+report-errors = FEHLER
+created-yes = ja
+created-no = nein
+created-skipped = übersprungen
+created-excluded = ausgeschlossen
+created-disabled = deaktiviert
+report-interrupted = Unterbrochen, die übrigen Einträge wurden übersprungen
+report-failed = { $failed } von { $total } Subvolumes hatten Fehler
+
+warning = Warnung: { $message }
+warnings-as-errors = { $count } Warnungen, Abbruch wegen --warnings-as-errors
+
+chain-step = ({ $step }/{ $steps }) btrsnap { $command }
+chain-failed = Schritt { $step } von { $steps } fehlgeschlagen
+
+summary-heading = Snapshots in { $dir }:
+summary-none = keine
+summary-subvol = { $subvol }: { $count } (älteste { $oldest }, neueste { $newest })
+summary-space = Dateisystem: { $used } von { $total } belegt ({ $available } frei)
+summary-breakdown = Live-Daten { $live }, exklusiv in Snapshots { $snapshots }, gelöschte Snapshots vor der Freigabe { $pending }
+summary-alarm = Alarm: { $alarm }
+summary-maintenance = Wartungsmodus aktiv { $state }, Löschungen sind ausgesetzt
+
+select-heading = Abgelaufene Snapshots:
+select-item = { $path }  { $age } alt, { $size } exklusiv
+select-total = Alle zu löschen gibt mindestens { $size } frei
+select-prompt = Nummern der zu behaltenden (z. B. 2 4-6), Enter löscht alle, { $quit } bricht ab:
+select-quit = q
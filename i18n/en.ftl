@@ -0,0 +1,52 @@
+# English messages, the fallback for every other locale
+
+after-help-env-heading = ENVIRONMENT VARIABLES:
+after-help-config =
+    Path to the TOML configuration file (e.g., /etc/btrsnap.toml), or a
+    colon-separated list merged in order like repeated --config options.
+    If set, allows running commands like `btrsnap create` without --config.
+
+need-root = Error: Must run with sudo or as root for BTRFS operations
+
+# Answers to confirm() prompts; the answers are comma-separated
+confirm-suffix = [y/N]
+confirm-yes = y, yes
+aborted = Aborted
+aborted-unchanged = Aborted, { $path } left unchanged
+confirm-append-fstab = Append these entries to { $path }?
+confirm-boot-root = Boot into { $snapshot } as the root filesystem?
+confirm-replace = Replace { $live } with { $snapshot }?
+
+report-subvolume = SUBVOLUME
+report-created = CREATED
+report-deleted = DELETED
+report-retries = RETRIES
+report-errors = ERRORS
+created-yes = yes
+created-no = no
+created-skipped = skipped
+created-excluded = excluded
+created-disabled = disabled
+report-interrupted = Interrupted, the remaining items were skipped
+report-failed = { $failed } of { $total } subvolumes had errors
+
+warning = warning: { $message }
+warnings-as-errors = { $count } warnings, failing because of --warnings-as-errors
+
+chain-step = ({ $step }/{ $steps }) btrsnap { $command }
+chain-failed = Step { $step } of { $steps } failed
+
+summary-heading = Snapshots in { $dir }:
+summary-none = none
+summary-subvol = { $subvol }: { $count } (oldest { $oldest }, newest { $newest })
+summary-space = Filesystem: { $used } used of { $total } ({ $available } available)
+summary-breakdown = Live data { $live }, snapshot exclusives { $snapshots }, deleted snapshots awaiting purge { $pending }
+summary-alarm = Alarm: { $alarm }
+summary-maintenance = Maintenance mode on { $state }, deletions are paused
+
+# Interactive cleanup; answering select-quit to the prompt quits
+select-heading = Expired snapshots:
+select-item = { $path }  { $age } old, { $size } exclusive
+select-total = Deleting all frees at least { $size }
+select-prompt = Numbers to keep (e.g. 2 4-6), Enter to delete all, { $quit } to quit:
+select-quit = q
@@ -0,0 +1,113 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A scratch btrfs filesystem on a loop device, torn down on drop
+pub struct LoopFs {
+    dir: PathBuf,
+    device: String,
+    pub mnt: PathBuf,
+}
+
+fn run(cmd: &str, args: &[&str]) -> String {
+    let output = Command::new(cmd)
+        .args(args)
+        .output()
+        .unwrap_or_else(|e| panic!("failed to run {}: {}", cmd, e));
+    assert!(
+        output.status.success(),
+        "{} {:?} failed: {}",
+        cmd,
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+impl LoopFs {
+    pub fn new(name: &str) -> Self {
+        assert!(
+            nix::unistd::Uid::effective().is_root(),
+            "integration tests need root for loop devices and mounts"
+        );
+        let dir = std::env::temp_dir().join(format!("btrsnap-it-{}-{}", std::process::id(), name));
+        let image = dir.join("fs.img");
+        let mnt = dir.join("mnt");
+        fs::create_dir_all(&mnt).unwrap();
+        fs::File::create(&image)
+            .unwrap()
+            .set_len(256 << 20)
+            .unwrap();
+        let image = image.to_str().unwrap();
+        run("mkfs.btrfs", &["-q", image]);
+        let device = run("losetup", &["--find", "--show", image]);
+        run("mount", &[&device, mnt.to_str().unwrap()]);
+        LoopFs { dir, device, mnt }
+    }
+
+    /// Create a subvolume at the top level of the filesystem
+    pub fn subvol(&self, name: &str) -> PathBuf {
+        let path = self.mnt.join(name);
+        run("btrfs", &["subvolume", "create", path.to_str().unwrap()]);
+        path
+    }
+
+    /// Write a config pointing at `snap_dir` and the given subvolumes
+    /// State dir the config points to, outside the filesystem under test
+    pub fn state_dir(&self) -> PathBuf {
+        self.dir.join("state")
+    }
+
+    pub fn config(&self, snap_dir: &Path, names: &[&str], extra: &str) -> PathBuf {
+        let path = self.dir.join("btrsnap.toml");
+        let names: Vec<String> = names.iter().map(|n| format!("{:?}", n)).collect();
+        fs::write(
+            &path,
+            format!(
+                "snap-dir = {:?}\nstate-dir = {:?}\nsubvol-base = {:?}\nsubvol-names = [{}]\n{}",
+                snap_dir,
+                self.state_dir(),
+                self.mnt,
+                names.join(", "),
+                extra
+            ),
+        )
+        .unwrap();
+        path
+    }
+}
+
+impl Drop for LoopFs {
+    fn drop(&mut self) {
+        let _ = Command::new("umount").arg(&self.mnt).status();
+        let _ = Command::new("losetup").args(["-d", &self.device]).status();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Run the btrsnap binary under test
+pub fn btrsnap(config: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_btrsnap"))
+        .arg("--config")
+        .arg(config)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Snapshot subvolumes directly inside `dir`, sorted by name
+pub fn snapshots(dir: &Path) -> Vec<PathBuf> {
+    let mut found: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| is_subvolume(p))
+        .collect();
+    found.sort();
+    found
+}
+
+/// Subvolume roots always have inode 256 on btrfs
+pub fn is_subvolume(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_dir() && m.ino() == 256)
+}
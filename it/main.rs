@@ -0,0 +1,85 @@
+//! End-to-end tests against a real btrfs filesystem on a loop device.
+//!
+//! Needs root, btrfs-progs and `losetup`. Run with
+//! `sudo -E cargo test --features integration-tests --test it`.
+
+mod fixture;
+
+use fixture::{LoopFs, btrsnap, snapshots};
+use std::fs::{self, File};
+use std::time::{Duration, SystemTime};
+
+#[test]
+fn create_list_delete() {
+    let fs = LoopFs::new("create");
+    let data = fs.subvol("@data");
+    fs::write(data.join("file"), "hello").unwrap();
+    let snap_dir = fs.subvol("@snapshots");
+    let config = fs.config(&snap_dir, &["@data"], "");
+
+    let out = btrsnap(&config, &["create"]);
+    assert!(out.status.success(), "{:?}", out);
+    let snaps = snapshots(&snap_dir);
+    assert_eq!(snaps.len(), 1);
+    let snap = &snaps[0];
+    let name = snap.file_name().unwrap().to_string_lossy();
+    assert!(name.starts_with("@data-"), "unexpected name {}", name);
+    assert_eq!(fs::read_to_string(snap.join("file")).unwrap(), "hello");
+    let manifests = fs::read_dir(fs.state_dir())
+        .unwrap()
+        .filter(|dir| dir.as_ref().unwrap().path().join("manifest.json").exists());
+    assert_eq!(manifests.count(), 1);
+    assert!(!snap_dir.join(".btrsnap").exists());
+
+    let out = btrsnap(&config, &["list"]);
+    assert!(out.status.success(), "{:?}", out);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains(&*snap.to_string_lossy()), "{}", stdout);
+    assert!(!stdout.contains("manifest"), "{}", stdout);
+
+    let out = btrsnap(&config, &["delete", "-s", snap.to_str().unwrap()]);
+    assert!(out.status.success(), "{:?}", out);
+    assert!(snapshots(&snap_dir).is_empty());
+    assert!(data.join("file").exists());
+}
+
+#[test]
+fn cleanup_removes_only_old_snapshots() {
+    let fs = LoopFs::new("cleanup");
+    fs.subvol("@data");
+    let snap_dir = fs.subvol("@snapshots");
+    let config = fs.config(&snap_dir, &["@data"], "keep = \"1h\"\n");
+
+    let old = snap_dir.join("@data-1000");
+    let out = btrsnap(&config, &["create"]);
+    assert!(out.status.success(), "{:?}", out);
+    let fresh = snapshots(&snap_dir).remove(0);
+    fs::rename(&fresh, &old).unwrap();
+    File::open(&old)
+        .unwrap()
+        .set_modified(SystemTime::now() - Duration::from_secs(7200))
+        .unwrap();
+    let out = btrsnap(&config, &["create"]);
+    assert!(out.status.success(), "{:?}", out);
+
+    let out = btrsnap(&config, &["cleanup"]);
+    assert!(out.status.success(), "{:?}", out);
+    let left = snapshots(&snap_dir);
+    assert_eq!(left.len(), 1);
+    assert_ne!(left[0], old);
+}
+
+#[test]
+fn source_subvolumes_are_never_deleted() {
+    let fs = LoopFs::new("protect");
+    let data = fs.subvol("@data");
+    let snap_dir = fs.subvol("@snapshots");
+    let config = fs.config(&snap_dir, &["@data"], "");
+
+    let out = btrsnap(
+        &config,
+        &["delete", "--force", "-s", data.to_str().unwrap()],
+    );
+    assert!(!out.status.success());
+    assert!(data.exists());
+}
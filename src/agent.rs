@@ -0,0 +1,211 @@
+use crate::{cleanup, create, delete, list};
+use anyhow::{Context, Result, bail};
+use clap::{Arg, CommandFactory};
+use log::info;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+/// Global options a controller may add to any step
+const GLOBAL_OPTIONS: &[&str] = &["warnings-as-errors"];
+
+#[derive(clap::Parser)]
+pub struct Agent {
+    /// Subcommand the controller may run (repeatable)
+    #[arg(
+        long = "allow",
+        default_values = ["create", "list", "cleanup"],
+        value_parser = ["create", "delete", "list", "cleanup"]
+    )]
+    pub allow: Vec<String>,
+}
+
+impl Agent {
+    pub fn execute(self, config_paths: Vec<PathBuf>) -> Result<()> {
+        let request = env::var("SSH_ORIGINAL_COMMAND")
+            .context("SSH_ORIGINAL_COMMAND not set, run the agent as an SSH forced command")?;
+        let mut words: Vec<&str> = request.split_whitespace().collect();
+        // Controllers address the remote side as `btrsnap <subcommand> ...`
+        if words
+            .first()
+            .is_some_and(|w| Path::new(w).file_name() == Some("btrsnap".as_ref()))
+        {
+            words.remove(0);
+        }
+        if words.is_empty() {
+            bail!("Empty request");
+        }
+        for subcommand in subcommands(&words) {
+            if !self.allow.iter().any(|a| a == subcommand) {
+                bail!("Request not allowed: {}", subcommand);
+            }
+        }
+        if let Some(word) = refused_option(&words) {
+            bail!("Request not allowed: {}", word);
+        }
+
+        info!("Agent running request: {}", words.join(" "));
+        let mut cmd = Command::new(env::current_exe()?);
+        // The agent's own config always wins over anything the controller sends
+        for path in config_paths {
+            cmd.arg("--config").arg(path);
+        }
+        let status = cmd.args(&words).status().context("Failed to run request")?;
+        if !status.success() {
+            process::exit(status.code().unwrap_or(1));
+        }
+        Ok(())
+    }
+}
+
+/// The subcommand of each step of a request, split at `--then` the way
+/// btrsnap splits its command line
+fn subcommands<'a>(words: &[&'a str]) -> Vec<&'a str> {
+    let mut subcommands = vec![];
+    let (mut step_start, mut verbatim) = (true, false);
+    for word in words {
+        if *word == "--then" && !verbatim {
+            step_start = true;
+            continue;
+        }
+        if step_start {
+            subcommands.push(*word);
+            step_start = false;
+        }
+        verbatim |= *word == "--";
+    }
+    subcommands
+}
+
+/// The clap definition of an allowed subcommand with the options a
+/// controller may set on it. Options the agent's own config decides, what
+/// gets snapshotted or deleted, where, and what runs or is read on its host,
+/// are left out, as is anything new until it is added to `AGENT_OPTIONS`.
+fn options(subcommand: &str) -> Option<(clap::Command, &'static [&'static str])> {
+    match subcommand {
+        "create" => Some((create::Create::command(), create::AGENT_OPTIONS)),
+        "delete" => Some((delete::Delete::command(), delete::AGENT_OPTIONS)),
+        "list" => Some((list::List::command(), list::AGENT_OPTIONS)),
+        "cleanup" => Some((cleanup::Cleanup::command(), cleanup::AGENT_OPTIONS)),
+        _ => None,
+    }
+}
+
+/// The first word of a request setting an option its step may not set, also
+/// as `--opt=value` or within bundled short options like `-ik`
+fn refused_option<'a>(words: &[&'a str]) -> Option<&'a str> {
+    let mut step = None;
+    let (mut step_start, mut verbatim) = (true, false);
+    for word in words.iter().copied() {
+        if word == "--then" && !verbatim {
+            step_start = true;
+            continue;
+        }
+        if step_start {
+            step = options(word);
+            step_start = false;
+            continue;
+        }
+        verbatim |= word == "--";
+        if verbatim {
+            continue;
+        }
+        if let Some((command, allowed)) = &step
+            && !may_set(command, allowed, word)
+        {
+            return Some(word);
+        }
+    }
+    None
+}
+
+/// Whether `word` only sets options in `allowed`, values and positional
+/// arguments set none
+fn may_set(command: &clap::Command, allowed: &[&str], word: &str) -> bool {
+    let ok = |arg: Option<&Arg>| arg.is_some_and(|a| allowed.contains(&a.get_id().as_str()));
+    if let Some(long) = word.strip_prefix("--") {
+        let name = long.split('=').next().unwrap_or(long);
+        GLOBAL_OPTIONS.contains(&name)
+            || ok(command.get_arguments().find(|a| a.get_long() == Some(name)))
+    } else if let Some(short) = word.strip_prefix('-') {
+        for c in short.chars() {
+            let arg = command.get_arguments().find(|a| a.get_short() == Some(c));
+            if !ok(arg) {
+                return false;
+            }
+            // The rest of the bundle is its value
+            if arg.is_some_and(|a| a.get_action().takes_values()) {
+                break;
+            }
+        }
+        true
+    } else {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_every_chained_step() {
+        let words = ["list", "--then", "delete", "-s", "/x"];
+        assert_eq!(subcommands(&words), ["list", "delete"]);
+        let words = ["create", "--", "--then", "x"];
+        assert_eq!(subcommands(&words), ["create"]);
+    }
+
+    #[test]
+    fn rejects_policy_options() {
+        let allowed = |request: &str| {
+            let words: Vec<&str> = request.split_whitespace().collect();
+            refused_option(&words).is_none()
+        };
+        assert!(allowed("create --json"));
+        assert!(allowed("cleanup --no-cache --then list -l"));
+        assert!(allowed("list --porcelain v1 --warnings-as-errors"));
+        assert!(allowed("delete -s /x"));
+        assert!(!allowed("cleanup --keep 1s --force --snap-dir /any"));
+        assert!(!allowed("cleanup --keep=1s"));
+        assert!(!allowed("cleanup -ik 1s"));
+        assert!(!allowed("create --subvol /any --snap-dir /any"));
+        assert!(!allowed("create -v /any"));
+        assert!(!allowed("list --then create --machines"));
+        assert!(!allowed("create --report-template=/etc/shadow"));
+        assert!(!allowed("delete -v home --keep-latest 0"));
+    }
+
+    #[test]
+    fn rejects_preset() {
+        let words = ["create", "--preset", "system"];
+        assert_eq!(refused_option(&words), Some("--preset"));
+    }
+
+    #[test]
+    fn rejects_all() {
+        let words = ["list", "--then", "create", "--all"];
+        assert_eq!(refused_option(&words), Some("--all"));
+    }
+
+    #[test]
+    fn rejects_ignore_min_interval() {
+        let words = ["create", "--json", "--ignore-min-interval"];
+        assert_eq!(refused_option(&words), Some("--ignore-min-interval"));
+    }
+
+    #[test]
+    fn allows_only_defined_options() {
+        for subcommand in ["create", "delete", "list", "cleanup"] {
+            let (command, allowed) = options(subcommand).unwrap();
+            for id in allowed {
+                assert!(
+                    command.get_arguments().any(|a| a.get_id() == id),
+                    "{} has no {}",
+                    subcommand,
+                    id
+                );
+            }
+        }
+    }
+}
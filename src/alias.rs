@@ -0,0 +1,82 @@
+use anyhow::{Result, anyhow, bail};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+
+/// The steps of the `[alias]` named by `words[0]`, each the arguments of
+/// one btrsnap command. Steps are separated by `&&` and run in order while
+/// they succeed.
+pub fn expand(aliases: &BTreeMap<String, String>, words: &[OsString]) -> Result<Vec<Vec<String>>> {
+    let name = words
+        .first()
+        .map(|w| w.to_string_lossy())
+        .unwrap_or_default();
+    let definition = aliases
+        .get(name.as_ref())
+        .ok_or_else(|| anyhow!("Unknown command '{}', and no [alias] of that name", name))?;
+    if words.len() > 1 {
+        bail!("Alias '{}' takes no arguments", name);
+    }
+    let mut steps = vec![vec![]];
+    for word in split_words(definition)? {
+        if word == "&&" {
+            steps.push(vec![]);
+        } else {
+            steps.last_mut().unwrap().push(word);
+        }
+    }
+    if steps.iter().any(Vec::is_empty) {
+        bail!("Empty step in alias '{}': {}", name, definition);
+    }
+    Ok(steps)
+}
+
+/// Split `s` into words at whitespace, keeping quoted parts together
+fn split_words(s: &str) -> Result<Vec<String>> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    for c in s.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_default().push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                word.get_or_insert_default();
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_default().push(c),
+        }
+    }
+    if quote.is_some() {
+        bail!("Unterminated quote in alias: {}", s);
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_steps_and_quoted_words() {
+        let aliases = BTreeMap::from([
+            (
+                "daily".to_string(),
+                "create --all --description 'before \"work\"' && cleanup 7d".to_string(),
+            ),
+            ("broken".to_string(), "create && ".to_string()),
+        ]);
+        let steps = expand(&aliases, &["daily".into()]).unwrap();
+        assert_eq!(
+            steps,
+            [
+                vec!["create", "--all", "--description", "before \"work\""],
+                vec!["cleanup", "7d"]
+            ]
+        );
+        assert!(expand(&aliases, &["broken".into()]).is_err());
+        assert!(expand(&aliases, &["daily".into(), "x".into()]).is_err());
+        assert!(expand(&aliases, &["weekly".into()]).is_err());
+    }
+}
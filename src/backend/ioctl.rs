@@ -0,0 +1,230 @@
+use super::search::{BTRFS_IOCTL_MAGIC, SEARCH_HEADER_LEN, SearchArgs, SearchKey, tree_search};
+use super::{FS_TREE_ID, SnapshotBackend, SubvolInfo, local_time, non_zero, uuid_opt};
+use log::debug;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use uuid::Uuid;
+
+/// Backend issuing the btrfs ioctls itself, no libbtrfsutil needed
+pub struct Ioctl;
+
+/// Inode number of every subvolume's root directory
+const FIRST_FREE_OBJECTID: u64 = 256;
+const SUBVOL_RDONLY: u64 = 1 << 1;
+const VOL_NAME_MAX: usize = 255;
+const PATH_NAME_MAX: usize = 4087;
+const SUBVOL_NAME_MAX: usize = 4039;
+const ROOT_TREE_OBJECTID: u64 = 1;
+/// Directory in the root tree holding the `default` entry
+const ROOT_TREE_DIR_OBJECTID: u64 = 6;
+const DIR_ITEM_KEY: u32 = 84;
+
+// Layouts from linux/btrfs.h
+
+#[repr(C)]
+struct VolArgs {
+    fd: i64,
+    name: [u8; PATH_NAME_MAX + 1],
+}
+
+#[repr(C)]
+struct VolArgsV2 {
+    fd: i64,
+    transid: u64,
+    flags: u64,
+    unused: [u64; 4],
+    name: [u8; SUBVOL_NAME_MAX + 1],
+}
+
+#[repr(C)]
+struct Timespec {
+    sec: u64,
+    nsec: u32,
+}
+
+#[repr(C)]
+struct GetSubvolInfoArgs {
+    treeid: u64,
+    name: [u8; VOL_NAME_MAX + 1],
+    parent_id: u64,
+    dirid: u64,
+    generation: u64,
+    flags: u64,
+    uuid: [u8; 16],
+    parent_uuid: [u8; 16],
+    received_uuid: [u8; 16],
+    ctransid: u64,
+    otransid: u64,
+    stransid: u64,
+    rtransid: u64,
+    ctime: Timespec,
+    otime: Timespec,
+    stime: Timespec,
+    rtime: Timespec,
+    reserved: [u64; 8],
+}
+
+const _: () = assert!(size_of::<VolArgs>() == 4096);
+const _: () = assert!(size_of::<VolArgsV2>() == 4096);
+const _: () = assert!(size_of::<GetSubvolInfoArgs>() == 504);
+
+nix::ioctl_write_ptr!(subvol_create, BTRFS_IOCTL_MAGIC, 14, VolArgs);
+nix::ioctl_write_ptr!(snap_destroy, BTRFS_IOCTL_MAGIC, 15, VolArgs);
+nix::ioctl_write_ptr!(set_default_subvol, BTRFS_IOCTL_MAGIC, 19, u64);
+nix::ioctl_write_ptr!(snap_create_v2, BTRFS_IOCTL_MAGIC, 23, VolArgsV2);
+nix::ioctl_read!(subvol_getflags, BTRFS_IOCTL_MAGIC, 25, u64);
+nix::ioctl_write_ptr!(subvol_setflags, BTRFS_IOCTL_MAGIC, 26, u64);
+nix::ioctl_read!(get_subvol_info, BTRFS_IOCTL_MAGIC, 60, GetSubvolInfoArgs);
+
+/// Open the directory containing `path` and return it with the entry name
+fn parent_and_name(path: &Path) -> io::Result<(File, &OsStr)> {
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(io::Error::other(format!("Invalid path {}", path.display())));
+    };
+    let parent = if parent.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        parent
+    };
+    Ok((File::open(parent)?, name))
+}
+
+/// Copy `name` into a NUL-terminated ioctl buffer
+fn copy_name(buf: &mut [u8], name: &OsStr) -> io::Result<()> {
+    let bytes = name.as_bytes();
+    if bytes.len() >= buf.len() {
+        return Err(io::Error::from_raw_os_error(nix::libc::ENAMETOOLONG));
+    }
+    buf[..bytes.len()].copy_from_slice(bytes);
+    Ok(())
+}
+
+impl SnapshotBackend for Ioctl {
+    fn is_subvolume(&self, path: &Path) -> bool {
+        let Ok(meta) = fs::metadata(path) else {
+            return false;
+        };
+        meta.is_dir() && meta.ino() == FIRST_FREE_OBJECTID && self.is_btrfs(path)
+    }
+
+    fn info(&self, path: &Path) -> io::Result<SubvolInfo> {
+        let dir = File::open(path)?;
+        // SAFETY: plain old data, filled in by the kernel
+        let mut args: GetSubvolInfoArgs = unsafe { std::mem::zeroed() };
+        unsafe { get_subvol_info(dir.as_raw_fd(), &mut args) }?;
+        Ok(SubvolInfo {
+            id: args.treeid,
+            path: path.to_path_buf(),
+            parent_id: non_zero(args.parent_id),
+            uuid: Uuid::from_bytes(args.uuid),
+            parent_uuid: uuid_opt(args.parent_uuid),
+            received_uuid: uuid_opt(args.received_uuid),
+            flags: args.flags,
+            generation: args.generation,
+            otransid: args.otransid,
+            otime: local_time(args.otime.sec as i64, args.otime.nsec),
+        })
+    }
+
+    fn create(&self, path: &Path) -> io::Result<()> {
+        let (parent, name) = parent_and_name(path)?;
+        let mut args: VolArgs = unsafe { std::mem::zeroed() };
+        copy_name(&mut args.name, name)?;
+        unsafe { subvol_create(parent.as_raw_fd(), &args) }?;
+        Ok(())
+    }
+
+    fn snapshot(&self, source: &Path, dest: &Path, read_only: bool) -> io::Result<()> {
+        let source = File::open(source)?;
+        let (parent, name) = parent_and_name(dest)?;
+        let mut args: VolArgsV2 = unsafe { std::mem::zeroed() };
+        args.fd = source.as_raw_fd().into();
+        if read_only {
+            args.flags = SUBVOL_RDONLY;
+        }
+        copy_name(&mut args.name, name)?;
+        unsafe { snap_create_v2(parent.as_raw_fd(), &args) }?;
+        Ok(())
+    }
+
+    fn delete(&self, path: &Path) -> io::Result<()> {
+        let (parent, name) = parent_and_name(path)?;
+        let mut args: VolArgs = unsafe { std::mem::zeroed() };
+        copy_name(&mut args.name, name)?;
+        unsafe { snap_destroy(parent.as_raw_fd(), &args) }?;
+        Ok(())
+    }
+
+    fn set_read_only(&self, path: &Path, read_only: bool) -> io::Result<()> {
+        let dir = File::open(path)?;
+        let mut flags = 0;
+        unsafe { subvol_getflags(dir.as_raw_fd(), &mut flags) }?;
+        if read_only {
+            flags |= SUBVOL_RDONLY;
+        } else {
+            flags &= !SUBVOL_RDONLY;
+        }
+        unsafe { subvol_setflags(dir.as_raw_fd(), &flags) }?;
+        Ok(())
+    }
+
+    /// Probes each directory entry, the tree search the library uses needs
+    /// far more ioctl plumbing than a snapshot dir warrants
+    fn list(&self, dir: &Path) -> io::Result<Vec<SubvolInfo>> {
+        let mut found = vec![];
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            // E.g. `latest-links`, which would list their snapshot twice
+            if entry.file_type()?.is_symlink() {
+                continue;
+            }
+            let path = entry.path();
+            if !self.is_subvolume(&path) {
+                debug!("Path {} is not a subvolume", path.display());
+                continue;
+            }
+            found.push(self.info(&path)?);
+        }
+        Ok(found)
+    }
+
+    fn set_default(&self, path: &Path) -> io::Result<()> {
+        let id = self.info(path)?.id;
+        let dir = File::open(path)?;
+        unsafe { set_default_subvol(dir.as_raw_fd(), &id) }?;
+        Ok(())
+    }
+
+    /// Reads the `default` dir item of the root tree like libbtrfsutil,
+    /// which needs CAP_SYS_ADMIN
+    fn default_subvol(&self, path: &Path) -> io::Result<u64> {
+        let dir = File::open(path)?;
+        let mut args: SearchArgs = unsafe { std::mem::zeroed() };
+        args.key = SearchKey {
+            tree_id: ROOT_TREE_OBJECTID,
+            min_objectid: ROOT_TREE_DIR_OBJECTID,
+            max_objectid: ROOT_TREE_DIR_OBJECTID,
+            min_offset: 0,
+            max_offset: u64::MAX,
+            min_transid: 0,
+            max_transid: u64::MAX,
+            min_type: DIR_ITEM_KEY,
+            max_type: DIR_ITEM_KEY,
+            nr_items: 1,
+            unused: 0,
+            unused1: [0; 4],
+        };
+        unsafe { tree_search(dir.as_raw_fd(), &mut args) }?;
+        if args.key.nr_items == 0 {
+            return Ok(FS_TREE_ID);
+        }
+        // The item is a btrfs_dir_item, starting with the key of its target
+        let item = &args.buf[SEARCH_HEADER_LEN..SEARCH_HEADER_LEN + 8];
+        Ok(u64::from_le_bytes(item.try_into().unwrap()))
+    }
+}
@@ -0,0 +1,174 @@
+use super::{SnapshotBackend, SubvolInfo, local_time, non_zero, uuid_opt};
+use btrfsutil::subvolume::{DeleteFlags, SnapshotFlags, Subvolume};
+use btrfsutil_sys::{
+    btrfs_util_create_subvolume_iterator, btrfs_util_destroy_subvolume_iterator, btrfs_util_error,
+    btrfs_util_error_BTRFS_UTIL_ERROR_STOP_ITERATION, btrfs_util_error_BTRFS_UTIL_OK,
+    btrfs_util_strerror, btrfs_util_subvolume_info, btrfs_util_subvolume_iterator,
+    btrfs_util_subvolume_iterator_next_info,
+};
+use log::debug;
+use nix::libc;
+use std::ffi::{CStr, CString, OsString};
+use std::io;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+/// Backend linking libbtrfsutil
+pub struct LibBtrfsutil;
+
+/// libbtrfsutil leaves the failing syscall's errno in place, prefer it over
+/// the library's own error code
+fn os_error(e: impl ToString) -> io::Error {
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(0) | None => io::Error::other(e.to_string()),
+        Some(_) => err,
+    }
+}
+
+impl SnapshotBackend for LibBtrfsutil {
+    fn is_subvolume(&self, path: &Path) -> bool {
+        Subvolume::is_subvolume(path).is_ok()
+    }
+
+    fn info(&self, path: &Path) -> io::Result<SubvolInfo> {
+        let info = Subvolume::get(path)
+            .and_then(|s| s.info())
+            .map_err(os_error)?;
+        Ok(SubvolInfo {
+            id: info.id,
+            path: path.to_path_buf(),
+            parent_id: info.parent_id,
+            uuid: info.uuid,
+            parent_uuid: info.parent_uuid,
+            received_uuid: info.received_uuid,
+            flags: info.flags,
+            generation: info.generation,
+            otransid: info.otransid,
+            otime: info.otime,
+        })
+    }
+
+    fn create(&self, path: &Path) -> io::Result<()> {
+        Subvolume::create(path, None).map_err(os_error)?;
+        Ok(())
+    }
+
+    fn snapshot(&self, source: &Path, dest: &Path, read_only: bool) -> io::Result<()> {
+        let flags = if read_only {
+            SnapshotFlags::READ_ONLY
+        } else {
+            SnapshotFlags::empty()
+        };
+        Subvolume::get(source)
+            .and_then(|s| s.snapshot(dest, flags, None))
+            .map_err(os_error)?;
+        Ok(())
+    }
+
+    fn delete(&self, path: &Path) -> io::Result<()> {
+        Subvolume::get(path)
+            .and_then(|s| s.delete(DeleteFlags::empty()))
+            .map_err(os_error)
+    }
+
+    fn set_read_only(&self, path: &Path, read_only: bool) -> io::Result<()> {
+        Subvolume::get(path)
+            .and_then(|s| s.set_ro(read_only))
+            .map_err(os_error)
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<SubvolInfo>> {
+        subvolumes_in(dir)
+    }
+
+    fn set_default(&self, path: &Path) -> io::Result<()> {
+        Subvolume::get(path)
+            .and_then(|s| s.set_default())
+            .map_err(os_error)
+    }
+
+    fn default_subvol(&self, path: &Path) -> io::Result<u64> {
+        Ok(Subvolume::get_default(path).map_err(os_error)?.id())
+    }
+}
+
+/// Owned libbtrfsutil iterator, destroyed on drop
+struct RawIter(*mut btrfs_util_subvolume_iterator);
+
+impl Drop for RawIter {
+    fn drop(&mut self) {
+        unsafe { btrfs_util_destroy_subvolume_iterator(self.0) }
+    }
+}
+
+fn check(err: btrfs_util_error) -> io::Result<()> {
+    if err == btrfs_util_error_BTRFS_UTIL_OK {
+        return Ok(());
+    }
+    let msg = unsafe { CStr::from_ptr(btrfs_util_strerror(err)) };
+    Err(os_error(msg.to_string_lossy()))
+}
+
+/// Root of the subvolume containing `path`
+fn subvolume_root(path: &Path) -> io::Result<&Path> {
+    path.ancestors()
+        .find(|p| Subvolume::is_subvolume(*p).is_ok())
+        .ok_or_else(|| io::Error::other(format!("{} is not on a BTRFS filesystem", path.display())))
+}
+
+/// Subvolumes directly inside `dir`, with their info, in a single pass over
+/// the filesystem tree instead of probing every directory entry
+fn subvolumes_in(dir: &Path) -> io::Result<Vec<SubvolInfo>> {
+    let root = subvolume_root(dir)?;
+    // Iterator paths are relative to the root of the containing subvolume
+    let rel_dir = dir.strip_prefix(root).map_err(io::Error::other)?;
+    debug!(
+        "Iterating subvolumes below {} for {}",
+        root.display(),
+        dir.display()
+    );
+
+    let c_dir = CString::new(dir.as_os_str().as_bytes())?;
+    let mut raw_iter: *mut btrfs_util_subvolume_iterator = ptr::null_mut();
+    check(unsafe { btrfs_util_create_subvolume_iterator(c_dir.as_ptr(), 0, 0, &mut raw_iter) })?;
+    let iter = RawIter(raw_iter);
+
+    let mut found = vec![];
+    loop {
+        let mut raw_path: *mut libc::c_char = ptr::null_mut();
+        let mut raw_info: btrfs_util_subvolume_info = unsafe { std::mem::zeroed() };
+        let err = unsafe {
+            btrfs_util_subvolume_iterator_next_info(iter.0, &mut raw_path, &mut raw_info)
+        };
+        if err == btrfs_util_error_BTRFS_UTIL_ERROR_STOP_ITERATION {
+            break;
+        }
+        check(err)?;
+        let rel = unsafe {
+            let bytes = CStr::from_ptr(raw_path).to_bytes().to_vec();
+            libc::free(raw_path.cast());
+            PathBuf::from(OsString::from_vec(bytes))
+        };
+        if rel.parent() == Some(rel_dir) {
+            found.push(to_info(&raw_info, root.join(rel)));
+        }
+    }
+    Ok(found)
+}
+
+fn to_info(raw: &btrfs_util_subvolume_info, path: PathBuf) -> SubvolInfo {
+    SubvolInfo {
+        id: raw.id,
+        path,
+        parent_id: non_zero(raw.parent_id),
+        uuid: uuid::Uuid::from_bytes(raw.uuid),
+        parent_uuid: uuid_opt(raw.parent_uuid),
+        received_uuid: uuid_opt(raw.received_uuid),
+        flags: raw.flags,
+        generation: raw.generation,
+        otransid: raw.otransid,
+        otime: local_time(raw.otime.tv_sec, raw.otime.tv_nsec as u32),
+    }
+}
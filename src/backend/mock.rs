@@ -0,0 +1,207 @@
+//! In-memory backend for unit tests.
+//!
+//! Subvolumes are plain directories below a scratch dir, their btrfs
+//! metadata lives in memory, so commands can run without root or btrfs.
+
+use super::{QgroupUsage, SnapshotBackend, SubvolInfo};
+use chrono::{DateTime, Local};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use uuid::Uuid;
+
+static SCRATCH: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Default)]
+pub struct MockBackend {
+    subvols: Mutex<BTreeMap<PathBuf, SubvolInfo>>,
+    /// Error numbers returned by the next deletions, e.g. `EBUSY`
+    pub delete_errors: Mutex<Vec<i32>>,
+    /// Subvolume passed to the last `set_default`
+    pub default: Mutex<Option<PathBuf>>,
+    /// Qgroup usage reported for subvolumes
+    pub usage: Mutex<BTreeMap<PathBuf, QgroupUsage>>,
+    /// Directories on filesystems other than btrfs, everything else is
+    pub other_fs: Mutex<Vec<PathBuf>>,
+}
+
+impl MockBackend {
+    /// A backend that lives for the rest of the test run, as commands expect
+    pub fn leak() -> &'static Self {
+        Box::leak(Box::default())
+    }
+
+    /// A fresh empty directory to hold the test's subvolumes
+    pub fn scratch_dir(&self) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "btrsnap-test-{}-{}",
+            std::process::id(),
+            SCRATCH.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    /// Add a subvolume created at `otime`
+    pub fn add(&self, path: &Path, otime: DateTime<Local>) {
+        fs::create_dir_all(path).unwrap();
+        let mut subvols = self.subvols.lock().unwrap();
+        let id = 256 + subvols.len() as u64;
+        subvols.insert(
+            path.to_path_buf(),
+            SubvolInfo {
+                id,
+                path: path.to_path_buf(),
+                parent_id: Some(5),
+                uuid: Uuid::from_u128(id as u128),
+                parent_uuid: None,
+                received_uuid: None,
+                flags: 0,
+                generation: 1,
+                otransid: 1,
+                otime,
+            },
+        );
+    }
+
+    /// Copy the files, directories and symlinks of `src` into `dest` as a
+    /// snapshot would, leaving nested subvolumes (and `dest` itself) empty
+    fn copy_tree(&self, src: &Path, dest: &Path) -> io::Result<()> {
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            let (from, to) = (entry.path(), dest.join(entry.file_name()));
+            let file_type = entry.file_type()?;
+            if file_type.is_symlink() {
+                std::os::unix::fs::symlink(fs::read_link(&from)?, &to)?;
+            } else if file_type.is_dir() {
+                fs::create_dir(&to)?;
+                if !self.exists(&from) && !dest.starts_with(&from) {
+                    self.copy_tree(&from, &to)?;
+                }
+            } else if file_type.is_file() {
+                fs::copy(&from, &to)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn exists(&self, path: &Path) -> bool {
+        self.subvols.lock().unwrap().contains_key(&resolve(path))
+    }
+}
+
+/// The directory behind a `/proc/self/fd/<n>` path, as the kernel would
+/// open it
+fn resolve(path: &Path) -> PathBuf {
+    match path.starts_with("/proc/self/fd") {
+        true => fs::read_link(path).unwrap_or_else(|_| path.to_path_buf()),
+        false => path.to_path_buf(),
+    }
+}
+
+fn not_found() -> io::Error {
+    io::Error::from_raw_os_error(nix::libc::ENOENT)
+}
+
+impl SnapshotBackend for MockBackend {
+    fn is_subvolume(&self, path: &Path) -> bool {
+        self.exists(path)
+    }
+
+    fn is_btrfs(&self, path: &Path) -> bool {
+        let other_fs = self.other_fs.lock().unwrap();
+        !other_fs.iter().any(|dir| path.starts_with(dir))
+    }
+
+    fn info(&self, path: &Path) -> io::Result<SubvolInfo> {
+        self.subvols
+            .lock()
+            .unwrap()
+            .get(&resolve(path))
+            .cloned()
+            .ok_or_else(not_found)
+    }
+
+    fn create(&self, path: &Path) -> io::Result<()> {
+        self.add(path, Local::now());
+        Ok(())
+    }
+
+    fn snapshot(&self, source: &Path, dest: &Path, read_only: bool) -> io::Result<()> {
+        let source = self.info(source)?;
+        if self.subvols.lock().unwrap().contains_key(dest) {
+            return Err(io::Error::from_raw_os_error(nix::libc::EEXIST));
+        }
+        self.add(dest, Local::now());
+        self.copy_tree(&source.path, dest)?;
+        let mut subvols = self.subvols.lock().unwrap();
+        let snap = subvols.get_mut(dest).unwrap();
+        snap.parent_uuid = Some(source.uuid);
+        snap.flags = if read_only { 1 } else { 0 };
+        Ok(())
+    }
+
+    fn delete(&self, path: &Path) -> io::Result<()> {
+        if let Some(errno) = self.delete_errors.lock().unwrap().pop() {
+            return Err(io::Error::from_raw_os_error(errno));
+        }
+        self.subvols
+            .lock()
+            .unwrap()
+            .remove(path)
+            .ok_or_else(not_found)?;
+        fs::remove_dir_all(path)
+    }
+
+    fn set_read_only(&self, path: &Path, read_only: bool) -> io::Result<()> {
+        let mut subvols = self.subvols.lock().unwrap();
+        let info = subvols.get_mut(path).ok_or_else(not_found)?;
+        info.flags = if read_only { 1 } else { 0 };
+        if !read_only {
+            info.received_uuid = None;
+        }
+        Ok(())
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<SubvolInfo>> {
+        Ok(self
+            .subvols
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|s| s.path.parent() == Some(dir))
+            .cloned()
+            .collect())
+    }
+
+    fn set_default(&self, path: &Path) -> io::Result<()> {
+        self.info(path)?;
+        *self.default.lock().unwrap() = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    fn default_subvol(&self, _path: &Path) -> io::Result<u64> {
+        match &*self.default.lock().unwrap() {
+            Some(path) => Ok(self.info(path)?.id),
+            None => Ok(super::FS_TREE_ID),
+        }
+    }
+
+    fn qgroups(&self, _path: &Path) -> io::Result<BTreeMap<u64, QgroupUsage>> {
+        let subvols = self.subvols.lock().unwrap();
+        Ok(self
+            .usage
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(path, usage)| Some((subvols.get(path)?.id, *usage)))
+            .collect())
+    }
+
+    fn quota_rescan(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+//! Subvolume operations, through libbtrfsutil or the btrfs ioctls directly.
+//!
+//! The implementation is picked at build time: the `ioctl` feature selects the
+//! pure-Rust backend (e.g. for static musl builds), otherwise libbtrfsutil is
+//! linked.
+
+use chrono::{DateTime, Local, TimeZone};
+use log::debug;
+use nix::libc;
+use nix::sys::statfs::{BTRFS_SUPER_MAGIC, statfs};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+#[cfg(feature = "ioctl")]
+mod ioctl;
+#[cfg(all(feature = "libbtrfsutil", not(feature = "ioctl")))]
+mod libbtrfsutil;
+#[cfg(test)]
+pub mod mock;
+mod qgroup;
+mod search;
+
+pub use qgroup::QgroupUsage;
+
+#[cfg(not(any(feature = "ioctl", feature = "libbtrfsutil")))]
+compile_error!("enable the `libbtrfsutil` or the `ioctl` feature");
+
+/// ID of the top-level subvolume
+pub const FS_TREE_ID: u64 = 5;
+/// `SubvolInfo::flags` bit of read-only subvolumes
+pub const ROOT_SUBVOL_RDONLY: u64 = 1;
+
+/// What btrsnap needs to know about a subvolume
+#[derive(Clone, Debug)]
+pub struct SubvolInfo {
+    pub id: u64,
+    pub path: PathBuf,
+    pub parent_id: Option<u64>,
+    pub uuid: Uuid,
+    pub parent_uuid: Option<Uuid>,
+    pub received_uuid: Option<Uuid>,
+    pub flags: u64,
+    pub generation: u64,
+    pub otransid: u64,
+    pub otime: DateTime<Local>,
+}
+
+impl SubvolInfo {
+    pub fn is_read_only(&self) -> bool {
+        self.flags & ROOT_SUBVOL_RDONLY != 0
+    }
+}
+
+/// Subvolume operations used by the commands.
+///
+/// Errors carry the OS error code where there is one, so callers can tell
+/// transient failures apart.
+pub trait SnapshotBackend: Send + Sync {
+    fn is_subvolume(&self, path: &Path) -> bool;
+    /// Whether `path` is on a btrfs filesystem
+    fn is_btrfs(&self, path: &Path) -> bool {
+        statfs(path).is_ok_and(|s| s.filesystem_type() == BTRFS_SUPER_MAGIC)
+    }
+    fn info(&self, path: &Path) -> io::Result<SubvolInfo>;
+    /// Create an empty subvolume
+    fn create(&self, path: &Path) -> io::Result<()>;
+    fn snapshot(&self, source: &Path, dest: &Path, read_only: bool) -> io::Result<()>;
+    fn delete(&self, path: &Path) -> io::Result<()>;
+    /// Set or clear the read-only flag. Clearing it drops the received UUID.
+    fn set_read_only(&self, path: &Path, read_only: bool) -> io::Result<()>;
+    /// Subvolumes directly inside `dir`
+    fn list(&self, dir: &Path) -> io::Result<Vec<SubvolInfo>>;
+    /// Make `path` the default subvolume of its filesystem, the one mounted
+    /// when no `subvol=` option is given
+    fn set_default(&self, path: &Path) -> io::Result<()>;
+    /// ID of the default subvolume of the filesystem containing `path`
+    fn default_subvol(&self, path: &Path) -> io::Result<u64>;
+    /// Space usage of every subvolume on the filesystem of `path`, by ID,
+    /// from the qgroups. Fails with ENOENT if quotas are disabled.
+    fn qgroups(&self, path: &Path) -> io::Result<BTreeMap<u64, QgroupUsage>> {
+        qgroup::usage(path)
+    }
+    /// Recount the qgroups of the filesystem of `path`, waiting until done
+    fn quota_rescan(&self, path: &Path) -> io::Result<()> {
+        qgroup::rescan(path)
+    }
+    /// Delete a subvolume, clearing its read-only flag only if the kernel
+    /// refuses to delete it otherwise (e.g. a received snapshot deleted
+    /// without CAP_SYS_ADMIN)
+    fn delete_any(&self, path: &Path) -> io::Result<()> {
+        let e = match self.delete(path) {
+            Err(e) if matches!(e.raw_os_error(), Some(libc::EPERM | libc::EROFS)) => e,
+            result => return result,
+        };
+        if !self.info(path).is_ok_and(|info| info.is_read_only()) {
+            return Err(e);
+        }
+        debug!(
+            "Clearing the read-only flag of {} to delete it",
+            path.display()
+        );
+        if self.set_read_only(path, false).is_err() {
+            return Err(e);
+        }
+        self.delete(path).inspect_err(|_| {
+            let _ = self.set_read_only(path, true);
+        })
+    }
+}
+
+/// The backend this binary was built with
+pub fn get() -> &'static dyn SnapshotBackend {
+    #[cfg(feature = "ioctl")]
+    return &ioctl::Ioctl;
+    #[cfg(all(feature = "libbtrfsutil", not(feature = "ioctl")))]
+    return &libbtrfsutil::LibBtrfsutil;
+}
+
+fn local_time(sec: i64, nsec: u32) -> DateTime<Local> {
+    Local.timestamp_opt(sec, nsec).single().unwrap_or_default()
+}
+
+/// `None` for the all-zero UUID btrfs uses for "unset"
+fn uuid_opt(bytes: [u8; 16]) -> Option<Uuid> {
+    Some(Uuid::from_bytes(bytes)).filter(|u| !u.is_nil())
+}
+
+fn non_zero(v: u64) -> Option<u64> {
+    Some(v).filter(|v| *v != 0)
+}
@@ -0,0 +1,71 @@
+//! Space accounting from the quota tree, kept by the kernel once quotas are
+//! enabled (`btrfs quota enable`)
+
+use super::search::{self, BTRFS_IOCTL_MAGIC};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+const QUOTA_TREE_OBJECTID: u64 = 8;
+const QGROUP_INFO_KEY: u32 = 242;
+
+/// Space referenced and exclusively held by a level-0 qgroup, i.e. one
+/// subvolume
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QgroupUsage {
+    pub referenced: u64,
+    pub exclusive: u64,
+}
+
+#[repr(C)]
+struct QuotaRescanArgs {
+    flags: u64,
+    progress: u64,
+    reserved: [u64; 6],
+}
+
+nix::ioctl_write_ptr!(quota_rescan, BTRFS_IOCTL_MAGIC, 44, QuotaRescanArgs);
+nix::ioctl_none!(quota_rescan_wait, BTRFS_IOCTL_MAGIC, 46);
+
+/// Usage of every subvolume on the filesystem of `path`, by subvolume ID.
+/// Fails with ENOENT if quotas are disabled.
+pub fn usage(path: &Path) -> io::Result<BTreeMap<u64, QgroupUsage>> {
+    let dir = File::open(path)?;
+    let items = search::items(dir.as_raw_fd(), QUOTA_TREE_OBJECTID, QGROUP_INFO_KEY)?;
+    let mut usage = BTreeMap::new();
+    for item in items {
+        // The offset is the qgroup ID, level in the top 16 bits
+        if item.offset >> 48 != 0 || item.data.len() < 40 {
+            continue;
+        }
+        // btrfs_qgroup_info_item: generation, rfer, rfer_cmpr, excl, excl_cmpr
+        let field = |i: usize| u64::from_le_bytes(item.data[i * 8..i * 8 + 8].try_into().unwrap());
+        usage.insert(
+            item.offset,
+            QgroupUsage {
+                referenced: field(1),
+                exclusive: field(3),
+            },
+        );
+    }
+    Ok(usage)
+}
+
+/// Recount all qgroups of the filesystem of `path` and wait until done
+pub fn rescan(path: &Path) -> io::Result<()> {
+    let dir = File::open(path)?;
+    let args = QuotaRescanArgs {
+        flags: 0,
+        progress: 0,
+        reserved: [0; 6],
+    };
+    match unsafe { quota_rescan(dir.as_raw_fd(), &args) } {
+        // A rescan is already running, wait for that one
+        Ok(_) | Err(nix::errno::Errno::EINPROGRESS) => {}
+        Err(e) => return Err(e.into()),
+    }
+    unsafe { quota_rescan_wait(dir.as_raw_fd()) }?;
+    Ok(())
+}
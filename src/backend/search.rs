@@ -0,0 +1,175 @@
+//! The TREE_SEARCH ioctl, for metadata neither backend's subvolume calls
+//! cover. Searching trees other than the caller's subvolume needs
+//! CAP_SYS_ADMIN.
+
+pub const BTRFS_IOCTL_MAGIC: u8 = 0x94;
+
+// Layouts from linux/btrfs.h
+
+#[repr(C)]
+pub struct SearchKey {
+    pub tree_id: u64,
+    pub min_objectid: u64,
+    pub max_objectid: u64,
+    pub min_offset: u64,
+    pub max_offset: u64,
+    pub min_transid: u64,
+    pub max_transid: u64,
+    pub min_type: u32,
+    pub max_type: u32,
+    pub nr_items: u32,
+    pub unused: u32,
+    pub unused1: [u64; 4],
+}
+
+#[repr(C)]
+pub struct SearchArgs {
+    pub key: SearchKey,
+    pub buf: [u8; 4096 - size_of::<SearchKey>()],
+}
+
+/// Precedes each item in `SearchArgs::buf`
+pub const SEARCH_HEADER_LEN: usize = 32;
+
+const _: () = assert!(size_of::<SearchKey>() == 104);
+
+nix::ioctl_readwrite!(tree_search, BTRFS_IOCTL_MAGIC, 17, SearchArgs);
+
+/// An item found by the search, with its key
+pub struct Item {
+    pub objectid: u64,
+    pub offset: u64,
+    pub item_type: u32,
+    pub data: Vec<u8>,
+}
+
+/// All items of `item_type` in tree `tree_id`, over as many ioctl calls as
+/// it takes. The kernel compares whole keys, so other item types in the
+/// range come back too and are dropped here.
+pub fn items(fd: i32, tree_id: u64, item_type: u32) -> std::io::Result<Vec<Item>> {
+    collect(tree_id, item_type, |args| {
+        unsafe { tree_search(fd, args) }?;
+        Ok(())
+    })
+}
+
+/// The items `search` finds, asking again from the key after the last one
+/// until it finds nothing
+fn collect(
+    tree_id: u64,
+    item_type: u32,
+    mut search: impl FnMut(&mut SearchArgs) -> std::io::Result<()>,
+) -> std::io::Result<Vec<Item>> {
+    let mut found = vec![];
+    let mut min = (0, item_type, 0);
+    loop {
+        let mut args: SearchArgs = unsafe { std::mem::zeroed() };
+        args.key = SearchKey {
+            tree_id,
+            min_objectid: min.0,
+            max_objectid: u64::MAX,
+            min_offset: min.2,
+            max_offset: u64::MAX,
+            min_transid: 0,
+            max_transid: u64::MAX,
+            min_type: min.1,
+            max_type: item_type,
+            nr_items: u32::MAX,
+            unused: 0,
+            unused1: [0; 4],
+        };
+        search(&mut args)?;
+        if args.key.nr_items == 0 {
+            break;
+        }
+        let mut pos = 0;
+        for _ in 0..args.key.nr_items {
+            let header = &args.buf[pos..pos + SEARCH_HEADER_LEN];
+            let u64_at = |at: usize| u64::from_ne_bytes(header[at..at + 8].try_into().unwrap());
+            let u32_at = |at: usize| u32::from_ne_bytes(header[at..at + 4].try_into().unwrap());
+            let (objectid, offset, item_type, len) =
+                (u64_at(8), u64_at(16), u32_at(24), u32_at(28));
+            let data_start = pos + SEARCH_HEADER_LEN;
+            let data = args.buf[data_start..data_start + len as usize].to_vec();
+            pos = data_start + len as usize;
+            found.push(Item {
+                objectid,
+                offset,
+                item_type,
+                data,
+            });
+        }
+        // Continue after the whole (objectid, type, offset) key of the last
+        // item, which may be of another type, rolling over like btrfs-progs
+        let last = found.last().expect("nr_items > 0");
+        min = match next_key((last.objectid, last.item_type, last.offset)) {
+            Some(key) => key,
+            None => break,
+        };
+    }
+    found.retain(|item| item.item_type == item_type);
+    Ok(found)
+}
+
+/// The smallest key after `key`
+fn next_key((objectid, item_type, offset): (u64, u32, u64)) -> Option<(u64, u32, u64)> {
+    if let Some(offset) = offset.checked_add(1) {
+        Some((objectid, item_type, offset))
+    } else if let Some(item_type) = item_type.checked_add(1).filter(|t| *t <= u8::MAX as u32) {
+        Some((objectid, item_type, 0))
+    } else {
+        objectid.checked_add(1).map(|objectid| (objectid, 0, 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// QGROUP_INFO items, each followed by a QGROUP_LIMIT item
+    const INFO: u32 = 242;
+    const LIMIT: u32 = 244;
+
+    #[test]
+    fn resumes_after_items_of_other_types() {
+        let tree: Vec<(u64, u32, u64)> = (0..10u64)
+            .flat_map(|n| [(0, INFO, n), (0, LIMIT, n), (1 << 48, INFO, n)])
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let mut calls = 0;
+        // Like the kernel: keys between min and max key, a few per call
+        let found = collect(5, INFO, |args| {
+            calls += 1;
+            assert!(calls < 100, "search does not advance");
+            let k = &args.key;
+            let (min, max) = (
+                (k.min_objectid, k.min_type, k.min_offset),
+                (k.max_objectid, k.max_type, k.max_offset),
+            );
+            let batch: Vec<_> = tree
+                .iter()
+                .filter(|key| **key >= min && **key <= max)
+                .take(3)
+                .collect();
+            let mut pos = 0;
+            for (objectid, item_type, offset) in &batch {
+                let header = &mut args.buf[pos..pos + SEARCH_HEADER_LEN];
+                header[8..16].copy_from_slice(&objectid.to_ne_bytes());
+                header[16..24].copy_from_slice(&offset.to_ne_bytes());
+                header[24..28].copy_from_slice(&item_type.to_ne_bytes());
+                header[28..32].copy_from_slice(&1u32.to_ne_bytes());
+                args.buf[pos + SEARCH_HEADER_LEN] = *offset as u8;
+                pos += SEARCH_HEADER_LEN + 1;
+            }
+            args.key.nr_items = batch.len() as u32;
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(found.len(), 20);
+        assert!(found.iter().all(|item| item.item_type == INFO));
+        assert_eq!(found[19].objectid, 1 << 48);
+        assert_eq!(found[19].data, [9]);
+    }
+}
@@ -0,0 +1,122 @@
+use crate::backend::SnapshotBackend;
+use crate::{interrupt, utils};
+use anyhow::{Context, Result, bail};
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+#[derive(clap::Parser)]
+pub struct Bench {
+    /// Test subvolume to snapshot (should be small)
+    #[arg(value_parser = utils::parse_path)]
+    pub subvol: PathBuf,
+    /// Number of synthetic snapshots to create and delete
+    #[arg(short = 'n', long, default_value_t = 100)]
+    pub snapshots: usize,
+    /// Number of times to list the snapshot dir
+    #[arg(long, default_value_t = 10)]
+    pub lists: usize,
+}
+
+impl Bench {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend) -> Result<()> {
+        if self.snapshots == 0 {
+            bail!("--snapshots must be at least 1");
+        }
+        if !backend.is_subvolume(&self.subvol) {
+            bail!("{} is not a subvolume", self.subvol.display());
+        }
+        let parent = self
+            .subvol
+            .parent()
+            .context("Cannot benchmark the filesystem root")?;
+        let dir = parent.join(format!(".btrsnap-bench-{}", std::process::id()));
+        fs::create_dir(&dir).context(format!("Failed to create {}", dir.display()))?;
+        info!(
+            "Benchmarking {} snapshots of {} in {}",
+            self.snapshots,
+            self.subvol.display(),
+            dir.display()
+        );
+
+        let result = run(backend, &self.subvol, &dir, self.snapshots, self.lists);
+        // Leave nothing behind, even after a failed run
+        for info in backend.list(&dir).unwrap_or_default() {
+            let _ = backend.delete(&info.path);
+        }
+        fs::remove_dir(&dir).context(format!("Failed to remove {}", dir.display()))?;
+
+        println!(
+            "{:<8} {:>6} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "OP", "COUNT", "MIN", "P50", "P90", "P99", "MAX"
+        );
+        for (op, mut samples) in result? {
+            samples.sort();
+            println!(
+                "{:<8} {:>6} {:>10} {:>10} {:>10} {:>10} {:>10}",
+                op,
+                samples.len(),
+                format_ms(samples[0]),
+                format_ms(percentile(&samples, 50)),
+                format_ms(percentile(&samples, 90)),
+                format_ms(percentile(&samples, 99)),
+                format_ms(samples[samples.len() - 1])
+            );
+        }
+        Ok(())
+    }
+}
+
+type Samples = Vec<(&'static str, Vec<Duration>)>;
+
+fn run(
+    backend: &dyn SnapshotBackend,
+    subvol: &Path,
+    dir: &Path,
+    snapshots: usize,
+    lists: usize,
+) -> Result<Samples> {
+    let mut create = vec![];
+    for i in 0..snapshots {
+        if interrupt::requested() {
+            bail!("Interrupted");
+        }
+        let dest = dir.join(format!("bench-{}", i));
+        let start = Instant::now();
+        backend
+            .snapshot(subvol, &dest, false)
+            .context(format!("Failed to create {}", dest.display()))?;
+        create.push(start.elapsed());
+    }
+
+    let mut list = vec![];
+    for _ in 0..lists.max(1) {
+        let start = Instant::now();
+        let found = backend.list(dir)?;
+        list.push(start.elapsed());
+        if found.len() != snapshots {
+            bail!("Listed {} snapshots, expected {}", found.len(), snapshots);
+        }
+    }
+
+    let mut delete = vec![];
+    for info in backend.list(dir)? {
+        let start = Instant::now();
+        backend
+            .delete(&info.path)
+            .context(format!("Failed to delete {}", info.path.display()))?;
+        delete.push(start.elapsed());
+    }
+    Ok(vec![("create", create), ("list", list), ("delete", delete)])
+}
+
+/// Nearest-rank percentile of sorted samples
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    let rank = (sorted.len() * p).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+fn format_ms(d: Duration) -> String {
+    format!("{:.3}ms", d.as_secs_f64() * 1000.0)
+}
@@ -0,0 +1,105 @@
+//! Keeping boot entries that boot into snapshots in step with them: the
+//! entries are removed before their snapshot is deleted, so the boot menu
+//! never offers a subvolume that is gone, and the boot loader config is
+//! regenerated after a run deleted snapshots
+
+use crate::config::BootConfig;
+use crate::warnings::warning;
+use anyhow::{Context, Result};
+use log::info;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Remove the entries in `boot.entries` that mount `snapshot` as a
+/// subvolume. Fails, so the snapshot is kept, if one cannot be removed.
+pub fn prune(boot: &BootConfig, snapshot: &Path) -> Result<()> {
+    let (Some(dir), Some(name)) = (&boot.entries, snapshot.file_name()) else {
+        return Ok(());
+    };
+    let Some(name) = name.to_str() else {
+        return Ok(());
+    };
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).context(format!("Failed to read {}", dir.display())),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|e| e != "conf") {
+            continue;
+        }
+        let content =
+            fs::read_to_string(&path).context(format!("Failed to read {}", path.display()))?;
+        if boots(&content, name) {
+            info!("Removing boot entry {}", path.display());
+            fs::remove_file(&path).context(format!(
+                "Failed to remove boot entry {}, keeping {}",
+                path.display(),
+                snapshot.display()
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether the boot entry `entry` mounts the snapshot called `name`, by a
+/// `subvol=` option on its kernel command line
+fn boots(entry: &str, name: &str) -> bool {
+    entry
+        .split_whitespace()
+        .map(|word| word.strip_prefix("rootflags=").unwrap_or(word))
+        .flat_map(|word| word.split(','))
+        .filter_map(|option| option.strip_prefix("subvol="))
+        .any(|subvol| Path::new(subvol).file_name().is_some_and(|n| n == name))
+}
+
+/// Run `boot.regenerate`, if set, after snapshots were deleted
+pub fn regenerate(boot: &BootConfig) {
+    let Some(cmd) = &boot.regenerate else {
+        return;
+    };
+    info!("Regenerating boot entries: {}", cmd);
+    match Command::new("sh").args(["-c", cmd]).status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => warning!("Boot entry command failed ({}), run it by hand", status),
+        Err(e) => warning!("Failed to run the boot entry command: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+
+    #[test]
+    fn removes_only_entries_of_the_snapshot() {
+        let dir = MockBackend::leak().scratch_dir();
+        let entries = dir.join("entries");
+        fs::create_dir(&entries).unwrap();
+        let entry = |name: &str, subvol: &str| {
+            let options = format!(
+                "options root=UUID=1234 rootflags=subvol={},ro quiet\n",
+                subvol
+            );
+            fs::write(entries.join(name), format!("title {}\n{}", name, options)).unwrap();
+        };
+        entry("root-1.conf", "/@snapshots/root-1");
+        entry("root-10.conf", "/@snapshots/root-10");
+        entry("live.conf", "@");
+        let boot = BootConfig {
+            entries: Some(entries.clone()),
+            regenerate: None,
+        };
+
+        prune(&boot, &dir.join("snapshots/root-1")).unwrap();
+        assert!(!entries.join("root-1.conf").exists());
+        assert!(entries.join("root-10.conf").exists());
+        assert!(entries.join("live.conf").exists());
+        assert!(boots(
+            "linux /vmlinuz\noptions subvol=@/.snapshots/5/snapshot",
+            "snapshot"
+        ));
+    }
+}
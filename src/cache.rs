@@ -0,0 +1,108 @@
+use crate::backend::SubvolInfo;
+use crate::state;
+use crate::warnings::warning;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CACHE_FILE: &str = "cache.json";
+
+/// Per-snapshot data that needs a syscall to get, keyed by path and only
+/// valid while the snapshot's generation is unchanged
+#[derive(Default)]
+pub struct InfoCache {
+    entries: BTreeMap<PathBuf, Cached>,
+    seen: BTreeSet<PathBuf>,
+    /// Write the cache back to the snapshot dir on `save`
+    file: Option<PathBuf>,
+    dirty: bool,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+struct Cached {
+    generation: u64,
+    mtime: DateTime<Local>,
+}
+
+impl InfoCache {
+    /// In-memory cache for one run, backed by `cache.json` in the state dir
+    /// if `persist` is set
+    pub fn open(snap_dir: &Path, persist: bool) -> Self {
+        if !persist {
+            return InfoCache::default();
+        }
+        let file = state::dir(snap_dir).join(CACHE_FILE);
+        // A broken cache is only a missed optimisation, start over
+        let entries = fs::read_to_string(&file)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        InfoCache {
+            entries,
+            file: Some(file),
+            ..Default::default()
+        }
+    }
+
+    /// Modification time of the snapshot's top directory
+    pub fn mtime(&mut self, info: &SubvolInfo) -> Result<DateTime<Local>> {
+        self.seen.insert(info.path.clone());
+        if let Some(cached) = self.entries.get(&info.path)
+            && cached.generation == info.generation
+        {
+            return Ok(cached.mtime);
+        }
+        debug!("Cache miss for {}", info.path.display());
+        let mtime = fs::metadata(&info.path)
+            .and_then(|m| m.modified())
+            .context(format!(
+                "Failed to get modification time for {}",
+                info.path.display()
+            ))?;
+        let mtime = DateTime::from(mtime);
+        self.entries.insert(
+            info.path.clone(),
+            Cached {
+                generation: info.generation,
+                mtime,
+            },
+        );
+        self.dirty = true;
+        Ok(mtime)
+    }
+
+    /// Drop a deleted snapshot
+    pub fn forget(&mut self, path: &Path) {
+        self.seen.remove(path);
+    }
+
+    /// Persist the cache if it is backed by a file and changed, dropping
+    /// snapshots that were not looked up in this run
+    pub fn save(mut self) {
+        let before = self.entries.len();
+        self.entries.retain(|path, _| self.seen.contains(path));
+        self.dirty |= self.entries.len() != before;
+        let Some(file) = self.file.filter(|_| self.dirty) else {
+            return;
+        };
+        let result = file
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| {
+                // JSON keys are strings, paths that are not UTF-8 go uncached
+                let entries: BTreeMap<_, _> = self
+                    .entries
+                    .iter()
+                    .filter_map(|(path, cached)| Some((path.to_str()?, cached)))
+                    .collect();
+                fs::write(&file, serde_json::to_string(&entries)?)
+            });
+        if let Err(e) = result {
+            warning!("Failed to write cache {}: {}", file.display(), e);
+        }
+    }
+}
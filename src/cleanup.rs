@@ -1,12 +1,30 @@
+use crate::backend::{SnapshotBackend, SubvolInfo};
+use crate::cache::InfoCache;
+use crate::config::{BootConfig, Config};
+use crate::i18n::tr;
+use crate::manifest::Manifest;
+use crate::porcelain::Porcelain;
+use crate::protect::Guard;
+use crate::report::{Action, Report};
+use crate::report_template;
+use crate::retry::{self, RetryPolicy};
+use crate::timeout::Timeouts;
+use crate::tombstone::{self, Tombstone};
 use crate::utils;
-use anyhow::{Context, Result, anyhow};
-use btrfsutil::subvolume::{DeleteFlags, Subvolume};
+use crate::warnings::warning;
+use crate::{boot, export, inhibit, interrupt, latest, maintenance};
+use anyhow::{Context, Result, anyhow, bail};
 use chrono::{DateTime, Duration, Local};
 use humantime::Duration as HumanDuration;
 use log::{debug, info};
-use std::fs;
-use std::path::PathBuf;
-use walkdir::DirEntry;
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// Options of `Cleanup` a fleet controller may set through `agent`, by clap
+/// id; retention and where to look are for the agent's config to decide
+pub const AGENT_OPTIONS: &[&str] = &["no_cache", "json", "porcelain", "interactive"];
 
 #[derive(clap::Parser)]
 pub struct Cleanup {
@@ -16,70 +34,606 @@ pub struct Cleanup {
     /// Retention duration (e.g., 7d, 30m)
     #[arg(short, long)]
     pub keep: Option<HumanDuration>,
+    /// Also delete snapshots younger than min-age-before-delete
+    #[arg(long)]
+    pub force: bool,
+    /// Don't use or update the snapshot info cache
+    #[arg(long)]
+    pub no_cache: bool,
+    /// Print the run report as JSON
+    #[arg(long)]
+    pub json: bool,
+    /// Print the run report in a stable format for scripts
+    #[arg(long, value_enum, value_name = "VERSION", conflicts_with = "json")]
+    pub porcelain: Option<Porcelain>,
+    /// Print the run report through a Jinja-style template, e.g. into
+    /// Markdown or HTML; the template sees the report as in --json
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["json", "porcelain"])]
+    pub report_template: Option<PathBuf>,
+    /// List the expired snapshots with their age and exclusive size and
+    /// ask which to keep before deleting. Space budgets are not enforced.
+    #[arg(short, long, conflicts_with_all = ["json", "porcelain", "report_template"])]
+    pub interactive: bool,
 }
 
 impl Cleanup {
-    pub fn execute(
-        self,
-        snap_dir: Option<PathBuf>,
-        keep_duration: Option<HumanDuration>,
-    ) -> Result<()> {
-        let snap_dir = utils::resolve_snap_dir(self.snap_dir, snap_dir)?;
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        let snap_dir =
+            utils::resolve_snap_dir(backend, self.snap_dir.clone(), config.snap_dir.clone())?;
+        let template = self
+            .report_template
+            .as_deref()
+            .map(report_template::load)
+            .transpose()?;
+        let (retry, timeouts) = (config.retry, config.timeouts);
         let keep = self
             .keep
-            .or(keep_duration)
+            .or(config.keep)
             .ok_or_else(|| anyhow!("Retention duration not specified"))?;
+        // `--keep` applies to every subvolume, else `[subvol."<name>"]` may
+        // set its own
+        let keep_for = |subvol: &str| self.keep.or(config.keep(subvol)).unwrap_or(keep);
+        let now = Local::now();
+        let cutoff = |subvol: &str| -> Result<DateTime<Local>> {
+            Ok(now - Duration::from_std(keep_for(subvol).into())?)
+        };
 
+        if let Some(maintenance) = maintenance::active(&snap_dir)? {
+            println!(
+                "Skipping cleanup, {} is in maintenance mode ({})",
+                snap_dir.display(),
+                maintenance
+            );
+            return Ok(());
+        }
         info!(
             "Cleaning snapshots in {} older than {}",
             snap_dir.display(),
             keep
         );
-        let cutoff = Local::now() - Duration::from_std(keep.into())?;
-        utils::scan_snapshots(&snap_dir, |entry| cleanup_snapshot(entry, cutoff))?;
+        let guard = Guard::new(backend, config.protect.clone(), self.force);
+        let mut cache = if self.no_cache {
+            InfoCache::default()
+        } else {
+            InfoCache::open(&snap_dir, config.cache)
+        };
+        let mut report = Report::default();
+        // Only once there is something to delete
+        let inhibitor = OnceCell::new();
+        let qgroups = OnceCell::new();
+        let manifest = Manifest::load(&snap_dir)?;
+        let deselected = if self.interactive {
+            match select(backend, &snap_dir, &cutoff, &mut cache, io::stdin().lock())? {
+                Some(deselected) => deselected,
+                None => {
+                    println!("Nothing deleted");
+                    return Ok(());
+                }
+            }
+        } else {
+            BTreeSet::new()
+        };
+        utils::scan_snapshots(backend, &snap_dir, |info| {
+            if interrupt::requested() {
+                report.interrupted = true;
+                return Ok(());
+            }
+            let name = utils::snapshot_name(&info);
+            let subvol = utils::parse_snapshot_name(&name).map_or(name.clone(), |(s, _)| s.into());
+            let subvol = subvol.as_str();
+            let keep = keep_for(subvol);
+            let item = report.subvol(subvol);
+            let mut tombstone = None;
+            let result = cutoff(subvol).and_then(|cutoff| {
+                let expired = is_expired(&info, cutoff, &mut cache)?;
+                if !expired {
+                    return Ok((Action::Kept, format!("newer than keep={}", keep)));
+                }
+                if deselected.contains(&info.path) {
+                    return Ok((Action::Kept, "kept by the operator".to_string()));
+                }
+                inhibitor.get_or_init(|| inhibit::take("Deleting expired snapshots"));
+                // Qgroups of deleted subvolumes may go away with them
+                let exclusive = qgroups
+                    .get_or_init(|| backend.qgroups(&snap_dir).ok())
+                    .as_ref()
+                    .and_then(|q| q.get(&info.id))
+                    .map(|q| q.exclusive);
+                let rule = format!("keep {}", keep);
+                tombstone = Some(Tombstone::new(&info, manifest.as_ref(), &rule, exclusive));
+                let refused = cleanup_snapshot(
+                    backend,
+                    &info,
+                    &guard,
+                    &config.boot,
+                    retry,
+                    timeouts,
+                    &mut item.retries,
+                )?;
+                Ok(match refused {
+                    Some(reason) => (Action::Kept, reason),
+                    None => (Action::Deleted, format!("older than keep={}", keep)),
+                })
+            });
+            match result {
+                Ok((action, reason)) => {
+                    if let (Action::Deleted, Some(tombstone)) = (action, &tombstone) {
+                        tombstone::record(&snap_dir, tombstone);
+                        cache.forget(&info.path);
+                        item.deleted += 1;
+                    }
+                    self.explain(&info, action, &reason);
+                    item.decide(&name, action, reason);
+                }
+                Err(e) => item.errors.push(format!("{:#}", e)),
+            }
+            Ok(())
+        })?;
+        if !report.interrupted && !self.interactive {
+            self.enforce_budgets(backend, &snap_dir, &config, &guard, &mut report, &mut cache)?;
+        }
+        cache.save();
+        for s in report.subvols.iter().filter(|s| s.deleted > 0) {
+            if config.latest_links {
+                latest::update(backend, &snap_dir, &s.subvol);
+            }
+            export::refresh(backend, &snap_dir, &s.subvol);
+        }
+        if report.subvols.iter().any(|s| s.deleted > 0) {
+            boot::regenerate(&config.boot);
+        }
+        report.finish(self.json, self.porcelain, template)
+    }
+
+    /// Print a decision about `info` unless the report is for scripts
+    fn explain(&self, info: &SubvolInfo, action: Action, reason: &str) {
+        if self.json || self.porcelain.is_some() || self.report_template.is_some() {
+            return;
+        }
+        let verb = match action {
+            Action::Deleted => "Cleaned",
+            Action::Kept => "Kept",
+        };
+        println!("{}: {} ({})", verb, info.path.display(), reason);
+    }
+
+    /// Delete the oldest snapshots of subvolumes whose snapshots use more
+    /// exclusive space than their `space-budget`, keeping the newest one
+    fn enforce_budgets(
+        &self,
+        backend: &'static dyn SnapshotBackend,
+        snap_dir: &Path,
+        config: &Config,
+        guard: &Guard,
+        report: &mut Report,
+        cache: &mut InfoCache,
+    ) -> Result<()> {
+        let mut budgeted: BTreeMap<String, (u64, Vec<SubvolInfo>)> = BTreeMap::new();
+        utils::scan_snapshots(backend, snap_dir, |info| {
+            let name = utils::snapshot_name(&info);
+            if let Some((subvol, _)) = utils::parse_snapshot_name(&name)
+                && let Some(budget) = config.space_budget(&subvol)
+            {
+                let entry = budgeted
+                    .entry(subvol.to_string())
+                    .or_insert((budget, vec![]));
+                entry.1.push(info);
+            }
+            Ok(())
+        })?;
+        if budgeted.is_empty() {
+            return Ok(());
+        }
+        let qgroups = match utils::qgroups(backend, snap_dir) {
+            Ok(qgroups) => qgroups,
+            Err(e) => {
+                for subvol in budgeted.keys() {
+                    let e = format!("Cannot enforce space-budget: {:#}", e);
+                    report.subvol(subvol).errors.push(e);
+                }
+                return Ok(());
+            }
+        };
+        let exclusive = |info: &SubvolInfo| qgroups.get(&info.id).map_or(0, |q| q.exclusive);
+        let manifest = Manifest::load(snap_dir)?;
+
+        for (subvol, (budget, mut snapshots)) in budgeted {
+            snapshots.sort_by_key(|s| s.otime);
+            let mut used: u64 = snapshots.iter().map(exclusive).sum();
+            let item = report.subvol(&subvol);
+            for info in &snapshots[..snapshots.len() - 1] {
+                if used <= budget || interrupt::requested() {
+                    break;
+                }
+                info!(
+                    "Snapshots of {} use {} of their {} space budget, deleting {}",
+                    subvol,
+                    utils::format_size(used),
+                    utils::format_size(budget),
+                    info.path.display()
+                );
+                let (retry, timeouts) = (config.retry, config.timeouts);
+                let rule = format!("space-budget {}", utils::format_size(budget));
+                let tombstone =
+                    Tombstone::new(info, manifest.as_ref(), rule, Some(exclusive(info)));
+                let name = utils::snapshot_name(info);
+                match cleanup_snapshot(
+                    backend,
+                    info,
+                    guard,
+                    &config.boot,
+                    retry,
+                    timeouts,
+                    &mut item.retries,
+                ) {
+                    Ok(None) => {
+                        tombstone::record(snap_dir, &tombstone);
+                        let reason = format!(
+                            "over space-budget={} with {} used",
+                            utils::format_size(budget),
+                            utils::format_size(used)
+                        );
+                        // Extents it shared with the remaining snapshots now
+                        // count against them, so this underestimates the use
+                        // until the next run
+                        used -= exclusive(info);
+                        cache.forget(&info.path);
+                        item.deleted += 1;
+                        self.explain(info, Action::Deleted, &reason);
+                        item.decide(&name, Action::Deleted, reason);
+                    }
+                    Ok(Some(reason)) => {
+                        self.explain(info, Action::Kept, &reason);
+                        item.decide(&name, Action::Kept, reason);
+                    }
+                    Err(e) => item.errors.push(format!("{:#}", e)),
+                }
+            }
+            if used > budget {
+                warning!(
+                    "Snapshots of {} still use {}, over their {} space budget",
+                    subvol,
+                    utils::format_size(used),
+                    utils::format_size(budget)
+                );
+            }
+        }
         Ok(())
     }
 }
 
-fn cleanup_snapshot(entry: DirEntry, cutoff: DateTime<Local>) -> Result<()> {
-    debug!("Checking path: {}", entry.path().display());
+/// Whether the snapshot was last modified before `cutoff`
+fn is_expired(info: &SubvolInfo, cutoff: DateTime<Local>, cache: &mut InfoCache) -> Result<bool> {
+    debug!("Checking path: {}", info.path.display());
 
-    // Get the modification time from file system metadata
-    let metadata = fs::metadata(entry.path()).context(format!(
-        "Failed to read metadata for {}",
-        entry.path().display()
-    ))?;
-    let mtime = metadata.modified().context(format!(
-        "Failed to get modification time for {}",
-        entry.path().display()
-    ))?;
-
-    // Convert SystemTime to DateTime<Local>
-    let mtime_local: DateTime<Local> = DateTime::from(mtime);
+    let mtime_local = cache.mtime(info)?;
 
     // Check if snapshot is newer than or equal to cutoff
     if mtime_local >= cutoff {
         debug!(
             "Snapshot {} is newer than cutoff, keeping",
-            entry.path().display()
+            info.path.display()
         );
-        return Ok(());
+        return Ok(false);
     }
+    Ok(true)
+}
 
-    // Verify it's a BTRFS subvolume
-    let subvol = match Subvolume::get(entry.path()) {
-        Ok(subvol) => subvol,
-        Err(_) => {
-            debug!(
-                "Path {} is not a BTRFS subvolume, skipping",
-                entry.path().display()
-            );
-            return Ok(());
+/// List the snapshots expired as of the `cutoff` of their subvolume,
+/// oldest first, and ask which to keep. Returns their paths, `None` if the
+/// operator quit.
+fn select(
+    backend: &dyn SnapshotBackend,
+    snap_dir: &Path,
+    cutoff: &dyn Fn(&str) -> Result<DateTime<Local>>,
+    cache: &mut InfoCache,
+    mut input: impl BufRead,
+) -> Result<Option<BTreeSet<PathBuf>>> {
+    let mut candidates = vec![];
+    utils::scan_snapshots(backend, snap_dir, |info| {
+        let name = utils::snapshot_name(&info);
+        let subvol = utils::parse_snapshot_name(&name).map_or(name.clone(), |(s, _)| s.into());
+        if is_expired(&info, cutoff(&subvol)?, cache)? {
+            candidates.push(info);
         }
+        Ok(())
+    })?;
+    if candidates.is_empty() {
+        return Ok(Some(BTreeSet::new()));
+    }
+    candidates.sort_by_key(|s| s.otime);
+    let qgroups = backend.qgroups(snap_dir).ok();
+    let exclusive = |info: &SubvolInfo| {
+        qgroups
+            .as_ref()
+            .and_then(|q| q.get(&info.id))
+            .map(|q| q.exclusive)
     };
+    println!("{}", tr!("select-heading"));
+    for (i, info) in candidates.iter().enumerate() {
+        let age = (Local::now() - info.otime).num_minutes().max(0) as u64;
+        let age = humantime::format_duration(std::time::Duration::from_secs(age * 60));
+        let line = tr!(
+            "select-item",
+            path = info.path.display().to_string(),
+            age = age.to_string(),
+            size = exclusive(info).map_or("?".to_string(), utils::format_size),
+        );
+        println!("{:>4}  {}", i + 1, line);
+    }
+    let total: u64 = candidates.iter().filter_map(exclusive).sum();
+    println!("{}", tr!("select-total", size = utils::format_size(total)));
+    let quit = tr!("select-quit");
+    print!("{} ", tr!("select-prompt", quit = quit.as_str()));
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    // End of input, e.g. Ctrl-D, quits rather than deleting everything
+    if input.read_line(&mut answer)? == 0 {
+        println!();
+        return Ok(None);
+    }
+    if answer.trim() == quit {
+        return Ok(None);
+    }
+    let keep = parse_selection(&answer, candidates.len())?;
+    Ok(Some(
+        keep.into_iter()
+            .map(|i| candidates[i - 1].path.clone())
+            .collect(),
+    ))
+}
+
+/// Numbers and ranges like `2 4-6` or `2,4-6`, each between 1 and `count`
+fn parse_selection(s: &str, count: usize) -> Result<BTreeSet<usize>> {
+    let mut selected = BTreeSet::new();
+    for part in s
+        .split([' ', ',', '\n'])
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+    {
+        let (first, last) = part.split_once('-').unwrap_or((part, part));
+        let (Ok(first), Ok(last)) = (first.parse::<usize>(), last.parse::<usize>()) else {
+            bail!(
+                "Invalid selection '{}', expected numbers like 2 or 4-6",
+                part
+            );
+        };
+        if first < 1 || last > count || first > last {
+            bail!("Selection '{}' is not within 1-{}", part, count);
+        }
+        selected.extend(first..=last);
+    }
+    Ok(selected)
+}
+
+/// Delete an expired snapshot unless the guard refuses, returns the
+/// guard's reason if it did
+fn cleanup_snapshot(
+    backend: &'static dyn SnapshotBackend,
+    info: &SubvolInfo,
+    guard: &Guard,
+    boot: &BootConfig,
+    retry: RetryPolicy,
+    timeouts: Timeouts,
+    retries: &mut u32,
+) -> Result<Option<String>> {
+    if let Err(e) = guard.check(info) {
+        warning!("Refusing to delete: {:#}", e);
+        return Ok(Some(format!("refused: {:#}", e)));
+    }
+    boot::prune(boot, &info.path)?;
+    export::release(&info.path)?;
 
     // Delete the snapshot
-    subvol.delete(DeleteFlags::empty())?;
-    println!("Cleaned: {}", entry.path().display());
-    Ok(())
+    let path = info.path.clone();
+    retry::retry_with_timeout(retry, timeouts.delete, "Delete", retries, move || {
+        backend.delete_any(&path)
+    })
+    .context(format!("Failed to delete snapshot {}", info.path.display()))?;
+    // The snapshot is gone, a stale entry must not turn that into an error
+    if let Some(snap_dir) = info.path.parent()
+        && let Err(e) = Manifest::forget(snap_dir, &utils::snapshot_name(info))
+    {
+        warning!("{:#}", e);
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::QgroupUsage;
+    use crate::backend::mock::MockBackend;
+    use crate::config::SubvolSettings;
+    use crate::protect::Policy;
+    use crate::retry::RetryPolicy;
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    fn cleanup(snap_dir: &std::path::Path) -> Cleanup {
+        Cleanup {
+            snap_dir: Some(snap_dir.to_path_buf()),
+            keep: Some("1h".parse().unwrap()),
+            force: false,
+            no_cache: true,
+            json: true,
+            porcelain: None,
+            report_template: None,
+            interactive: false,
+        }
+    }
+
+    fn age(path: &std::path::Path, secs: u64) {
+        File::open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(secs))
+            .unwrap();
+    }
+
+    #[test]
+    fn deletes_only_expired_snapshots() {
+        let backend = MockBackend::leak();
+        let snap_dir = backend.scratch_dir();
+        let (old, new) = (snap_dir.join("home-1"), snap_dir.join("home-2"));
+        backend.add(&old, Local::now());
+        backend.add(&new, Local::now());
+        age(&old, 7200);
+
+        cleanup(&snap_dir)
+            .execute(backend, Config::default())
+            .unwrap();
+        assert!(!backend.exists(&old));
+        assert!(backend.exists(&new));
+    }
+
+    #[test]
+    fn counts_deletes_the_manifest_failed_to_forget() {
+        let backend = MockBackend::leak();
+        let snap_dir = backend.scratch_dir();
+        let old = snap_dir.join("home-1");
+        backend.add(&old, Local::now());
+        age(&old, 7200);
+        let entry =
+            crate::manifest::Entry::new(&snap_dir.join("home"), &backend.info(&old).unwrap());
+        Manifest::record(&snap_dir, "home-1", entry).unwrap();
+        // Saving the manifest fails once its temporary file can't be written
+        let manifest = crate::state::dir(&snap_dir).join("manifest.json");
+        std::fs::create_dir(manifest.with_extension("json.tmp")).unwrap();
+
+        cleanup(&snap_dir)
+            .execute(backend, Config::default())
+            .unwrap();
+        assert!(!backend.exists(&old));
+        let tombstones = tombstone::load(&snap_dir).unwrap();
+        assert_eq!(tombstones.len(), 1);
+    }
+
+    #[test]
+    fn keeps_protected_snapshots() {
+        let backend = MockBackend::leak();
+        let snap_dir = backend.scratch_dir();
+        let old = snap_dir.join("home-1");
+        backend.add(&old, Local::now());
+        age(&old, 7200);
+        let config = Config {
+            protect: Policy {
+                min_age: None,
+                protected: vec![old.clone()],
+            },
+            ..Default::default()
+        };
+
+        cleanup(&snap_dir).execute(backend, config).unwrap();
+        assert!(backend.exists(&old));
+    }
+
+    #[test]
+    fn prunes_oldest_snapshots_over_space_budget() {
+        let backend = MockBackend::leak();
+        let snap_dir = backend.scratch_dir();
+        let snapshots: Vec<_> = (1..=3)
+            .map(|i| snap_dir.join(format!("home-{}", i)))
+            .collect();
+        for (i, snap) in snapshots.iter().enumerate() {
+            let otime = Local::now() - chrono::Duration::minutes(10 - i as i64);
+            backend.add(snap, otime);
+            let usage = QgroupUsage {
+                referenced: 0,
+                exclusive: 1 << 30,
+            };
+            backend.usage.lock().unwrap().insert(snap.clone(), usage);
+        }
+        let mut config = Config::default();
+        config.subvol_settings.insert(
+            "home".to_string(),
+            SubvolSettings {
+                space_budget: Some(3 << 29),
+                ..Default::default()
+            },
+        );
+
+        cleanup(&snap_dir).execute(backend, config).unwrap();
+        assert!(!backend.exists(&snapshots[0]));
+        assert!(!backend.exists(&snapshots[1]));
+        assert!(backend.exists(&snapshots[2]));
+    }
+
+    #[test]
+    fn clears_read_only_flag_only_when_deletion_needs_it() {
+        let backend = MockBackend::leak();
+        let snap_dir = backend.scratch_dir();
+        let received = snap_dir.join("home-1");
+        backend.add(&received, Local::now());
+        backend.set_read_only(&received, true).unwrap();
+        age(&received, 7200);
+        backend.delete_errors.lock().unwrap().push(nix::libc::EPERM);
+
+        cleanup(&snap_dir)
+            .execute(backend, Config::default())
+            .unwrap();
+        assert!(!backend.exists(&received));
+    }
+
+    #[test]
+    fn retries_busy_deletes() {
+        let backend = MockBackend::leak();
+        let snap_dir = backend.scratch_dir();
+        let old = snap_dir.join("home-1");
+        backend.add(&old, Local::now());
+        backend.delete_errors.lock().unwrap().push(nix::libc::EBUSY);
+        let guard = Guard::new(backend, Policy::default(), false);
+        let retry = RetryPolicy {
+            retries: 1,
+            delay: Duration::ZERO,
+        };
+        let info = backend.info(&old).unwrap();
+
+        let mut retries = 0;
+        let refused = cleanup_snapshot(
+            backend,
+            &info,
+            &guard,
+            &BootConfig::default(),
+            retry,
+            Timeouts::default(),
+            &mut retries,
+        )
+        .unwrap();
+        assert!(refused.is_none());
+        assert_eq!(retries, 1);
+        assert!(!backend.exists(&old));
+    }
+
+    #[test]
+    fn end_of_input_deletes_nothing() {
+        let backend = MockBackend::leak();
+        let snap_dir = backend.scratch_dir();
+        backend.add(&snap_dir.join("home-1"), Local::now());
+        let cutoff = |_: &str| Ok(Local::now() + chrono::Duration::hours(1));
+        let select = |input: &[u8]| {
+            select(
+                backend,
+                &snap_dir,
+                &cutoff,
+                &mut InfoCache::default(),
+                input,
+            )
+        };
+
+        assert!(select(b"").unwrap().is_none());
+        assert!(select(b"q\n").unwrap().is_none());
+        assert_eq!(select(b"\n").unwrap(), Some(BTreeSet::new()));
+    }
+
+    #[test]
+    fn parses_numbers_and_ranges_to_keep() {
+        assert_eq!(
+            parse_selection("2, 4-6\n", 6).unwrap(),
+            BTreeSet::from([2, 4, 5, 6])
+        );
+        assert!(parse_selection("\n", 3).unwrap().is_empty());
+        assert!(parse_selection("0", 3).is_err());
+        assert!(parse_selection("2-4", 3).is_err());
+        assert!(parse_selection("two", 3).is_err());
+    }
 }
@@ -0,0 +1,122 @@
+use crate::backend::SnapshotBackend;
+use crate::selector;
+use anyhow::{Context, Result, anyhow, bail};
+use log::debug;
+use std::path::{Path, PathBuf};
+
+#[derive(clap::Parser)]
+pub struct CloneSnapshot {
+    /// Snapshot (or subvolume) to clone, or a selector like @home:latest
+    #[arg(value_parser = selector::parse_snapshot)]
+    pub snapshot: PathBuf,
+    /// Path of the writable clone, on the same filesystem
+    pub dest: PathBuf,
+    /// Allow creating the clone inside the snapshot directory
+    #[arg(long)]
+    pub into_snap_dir: bool,
+}
+
+impl CloneSnapshot {
+    pub fn execute(
+        self,
+        backend: &'static dyn SnapshotBackend,
+        snap_dir: Option<PathBuf>,
+    ) -> Result<()> {
+        if !backend.is_subvolume(&self.snapshot) {
+            bail!("{} is not a subvolume", self.snapshot.display());
+        }
+        let dest = resolve_dest(&self.dest)?;
+        if let Some(snap_dir) = snap_dir.and_then(|d| d.canonicalize().ok())
+            && dest.starts_with(&snap_dir)
+            && !self.into_snap_dir
+        {
+            bail!(
+                "{} is in the snapshot directory, where cleanup would treat it as a snapshot. \
+                 Use --into-snap-dir to clone there anyway",
+                dest.display()
+            );
+        }
+
+        debug!("Cloning {} to {}", self.snapshot.display(), dest.display());
+        backend
+            .snapshot(&self.snapshot, &dest, false)
+            .map_err(|e| {
+                if e.raw_os_error() == Some(nix::libc::EXDEV) {
+                    anyhow!(
+                        "{} is on another filesystem than {}",
+                        dest.display(),
+                        self.snapshot.display()
+                    )
+                } else {
+                    anyhow!(e)
+                }
+            })
+            .context(format!("Failed to clone {}", self.snapshot.display()))?;
+        println!(
+            "Cloned {} to {} (writable)",
+            self.snapshot.display(),
+            dest.display()
+        );
+        Ok(())
+    }
+}
+
+/// Absolute path of `dest`, which must not exist yet but whose parent must
+fn resolve_dest(dest: &Path) -> Result<PathBuf> {
+    if dest.symlink_metadata().is_ok() {
+        bail!("{} already exists", dest.display());
+    }
+    let name = dest
+        .file_name()
+        .ok_or_else(|| anyhow!("Invalid destination {}", dest.display()))?;
+    let parent = match dest.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let parent = parent.canonicalize().context(format!(
+        "Invalid destination directory {}",
+        parent.display()
+    ))?;
+    Ok(parent.join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use chrono::Local;
+    use std::fs;
+
+    #[test]
+    fn refuses_snap_dir_unless_asked() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let snap_dir = dir.join("snapshots");
+        let snapshot = snap_dir.join("db-1000");
+        backend.add(&snapshot, Local::now());
+        let clone = |dest: PathBuf, into_snap_dir| CloneSnapshot {
+            snapshot: snapshot.clone(),
+            dest,
+            into_snap_dir,
+        };
+
+        let inside = snap_dir.join("db-test");
+        assert!(
+            clone(inside.clone(), false)
+                .execute(backend, Some(snap_dir.clone()))
+                .is_err()
+        );
+        assert!(!backend.exists(&inside));
+
+        clone(dir.join("db-test"), false)
+            .execute(backend, Some(snap_dir.clone()))
+            .unwrap();
+        let info = backend.info(&dir.join("db-test")).unwrap();
+        assert!(!info.is_read_only());
+        clone(inside.clone(), true)
+            .execute(backend, Some(snap_dir))
+            .unwrap();
+        assert!(backend.exists(&inside));
+        assert!(fs::metadata(&inside).unwrap().is_dir());
+    }
+}
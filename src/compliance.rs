@@ -0,0 +1,174 @@
+//! Compliance mode: a minimum retention that holds for a lock period once
+//! armed. The lock lives in a fixed root-owned directory, keyed by the
+//! snapshot dir's subvolume, so neither `--force`, a shorter `--keep`,
+//! another `state-dir`, another mount path nor editing or removing
+//! `[compliance]` in the config releases snapshots early.
+
+use crate::backend::SnapshotBackend;
+use crate::config::Compliance;
+use crate::warnings::warning;
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Local};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Not below `state-dir`, which the config sets
+const LOCK_DIR: &str = "/var/lib/btrsnap/compliance";
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Lock {
+    /// Snapshots younger than this are never deleted, in seconds
+    pub min_retention: u64,
+    /// When `min_retention` may be lowered again
+    pub until: DateTime<Local>,
+}
+
+impl Lock {
+    pub fn min_retention(&self) -> Duration {
+        Duration::from_secs(self.min_retention)
+    }
+}
+
+/// The lock on `snap_dir`, if one is in force
+pub fn active(backend: &dyn SnapshotBackend, snap_dir: &Path) -> Result<Option<Lock>> {
+    active_in(backend, Path::new(LOCK_DIR), snap_dir)
+}
+
+/// Lock `snap_dir` to the configured retention. While a lock is in force
+/// the retention can only be raised, which also extends the lock.
+pub fn arm(backend: &dyn SnapshotBackend, snap_dir: &Path, compliance: &Compliance) -> Result<()> {
+    arm_in(backend, Path::new(LOCK_DIR), snap_dir, compliance)
+}
+
+fn active_in(backend: &dyn SnapshotBackend, root: &Path, snap_dir: &Path) -> Result<Option<Lock>> {
+    match lock_path(backend, root, snap_dir)? {
+        Some(path) => read(&path),
+        None => Ok(None),
+    }
+}
+
+fn arm_in(
+    backend: &dyn SnapshotBackend,
+    root: &Path,
+    snap_dir: &Path,
+    compliance: &Compliance,
+) -> Result<()> {
+    if !snap_dir.exists() {
+        // Locked on the first run after it was created
+        return Ok(());
+    }
+    let path = lock_path(backend, root, snap_dir)?
+        .ok_or_else(|| anyhow!("{} is not on btrfs", snap_dir.display()))?;
+    let wanted = compliance.min_retention.as_secs();
+    let until = Local::now() + chrono::Duration::from_std(compliance.lock)?;
+    let lock = match active_in(backend, root, snap_dir)? {
+        Some(lock) if lock.min_retention >= wanted => {
+            if lock.min_retention > wanted {
+                warning!(
+                    "Compliance lock on {} keeps min-retention at {} until {}",
+                    snap_dir.display(),
+                    humantime::format_duration(lock.min_retention()),
+                    lock.until.format("%Y-%m-%d %H:%M")
+                );
+            }
+            return Ok(());
+        }
+        Some(lock) => Lock {
+            min_retention: wanted,
+            until: until.max(lock.until),
+        },
+        None => Lock {
+            min_retention: wanted,
+            until,
+        },
+    };
+    info!(
+        "Locking min-retention {} on {} until {}",
+        humantime::format_duration(lock.min_retention()),
+        snap_dir.display(),
+        lock.until
+    );
+    fs::create_dir_all(root).context(format!("Failed to create {}", root.display()))?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(&lock)?)
+        .context(format!("Failed to write {}", tmp.display()))?;
+    fs::rename(&tmp, &path).context(format!("Failed to replace {}", path.display()))
+}
+
+/// The lock file of `snap_dir` below `root`, named after the subvolume
+/// holding the snapshot dir and its inode there, which stay the same
+/// whatever path or mount it is reached through. `None` off btrfs, where
+/// there are no snapshots to lock.
+fn lock_path(
+    backend: &dyn SnapshotBackend,
+    root: &Path,
+    snap_dir: &Path,
+) -> Result<Option<PathBuf>> {
+    let dir = snap_dir
+        .canonicalize()
+        .context(format!("Failed to resolve {}", snap_dir.display()))?;
+    // Every btrfs mount is of a subvolume, so this ends on the same filesystem
+    let Some(subvol) = dir.ancestors().find(|p| backend.is_subvolume(p)) else {
+        return Ok(None);
+    };
+    let uuid = backend
+        .info(subvol)
+        .context(format!("Failed to get info of {}", subvol.display()))?
+        .uuid;
+    let inode = fs::metadata(&dir)
+        .context(format!("Failed to stat {}", dir.display()))?
+        .ino();
+    Ok(Some(root.join(format!("{}-{}.json", uuid, inode))))
+}
+
+fn read(path: &Path) -> Result<Option<Lock>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context(format!("Failed to read {}", path.display())),
+    };
+    // A broken lock must not unlock anything, so this fails deletions
+    let lock: Lock = serde_json::from_str(&content)
+        .context(format!("Invalid compliance lock {}", path.display()))?;
+    Ok((lock.until > Local::now()).then_some(lock))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+
+    #[test]
+    fn retention_only_goes_up_while_locked() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let (root, top) = (dir.join("locks"), dir.join("top"));
+        backend.add(&top, Local::now());
+        let snap_dir = top.join("snapshots");
+        fs::create_dir(&snap_dir).unwrap();
+        // Another path to the same snapshot dir
+        let other = dir.join("other");
+        std::os::unix::fs::symlink(&snap_dir, &other).unwrap();
+        let compliance = |days: u64| Compliance {
+            min_retention: Duration::from_secs(days * 86400),
+            lock: Duration::from_secs(90 * 86400),
+        };
+        let retention = |snap_dir: &Path| {
+            active_in(backend, &root, snap_dir)
+                .unwrap()
+                .map(|l| l.min_retention)
+        };
+        assert_eq!(retention(&snap_dir), None);
+
+        arm_in(backend, &root, &snap_dir, &compliance(30)).unwrap();
+        arm_in(backend, &root, &other, &compliance(1)).unwrap();
+        assert_eq!(retention(&snap_dir), Some(30 * 86400));
+
+        arm_in(backend, &root, &snap_dir, &compliance(60)).unwrap();
+        assert_eq!(retention(&other), Some(60 * 86400));
+    }
+}
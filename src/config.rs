@@ -1,32 +1,375 @@
-use anyhow::{Context, Result, anyhow};
+use crate::backend::SnapshotBackend;
+use crate::notify::Severity;
+use crate::permissions::{self, Access, SnapshotAccess};
+use crate::protect::Policy;
+use crate::retry::RetryPolicy;
+use crate::timeout::Timeouts;
+use crate::warnings::warning;
+use crate::{discover, facts, ransomware, signing, utils};
+use anyhow::{Context, Result, anyhow, bail};
 use humantime;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use toml::Value;
 
-pub fn load(
-    config_path: Option<PathBuf>,
-) -> Result<(Option<PathBuf>, Vec<PathBuf>, Option<humantime::Duration>)> {
-    let mut snap_dir: Option<PathBuf> = None;
-    let mut toml_subvols: Vec<PathBuf> = vec![];
-    let mut toml_keep: Option<humantime::Duration> = None;
+/// Settings loaded from the TOML config file
+#[derive(Clone, Default)]
+pub struct Config {
+    /// Files the config was merged from, in order
+    pub paths: Vec<PathBuf>,
+    /// Files pulled in through `configs` lists
+    pub included: Vec<PathBuf>,
+    pub snap_dir: Option<PathBuf>,
+    /// Where manifests, tombstones and caches are kept (`state-dir`),
+    /// [`crate::state::DEFAULT_ROOT`] if unset
+    pub state_dir: Option<PathBuf>,
+    /// Directory holding the subvolumes (`subvol-base`)
+    pub subvol_base: Option<PathBuf>,
+    pub subvols: Vec<PathBuf>,
+    /// Subvolumes `create --all` leaves out, matched against the path below
+    /// `subvol-base` and the name (`exclude`)
+    pub exclude: Vec<glob::Pattern>,
+    /// Parent directories included in snapshot names, to tell apart
+    /// subvolumes with the same name (`name-parents`)
+    pub name_parents: usize,
+    pub keep: Option<humantime::Duration>,
+    /// Template for snapshot descriptions (`description`)
+    pub description: Option<String>,
+    pub protect: Policy,
+    pub email: Option<EmailConfig>,
+    /// `[[notify.chat]]` backends
+    pub chat: Vec<ChatConfig>,
+    /// `[notify]` policies against repeated notifications
+    pub notify: NotifyPolicy,
+    pub retry: RetryPolicy,
+    pub timeouts: Timeouts,
+    /// Keep a snapshot info cache in the snapshot dir (`cache`)
+    pub cache: bool,
+    /// Skip `create` if a snapshot younger than this exists (`min-interval`)
+    pub min_interval: Option<Duration>,
+    /// `[[watch]]` rules for `watch`
+    pub watches: Vec<WatchRule>,
+    /// `[subvol."<name>"]` overrides, keyed by subvolume name or glob
+    pub subvol_settings: BTreeMap<String, SubvolSettings>,
+    /// `[rollback]` settings for `restore --root`
+    pub rollback: RollbackConfig,
+    /// `[boot]` entries kept in step with the snapshots they boot
+    pub boot: BootConfig,
+    /// `[permissions]` for the snapshot dir and new snapshots
+    pub permissions: SnapshotAccess,
+    /// Let users run the setuid binary on subvolumes they own
+    /// (`self-service`)
+    pub self_service: bool,
+    /// Keep `<name>-latest` symlinks to the newest snapshots in the
+    /// snapshot dir (`latest-links`)
+    pub latest_links: bool,
+    pub compliance: Option<Compliance>,
+    pub ransomware: Option<RansomwareConfig>,
+    /// `[<command>]` sections with default flags for that subcommand,
+    /// keyed by subcommand name
+    pub command_defaults: BTreeMap<String, toml::Table>,
+    /// `[alias]` commands run as `btrsnap <name>`, by name
+    pub aliases: BTreeMap<String, String>,
+}
+
+/// Top-level tables that configure btrsnap itself, all others hold
+/// subcommand defaults
+const SECTIONS: &[&str] = &[
+    "alias",
+    "boot",
+    "compliance",
+    "notify",
+    "permissions",
+    "ransomware",
+    "rollback",
+    "subvol",
+    "timeouts",
+    "watch",
+];
+
+/// Snapshot `subvol` when anything below `path` changes, at most once per
+/// `debounce`
+#[derive(Clone)]
+pub struct WatchRule {
+    pub path: PathBuf,
+    pub subvol: PathBuf,
+    pub debounce: Duration,
+    /// Report what changed since the previous snapshot, and copy `path`
+    /// into a new subvolume if it is not one (`drift`)
+    pub drift: bool,
+}
+
+/// Per-subvolume settings from a `[subvol."<name>"]` table
+#[derive(Clone, Default)]
+pub struct SubvolSettings {
+    /// `enabled = false` stops snapshots of the subvolume
+    pub enabled: Option<bool>,
+    pub min_interval: Option<Duration>,
+    /// Retention of the subvolume's snapshots instead of the global `keep`
+    pub keep: Option<humantime::Duration>,
+    /// Bytes of exclusive space the subvolume's snapshots may use
+    pub space_budget: Option<u64>,
+    /// Alarm in `summary` when the newest snapshot is older than this
+    pub warn_if_older_than: Option<Duration>,
+    /// Alarm in `summary` when there are fewer snapshots than this
+    pub warn_if_fewer_than: Option<usize>,
+}
+
+/// `[compliance]`: retention that cannot be shortened for `lock` once
+/// armed, see [`crate::compliance`]
+#[derive(Clone)]
+pub struct Compliance {
+    pub min_retention: Duration,
+    pub lock: Duration,
+}
+
+/// `[ransomware]` heuristics applied by `create`, see [`crate::ransomware`]
+#[derive(Clone)]
+pub struct RansomwareConfig {
+    /// Alarm when more than this fraction of files changed (`max-changed`)
+    pub max_changed: f64,
+    pub extensions: Vec<String>,
+    /// Alarm when this many changed files have one of `extensions`
+    /// (`suspicious-files`)
+    pub suspicious_files: usize,
+    /// Subvolumes with fewer files are not judged (`min-files`)
+    pub min_files: usize,
+    /// Pin the last snapshot before an alarm
+    pub pin: bool,
+}
+
+/// What `restore --root` updates besides the default subvolume
+#[derive(Clone, Default)]
+pub struct RollbackConfig {
+    /// Drop `subvol=`/`subvolid=` from the `/` entry in the new root's fstab
+    pub fstab: bool,
+    /// Shell command regenerating the bootloader config, run with
+    /// `BTRSNAP_NEW_ROOT` set to the new root subvolume
+    pub bootloader_command: Option<String>,
+}
+
+/// `[boot]`: boot entries that boot into snapshots, see [`crate::boot`]
+#[derive(Clone, Default)]
+pub struct BootConfig {
+    /// Directory of Boot Loader Specification entries, those mounting a
+    /// snapshot are removed before it is deleted
+    pub entries: Option<PathBuf>,
+    /// Shell command regenerating the boot loader config after snapshots
+    /// were deleted, e.g. for grub-btrfs
+    pub regenerate: Option<String>,
+}
+
+impl Config {
+    /// `[subvol."<name>"]` table for `name`: an exact match, else the first
+    /// glob matching it
+    pub fn subvol_settings(&self, name: &str) -> Option<&SubvolSettings> {
+        self.subvol_settings.get(name).or_else(|| {
+            self.subvol_settings
+                .iter()
+                .find(|(key, _)| glob::Pattern::new(key).is_ok_and(|p| p.matches(name)))
+                .map(|(_, s)| s)
+        })
+    }
+
+    /// Effective `min-interval` for the subvolume called `name`
+    pub fn min_interval(&self, name: &str) -> Option<Duration> {
+        self.subvol_settings(name)
+            .and_then(|s| s.min_interval)
+            .or(self.min_interval)
+    }
+
+    /// Effective `keep` for the subvolume called `name`
+    pub fn keep(&self, name: &str) -> Option<humantime::Duration> {
+        self.subvol_settings(name)
+            .and_then(|s| s.keep)
+            .or(self.keep)
+    }
+
+    /// `space-budget` for the subvolume called `name`
+    pub fn space_budget(&self, name: &str) -> Option<u64> {
+        self.subvol_settings(name).and_then(|s| s.space_budget)
+    }
+}
+
+/// `[notify.email]` settings
+#[derive(Clone)]
+pub struct EmailConfig {
+    pub smtp_server: String,
+    pub smtp_port: Option<u16>,
+    /// One of `starttls` (default), `tls` or `none`
+    pub smtp_tls: String,
+    pub smtp_user: Option<String>,
+    pub smtp_password_file: Option<PathBuf>,
+    pub from: String,
+    pub to: Vec<String>,
+    /// Events to mail: `failure`, `weekly-summary`, `ransomware`, `drift`
+    pub on: Vec<String>,
+}
+
+/// `[notify]` settings that keep notifications from becoming noise
+#[derive(Clone, Default)]
+pub struct NotifyPolicy {
+    /// Notify an unchanged failure again only after this long
+    /// (`repeat-after`)
+    pub repeat_after: Option<Duration>,
+    /// Notify when a failing subvolume or command succeeds again
+    /// (`recovery`)
+    pub recovery: bool,
+    /// Collect notifications, except ransomware alerts, into one message
+    /// per interval (`digest`, e.g. `1d`)
+    pub digest: Option<Duration>,
+}
+
+/// A `[[notify.chat]]` backend
+#[derive(Clone, Debug)]
+pub struct ChatConfig {
+    pub service: ChatService,
+    /// Least severe event posted (`min-severity`, default `error`)
+    pub min_severity: Severity,
+}
+
+/// Where a chat notification goes. Secrets are read from files when
+/// sending, like `smtp-password-file`.
+#[derive(Clone, Debug)]
+pub enum ChatService {
+    /// Slack or Mattermost incoming webhook
+    Webhook { url_file: PathBuf },
+    /// A room, through the Matrix client-server API
+    Matrix {
+        homeserver: String,
+        room: String,
+        token_file: PathBuf,
+    },
+    /// A chat, through the Telegram bot API
+    Telegram {
+        token_file: PathBuf,
+        chat_id: String,
+    },
+}
+
+/// Load the config merged from `paths` (see [`read_merged`]), expanding
+/// wildcard `subvol-names` against the subvolumes present now
+pub fn load(paths: &[PathBuf], backend: &dyn SnapshotBackend) -> Result<Config> {
+    let mut config = Config::default();
+
+    // Errors name the last file, the one overriding the others
+    if let Some(path) = paths.last().cloned() {
+        let (config_toml, included) = read_merged(paths)?;
+        signing::enforce(paths.iter().chain(&included))?;
+        config.included = included;
+        config.snap_dir = Some(parse_snap_dir(&config_toml, &path)?);
+        config.subvol_base = parse_subvol_base(&config_toml, &path)?;
+        config.exclude = parse_exclude(&config_toml)?;
+        config.subvols = match config.subvol_base.as_deref() {
+            Some(base) => parse_subvols(&config_toml, base, &config.exclude, backend)?,
+            None if config_toml.get("subvol-names").is_some() => {
+                bail!("Missing 'subvol-base' in config file: {}", path.display())
+            }
+            None => vec![],
+        };
+        config.name_parents = parse_name_parents(&config_toml)?;
+        config.keep = parse_keep_duration(&config_toml)?;
+        config.description = parse_description(&config_toml)?;
+        config.protect = Policy {
+            min_age: parse_min_age(&config_toml)?,
+            protected: config
+                .subvols
+                .iter()
+                .chain(&config.snap_dir)
+                .cloned()
+                .collect(),
+        };
+        config.email = parse_email(&config_toml)?;
+        config.chat = parse_chat(&config_toml)?;
+        config.notify = parse_notify_policy(&config_toml)?;
+        config.retry = parse_retry(&config_toml)?;
+        config.timeouts = parse_timeouts(&config_toml)?;
+        config.cache = parse_cache(&config_toml)?;
+        config.min_interval = parse_duration_key(&config_toml, "min-interval")?;
+        config.subvol_settings = parse_subvol_settings(&config_toml)?;
+        config.watches = parse_watches(&config_toml)?;
+        config.rollback = parse_rollback(&config_toml)?;
+        config.boot = parse_boot(&config_toml)?;
+        config.permissions = parse_permissions(&config_toml)?;
+        config.self_service = parse_self_service(&config_toml)?;
+        config.latest_links = parse_latest_links(&config_toml)?;
+        config.state_dir = parse_state_dir(&config_toml, &path)?;
+        config.compliance = parse_compliance(&config_toml)?;
+        config.ransomware = parse_ransomware(&config_toml)?;
+        config.command_defaults = parse_command_defaults(&config_toml);
+        config.aliases = parse_aliases(&config_toml)?;
+        config.paths = paths.to_vec();
+    }
+    Ok(config)
+}
 
-    if let Some(path) = config_path {
-        let config_toml = read_toml(&path)?;
-        snap_dir = Some(parse_snap_dir(&config_toml, &path)?);
-        toml_subvols = parse_subvols(&config_toml, &path)?;
-        toml_keep = parse_keep_duration(&config_toml)?;
+/// The config files in `paths` merged in order, each on top of the files
+/// in its own `configs` list (relative to its directory). Tables merge key
+/// by key; any other value, arrays included, replaces the earlier one.
+/// Also returns the files read through `configs`.
+pub fn read_merged(paths: &[PathBuf]) -> Result<(Value, Vec<PathBuf>)> {
+    let mut merged = Value::Table(Default::default());
+    let mut included = vec![];
+    for path in paths {
+        merge_file(path, &mut merged, &mut vec![], &mut included)?;
     }
-    Ok((snap_dir, toml_subvols, toml_keep))
+    Ok((merged, included))
 }
 
-fn read_toml(path: &PathBuf) -> Result<Value> {
+/// Merge `path` and its `configs` into `merged`; `including` are the files
+/// whose `configs` led here, to catch cycles
+fn merge_file(
+    path: &Path,
+    merged: &mut Value,
+    including: &mut Vec<PathBuf>,
+    included: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let mut value = read_toml(path)?;
+    let canonical = path.canonicalize()?;
+    if including.contains(&canonical) {
+        bail!("{} includes itself through 'configs'", path.display());
+    }
+    if let Some(configs) = value.as_table_mut().and_then(|t| t.remove("configs")) {
+        let invalid = || anyhow!("'configs' must be a list of paths in {}", path.display());
+        let dir = path.parent().unwrap_or(Path::new("."));
+        including.push(canonical);
+        for config in configs.as_array().ok_or_else(invalid)? {
+            let config = dir.join(config.as_str().ok_or_else(invalid)?);
+            merge_file(&config, merged, including, included)?;
+            included.push(config);
+        }
+        including.pop();
+    }
+    merge(merged, value);
+    Ok(())
+}
+
+fn merge(base: &mut Value, over: Value) {
+    match (base, over) {
+        (Value::Table(base), Value::Table(over)) => {
+            for (key, value) in over {
+                match base.get_mut(&key) {
+                    Some(old) => merge(old, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, over) => *base = over,
+    }
+}
+
+/// Parse the config file without interpreting it
+pub fn read_toml(path: &Path) -> Result<Value> {
     let content = fs::read_to_string(path)
         .context(format!("Failed to read config file: {}", path.display()))?;
     toml::from_str(&content).context("Invalid TOML in config file")
 }
 
-fn parse_snap_dir(config: &Value, path: &PathBuf) -> Result<PathBuf> {
+fn parse_snap_dir(config: &Value, path: &Path) -> Result<PathBuf> {
     let snap_str = config
         .get("snap-dir")
         .and_then(|v| v.as_str())
@@ -37,31 +380,100 @@ fn parse_snap_dir(config: &Value, path: &PathBuf) -> Result<PathBuf> {
     ))
 }
 
-fn parse_subvols(config: &Value, path: &PathBuf) -> Result<Vec<PathBuf>> {
-    let names_arr = config.get("subvol-names").and_then(|v| v.as_array());
-    if let Some(names) = names_arr {
-        let subvol_names: Vec<String> = names
+fn parse_state_dir(config: &Value, path: &Path) -> Result<Option<PathBuf>> {
+    let Some(dir) = config.get("state-dir") else {
+        return Ok(None);
+    };
+    match dir.as_str().map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => Ok(Some(dir)),
+        _ => bail!(
+            "'state-dir' must be an absolute path in config file: {}",
+            path.display()
+        ),
+    }
+}
+
+fn parse_subvol_base(config: &Value, path: &Path) -> Result<Option<PathBuf>> {
+    let Some(base_str) = config.get("subvol-base").and_then(|v| v.as_str()) else {
+        return Ok(None);
+    };
+    let subvol_base = PathBuf::from(base_str).canonicalize().context(format!(
+        "Invalid 'subvol-base' path in config file: {}",
+        path.display()
+    ))?;
+    Ok(Some(subvol_base))
+}
+
+/// `subvol-names` below `subvol-base`. Names with glob characters expand to
+/// the matching subvolumes in `subvol-base`, minus `exclude`.
+fn parse_subvols(
+    config: &Value,
+    subvol_base: &Path,
+    exclude: &[glob::Pattern],
+    backend: &dyn SnapshotBackend,
+) -> Result<Vec<PathBuf>> {
+    let Some(names) = config.get("subvol-names").and_then(|v| v.as_array()) else {
+        return Ok(vec![]);
+    };
+    let mut children = None;
+    let mut subvols = vec![];
+    for name in names.iter().filter_map(|v| v.as_str()) {
+        if !name.contains(['*', '?', '[']) {
+            subvols.push(subvol_base.join(name));
+            continue;
+        }
+        let pattern = glob::Pattern::new(name)
+            .context(format!("Invalid pattern in 'subvol-names': {}", name))?;
+        let children = match &mut children {
+            Some(children) => children,
+            None => children.insert(backend.list(subvol_base).context(format!(
+                "Failed to list subvolumes in {}",
+                subvol_base.display()
+            ))?),
+        };
+        let mut matched: Vec<PathBuf> = children
             .iter()
-            .filter_map(|v| v.as_str().map(|s| s.to_string()))
+            .map(|info| info.path.clone())
+            .filter(|p| {
+                p.file_name()
+                    .is_some_and(|n| pattern.matches_path(n.as_ref()))
+            })
+            .filter(|p| discover::excluded_by(exclude, subvol_base, p).is_none())
             .collect();
-        if !subvol_names.is_empty() {
-            let base_str = config
-                .get("subvol-base")
-                .and_then(|v| v.as_str())
-                .ok_or_else(|| {
-                    anyhow!("Missing 'subvol-base' in config file: {}", path.display())
-                })?;
-            let subvol_base = PathBuf::from(base_str).canonicalize().context(format!(
-                "Invalid 'subvol-base' path in config file: {}",
-                path.display()
-            ))?;
-            return Ok(subvol_names
-                .iter()
-                .map(|name| subvol_base.join(name))
-                .collect());
+        if matched.is_empty() {
+            warning!("'{}' in 'subvol-names' matches no subvolume", name);
         }
+        matched.sort();
+        subvols.extend(matched);
+    }
+    let mut seen = BTreeSet::new();
+    subvols.retain(|p| seen.insert(p.clone()));
+    Ok(subvols)
+}
+
+fn parse_exclude(config: &Value) -> Result<Vec<glob::Pattern>> {
+    let Some(patterns) = config.get("exclude").and_then(|v| v.as_array()) else {
+        return Ok(vec![]);
+    };
+    patterns
+        .iter()
+        .map(|v| {
+            let s = v
+                .as_str()
+                .ok_or_else(|| anyhow!("Invalid 'exclude' entry in config: {}", v))?;
+            glob::Pattern::new(s).context(format!("Invalid 'exclude' pattern in config: {}", s))
+        })
+        .collect()
+}
+
+fn parse_name_parents(config: &Value) -> Result<usize> {
+    match config.get("name-parents") {
+        Some(v) => v
+            .as_integer()
+            .and_then(|n| usize::try_from(n).ok())
+            .ok_or_else(|| anyhow!("Invalid 'name-parents' in config: {}", v)),
+        None => Ok(0),
     }
-    Ok(vec![])
 }
 
 fn parse_keep_duration(config: &Value) -> Result<Option<humantime::Duration>> {
@@ -73,3 +485,541 @@ fn parse_keep_duration(config: &Value) -> Result<Option<humantime::Duration>> {
         Ok(None)
     }
 }
+
+fn parse_description(config: &Value) -> Result<Option<String>> {
+    match config.get("description") {
+        Some(v) => {
+            let template = v
+                .as_str()
+                .ok_or_else(|| anyhow!("Invalid 'description' in config: expected a string"))?;
+            Ok(Some(
+                facts::parse_template(template).context("Invalid 'description' in config")?,
+            ))
+        }
+        None => Ok(None),
+    }
+}
+
+fn parse_min_age(config: &Value) -> Result<Option<std::time::Duration>> {
+    match config.get("min-age-before-delete").and_then(|v| v.as_str()) {
+        Some(age_str) => Ok(Some(humantime::parse_duration(age_str).context(
+            format!(
+                "Invalid 'min-age-before-delete' duration in config: {}",
+                age_str
+            ),
+        )?)),
+        None => Ok(None),
+    }
+}
+
+fn parse_retry(config: &Value) -> Result<RetryPolicy> {
+    let mut retry = RetryPolicy::default();
+    if let Some(retries) = config.get("retries").and_then(|v| v.as_integer()) {
+        retry.retries =
+            u32::try_from(retries).context(format!("Invalid 'retries' in config: {}", retries))?;
+    }
+    if let Some(delay_str) = config.get("retry-delay").and_then(|v| v.as_str()) {
+        retry.delay = humantime::parse_duration(delay_str).context(format!(
+            "Invalid 'retry-delay' duration in config: {}",
+            delay_str
+        ))?;
+    }
+    Ok(retry)
+}
+
+fn parse_timeouts(config: &Value) -> Result<Timeouts> {
+    let Some(table) = config.get("timeouts") else {
+        return Ok(Timeouts::default());
+    };
+    let parse = |key: &str| -> Result<Option<std::time::Duration>> {
+        match table.get(key).and_then(|v| v.as_str()) {
+            Some(s) => Ok(Some(humantime::parse_duration(s).context(format!(
+                "Invalid 'timeouts.{}' duration in config: {}",
+                key, s
+            ))?)),
+            None => Ok(None),
+        }
+    };
+    Ok(Timeouts {
+        create: parse("create")?,
+        delete: parse("delete")?,
+    })
+}
+
+fn parse_cache(config: &Value) -> Result<bool> {
+    match config.get("cache") {
+        Some(v) => v
+            .as_bool()
+            .ok_or_else(|| anyhow!("Invalid 'cache' in config: expected true or false")),
+        None => Ok(false),
+    }
+}
+
+fn parse_command_defaults(config: &Value) -> BTreeMap<String, toml::Table> {
+    config
+        .as_table()
+        .into_iter()
+        .flatten()
+        .filter(|(key, _)| !SECTIONS.contains(&key.as_str()))
+        .filter_map(|(key, value)| Some((key.clone(), value.as_table()?.clone())))
+        .collect()
+}
+
+fn parse_self_service(config: &Value) -> Result<bool> {
+    match config.get("self-service") {
+        Some(v) => v
+            .as_bool()
+            .ok_or_else(|| anyhow!("Invalid 'self-service' in config: expected true or false")),
+        None => Ok(false),
+    }
+}
+
+fn parse_latest_links(config: &Value) -> Result<bool> {
+    match config.get("latest-links") {
+        Some(v) => v
+            .as_bool()
+            .ok_or_else(|| anyhow!("Invalid 'latest-links' in config: expected true or false")),
+        None => Ok(false),
+    }
+}
+
+/// Optional duration under `key` in `table`
+fn parse_duration_key(table: &Value, key: &str) -> Result<Option<Duration>> {
+    match table.get(key).and_then(|v| v.as_str()) {
+        Some(s) => Ok(Some(
+            humantime::parse_duration(s)
+                .context(format!("Invalid '{}' duration in config: {}", key, s))?,
+        )),
+        None => Ok(None),
+    }
+}
+
+fn parse_subvol_settings(config: &Value) -> Result<BTreeMap<String, SubvolSettings>> {
+    let Some(table) = config.get("subvol").and_then(|v| v.as_table()) else {
+        return Ok(BTreeMap::new());
+    };
+    let mut settings = BTreeMap::new();
+    for (name, value) in table {
+        let context = || format!("In [subvol.\"{}\"]", name);
+        let s = SubvolSettings {
+            enabled: match value.get("enabled") {
+                Some(v) => Some(
+                    v.as_bool()
+                        .ok_or_else(|| anyhow!("Invalid 'enabled': expected true or false"))
+                        .with_context(context)?,
+                ),
+                None => None,
+            },
+            min_interval: parse_duration_key(value, "min-interval").with_context(context)?,
+            keep: parse_duration_key(value, "keep")
+                .with_context(context)?
+                .map(Into::into),
+            space_budget: match value.get("space-budget").and_then(|v| v.as_str()) {
+                Some(s) => Some(
+                    utils::parse_size(s)
+                        .context(format!("Invalid 'space-budget' in config: {}", s))
+                        .with_context(context)?,
+                ),
+                None => None,
+            },
+            warn_if_older_than: parse_duration_key(value, "warn-if-older-than")
+                .with_context(context)?,
+            warn_if_fewer_than: match value.get("warn-if-fewer-than") {
+                Some(v) => Some(
+                    v.as_integer()
+                        .and_then(|n| usize::try_from(n).ok())
+                        .ok_or_else(|| anyhow!("Invalid 'warn-if-fewer-than': expected a count"))
+                        .with_context(context)?,
+                ),
+                None => None,
+            },
+        };
+        settings.insert(name.clone(), s);
+    }
+    Ok(settings)
+}
+
+fn parse_watches(config: &Value) -> Result<Vec<WatchRule>> {
+    let Some(rules) = config.get("watch").and_then(|v| v.as_array()) else {
+        return Ok(vec![]);
+    };
+    let mut watches = vec![];
+    for rule in rules {
+        let path_str = rule
+            .get("path")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow!("Missing 'path' in [[watch]]"))?;
+        let path = PathBuf::from(path_str)
+            .canonicalize()
+            .context(format!("Invalid [[watch]] path: {}", path_str))?;
+        let subvol = match rule.get("subvol").and_then(|v| v.as_str()) {
+            Some(s) => PathBuf::from(s)
+                .canonicalize()
+                .context(format!("Invalid [[watch]] subvol: {}", s))?,
+            None => path.clone(),
+        };
+        let debounce = parse_duration_key(rule, "debounce")
+            .context("In [[watch]]")?
+            .unwrap_or(Duration::from_secs(600));
+        let drift = match rule.get("drift") {
+            Some(v) => v
+                .as_bool()
+                .ok_or_else(|| anyhow!("Invalid 'drift' in [[watch]]: expected true or false"))?,
+            None => false,
+        };
+        watches.push(WatchRule {
+            path,
+            subvol,
+            debounce,
+            drift,
+        });
+    }
+    Ok(watches)
+}
+
+fn parse_rollback(config: &Value) -> Result<RollbackConfig> {
+    let Some(table) = config.get("rollback") else {
+        return Ok(RollbackConfig::default());
+    };
+    let fstab = match table.get("fstab") {
+        Some(v) => v
+            .as_bool()
+            .ok_or_else(|| anyhow!("Invalid 'rollback.fstab': expected true or false"))?,
+        None => false,
+    };
+    let bootloader_command = match table.get("bootloader-command") {
+        Some(v) => Some(
+            v.as_str()
+                .ok_or_else(|| anyhow!("Invalid 'rollback.bootloader-command': expected a string"))?
+                .to_string(),
+        ),
+        None => None,
+    };
+    Ok(RollbackConfig {
+        fstab,
+        bootloader_command,
+    })
+}
+
+fn parse_boot(config: &Value) -> Result<BootConfig> {
+    let Some(table) = config.get("boot") else {
+        return Ok(BootConfig::default());
+    };
+    let string = |key: &str| match table.get(key) {
+        Some(v) => v
+            .as_str()
+            .map(|s| Some(s.to_string()))
+            .ok_or_else(|| anyhow!("Invalid 'boot.{}': expected a string", key)),
+        None => Ok(None),
+    };
+    Ok(BootConfig {
+        entries: string("entries")?.map(PathBuf::from),
+        regenerate: string("regenerate")?,
+    })
+}
+
+fn parse_compliance(config: &Value) -> Result<Option<Compliance>> {
+    let Some(table) = config.get("compliance") else {
+        return Ok(None);
+    };
+    let required = |key: &str| {
+        parse_duration_key(table, key)?
+            .ok_or_else(|| anyhow!("Missing 'compliance.{}' in config", key))
+    };
+    Ok(Some(Compliance {
+        min_retention: required("min-retention")?,
+        lock: required("lock")?,
+    }))
+}
+
+fn parse_aliases(config: &Value) -> Result<BTreeMap<String, String>> {
+    let Some(table) = config.get("alias") else {
+        return Ok(BTreeMap::new());
+    };
+    table
+        .as_table()
+        .ok_or_else(|| anyhow!("Invalid 'alias' in config: expected a table"))?
+        .iter()
+        .map(|(name, v)| match v.as_str() {
+            Some(command) => Ok((name.clone(), command.to_string())),
+            None => bail!("Invalid 'alias.{}' in config: expected a string", name),
+        })
+        .collect()
+}
+
+fn parse_ransomware(config: &Value) -> Result<Option<RansomwareConfig>> {
+    let Some(table) = config.get("ransomware") else {
+        return Ok(None);
+    };
+    let count = |key: &str, default: usize| -> Result<usize> {
+        match table.get(key) {
+            Some(v) => v
+                .as_integer()
+                .and_then(|n| usize::try_from(n).ok())
+                .ok_or_else(|| anyhow!("Invalid 'ransomware.{}': expected a count", key)),
+            None => Ok(default),
+        }
+    };
+    let max_changed = match table.get("max-changed") {
+        Some(v) => v
+            .as_float()
+            .filter(|f| (0.0..=1.0).contains(f))
+            .ok_or_else(|| anyhow!("Invalid 'ransomware.max-changed': expected 0.0 to 1.0"))?,
+        None => 0.5,
+    };
+    let extensions = match table.get("extensions").and_then(|v| v.as_array()) {
+        Some(list) => list
+            .iter()
+            .map(|v| v.as_str().map(|s| s.trim_start_matches('.').to_string()))
+            .collect::<Option<_>>()
+            .ok_or_else(|| anyhow!("Invalid 'ransomware.extensions': expected strings"))?,
+        None => ransomware::default_extensions(),
+    };
+    let pin = match table.get("pin") {
+        Some(v) => v
+            .as_bool()
+            .ok_or_else(|| anyhow!("Invalid 'ransomware.pin': expected true or false"))?,
+        None => true,
+    };
+    Ok(Some(RansomwareConfig {
+        max_changed,
+        extensions,
+        suspicious_files: count("suspicious-files", 20)?,
+        min_files: count("min-files", 100)?,
+        pin,
+    }))
+}
+
+fn parse_permissions(config: &Value) -> Result<SnapshotAccess> {
+    let Some(table) = config.get("permissions") else {
+        return Ok(SnapshotAccess::default());
+    };
+    let access = |prefix: &str| -> Result<Access> {
+        let mut access = Access::default();
+        let key = format!("{}-mode", prefix);
+        if let Some(mode) = table.get(&key) {
+            let mode = mode
+                .as_str()
+                .ok_or_else(|| anyhow!("Invalid 'permissions.{}': expected a string", key))?;
+            access.mode = Some(
+                permissions::parse_mode(mode)
+                    .context(format!("Invalid 'permissions.{}' in config", key))?,
+            );
+        }
+        let key = format!("{}-owner", prefix);
+        if let Some(owner) = table.get(&key) {
+            let owner = owner
+                .as_str()
+                .ok_or_else(|| anyhow!("Invalid 'permissions.{}': expected a string", key))?;
+            (access.owner, access.group) = permissions::parse_owner(owner)
+                .context(format!("Invalid 'permissions.{}' in config", key))?;
+        }
+        Ok(access)
+    };
+    Ok(SnapshotAccess {
+        snap_dir: access("snap-dir")?,
+        snapshots: access("snapshot")?,
+    })
+}
+
+fn parse_email(config: &Value) -> Result<Option<EmailConfig>> {
+    let Some(email) = config.get("notify").and_then(|n| n.get("email")) else {
+        return Ok(None);
+    };
+    let get_str = |key: &str| email.get(key).and_then(|v| v.as_str()).map(String::from);
+    let get_list = |key: &str| -> Vec<String> {
+        email
+            .get(key)
+            .and_then(|v| v.as_array())
+            .map(|a| {
+                a.iter()
+                    .filter_map(|v| v.as_str().map(String::from))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let smtp_port = match email.get("smtp-port").and_then(|v| v.as_integer()) {
+        Some(port) => Some(
+            u16::try_from(port).context(format!("Invalid 'notify.email.smtp-port': {}", port))?,
+        ),
+        None => None,
+    };
+    let smtp_tls = get_str("smtp-tls").unwrap_or_else(|| "starttls".to_string());
+    if !["starttls", "tls", "none"].contains(&smtp_tls.as_str()) {
+        bail!("Invalid 'notify.email.smtp-tls': {}", smtp_tls);
+    }
+    let on = get_list("on");
+    if let Some(event) = on
+        .iter()
+        .find(|e| !["failure", "weekly-summary", "ransomware", "drift"].contains(&e.as_str()))
+    {
+        bail!("Unknown event in 'notify.email.on': {}", event);
+    }
+    let to = get_list("to");
+    if to.is_empty() {
+        bail!("Missing 'notify.email.to' in config file");
+    }
+
+    Ok(Some(EmailConfig {
+        smtp_server: get_str("smtp-server")
+            .ok_or_else(|| anyhow!("Missing 'notify.email.smtp-server' in config file"))?,
+        smtp_port,
+        smtp_tls,
+        smtp_user: get_str("smtp-user"),
+        smtp_password_file: get_str("smtp-password-file").map(PathBuf::from),
+        from: get_str("from")
+            .ok_or_else(|| anyhow!("Missing 'notify.email.from' in config file"))?,
+        to,
+        on,
+    }))
+}
+
+fn parse_notify_policy(config: &Value) -> Result<NotifyPolicy> {
+    let Some(table) = config.get("notify") else {
+        return Ok(NotifyPolicy::default());
+    };
+    let recovery = match table.get("recovery") {
+        Some(v) => v
+            .as_bool()
+            .ok_or_else(|| anyhow!("Invalid 'notify.recovery': expected true or false"))?,
+        None => false,
+    };
+    Ok(NotifyPolicy {
+        repeat_after: parse_duration_key(table, "repeat-after")?,
+        recovery,
+        digest: parse_duration_key(table, "digest")?,
+    })
+}
+
+fn parse_chat(config: &Value) -> Result<Vec<ChatConfig>> {
+    let Some(chats) = config.get("notify").and_then(|n| n.get("chat")) else {
+        return Ok(vec![]);
+    };
+    let chats = chats
+        .as_array()
+        .ok_or_else(|| anyhow!("Invalid 'notify.chat': expected [[notify.chat]] tables"))?;
+    let mut parsed = vec![];
+    for chat in chats {
+        let string = |key: &str| match chat.get(key) {
+            Some(v) => v
+                .as_str()
+                .map(String::from)
+                .ok_or_else(|| anyhow!("Invalid 'notify.chat.{}': expected a string", key)),
+            None => bail!("Missing 'notify.chat.{}' in config file", key),
+        };
+        let kind = string("type")?;
+        let service = match kind.as_str() {
+            "slack" | "mattermost" => ChatService::Webhook {
+                url_file: string("webhook-url-file")?.into(),
+            },
+            "matrix" => ChatService::Matrix {
+                homeserver: string("homeserver")?.trim_end_matches('/').to_string(),
+                room: string("room")?,
+                token_file: string("token-file")?.into(),
+            },
+            "telegram" => ChatService::Telegram {
+                token_file: string("token-file")?.into(),
+                // Numeric ids are common, channel names start with @
+                chat_id: match chat.get("chat-id").and_then(|v| v.as_integer()) {
+                    Some(id) => id.to_string(),
+                    None => string("chat-id")?,
+                },
+            },
+            _ => bail!(
+                "Invalid 'notify.chat.type': {}, expected slack, mattermost, matrix or telegram",
+                kind
+            ),
+        };
+        let min_severity = match chat.get("min-severity") {
+            Some(_) => {
+                let severity = string("min-severity")?;
+                Severity::parse(&severity).ok_or_else(|| {
+                    anyhow!(
+                        "Invalid 'notify.chat.min-severity': {}, expected warning, error or critical",
+                        severity
+                    )
+                })?
+            }
+            None => Severity::Error,
+        };
+        parsed.push(ChatConfig {
+            service,
+            min_severity,
+        });
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use chrono::Local;
+
+    #[test]
+    fn expands_wildcard_subvol_names() {
+        let backend = MockBackend::leak();
+        let base = backend.scratch_dir();
+        for name in ["vm-a", "vm-b", "vm-scratch", "ct-a", "home"] {
+            backend.add(&base.join(name), Local::now());
+        }
+        let toml: Value = toml::from_str(r#"subvol-names = ["home", "vm-*", "vm-a"]"#).unwrap();
+        let exclude = vec![glob::Pattern::new("*-scratch").unwrap()];
+
+        let subvols = parse_subvols(&toml, &base, &exclude, backend).unwrap();
+
+        assert_eq!(
+            subvols,
+            [base.join("home"), base.join("vm-a"), base.join("vm-b")]
+        );
+    }
+
+    #[test]
+    fn subvol_settings_fall_back_to_globs() {
+        let mut config = Config::default();
+        for (key, secs) in [("vm-*", 60), ("vm-db", 10)] {
+            config.subvol_settings.insert(
+                key.to_string(),
+                SubvolSettings {
+                    min_interval: Some(Duration::from_secs(secs)),
+                    ..Default::default()
+                },
+            );
+        }
+        assert_eq!(config.min_interval("vm-db"), Some(Duration::from_secs(10)));
+        assert_eq!(config.min_interval("vm-web"), Some(Duration::from_secs(60)));
+        assert_eq!(config.min_interval("home"), None);
+    }
+
+    #[test]
+    fn merges_configs_in_order() {
+        let dir = MockBackend::leak().scratch_dir();
+        let write = |name: &str, content: &str| {
+            fs::write(dir.join(name), content).unwrap();
+            dir.join(name)
+        };
+        write(
+            "base.toml",
+            "keep = \"30d\"\nsubvol-names = [\"home\", \"root\"]\n[retry]\nretries = 3\ndelay = \"1s\"\n",
+        );
+        let role = write(
+            "role.toml",
+            "configs = [\"base.toml\"]\nsubvol-names = [\"srv\"]\n",
+        );
+        let host = write("host.toml", "keep = \"7d\"\n[retry]\nretries = 5\n");
+
+        let (merged, included) = read_merged(&[role, host.clone()]).unwrap();
+
+        assert_eq!(merged["keep"].as_str(), Some("7d"));
+        assert_eq!(merged["subvol-names"].as_array().unwrap().len(), 1);
+        assert_eq!(merged["retry"]["retries"].as_integer(), Some(5));
+        assert_eq!(merged["retry"]["delay"].as_str(), Some("1s"));
+        assert!(merged.get("configs").is_none());
+        assert_eq!(included, [dir.join("base.toml")]);
+
+        write("host.toml", "configs = [\"loop.toml\"]\n");
+        write("loop.toml", "configs = [\"host.toml\"]\n");
+        assert!(read_merged(&[host]).is_err());
+    }
+}
@@ -0,0 +1,293 @@
+use crate::backend::SnapshotBackend;
+use crate::config::{self, Config};
+use crate::distro::Layout;
+use crate::{create, signing, utils};
+use anyhow::{Result, anyhow, bail};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+#[derive(clap::Parser)]
+pub struct ConfigCommand {
+    #[command(subcommand)]
+    action: Action,
+}
+
+#[derive(clap::Subcommand)]
+enum Action {
+    /// Load the config and check it for conflicting settings
+    Validate,
+    /// Create a key pair for signing configs
+    Keygen {
+        /// File to write the private key to
+        key: PathBuf,
+    },
+    /// Sign a config file, writing <config>.sig
+    Sign {
+        config: PathBuf,
+        /// Private key from `config keygen`
+        #[arg(short, long)]
+        key: PathBuf,
+    },
+    /// Check the signatures of config files against the trusted keys,
+    /// by default those of the loaded config
+    Verify { configs: Vec<PathBuf> },
+}
+
+impl ConfigCommand {
+    pub fn needs_config(&self) -> bool {
+        match &self.action {
+            Action::Validate => true,
+            Action::Keygen { .. } | Action::Sign { .. } => false,
+            Action::Verify { configs } => configs.is_empty(),
+        }
+    }
+
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        match self.action {
+            Action::Validate => validate(backend, &config),
+            Action::Keygen { key } => {
+                let public = signing::generate(&key)?;
+                println!("Wrote private key {}", key.display());
+                println!("Public key, add it to {}:", signing::TRUST_FILE);
+                println!("{}", public);
+                Ok(())
+            }
+            Action::Sign { config, key } => {
+                let sig = signing::sign(&config, &key)?;
+                println!("Wrote {}", sig.display());
+                Ok(())
+            }
+            Action::Verify { configs } => verify(config, configs),
+        }
+    }
+}
+
+fn validate(backend: &dyn SnapshotBackend, config: &Config) -> Result<()> {
+    if config.paths.is_empty() {
+        bail!("No config file given, use --config or BTRSNAP_CONFIG");
+    }
+    let files: Vec<String> = config
+        .paths
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect();
+    let files = files.join(" + ");
+    let (raw, _) = config::read_merged(&config.paths)?;
+    let problems = lint(backend, config, &raw);
+    for problem in &problems {
+        println!("{}: {}", files, problem);
+    }
+    if !problems.is_empty() {
+        bail!("{} problems in {}", problems.len(), files);
+    }
+    println!("{} is valid", files);
+    Ok(())
+}
+
+fn verify(config: Config, configs: Vec<PathBuf>) -> Result<()> {
+    let keys = signing::trusted_keys()?
+        .ok_or_else(|| anyhow!("No trusted keys, create {}", signing::TRUST_FILE))?;
+    let configs = if configs.is_empty() {
+        config.paths.into_iter().chain(config.included).collect()
+    } else {
+        configs
+    };
+    if configs.is_empty() {
+        bail!("No config file given, use --config or BTRSNAP_CONFIG");
+    }
+    for path in &configs {
+        signing::verify(path, &keys)?;
+        println!("{}: signature OK", path.display());
+    }
+    Ok(())
+}
+
+/// Settings that load fine but conflict with each other, one message each
+pub fn lint(backend: &dyn SnapshotBackend, config: &Config, raw: &Value) -> Vec<String> {
+    let mut problems = duplicate_subvols(config, raw);
+    if let Some(snap_dir) = &config.snap_dir {
+        if snap_dir.exists() && !backend.is_btrfs(snap_dir) {
+            problems.push(utils::not_btrfs(snap_dir));
+        }
+        problems.extend(snap_dir_nesting(backend, config, snap_dir));
+        problems.extend(snapper_snap_dir(Layout::current(), snap_dir));
+    }
+    problems.extend(name_collisions(config));
+    problems.extend(retention_vs_interval(config));
+    problems.extend(keep_vs_compliance(config));
+    problems
+}
+
+/// `subvol-names` entries naming the same subvolume more than once
+fn duplicate_subvols(config: &Config, raw: &Value) -> Vec<String> {
+    let (Some(base), Some(names)) = (
+        &config.subvol_base,
+        raw.get("subvol-names").and_then(|v| v.as_array()),
+    ) else {
+        return vec![];
+    };
+    let mut seen: BTreeMap<PathBuf, &str> = BTreeMap::new();
+    let mut problems = vec![];
+    for name in names.iter().filter_map(|v| v.as_str()) {
+        // Overlapping globs are expected, only literal names are checked
+        if name.contains(['*', '?', '[']) {
+            continue;
+        }
+        // Path comparison ignores trailing slashes and `.` components
+        let path = base.join(name);
+        match seen.get(&path) {
+            Some(first) => problems.push(format!(
+                "'{}' in 'subvol-names' duplicates '{}', {} would be snapshotted once",
+                name,
+                first,
+                path.display()
+            )),
+            None => {
+                seen.insert(path, name);
+            }
+        }
+    }
+    problems
+}
+
+/// A snap-dir that is a plain directory inside a snapshotted subvolume
+/// belongs to it: every snapshot carries an (empty) copy of the snapshot
+/// tree, and restoring the subvolume swaps the snapshots out with its old
+/// state
+fn snap_dir_nesting(
+    backend: &dyn SnapshotBackend,
+    config: &Config,
+    snap_dir: &Path,
+) -> Vec<String> {
+    if backend.is_subvolume(snap_dir) {
+        return vec![];
+    }
+    config
+        .subvols
+        .iter()
+        .filter(|sv| snap_dir.starts_with(sv))
+        .map(|sv| {
+            format!(
+                "snap-dir {} is a directory inside snapshotted subvolume {}, \
+                 restoring {} would take the snapshots with it; make snap-dir \
+                 a subvolume of its own or move it out",
+                snap_dir.display(),
+                sv.display(),
+                sv.display()
+            )
+        })
+        .collect()
+}
+
+/// A snap-dir of `/.snapshots` where snapper keeps its own snapshots there
+fn snapper_snap_dir(layout: Layout, snap_dir: &Path) -> Option<String> {
+    (layout == Layout::Snapper && snap_dir == Path::new("/.snapshots")).then(|| {
+        format!(
+            "snap-dir {} holds snapper's snapshots on this {}, btrsnap's \
+             would mix with them; use a directory of its own",
+            snap_dir.display(),
+            layout.description()
+        )
+    })
+}
+
+/// Subvolumes whose snapshots would get the same names in the flat layout
+fn name_collisions(config: &Config) -> Vec<String> {
+    create::name_collisions(&config.subvols, config.name_parents)
+        .into_iter()
+        .map(|(name, paths)| {
+            let paths: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+            format!(
+                "{} all have snapshots named {}-<timestamp>, cleanup and \
+                 restore cannot tell them apart; set or raise 'name-parents'",
+                paths.join(", "),
+                name
+            )
+        })
+        .collect()
+}
+
+/// Subvolumes whose snapshots expire before the next one may be created
+fn retention_vs_interval(config: &Config) -> Vec<String> {
+    config
+        .subvols
+        .iter()
+        .filter_map(|sv| {
+            let name = create::subvol_name(sv, config.name_parents);
+            let keep: std::time::Duration = config.keep(&name)?.into();
+            let interval = config.min_interval(&name)?;
+            (keep < interval).then(|| {
+                format!(
+                    "keep ({}) is shorter than the min-interval of {} ({}), \
+                     cleanup would leave it without snapshots between runs",
+                    humantime::format_duration(keep),
+                    name,
+                    humantime::format_duration(interval)
+                )
+            })
+        })
+        .collect()
+}
+
+/// A keep below the compliance retention, which cleanup can never honour
+fn keep_vs_compliance(config: &Config) -> Option<String> {
+    let (Some(keep), Some(compliance)) = (config.keep, &config.compliance) else {
+        return None;
+    };
+    let keep: std::time::Duration = keep.into();
+    (keep < compliance.min_retention).then(|| {
+        format!(
+            "keep ({}) is shorter than compliance.min-retention ({}), cleanup \
+             will be refused for snapshots in between",
+            humantime::format_duration(keep),
+            humantime::format_duration(compliance.min_retention)
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use chrono::Local;
+    use std::fs;
+    use std::time::Duration;
+
+    #[test]
+    fn reports_each_conflict() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        for sv in ["home", "a/data", "b/data"] {
+            backend.add(&dir.join(sv), Local::now());
+        }
+        let snap_dir = dir.join("home/.snapshots");
+        fs::create_dir(&snap_dir).unwrap();
+        let config = Config {
+            snap_dir: Some(snap_dir),
+            subvol_base: Some(dir.clone()),
+            subvols: ["home", "a/data", "b/data"]
+                .iter()
+                .map(|sv| dir.join(sv))
+                .collect(),
+            keep: Some(Duration::from_secs(3600).into()),
+            min_interval: Some(Duration::from_secs(86400)),
+            ..Default::default()
+        };
+        let raw: Value =
+            toml::from_str(r#"subvol-names = ["home", "a/data", "b/data", "home/"]"#).unwrap();
+
+        let problems = lint(backend, &config, &raw);
+
+        assert_eq!(problems.len(), 6, "{:#?}", problems);
+        assert!(problems[0].contains("'home/' in 'subvol-names' duplicates 'home'"));
+        assert!(problems[1].starts_with("snap-dir"));
+        assert!(problems[2].contains("snapshots named data-<timestamp>"));
+        assert!(problems[3..].iter().all(|p| p.starts_with("keep (1h)")));
+
+        backend.other_fs.lock().unwrap().push(dir.join("home"));
+        let problems = lint(backend, &config, &raw);
+        assert!(problems[1].contains(", not btrfs."));
+        assert!(utils::resolve_snap_dir(backend, config.snap_dir.clone(), None).is_err());
+    }
+}
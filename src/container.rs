@@ -0,0 +1,191 @@
+//! Docker and Podman containers as snapshot subjects: `create --container`
+//! resolves the subvolumes holding a container's volumes and bind mounts
+//! and pauses the container while they are snapshotted, so snapshots of
+//! several volumes are consistent with each other.
+
+use crate::backend::SnapshotBackend;
+use crate::warnings::warning;
+use anyhow::{Context, Result, anyhow, bail};
+use log::{debug, info};
+use serde_json::Value;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::process::Command;
+
+const ENGINES: &[&str] = &["docker", "podman"];
+
+/// A container found by `docker inspect` or `podman inspect`
+pub struct Container {
+    pub engine: String,
+    pub name: String,
+    id: String,
+    running: bool,
+    /// Writable directories mounted into the container
+    sources: Vec<PathBuf>,
+}
+
+impl Container {
+    /// Look up `arg`, `[docker:|podman:]<name or id>`, trying Docker
+    /// before Podman unless the engine is given
+    pub fn find(arg: &str) -> Result<Self> {
+        let (engines, name) = match arg.split_once(':') {
+            Some((engine, name)) if ENGINES.contains(&engine) => (vec![engine], name),
+            _ => (ENGINES.to_vec(), arg),
+        };
+        let mut errors = vec![];
+        for engine in engines {
+            match Command::new(engine)
+                .args(["inspect", "--type", "container", name])
+                .output()
+            {
+                Ok(output) if output.status.success() => {
+                    let inspect: Value = serde_json::from_slice(&output.stdout)
+                        .context(format!("Invalid output of {} inspect", engine))?;
+                    return Container::parse(engine, name, &inspect[0]);
+                }
+                Ok(output) => errors.push(format!(
+                    "{}: {}",
+                    engine,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )),
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => errors.push(format!("{}: {}", engine, e)),
+            }
+        }
+        if errors.is_empty() {
+            bail!("Neither docker nor podman is installed");
+        }
+        bail!("No container {} ({})", name, errors.join("; "))
+    }
+
+    fn parse(engine: &str, name: &str, inspect: &Value) -> Result<Self> {
+        let id = inspect["Id"]
+            .as_str()
+            .ok_or_else(|| anyhow!("No id for container {} in {} inspect", name, engine))?;
+        let sources = inspect["Mounts"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            // Read-only mounts and single files such as /etc/localtime
+            // hold no data of the container
+            .filter(|m| m["RW"].as_bool() != Some(false))
+            .filter_map(|m| m["Source"].as_str().map(PathBuf::from))
+            .filter(|source| source.is_dir())
+            .collect();
+        Ok(Container {
+            engine: engine.to_string(),
+            name: name.to_string(),
+            id: id.to_string(),
+            running: inspect["State"]["Running"].as_bool() == Some(true)
+                && inspect["State"]["Paused"].as_bool() != Some(true),
+            sources,
+        })
+    }
+
+    /// Subvolumes holding the container's mounts, each once
+    pub fn subvols(&self, backend: &dyn SnapshotBackend) -> Result<Vec<PathBuf>> {
+        let mut subvols = vec![];
+        for source in &self.sources {
+            // Every btrfs mount is of a subvolume, so the search stays on
+            // the filesystem of the source
+            let subvol = backend
+                .is_btrfs(source)
+                .then(|| source.ancestors().find(|p| backend.is_subvolume(p)))
+                .flatten();
+            let Some(subvol) = subvol else {
+                warning!(
+                    "Skipping {} of container {}: not on btrfs",
+                    source.display(),
+                    self.name
+                );
+                continue;
+            };
+            debug!("{} is in subvolume {}", source.display(), subvol.display());
+            if !subvols.iter().any(|s: &PathBuf| s == subvol) {
+                subvols.push(subvol.to_path_buf());
+            }
+        }
+        if subvols.is_empty() {
+            bail!("Container {} has no writable mounts on btrfs", self.name);
+        }
+        Ok(subvols)
+    }
+
+    /// Pause the container if it is running, until the returned guard is
+    /// dropped
+    pub fn pause(&self) -> Result<Option<Paused<'_>>> {
+        if !self.running {
+            return Ok(None);
+        }
+        info!("Pausing container {}", self.name);
+        self.run("pause")?;
+        Ok(Some(Paused(self)))
+    }
+
+    fn run(&self, action: &str) -> Result<()> {
+        let output = Command::new(&self.engine)
+            .args([action, &self.id])
+            .output()
+            .context(format!("Failed to run {}", self.engine))?;
+        if !output.status.success() {
+            bail!(
+                "Failed to {} container {}: {}",
+                action,
+                self.name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A paused container, unpaused when dropped, also on errors
+pub struct Paused<'a>(&'a Container);
+
+impl Drop for Paused<'_> {
+    fn drop(&mut self) {
+        info!("Unpausing container {}", self.0.name);
+        if let Err(e) = self.0.run("unpause") {
+            warning!("{:#}, unpause it by hand", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use serde_json::json;
+    use std::fs;
+
+    #[test]
+    fn resolves_writable_mounts_to_subvolumes() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let (volumes, config) = (dir.join("volumes"), dir.join("config"));
+        backend.add(&volumes, chrono::Local::now());
+        for path in [
+            volumes.join("db/_data"),
+            volumes.join("wal/_data"),
+            config.clone(),
+        ] {
+            fs::create_dir_all(path).unwrap();
+        }
+        let inspect = json!({
+            "Id": "f00d",
+            "State": {"Running": true, "Paused": false},
+            "Mounts": [
+                {"Type": "volume", "Source": volumes.join("db/_data"), "RW": true},
+                {"Type": "volume", "Source": volumes.join("wal/_data"), "RW": true},
+                {"Type": "bind", "Source": config, "RW": false},
+                {"Type": "bind", "Source": "/etc/localtime", "RW": true},
+            ],
+        });
+
+        let container = Container::parse("docker", "mydb", &inspect).unwrap();
+        assert!(container.running);
+        assert_eq!(container.subvols(backend).unwrap(), [volumes]);
+        backend.other_fs.lock().unwrap().push(dir);
+        assert!(container.subvols(backend).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use crate::backend::SnapshotBackend;
+use crate::warnings::warning;
+use crate::{in_use, utils};
+use anyhow::{Context, Result, bail};
+use log::{debug, info};
+use nix::errno::Errno;
+use nix::fcntl::{AT_FDCWD, RenameFlags, renameat2};
+use nix::libc::{c_int, c_long};
+use std::fs::{self, File};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Inode flags (`chattr`) set on the copy before `cp` fills it, as `cp`
+/// does not carry them: sync, nodump, noatime, dirsync, NOCOW, compress and
+/// nocompress. NOCOW only takes on empty files, and reflinks between NOCOW
+/// and COW files fail. Immutable and append-only would stop the copy.
+const CARRIED_FLAGS: c_int = 0x8 | 0x40 | 0x80 | 0x10000 | 0x0080_0000 | 0x4 | 0x400;
+
+nix::ioctl_read_bad!(
+    get_flags,
+    nix::request_code_read!(b'f', 1, size_of::<c_long>()),
+    c_int
+);
+nix::ioctl_write_ptr_bad!(
+    set_flags,
+    nix::request_code_write!(b'f', 2, size_of::<c_long>()),
+    c_int
+);
+
+#[derive(clap::Parser)]
+pub struct ConvertToSubvol {
+    /// Directory to convert into a subvolume
+    #[arg(value_parser = utils::parse_path)]
+    pub dir: PathBuf,
+    /// Remove the original directory after a successful conversion
+    #[arg(long)]
+    pub remove_original: bool,
+    /// Convert even if processes use the directory or something is mounted
+    /// on or below it; what they write during the copy is lost
+    #[arg(long)]
+    pub force: bool,
+}
+
+impl ConvertToSubvol {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend) -> Result<()> {
+        let dir = self.dir;
+        if !dir.is_dir() {
+            bail!("{} is not a directory", dir.display());
+        }
+        if backend.is_subvolume(&dir) {
+            bail!("{} is already a subvolume", dir.display());
+        }
+        let staging = utils::sibling(&dir, "btrsnap-new")?;
+        let original = utils::sibling(&dir, "btrsnap-orig")?;
+        for p in [&staging, &original] {
+            if p.exists() {
+                bail!("{} already exists, remove it first", p.display());
+            }
+        }
+
+        // Writes after the copy started would be left behind in the original
+        in_use::refuse_busy(&dir, self.force)?;
+
+        info!("Converting {} into a subvolume", dir.display());
+        backend
+            .create(&staging)
+            .context(format!("Failed to create subvolume {}", staging.display()))?;
+        if let Err(e) = copy_contents(&dir, &staging) {
+            if let Err(del) = backend.delete(&staging) {
+                warning!("Failed to remove {}: {}", staging.display(), del);
+            }
+            return Err(e);
+        }
+
+        // Swap both entries in one step so `dir` never disappears
+        renameat2(
+            AT_FDCWD,
+            &staging,
+            AT_FDCWD,
+            &dir,
+            RenameFlags::RENAME_EXCHANGE,
+        )
+        .context(format!(
+            "Failed to swap {} with {}",
+            staging.display(),
+            dir.display()
+        ))?;
+        fs::rename(&staging, &original).context(format!(
+            "Failed to rename {} to {}",
+            staging.display(),
+            original.display()
+        ))?;
+        println!("Converted: {}", dir.display());
+
+        if self.remove_original {
+            fs::remove_dir_all(&original)
+                .context(format!("Failed to remove {}", original.display()))?;
+            println!("Removed original: {}", original.display());
+        } else {
+            println!("Original kept at: {}", original.display());
+        }
+        Ok(())
+    }
+}
+
+pub fn copy_contents(src: &Path, dst: &Path) -> Result<()> {
+    debug!("Copying {} to {}", src.display(), dst.display());
+    carry_flags(src, dst)?;
+    // Copying `src/.` also carries the ownership, mode and xattrs of the
+    // top-level directory over to the new subvolume
+    let status = Command::new("cp")
+        .arg("--archive")
+        .arg("--reflink=always")
+        .arg(src.join("."))
+        .arg(dst)
+        .status()
+        .context("Failed to run cp")?;
+    if !status.success() {
+        bail!(
+            "Failed to copy {} to {} ({})",
+            src.display(),
+            dst.display(),
+            status
+        );
+    }
+    Ok(())
+}
+
+/// Create the directories of `src` in `dst` with their inode flags, and
+/// empty files for those whose flags differ from their directory's, so `cp`
+/// copies into entries with the right flags
+fn carry_flags(src: &Path, dst: &Path) -> Result<()> {
+    let flags = inode_flags(src)?;
+    update_flags(dst, flags)?;
+    let entries = fs::read_dir(src).context(format!("Failed to read {}", src.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let target = dst.join(entry.file_name());
+        if file_type.is_dir() {
+            fs::create_dir(&target).context(format!("Failed to create {}", target.display()))?;
+            carry_flags(&entry.path(), &target)?;
+        } else if file_type.is_file() {
+            // New files inherit the directory's flags
+            let file_flags = inode_flags(&entry.path())?;
+            if file_flags != flags {
+                File::create(&target).context(format!("Failed to create {}", target.display()))?;
+                update_flags(&target, file_flags)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The `CARRIED_FLAGS` set on `path`, none on filesystems without flags
+fn inode_flags(path: &Path) -> Result<c_int> {
+    let file = File::open(path).context(format!("Failed to open {}", path.display()))?;
+    let mut flags = 0;
+    match unsafe { get_flags(file.as_raw_fd(), &mut flags) } {
+        Ok(_) => Ok(flags & CARRIED_FLAGS),
+        Err(Errno::ENOTTY | Errno::EOPNOTSUPP) => Ok(0),
+        Err(e) => Err(e).context(format!(
+            "Failed to get the inode flags of {}",
+            path.display()
+        )),
+    }
+}
+
+/// Make the `CARRIED_FLAGS` of `path` exactly `flags`
+fn update_flags(path: &Path, flags: c_int) -> Result<()> {
+    let file = File::open(path).context(format!("Failed to open {}", path.display()))?;
+    let mut current = 0;
+    unsafe { get_flags(file.as_raw_fd(), &mut current) }.context(format!(
+        "Failed to get the inode flags of {}",
+        path.display()
+    ))?;
+    let wanted = current & !CARRIED_FLAGS | flags;
+    if wanted != current {
+        unsafe { set_flags(file.as_raw_fd(), &wanted) }.context(format!(
+            "Failed to set the inode flags of {}",
+            path.display()
+        ))?;
+    }
+    Ok(())
+}
@@ -1,65 +1,778 @@
+use crate::backend::{SnapshotBackend, SubvolInfo};
+use crate::config::Config;
+use crate::container::{Container, Paused};
+use crate::machine::{self, Machine};
+use crate::manifest::{self, Manifest};
+use crate::porcelain::Porcelain;
+use crate::preset::Preset;
+use crate::report::{Created, Report};
+use crate::report_template;
+use crate::retry::{self, RetryPolicy};
+use crate::timeout::Timeouts;
 use crate::utils;
-use anyhow::{Context, Result, bail};
-use btrfsutil::subvolume::{SnapshotFlags, Subvolume};
+use crate::warnings::warning;
+use crate::{
+    discover, export, facts, interrupt, latest, os_path, ransomware, run, self_service, suspend,
+};
+use anyhow::{Context, Result, anyhow, bail};
 use chrono::Local;
 use log::{debug, info};
+use nix::libc;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
-use std::path::PathBuf;
+use std::io::{self, BufRead};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Component, Path, PathBuf};
+use uuid::Uuid;
+
+/// Trigger recorded for snapshots taken by `create --pkg-hook`
+const PKG_HOOK: &str = "pkg-hook";
+
+/// Options of `Create` a fleet controller may set through `agent`, by clap
+/// id; everything else is for the agent's own config to decide
+pub const AGENT_OPTIONS: &[&str] = &["json", "porcelain"];
 
 #[derive(clap::Parser)]
 pub struct Create {
     /// Path to subvolume (repeatable)
     #[arg(short = 'v', long, value_parser = utils::parse_path)]
     pub subvol: Vec<PathBuf>,
+    /// Snapshot every subvolume found below subvol-base, except the
+    /// configured exclude patterns
+    #[arg(long, conflicts_with = "subvol")]
+    pub all: bool,
+    /// Snapshot a built-in set of subvolumes detected from the mounts,
+    /// into /.snapshots unless a snapshot dir is set
+    #[arg(long, value_enum, conflicts_with_all = ["subvol", "all"])]
+    pub preset: Option<Preset>,
+    /// Snapshot the subvolumes holding the volumes and bind mounts of a
+    /// Docker or Podman container, `[docker:|podman:]<name>` (repeatable)
+    #[arg(long, conflicts_with_all = ["all", "preset"])]
+    pub container: Vec<String>,
+    /// Don't pause running containers while snapshotting them
+    #[arg(long, requires = "container")]
+    pub no_pause: bool,
+    /// Snapshot the systemd-nspawn machines in /var/lib/machines and the
+    /// Incus/LXD instances in btrfs storage pools, each named after the
+    /// machine; all of them unless names are given
+    #[arg(long, value_name = "NAME", num_args = 0.., conflicts_with_all = ["all", "preset"])]
+    pub machines: Option<Vec<String>>,
+    /// Freeze each running machine while it is snapshotted
+    #[arg(long, requires = "machines")]
+    pub freeze: bool,
     /// Snapshot directory
     #[arg(short = 'd', long, value_parser = utils::parse_path)]
     pub snap_dir: Option<PathBuf>,
+    /// Description stored with the snapshots, may use {subvol}, {hostname},
+    /// {kernel}, {uptime}, {packages} and {version}
+    #[arg(long, value_parser = facts::parse_template)]
+    pub description: Option<String>,
+    /// Run from a package-manager hook: record the packages read from stdin
+    /// (names, or package file paths as apt passes them)
+    #[arg(long)]
+    pub pkg_hook: bool,
+    /// Create snapshots even if one younger than min-interval exists
+    #[arg(long)]
+    pub ignore_min_interval: bool,
+    /// Print the run report as JSON
+    #[arg(long)]
+    pub json: bool,
+    /// Print the run report in a stable format for scripts
+    #[arg(long, value_enum, value_name = "VERSION", conflicts_with = "json")]
+    pub porcelain: Option<Porcelain>,
+    /// Print the run report through a Jinja-style template, e.g. into
+    /// Markdown or HTML; the template sees the report as in --json
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["json", "porcelain"])]
+    pub report_template: Option<PathBuf>,
+    /// UUIDs of the `--subvol` subvolumes as checked for a self-service
+    /// caller; snapshots taken of anything else are deleted again
+    #[arg(skip)]
+    pub owned: BTreeMap<PathBuf, Uuid>,
 }
 
 impl Create {
-    pub fn execute(self, snap_dir: Option<PathBuf>, subvols: Vec<PathBuf>) -> Result<()> {
-        let snap_dir = utils::resolve_snap_dir(self.snap_dir, snap_dir)?;
-        let subvols_to_snap = if !self.subvol.is_empty() {
-            self.subvol
-        } else if !subvols.is_empty() {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        let template = self
+            .report_template
+            .as_deref()
+            .map(report_template::load)
+            .transpose()?;
+        let snap_dir = match (self.preset, self.snap_dir.or(config.snap_dir.clone())) {
+            (Some(preset), None) => Some(preset.snap_dir(backend)?),
+            (_, snap_dir) => snap_dir,
+        };
+        let snap_dir = utils::resolve_snap_dir(backend, snap_dir, None)?;
+        let (retry, timeouts) = (config.retry, config.timeouts);
+        let mut excluded = vec![];
+        let containers = self
+            .container
+            .iter()
+            .map(|c| Container::find(c))
+            .collect::<Result<Vec<_>>>()?;
+        let machines = match &self.machines {
+            Some(names) => machine::select(backend, names)?,
+            None => vec![],
+        };
+        let container_subvols = containers
+            .iter()
+            .map(|c| c.subvols(backend))
+            .collect::<Result<Vec<_>>>()?;
+        // Recorded as the trigger of the container's or machine's snapshots
+        let mut triggers = BTreeMap::new();
+        for (container, subvols) in containers.iter().zip(&container_subvols) {
+            for sv in subvols {
+                triggers
+                    .entry(sv.clone())
+                    .or_insert_with(|| format!("container:{}", container.name));
+            }
+        }
+        for machine in &machines {
+            triggers
+                .entry(machine.subvol.clone())
+                .or_insert_with(|| format!("machine:{}", machine.name));
+        }
+        let subvols_to_snap = if let Some(preset) = self.preset {
+            preset.subvols(backend, &snap_dir)?
+        } else if self.all {
+            let base = config
+                .subvol_base
+                .as_deref()
+                .ok_or_else(|| anyhow!("--all needs 'subvol-base' in the config"))?;
+            let found = discover::discover(backend, base, &snap_dir, &config.exclude)?;
+            excluded = found.excluded;
+            found.subvols
+        } else if !self.subvol.is_empty() || !triggers.is_empty() {
+            let mut subvols = self.subvol;
+            for sv in triggers.keys() {
+                if !subvols.contains(sv) {
+                    subvols.push(sv.clone());
+                }
+            }
             subvols
+        } else if !config.subvols.is_empty() {
+            config.subvols.clone()
         } else {
             bail!("Subvolumes not specified");
         };
 
+        let parents = config.name_parents;
+        // Configured subvolumes count too, a one-off `-v` must not take
+        // names their snapshots use
+        let known: BTreeSet<&PathBuf> = subvols_to_snap.iter().chain(&config.subvols).collect();
+        if let Some((name, paths)) = name_collisions(known, parents)
+            .into_iter()
+            .find(|(_, paths)| paths.iter().any(|p| subvols_to_snap.contains(p)))
+        {
+            let paths: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+            bail!(
+                "Snapshots of {} would all be named {}-<timestamp>, set or raise \
+                 'name-parents' to include parent directories in the name",
+                paths.join(", "),
+                name
+            );
+        }
+
+        let description = self.description.or(config.description.clone());
+        let facts = description.as_deref().map(facts::Facts::gather);
+        let packages = if self.pkg_hook {
+            read_packages(io::stdin().lock())?
+        } else {
+            vec![]
+        };
+
         info!("Creating snapshots in {}", snap_dir.display());
+        if config.permissions.snap_dir.is_set()
+            && let Err(e) = config.permissions.snap_dir.apply(&snap_dir)
+        {
+            warning!("{:#}", e);
+        }
         let ts = Local::now().timestamp();
+        let mut report = Report::default();
+        let existing = backend.list(&snap_dir).context(format!(
+            "Failed to list subvolumes in {}",
+            snap_dir.display()
+        ))?;
+        // Containers with the subvolumes of theirs still to snapshot
+        let mut paused = vec![];
+        if !self.no_pause {
+            for (container, subvols) in containers.iter().zip(container_subvols) {
+                let pending: Vec<PathBuf> = subvols
+                    .into_iter()
+                    .filter(|sv| subvols_to_snap.contains(sv))
+                    .collect();
+                if !pending.is_empty() {
+                    paused.push((pending, container.pause()?));
+                }
+            }
+        }
         for sv in subvols_to_snap {
-            create_snapshot(&snap_dir, &sv, ts)?;
+            if interrupt::requested() {
+                report.interrupted = true;
+                break;
+            }
+            let subvol_name = subvol_name(&sv, parents);
+            let entry = report.subvol(&subvol_name);
+            if let Some(reason) = suspend::disabled(&config, &snap_dir, &subvol_name)? {
+                info!("Skipping {}, {}", subvol_name, reason);
+                entry.created = Some(Created::Disabled);
+                unpause_done(&mut paused, &sv);
+                continue;
+            }
+            if let Some(interval) = config.min_interval(&subvol_name)
+                && !self.ignore_min_interval
+                && let Some(newest) = newest_snapshot(&existing, &subvol_name)
+                && Local::now() - newest.otime < chrono::Duration::from_std(interval)?
+            {
+                info!(
+                    "Skipping {}, last snapshot is younger than min-interval ({})",
+                    subvol_name,
+                    humantime::format_duration(interval)
+                );
+                entry.created = Some(Created::Skipped);
+                unpause_done(&mut paused, &sv);
+                continue;
+            }
+            let snap_path = snapshot_path(&snap_dir, &sv, parents, ts);
+            let frozen = if self.freeze {
+                freeze(&machines, &sv)
+            } else {
+                None
+            };
+            let result = create_snapshot(
+                backend,
+                &sv,
+                &snap_path,
+                retry,
+                timeouts,
+                triggers
+                    .get(&sv)
+                    .map(String::as_str)
+                    .or(self.pkg_hook.then_some(PKG_HOOK)),
+                &mut entry.retries,
+            );
+            drop(frozen);
+            unpause_done(&mut paused, &sv);
+            let result = match self.owned.get(&sv) {
+                Some(uuid) => result.and_then(|snap_path| {
+                    self_service::verify_snapshot(backend, &snap_path, *uuid).map(|()| snap_path)
+                }),
+                None => result,
+            };
+            match result {
+                Ok(snap_path) => {
+                    entry.created = Some(Created::Yes);
+                    if config.latest_links {
+                        latest::update(backend, &snap_dir, &subvol_name);
+                    }
+                    export::refresh(backend, &snap_dir, &subvol_name);
+                    if config.permissions.snapshots.is_set()
+                        && let Err(e) = config.permissions.snapshots.apply(&snap_path)
+                    {
+                        entry.errors.push(format!("{:#}", e));
+                    }
+                    if let (Some(template), Some(facts)) = (&description, &facts)
+                        && let Some(name) = utils::file_name(&snap_path)
+                    {
+                        let text = facts.render(template, &sv);
+                        if let Err(e) =
+                            Manifest::update(&snap_dir, &name, |e| e.description = Some(text))
+                        {
+                            entry.errors.push(format!("{:#}", e));
+                        }
+                    }
+                    if !packages.is_empty()
+                        && let Some(name) = utils::file_name(&snap_path)
+                        && let Err(e) =
+                            Manifest::update(&snap_dir, &name, |e| e.packages = packages.clone())
+                    {
+                        entry.errors.push(format!("{:#}", e));
+                    }
+                    if let Some(rules) = &config.ransomware
+                        && let Some(previous) = newest_snapshot(&existing, &subvol_name)
+                        && let Err(e) = ransomware::check(&config, rules, &sv, previous, &snap_path)
+                    {
+                        entry.errors.push(format!("{:#}", e));
+                    }
+                    if !self.json && self.porcelain.is_none() && self.report_template.is_none() {
+                        println!("Created snapshot: {}", snap_path.display());
+                    }
+                }
+                Err(e) => {
+                    entry.created = Some(Created::No);
+                    entry.errors.push(format!("{:#}", e));
+                }
+            }
+        }
+        drop(paused);
+        for (sv, pattern) in excluded {
+            info!("Excluded {} (matches {})", sv.display(), pattern);
+            report.subvol(&subvol_name(&sv, parents)).created = Some(Created::Excluded);
+        }
+        report.finish(self.json, self.porcelain, template)
+    }
+}
+
+/// Take `subvol` off the pending subvolumes of the `paused` containers,
+/// unpausing those with none left
+fn unpause_done(paused: &mut Vec<(Vec<PathBuf>, Option<Paused<'_>>)>, subvol: &Path) {
+    for (pending, _) in paused.iter_mut() {
+        pending.retain(|sv| sv != subvol);
+    }
+    paused.retain(|(pending, _)| !pending.is_empty());
+}
+
+/// Freeze the machine whose root is `subvol`, if any. A machine that
+/// cannot be frozen is snapshotted running, as after a power loss.
+fn freeze<'a>(machines: &'a [Machine], subvol: &Path) -> Option<machine::Frozen<'a>> {
+    let machine = machines.iter().find(|m| m.subvol == subvol)?;
+    machine.freeze().unwrap_or_else(|e| {
+        warning!("{:#}, snapshotting machine {} unfrozen", e, machine.name);
+        None
+    })
+}
+
+/// Package names in a hook's stdin, one per line. apt passes the paths of
+/// the `.deb` files, named `<package>_<version>_<arch>.deb`
+fn read_packages(input: impl BufRead) -> Result<Vec<String>> {
+    let mut packages = vec![];
+    for line in input.lines() {
+        let line = line.context("Failed to read packages from stdin")?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let name = match line.strip_suffix(".deb") {
+            Some(deb) => {
+                let file = deb.rsplit('/').next().unwrap_or(deb);
+                file.split('_').next().unwrap_or(file)
+            }
+            None => line,
+        };
+        packages.push(name.to_string());
+    }
+    Ok(packages)
+}
+
+/// Newest snapshot of the subvolume called `name`
+pub fn newest_snapshot<'a>(snapshots: &'a [SubvolInfo], name: &str) -> Option<&'a SubvolInfo> {
+    snapshots
+        .iter()
+        .filter(|s| {
+            utils::parse_snapshot_name(&utils::snapshot_name(s)).is_some_and(|(n, _)| n == name)
+        })
+        .max_by_key(|s| s.otime)
+}
+
+/// Name snapshots of `sv` are prefixed with: its last `parents + 1` path
+/// components (`name-parents`), escaped with [`escape_name`] and joined by
+/// [`SEPARATOR`], `root` for `/`
+pub fn subvol_name(sv: &Path, parents: usize) -> String {
+    let names: Vec<String> = sv
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(n) => Some(escape_name(n.as_bytes())),
+            _ => None,
+        })
+        .collect();
+    if names.is_empty() {
+        return if sv == Path::new("/") {
+            "root"
+        } else {
+            "unknown"
+        }
+        .to_string();
+    }
+    names[names.len().saturating_sub(parents + 1)..].join(SEPARATOR)
+}
+
+/// Between the path components of a snapshot name: the escaped `/`, which
+/// escaped components never contain, unlike `-`
+const SEPARATOR: &str = "%2F";
+
+/// Whether [`escape_name`] keeps `byte` as it is
+fn is_plain(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"-_.+@".contains(&byte)
+}
+
+/// The subvolume part of a snapshot name as [`subvol_name`] writes it now.
+/// Snapshots named before names were escaped, e.g. `My Files-<ts>`, have
+/// bytes it would escape, and are matched by their escaped name.
+pub fn canonical_name(name: &str) -> Cow<'_, str> {
+    if name.bytes().all(|b| is_plain(b) || b == b'%') {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(escape_name(name.as_bytes()))
+    }
+}
+
+/// Snapshot name prefix for `subvol` given on the command line, either a
+/// name as in snapshot names (e.g. `home`) or the subvolume's path
+pub fn name_arg(subvol: &str, parents: usize) -> String {
+    if subvol.contains('/') {
+        subvol_name(Path::new(subvol), parents)
+    } else {
+        // Given escaped or not
+        escape_name(&unescape_name(subvol))
+    }
+}
+
+/// `name` with every byte but ASCII letters, digits and `-_.+@` written as
+/// `%XX`, so spaces, unicode and invalid UTF-8 give plain ASCII snapshot
+/// names that [`unescape_name`] turns back into the original
+pub fn escape_name(name: &[u8]) -> String {
+    let mut escaped = String::new();
+    for &byte in name {
+        if is_plain(byte) {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    escaped
+}
+
+/// The bytes [`escape_name`] made `name` from; a `%` not followed by two
+/// hex digits is kept as it is
+pub fn unescape_name(name: &str) -> Vec<u8> {
+    let bytes = name.as_bytes();
+    let mut name = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                name.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                name.push(byte);
+                i += 1;
+            }
         }
-        Ok(())
     }
+    name
 }
 
-fn create_snapshot(snap_dir: &PathBuf, sv: &PathBuf, ts: i64) -> Result<()> {
-    let subvol_name = sv.file_name().and_then(|n| n.to_str()).unwrap_or("unknown");
+/// Path of the snapshot of `sv` taken at `ts`, `<snap-dir>/<name>-<ts>`, or
+/// `<name>-<ts>.<n>` with the first free counter if snapshots of `sv` were
+/// already taken within that second
+pub fn snapshot_path(snap_dir: &Path, sv: &Path, parents: usize, ts: i64) -> PathBuf {
+    let name = format!("{}-{}", subvol_name(sv, parents), ts);
+    (0..)
+        .map(|n| match n {
+            0 => snap_dir.join(&name),
+            n => snap_dir.join(format!("{}.{}", name, n)),
+        })
+        .find(|path| path.symlink_metadata().is_err())
+        .unwrap()
+}
+
+/// The first free path after `snap_path` in the counting of
+/// [`snapshot_path`], for when another run took `snap_path` first
+fn next_path(snap_path: &Path) -> PathBuf {
+    let name = snap_path.file_name().unwrap_or_default().to_string_lossy();
+    // Timestamps follow a `-`, so a numeric suffix after a `.` is a counter
+    let (base, n) = match name.rsplit_once('.') {
+        Some((base, n)) if n.parse::<u32>().is_ok() => (base, n.parse::<u32>().unwrap()),
+        _ => (name.as_ref(), 0),
+    };
+    (n + 1..)
+        .map(|n| snap_path.with_file_name(format!("{}.{}", base, n)))
+        .find(|path| path.symlink_metadata().is_err())
+        .unwrap()
+}
+
+/// Names shared by more than one of `subvols`, with the subvolumes sharing
+/// them
+pub fn name_collisions<'a>(
+    subvols: impl IntoIterator<Item = &'a PathBuf>,
+    parents: usize,
+) -> BTreeMap<String, Vec<&'a PathBuf>> {
+    let mut by_name: BTreeMap<String, Vec<&PathBuf>> = BTreeMap::new();
+    for sv in subvols {
+        let paths = by_name.entry(subvol_name(sv, parents)).or_default();
+        if !paths.contains(&sv) {
+            paths.push(sv);
+        }
+    }
+    by_name.retain(|_, paths| paths.len() > 1);
+    by_name
+}
+
+/// Snapshot `sv` to `snap_path` and record it in the manifest, with what
+/// triggered it if it was not a plain `create`. If another run created
+/// `snap_path` in the meantime, the next counter is used; returns the path
+/// the snapshot was created at.
+pub fn create_snapshot(
+    backend: &'static dyn SnapshotBackend,
+    sv: &Path,
+    snap_path: &Path,
+    retry: RetryPolicy,
+    timeouts: Timeouts,
+    trigger: Option<&str>,
+    retries: &mut u32,
+) -> Result<PathBuf> {
     debug!("Processing subvolume: {}", sv.display());
-    let snap_name = format!("{}-{}", subvol_name, ts);
-    let snap_path = snap_dir.join(&snap_name);
-    let subvol = Subvolume::get(sv.as_path())
-        .context(format!("Failed to get subvolume {}", sv.display()))?;
-    subvol
-        .snapshot(snap_path.as_path(), SnapshotFlags::empty(), None)
-        .context(format!(
-            "Failed to create snapshot {} for subvolume {}",
-            snap_path.display(),
-            sv.display()
-        ))?;
-    println!("Created snapshot: {}", snap_path.display());
+    if !backend.is_subvolume(sv) {
+        bail!("Failed to get subvolume {}", sv.display());
+    }
+    let mut snap_path = snap_path.to_path_buf();
+    loop {
+        let (source, dest) = (sv.to_path_buf(), snap_path.clone());
+        let result =
+            retry::retry_with_timeout(retry, timeouts.create, "Snapshot", retries, move || {
+                backend.snapshot(&source, &dest, false)
+            });
+        match result {
+            Err(e)
+                if e.downcast_ref::<io::Error>()
+                    .is_some_and(|e| e.kind() == io::ErrorKind::AlreadyExists) =>
+            {
+                let next = next_path(&snap_path);
+                debug!(
+                    "{} was taken meanwhile, trying {}",
+                    snap_path.display(),
+                    next.display()
+                );
+                snap_path = next;
+            }
+            result => {
+                result.context(format!(
+                    "Failed to create snapshot {} for subvolume {}",
+                    snap_path.display(),
+                    sv.display()
+                ))?;
+                break;
+            }
+        }
+    }
 
+    // The source's owner may have planted a symlink or FIFO there, which
+    // must not make root create or block on anything
     let ignore_path = snap_path.join(".ignore");
-    fs::OpenOptions::new()
+    let ignore = fs::OpenOptions::new()
         .create(true)
+        .truncate(false)
         .write(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
         .open(ignore_path.as_path())
         .context(format!(
             "Failed to touch .ignore in snapshot {}",
             snap_path.display()
         ))?;
-    Ok(())
+    if !ignore.metadata()?.is_file() {
+        bail!(
+            "{} is not a regular file in snapshot {}",
+            ignore_path.display(),
+            snap_path.display()
+        );
+    }
+
+    let info = backend.info(&snap_path).context(format!(
+        "Failed to get info for snapshot {}",
+        snap_path.display()
+    ))?;
+    if let (Some(snap_dir), Some(name)) = (snap_path.parent(), snap_path.file_name()) {
+        let mut entry = manifest::Entry::new(sv, &info);
+        entry.trigger = trigger.map(String::from);
+        entry.created_by_run = Some(run::id().to_string());
+        Manifest::record(snap_dir, &os_path::name(name), entry)?;
+    }
+    Ok(snap_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use std::time::Duration;
+
+    fn create(source: &Path, snap_dir: &Path) -> Create {
+        Create {
+            subvol: vec![source.to_path_buf()],
+            all: false,
+            preset: None,
+            description: None,
+            pkg_hook: false,
+            snap_dir: Some(snap_dir.to_path_buf()),
+            ignore_min_interval: false,
+            json: true,
+            porcelain: None,
+            report_template: None,
+            container: vec![],
+            no_pause: false,
+            machines: None,
+            freeze: false,
+            owned: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn names_snapshots_after_subvolume_and_time() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let (source, snap_dir) = (dir.join("data"), dir.join("snapshots"));
+        backend.add(&source, Local::now());
+        fs::create_dir(&snap_dir).unwrap();
+
+        let before = Local::now().timestamp();
+        create(&source, &snap_dir)
+            .execute(backend, Config::default())
+            .unwrap();
+
+        let snaps = backend.list(&snap_dir).unwrap();
+        assert_eq!(snaps.len(), 1);
+        let name = utils::snapshot_name(&snaps[0]);
+        let (subvol, ts) = utils::parse_snapshot_name(&name).unwrap();
+        assert_eq!(subvol, "data");
+        assert!(ts >= before);
+        let manifest = Manifest::load(&snap_dir).unwrap().unwrap();
+        assert_eq!(manifest.snapshots[&name].source, source);
+    }
+
+    #[test]
+    fn counts_up_within_the_same_second() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let sv = dir.join("data");
+        let first = snapshot_path(&dir, &sv, 0, 1700000000);
+        backend.add(&first, Local::now());
+        let second = snapshot_path(&dir, &sv, 0, 1700000000);
+        backend.add(&second, Local::now());
+
+        assert_eq!(first, dir.join("data-1700000000"));
+        assert_eq!(second, dir.join("data-1700000000.1"));
+        assert_eq!(
+            snapshot_path(&dir, &sv, 0, 1700000000),
+            dir.join("data-1700000000.2")
+        );
+        assert_eq!(
+            utils::parse_snapshot_name("data-1700000000.1"),
+            Some(("data".into(), 1700000000))
+        );
+    }
+
+    #[test]
+    fn unpauses_containers_once_their_subvolumes_are_done() {
+        let (a, b) = (PathBuf::from("/a"), PathBuf::from("/b"));
+        let mut paused = vec![(vec![a.clone(), b.clone()], None), (vec![a.clone()], None)];
+
+        unpause_done(&mut paused, &a);
+        assert_eq!(paused.len(), 1);
+        assert_eq!(paused[0].0, [b.as_path()]);
+        unpause_done(&mut paused, &b);
+        assert!(paused.is_empty());
+    }
+
+    #[test]
+    fn takes_the_next_counter_if_another_run_was_faster() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let (sv, snap_dir) = (dir.join("data"), dir.join("snapshots"));
+        backend.add(&sv, Local::now());
+        let path = snapshot_path(&snap_dir, &sv, 0, 1700000000);
+        // Both runs picked `path`, the other one snapshotted first
+        backend.add(&path, Local::now());
+        backend.add(&snap_dir.join("data-1700000000.1"), Local::now());
+
+        let mut retries = 0;
+        let created = create_snapshot(
+            backend,
+            &sv,
+            &path,
+            RetryPolicy::default(),
+            Timeouts::default(),
+            None,
+            &mut retries,
+        )
+        .unwrap();
+        assert_eq!(created, snap_dir.join("data-1700000000.2"));
+        let manifest = Manifest::load(&snap_dir).unwrap().unwrap();
+        assert!(manifest.snapshots.contains_key("data-1700000000.2"));
+    }
+
+    #[test]
+    fn skips_subvolumes_within_min_interval() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let (source, snap_dir) = (dir.join("data"), dir.join("snapshots"));
+        backend.add(&source, Local::now());
+        backend.add(&snap_dir.join("data-1"), Local::now());
+        backend.add(
+            &snap_dir.join("other-1"),
+            Local::now() - Duration::from_secs(7200),
+        );
+        let config = Config {
+            min_interval: Some(Duration::from_secs(3600)),
+            ..Default::default()
+        };
+
+        create(&source, &snap_dir)
+            .execute(backend, config.clone())
+            .unwrap();
+        assert_eq!(backend.list(&snap_dir).unwrap().len(), 2);
+
+        let mut forced = create(&source, &snap_dir);
+        forced.ignore_min_interval = true;
+        forced.execute(backend, config).unwrap();
+        assert_eq!(backend.list(&snap_dir).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn colliding_names_need_parent_directories() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let (a, b) = (dir.join("a/data"), dir.join("b/data"));
+        let snap_dir = dir.join("snapshots");
+        backend.add(&a, Local::now());
+        backend.add(&b, Local::now());
+        fs::create_dir(&snap_dir).unwrap();
+        let mut config = Config {
+            subvols: vec![a.clone(), b],
+            ..Default::default()
+        };
+
+        let err = create(&a, &snap_dir)
+            .execute(backend, config.clone())
+            .unwrap_err();
+        assert!(err.to_string().contains("name-parents"), "{}", err);
+        assert!(backend.list(&snap_dir).unwrap().is_empty());
+
+        config.name_parents = 1;
+        create(&a, &snap_dir).execute(backend, config).unwrap();
+        let name = utils::snapshot_name(&backend.list(&snap_dir).unwrap()[0]);
+        assert_eq!(utils::parse_snapshot_name(&name).unwrap().0, "a%2Fdata");
+    }
+
+    #[test]
+    fn escapes_awkward_names_reversibly() {
+        let sv = Path::new("/data/My Files/café");
+        assert_eq!(subvol_name(sv, 1), "My%20Files%2Fcaf%C3%A9");
+        assert_ne!(
+            subvol_name(Path::new("/a/data"), 1),
+            subvol_name(Path::new("/a-data"), 1)
+        );
+        let legacy = utils::parse_snapshot_name("My Files-1700000000").unwrap();
+        assert_eq!(legacy.0, subvol_name(Path::new("/My Files"), 0));
+        assert_eq!(unescape_name("caf%C3%A9"), "café".as_bytes());
+        assert_eq!(name_arg("café", 0), name_arg("caf%C3%A9", 0));
+        assert_eq!(name_arg("@home", 0), "@home");
+        let raw = [b'a', 0xff, b'%'];
+        assert_eq!(unescape_name(&escape_name(&raw)), raw);
+        assert_eq!(unescape_name("100%"), b"100%");
+    }
+
+    #[test]
+    fn reads_package_names_from_hook_input() {
+        let input = "linux\n\n/var/cache/apt/archives/nvidia-driver_535.1-1_amd64.deb\n";
+        assert_eq!(
+            read_packages(input.as_bytes()).unwrap(),
+            ["linux", "nvidia-driver"]
+        );
+    }
 }
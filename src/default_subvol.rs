@@ -0,0 +1,181 @@
+use crate::backend::SnapshotBackend;
+use crate::config::Config;
+use crate::utils;
+use anyhow::{Context, Result, bail};
+use log::debug;
+use std::path::{Path, PathBuf};
+
+/// Any of these makes a subvolume look like a root filesystem
+const INIT_PATHS: &[&str] = &["sbin/init", "usr/sbin/init", "usr/lib/systemd/systemd"];
+
+#[derive(clap::Parser)]
+pub struct DefaultSubvol {
+    #[command(subcommand)]
+    action: Action,
+}
+
+#[derive(clap::Subcommand)]
+enum Action {
+    /// Show the default subvolume of a filesystem
+    Show {
+        /// Any path on the filesystem
+        #[arg(value_parser = utils::parse_path, default_value = "/")]
+        path: PathBuf,
+    },
+    /// Make a snapshot or subvolume the default, mounted when no subvol= is given
+    Set {
+        #[arg(value_parser = utils::parse_path)]
+        target: PathBuf,
+        /// Skip the writable and bootable layout checks
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+impl DefaultSubvol {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        match self.action {
+            Action::Show { path } => show(backend, &config, &path),
+            Action::Set { target, force } => {
+                set(backend, &target, force)?;
+                println!(
+                    "{} is now the default subvolume, used from the next mount",
+                    target.display()
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+fn show(backend: &dyn SnapshotBackend, config: &Config, path: &Path) -> Result<()> {
+    let id = backend.default_subvol(path).context(format!(
+        "Failed to get the default subvolume of {}",
+        path.display()
+    ))?;
+    match find_by_id(backend, config, id) {
+        Some(found) => println!("Default subvolume: ID {} ({})", id, found.display()),
+        None => println!("Default subvolume: ID {}", id),
+    }
+    if let Ok(root) = backend.info(Path::new("/"))
+        && root.id != id
+    {
+        println!("Mounted at /: ID {} (not the default)", root.id);
+    }
+    Ok(())
+}
+
+/// Path of subvolume `id` among those btrsnap knows: `/`, the configured
+/// subvolumes and the snapshots
+fn find_by_id(backend: &dyn SnapshotBackend, config: &Config, id: u64) -> Option<PathBuf> {
+    let known = [PathBuf::from("/")]
+        .into_iter()
+        .chain(config.subvols.clone());
+    let mut found = known
+        .filter_map(|p| backend.info(&p).ok())
+        .find(|info| info.id == id);
+    if found.is_none()
+        && let Some(snap_dir) = &config.snap_dir
+    {
+        found = backend
+            .list(snap_dir)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|info| info.id == id);
+    }
+    found.map(|info| info.path)
+}
+
+/// Fail unless `path` can be booted from: a writable subvolume with a root
+/// filesystem layout
+pub fn check(backend: &dyn SnapshotBackend, path: &Path) -> Result<()> {
+    // `info` of a plain directory describes the subvolume holding it
+    if !backend.is_subvolume(path) {
+        bail!("{} is not a subvolume", path.display());
+    }
+    let info = backend
+        .info(path)
+        .context(format!("{} is not a subvolume", path.display()))?;
+    if info.is_read_only() {
+        bail!(
+            "{} is read-only and cannot be booted, use `restore --root` for a writable copy",
+            path.display()
+        );
+    }
+    check_layout(path)
+}
+
+/// Fail unless `path` has `/etc/fstab` and an init binary
+pub fn check_layout(path: &Path) -> Result<()> {
+    if !path.join("etc/fstab").exists() {
+        bail!("{} has no etc/fstab, not a root filesystem", path.display());
+    }
+    if !INIT_PATHS
+        .iter()
+        .any(|p| path.join(p).symlink_metadata().is_ok())
+    {
+        bail!("{} has no init, not a root filesystem", path.display());
+    }
+    Ok(())
+}
+
+/// Make `target` the default subvolume after the sanity checks, unless `force`
+pub fn set(backend: &dyn SnapshotBackend, target: &Path, force: bool) -> Result<()> {
+    if force {
+        // Even forced, a directory would make its subvolume the default
+        if !backend.is_subvolume(target) {
+            bail!("{} is not a subvolume", target.display());
+        }
+        debug!(
+            "Not checking {} before setting it as default",
+            target.display()
+        );
+    } else {
+        check(backend, target)?;
+    }
+    backend.set_default(target).context(format!(
+        "Failed to make {} the default subvolume",
+        target.display()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use chrono::Local;
+    use std::fs;
+
+    #[test]
+    fn set_refuses_non_root_layouts() {
+        let backend = MockBackend::leak();
+        let target = backend.scratch_dir().join("root");
+        backend.add(&target, Local::now());
+
+        assert!(set(backend, &target, false).is_err());
+        assert_eq!(backend.default_subvol(&target).unwrap(), 5);
+
+        fs::create_dir_all(target.join("etc")).unwrap();
+        fs::write(target.join("etc/fstab"), "").unwrap();
+        fs::create_dir_all(target.join("sbin")).unwrap();
+        fs::write(target.join("sbin/init"), "").unwrap();
+        set(backend, &target, false).unwrap();
+        assert_eq!(
+            backend.default_subvol(&target).unwrap(),
+            backend.info(&target).unwrap().id
+        );
+    }
+
+    #[test]
+    fn set_refuses_plain_directories() {
+        let backend = MockBackend::leak();
+        let target = backend.scratch_dir().join("root");
+        backend.add(&target, Local::now());
+        let dir = target.join("var");
+        fs::create_dir_all(&dir).unwrap();
+
+        assert!(set(backend, &dir, false).is_err());
+        assert!(set(backend, &dir, true).is_err());
+        assert!(backend.default.lock().unwrap().is_none());
+    }
+}
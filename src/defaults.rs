@@ -0,0 +1,121 @@
+use anyhow::{Result, bail};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use toml::Value;
+
+/// `args` with the flags from the config's `[<command>]` section for the
+/// subcommand in `matches` inserted after the subcommand name, `None` if
+/// there are none to add. Flags given on the command line, or conflicting
+/// with ones given there, are left out so the command line wins.
+pub fn apply(
+    cli: &Command,
+    matches: &ArgMatches,
+    args: &[OsString],
+    defaults: &BTreeMap<String, toml::Table>,
+) -> Result<Option<Vec<OsString>>> {
+    let Some((name, sub_matches)) = matches.subcommand() else {
+        return Ok(None);
+    };
+    let (Some(table), Some(sub)) = (defaults.get(name), cli.find_subcommand(name)) else {
+        return Ok(None);
+    };
+    let given = |id: &clap::Id| {
+        sub_matches.try_contains_id(id.as_str()).unwrap_or(false)
+            && sub_matches.value_source(id.as_str()) == Some(ValueSource::CommandLine)
+    };
+    let mut flags = vec![];
+    for (key, value) in table {
+        let Some(arg) = sub.get_arguments().find(|a| a.get_long() == Some(key)) else {
+            bail!("Unknown option '{}' in [{}] of the config", key, name);
+        };
+        if given(arg.get_id())
+            || sub
+                .get_arg_conflicts_with(arg)
+                .iter()
+                .any(|other| given(other.get_id()))
+        {
+            continue;
+        }
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                Value::Boolean(set) if !arg.get_action().takes_values() => {
+                    if *set {
+                        flags.push(format!("--{}", key).into());
+                    }
+                    continue;
+                }
+                Value::String(s) => s.clone(),
+                Value::Integer(_) | Value::Float(_) | Value::Boolean(_) => value.to_string(),
+                _ => bail!("Invalid value for '{}' in [{}] of the config", key, name),
+            };
+            flags.push(format!("--{}={}", key, value).into());
+        }
+    }
+    if flags.is_empty() {
+        return Ok(None);
+    }
+
+    // The subcommand is the first argument that is not an option or the
+    // value of one
+    let mut pos = 1;
+    while let Some(arg) = args.get(pos) {
+        if arg == name {
+            break;
+        }
+        pos += if arg == "-c" || arg == "--config" {
+            2
+        } else {
+            1
+        };
+    }
+    let mut args = args.to_vec();
+    args.splice(pos + 1..pos + 1, flags);
+    Ok(Some(args))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, ArgAction};
+
+    #[test]
+    fn config_flags_yield_to_the_command_line() {
+        let cli = Command::new("btrsnap")
+            .arg(Arg::new("config").short('c').long("config"))
+            .subcommand(
+                Command::new("list")
+                    .arg(Arg::new("long").long("long").action(ArgAction::SetTrue))
+                    .arg(Arg::new("format").long("format"))
+                    .arg(
+                        Arg::new("json")
+                            .long("json")
+                            .action(ArgAction::SetTrue)
+                            .conflicts_with("format"),
+                    ),
+            );
+        let defaults = BTreeMap::from([(
+            "list".to_string(),
+            toml::from_str("long = true\nformat = \"table\"\njson = true").unwrap(),
+        )]);
+        let args: Vec<OsString> = ["btrsnap", "-c", "list", "list", "--format=csv"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        let matches = cli.clone().get_matches_from(&args);
+
+        let args = apply(&cli, &matches, &args, &defaults).unwrap().unwrap();
+        assert_eq!(
+            args,
+            ["btrsnap", "-c", "list", "list", "--long", "--format=csv"]
+        );
+
+        let defaults = BTreeMap::from([("list".to_string(), toml::from_str("x = 1").unwrap())]);
+        assert!(apply(&cli, &matches, &args, &defaults).is_err());
+    }
+}
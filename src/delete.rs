@@ -1,35 +1,175 @@
-use crate::utils;
+use crate::backend::{SnapshotBackend, SubvolInfo};
+use crate::config::Config;
+use crate::manifest::Manifest;
+use crate::protect::Guard;
+use crate::retry;
+use crate::tombstone::{self, Tombstone};
+use crate::{boot, create, export, interrupt, latest, selector, utils};
 use anyhow::{Context, Result, bail};
-use btrfsutil::subvolume::{DeleteFlags, Subvolume};
 use log::debug;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/// Options of `Delete` a fleet controller may set through `agent`, by clap
+/// id: only naming snapshots, not picking them by subvolume
+pub const AGENT_OPTIONS: &[&str] = &["snapshot"];
 
 #[derive(clap::Parser)]
 pub struct Delete {
-    /// Path to snapshot (repeatable)
-    #[arg(short, long, value_parser = utils::parse_path)]
+    /// Path to snapshot, or a selector like @home:oldest (repeatable)
+    #[arg(short, long, value_parser = selector::parse_snapshot)]
     pub snapshot: Vec<PathBuf>,
+    /// Delete snapshots of this subvolume (name as in snapshot names, or its
+    /// path), with --keep-latest
+    #[arg(
+        short = 'v',
+        long,
+        requires = "keep_latest",
+        conflicts_with = "snapshot"
+    )]
+    pub subvol: Option<String>,
+    /// Delete all but the newest N snapshots of --subvol, whatever the
+    /// cleanup policy
+    #[arg(long, value_name = "N", requires = "subvol")]
+    pub keep_latest: Option<usize>,
+    /// Snapshot dir to delete from, with --subvol
+    #[arg(short = 'd', long, value_parser = utils::parse_path)]
+    pub snap_dir: Option<PathBuf>,
+    /// Also delete snapshots younger than min-age-before-delete
+    #[arg(long)]
+    pub force: bool,
 }
 
 impl Delete {
-    pub fn execute(self) -> Result<()> {
-        if self.snapshot.is_empty() {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        let snapshots = match (&self.subvol, self.keep_latest) {
+            (Some(subvol), Some(keep)) => {
+                let snap_dir = utils::resolve_snap_dir(
+                    backend,
+                    self.snap_dir.clone(),
+                    config.snap_dir.clone(),
+                )?;
+                let name = create::name_arg(subvol, config.name_parents);
+                all_but_latest(backend, &snap_dir, &name, keep)?
+            }
+            _ => self.snapshot,
+        };
+        if snapshots.is_empty() {
+            if self.subvol.is_some() {
+                println!("Nothing to delete");
+                return Ok(());
+            }
             bail!("Snapshots not specified");
         }
-        for s in self.snapshot {
-            delete_snapshot(&s)?;
+        let guard = Guard::new(backend, config.protect.clone(), self.force);
+        let mut deleted = 0;
+        let result = snapshots.iter().try_for_each(|s| {
+            if interrupt::requested() {
+                bail!("Interrupted, the remaining snapshots were not deleted");
+            }
+            delete_snapshot(backend, s, &guard, &config)?;
+            deleted += 1;
+            Ok(())
+        });
+        if deleted > 0 {
+            boot::regenerate(&config.boot);
         }
-        Ok(())
+        result
     }
 }
 
-fn delete_snapshot(s: &PathBuf) -> Result<()> {
+/// Snapshots of the subvolume called `name` except the newest `keep`
+fn all_but_latest(
+    backend: &dyn SnapshotBackend,
+    snap_dir: &Path,
+    name: &str,
+    keep: usize,
+) -> Result<Vec<PathBuf>> {
+    let mut snapshots: Vec<SubvolInfo> = backend
+        .list(snap_dir)
+        .context(format!(
+            "Failed to list subvolumes in {}",
+            snap_dir.display()
+        ))?
+        .into_iter()
+        .filter(|s| {
+            utils::parse_snapshot_name(&utils::snapshot_name(s)).is_some_and(|(n, _)| n == name)
+        })
+        .collect();
+    snapshots.sort_by_key(|s| std::cmp::Reverse(s.otime));
+    Ok(snapshots.into_iter().skip(keep).map(|s| s.path).collect())
+}
+
+/// Delete snapshot `s` unless `guard` protects it, with its boot entries,
+/// and drop it from the manifest
+pub fn delete_snapshot(
+    backend: &'static dyn SnapshotBackend,
+    s: &Path,
+    guard: &Guard,
+    config: &Config,
+) -> Result<()> {
     debug!("Deleting snapshot: {}", s.display());
-    let subvol =
-        Subvolume::get(s.as_path()).context(format!("Failed to get subvolume {}", s.display()))?;
-    subvol
-        .delete(DeleteFlags::empty())
-        .context(format!("Failed to delete snapshot {}", s.display()))?;
+    let info = backend
+        .info(s)
+        .context(format!("Failed to get subvolume {}", s.display()))?;
+    guard.check(&info)?;
+    boot::prune(&config.boot, s)?;
+    export::release(s)?;
+    let snap_dir = s.parent().unwrap_or(Path::new("/"));
+    let manifest = Manifest::load(snap_dir)?;
+    let exclusive = backend
+        .qgroups(snap_dir)
+        .ok()
+        .and_then(|q| q.get(&info.id).map(|q| q.exclusive));
+    let tombstone = Tombstone::new(&info, manifest.as_ref(), "delete", exclusive);
+    let path = s.to_path_buf();
+    let mut retries = 0;
+    let (retry, timeouts) = (config.retry, config.timeouts);
+    retry::retry_with_timeout(retry, timeouts.delete, "Delete", &mut retries, move || {
+        backend.delete_any(&path)
+    })
+    .context(format!("Failed to delete snapshot {}", s.display()))?;
+    tombstone::record(snap_dir, &tombstone);
+    Manifest::forget(snap_dir, &tombstone.name)?;
+    if let Some((subvol, _)) = utils::parse_snapshot_name(&tombstone.name) {
+        if config.latest_links {
+            latest::update(backend, snap_dir, &subvol);
+        }
+        export::refresh(backend, snap_dir, &subvol);
+    }
     println!("Deleted: {}", s.display());
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use chrono::{Duration, Local};
+
+    #[test]
+    fn keeps_only_the_latest_snapshots_of_one_subvolume() {
+        let backend = MockBackend::leak();
+        let snap_dir = backend.scratch_dir();
+        for (name, age) in [("home-1", 3), ("home-2", 2), ("home-3", 1), ("var-4", 9)] {
+            backend.add(&snap_dir.join(name), Local::now() - Duration::hours(age));
+        }
+
+        Delete {
+            snapshot: vec![],
+            subvol: Some("home".to_string()),
+            keep_latest: Some(2),
+            snap_dir: Some(snap_dir.clone()),
+            force: true,
+        }
+        .execute(backend, Config::default())
+        .unwrap();
+
+        let left: Vec<String> = backend
+            .list(&snap_dir)
+            .unwrap()
+            .iter()
+            .map(utils::snapshot_name)
+            .collect();
+        assert_eq!(left, ["home-2", "home-3", "var-4"]);
+    }
+}
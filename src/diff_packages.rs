@@ -0,0 +1,372 @@
+//! Package changes between two snapshots of `/`, read from the package
+//! database inside each: dpkg, pacman or rpm
+
+use crate::backend::SnapshotBackend;
+use crate::config::Config;
+use crate::selector;
+use anyhow::{Context, Result, bail};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Installed versions by package name, several for install-only packages
+/// such as kernels
+type Packages = BTreeMap<String, BTreeSet<String>>;
+
+#[derive(clap::Parser)]
+pub struct DiffPackages {
+    /// Snapshot of / to compare from, or a selector like @root:latest-1
+    #[arg(value_parser = selector::parse_snapshot)]
+    pub old: PathBuf,
+    /// Snapshot to compare to, or `live` for the running system (default)
+    #[arg(value_parser = parse_new)]
+    pub new: Option<PathBuf>,
+}
+
+impl DiffPackages {
+    pub fn execute(self, _backend: &'static dyn SnapshotBackend, _config: Config) -> Result<()> {
+        let new = self.new.unwrap_or_else(|| PathBuf::from("/"));
+        let (old_db, old_packages) = read(&self.old)?;
+        let (new_db, new_packages) = read(&new)?;
+        if old_db != new_db {
+            bail!(
+                "{} uses {} but {} uses {}, their packages cannot be compared",
+                self.old.display(),
+                old_db,
+                new.display(),
+                new_db
+            );
+        }
+        println!(
+            "Packages ({}) from {} to {}:",
+            old_db,
+            self.old.display(),
+            new.display()
+        );
+        print!("{}", Diff::new(&old_packages, &new_packages));
+        Ok(())
+    }
+}
+
+/// `live` for the running system, else a snapshot
+fn parse_new(s: &str) -> Result<PathBuf> {
+    match s {
+        "live" => Ok(PathBuf::from("/")),
+        _ => selector::parse_snapshot(s),
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Diff {
+    added: Vec<(String, String)>,
+    removed: Vec<(String, String)>,
+    /// Name, old and new versions
+    upgraded: Vec<(String, String, String)>,
+    downgraded: Vec<(String, String, String)>,
+}
+
+impl Diff {
+    fn new(old: &Packages, new: &Packages) -> Self {
+        let join =
+            |versions: &BTreeSet<String>| versions.iter().cloned().collect::<Vec<_>>().join(", ");
+        let newest = |versions: &BTreeSet<String>| {
+            versions
+                .iter()
+                .max_by(|a, b| compare_versions(a, b))
+                .cloned()
+        };
+        let mut diff = Diff::default();
+        for (name, versions) in new {
+            match old.get(name) {
+                None => diff.added.push((name.clone(), join(versions))),
+                Some(before) if before != versions => {
+                    let change = (name.clone(), join(before), join(versions));
+                    match compare_versions(
+                        &newest(before).unwrap_or_default(),
+                        &newest(versions).unwrap_or_default(),
+                    ) {
+                        Ordering::Greater => diff.downgraded.push(change),
+                        _ => diff.upgraded.push(change),
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+        for (name, versions) in old {
+            if !new.contains_key(name) {
+                diff.removed.push((name.clone(), join(versions)));
+            }
+        }
+        diff
+    }
+}
+
+impl std::fmt::Display for Diff {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} added, {} removed, {} upgraded, {} downgraded",
+            self.added.len(),
+            self.removed.len(),
+            self.upgraded.len(),
+            self.downgraded.len()
+        )?;
+        for (heading, list) in [("Added", &self.added), ("Removed", &self.removed)] {
+            if !list.is_empty() {
+                writeln!(f, "{}:", heading)?;
+            }
+            for (name, version) in list {
+                writeln!(f, "  {} {}", name, version)?;
+            }
+        }
+        for (heading, list) in [
+            ("Upgraded", &self.upgraded),
+            ("Downgraded", &self.downgraded),
+        ] {
+            if !list.is_empty() {
+                writeln!(f, "{}:", heading)?;
+            }
+            for (name, old, new) in list {
+                writeln!(f, "  {} {} -> {}", name, old, new)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The package database found below `root` and its packages
+fn read(root: &Path) -> Result<(&'static str, Packages)> {
+    let dpkg = root.join("var/lib/dpkg/status");
+    if dpkg.exists() {
+        let status =
+            fs::read_to_string(&dpkg).context(format!("Failed to read {}", dpkg.display()))?;
+        let native = fs::read_to_string(root.join("var/lib/dpkg/arch")).ok();
+        let native = native.as_deref().and_then(|a| a.lines().next());
+        return Ok(("dpkg", parse_dpkg(&status, native)));
+    }
+    let pacman = root.join("var/lib/pacman/local");
+    if pacman.is_dir() {
+        return Ok(("pacman", read_pacman(&pacman)?));
+    }
+    if ["usr/lib/sysimage/rpm", "var/lib/rpm"]
+        .iter()
+        .any(|db| root.join(db).is_dir())
+    {
+        return Ok(("rpm", read_rpm(root)?));
+    }
+    bail!(
+        "No dpkg, pacman or rpm database in {}, is it a snapshot of /?",
+        root.display()
+    )
+}
+
+/// Installed packages in a dpkg `status` file. Packages of architectures
+/// other than `native` are named `<name>:<arch>`, as dpkg does.
+fn parse_dpkg(status: &str, native: Option<&str>) -> Packages {
+    let mut packages = Packages::new();
+    for stanza in status.split("\n\n") {
+        let field = |key: &str| {
+            stanza
+                .lines()
+                .find_map(|l| l.strip_prefix(key)?.strip_prefix(':'))
+                .map(str::trim)
+        };
+        let (Some(name), Some(version)) = (field("Package"), field("Version")) else {
+            continue;
+        };
+        if !field("Status").is_some_and(|s| s.ends_with(" installed")) {
+            continue;
+        }
+        let name = match field("Architecture") {
+            Some(arch) if arch != "all" && Some(arch) != native => format!("{}:{}", name, arch),
+            _ => name.to_string(),
+        };
+        packages
+            .entry(name)
+            .or_default()
+            .insert(version.to_string());
+    }
+    packages
+}
+
+/// Packages from the `desc` files in pacman's `local` database
+fn read_pacman(local: &Path) -> Result<Packages> {
+    let mut packages = Packages::new();
+    let entries = fs::read_dir(local).context(format!("Failed to read {}", local.display()))?;
+    for entry in entries {
+        let Ok(desc) = fs::read_to_string(entry?.path().join("desc")) else {
+            continue;
+        };
+        let field = |key: &str| {
+            let mut lines = desc.lines();
+            lines.find(|l| *l == key)?;
+            lines.next().map(str::to_string)
+        };
+        if let (Some(name), Some(version)) = (field("%NAME%"), field("%VERSION%")) {
+            packages.entry(name).or_default().insert(version);
+        }
+    }
+    Ok(packages)
+}
+
+/// Packages from rpm's database below `root`, through `rpm --root`
+fn read_rpm(root: &Path) -> Result<Packages> {
+    let output = Command::new("rpm")
+        .arg("--root")
+        .arg(root)
+        .args([
+            "-qa",
+            "--qf",
+            "%{NAME}.%{ARCH} %{EPOCHNUM}:%{VERSION}-%{RELEASE}\\n",
+        ])
+        .output()
+        .context("Failed to run rpm")?;
+    if !output.status.success() {
+        bail!(
+            "rpm failed on {}: {}",
+            root.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let mut packages = Packages::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some((name, version)) = line.split_once(' ') {
+            let name = name.strip_suffix(".(none)").unwrap_or(name);
+            let version = version.strip_prefix("0:").unwrap_or(version);
+            packages
+                .entry(name.to_string())
+                .or_default()
+                .insert(version.to_string());
+        }
+    }
+    Ok(packages)
+}
+
+/// Order of package versions `[epoch:]version[-release]`, as dpkg and
+/// rpm compare them: runs of digits numerically, other runs by character,
+/// with `~` sorting before everything, even the end
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let epoch = |v: &str| -> (u64, String) {
+        match v.split_once(':') {
+            Some((e, rest)) if e.chars().all(|c| c.is_ascii_digit()) => {
+                (e.parse().unwrap_or(0), rest.to_string())
+            }
+            _ => (0, v.to_string()),
+        }
+    };
+    let ((epoch_a, a), (epoch_b, b)) = (epoch(a), epoch(b));
+    epoch_a.cmp(&epoch_b).then_with(|| {
+        let (mut a, mut b) = (a.as_str(), b.as_str());
+        loop {
+            (a, b) = (skip_separators(a), skip_separators(b));
+            match (a.strip_prefix('~'), b.strip_prefix('~')) {
+                (Some(ra), Some(rb)) => {
+                    (a, b) = (ra, rb);
+                    continue;
+                }
+                (Some(_), None) => return Ordering::Less,
+                (None, Some(_)) => return Ordering::Greater,
+                (None, None) => {}
+            }
+            if a.is_empty() || b.is_empty() {
+                return a.len().cmp(&b.len());
+            }
+            let digits = a.starts_with(|c: char| c.is_ascii_digit());
+            if digits != b.starts_with(|c: char| c.is_ascii_digit()) {
+                // Numbers sort after letters
+                return if digits {
+                    Ordering::Greater
+                } else {
+                    Ordering::Less
+                };
+            }
+            let run = |s: &str| {
+                s.find(|c: char| c.is_ascii_digit() != digits || !c.is_ascii_alphanumeric())
+                    .unwrap_or(s.len())
+            };
+            let (ra, rb) = (&a[..run(a)], &b[..run(b)]);
+            let order = if digits {
+                let (ra, rb) = (ra.trim_start_matches('0'), rb.trim_start_matches('0'));
+                ra.len().cmp(&rb.len()).then(ra.cmp(rb))
+            } else {
+                ra.cmp(rb)
+            };
+            if order != Ordering::Equal {
+                return order;
+            }
+            a = &a[run(a)..];
+            b = &b[run(b)..];
+        }
+    })
+}
+
+fn skip_separators(s: &str) -> &str {
+    s.trim_start_matches(|c: char| !c.is_ascii_alphanumeric() && c != '~')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+
+    #[test]
+    fn reports_package_changes_between_trees() {
+        let dir = MockBackend::leak().scratch_dir();
+        let tree = |name: &str, status: &str| {
+            let root = dir.join(name);
+            fs::create_dir_all(root.join("var/lib/dpkg")).unwrap();
+            fs::write(root.join("var/lib/dpkg/status"), status).unwrap();
+            fs::write(root.join("var/lib/dpkg/arch"), "amd64\ni386\n").unwrap();
+            root
+        };
+        let stanza = |name: &str, version: &str, arch: &str| {
+            format!(
+                "Package: {}\nStatus: install ok installed\nArchitecture: {}\nVersion: {}\n\n",
+                name, arch, version
+            )
+        };
+        let old = tree(
+            "old",
+            &[
+                stanza("bash", "5.2-1", "amd64"),
+                stanza("libc6", "2.36-9", "i386"),
+                stanza("vim", "2:9.0.1378-2", "amd64"),
+                stanza("tzdata", "2024a-1", "all"),
+            ]
+            .concat(),
+        );
+        let new = tree(
+            "new",
+            &[
+                stanza("bash", "5.2~rc1-1", "amd64"),
+                stanza("libc6", "2.36-9", "i386"),
+                stanza("vim", "2:9.0.1378-10", "amd64"),
+                stanza("htop", "3.2.2-2", "amd64"),
+                "Package: tzdata\nStatus: deinstall ok config-files\nVersion: 2024a-1\n".into(),
+            ]
+            .concat(),
+        );
+
+        let (db, old) = read(&old).unwrap();
+        let (_, new) = read(&new).unwrap();
+        assert_eq!(db, "dpkg");
+        assert!(old.contains_key("libc6:i386"));
+        let diff = Diff::new(&old, &new);
+        assert_eq!(diff.added, [("htop".into(), "3.2.2-2".into())]);
+        assert_eq!(diff.removed, [("tzdata".into(), "2024a-1".into())]);
+        assert_eq!(
+            diff.upgraded,
+            [("vim".into(), "2:9.0.1378-2".into(), "2:9.0.1378-10".into())]
+        );
+        assert_eq!(
+            diff.downgraded,
+            [("bash".into(), "5.2-1".into(), "5.2~rc1-1".into())]
+        );
+        assert_eq!(compare_versions("1.0~rc1", "1.0"), Ordering::Less);
+        assert_eq!(compare_versions("1:0.9", "2.0"), Ordering::Greater);
+        assert_eq!(compare_versions("1.0a", "1.0.1"), Ordering::Less);
+        assert!(read(&dir).is_err());
+    }
+}
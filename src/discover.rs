@@ -0,0 +1,101 @@
+use crate::backend::SnapshotBackend;
+use anyhow::{Context, Result};
+use log::debug;
+use std::path::{Path, PathBuf};
+
+/// Subvolumes found below a base directory, split by the exclude patterns
+#[derive(Default)]
+pub struct Discovered {
+    pub subvols: Vec<PathBuf>,
+    /// Excluded subvolumes with the pattern that matched
+    pub excluded: Vec<(PathBuf, String)>,
+}
+
+/// Subvolumes directly inside `base` or inside another subvolume found there,
+/// except `snap_dir` and what it contains
+pub fn discover(
+    backend: &dyn SnapshotBackend,
+    base: &Path,
+    snap_dir: &Path,
+    exclude: &[glob::Pattern],
+) -> Result<Discovered> {
+    let mut found = Discovered::default();
+    let mut todo = vec![base.to_path_buf()];
+    while let Some(dir) = todo.pop() {
+        let children = backend
+            .list(&dir)
+            .context(format!("Failed to list subvolumes in {}", dir.display()))?;
+        for info in children {
+            let path = info.path;
+            if path.starts_with(snap_dir) {
+                continue;
+            }
+            // Nested subvolumes are not part of their parent's snapshots,
+            // so excluded ones are still searched
+            todo.push(path.clone());
+            match excluded_by(exclude, base, &path) {
+                Some(pattern) => {
+                    debug!("Excluding {} ({})", path.display(), pattern);
+                    found.excluded.push((path, pattern.to_string()));
+                }
+                None => found.subvols.push(path),
+            }
+        }
+    }
+    found.subvols.sort();
+    found.excluded.sort();
+    Ok(found)
+}
+
+/// First pattern matching `path` relative to `base` or its name
+pub fn excluded_by<'a>(
+    exclude: &'a [glob::Pattern],
+    base: &Path,
+    path: &Path,
+) -> Option<&'a glob::Pattern> {
+    let rel = path.strip_prefix(base).unwrap_or(path);
+    exclude.iter().find(|p| {
+        p.matches_path(rel) || path.file_name().is_some_and(|n| p.matches_path(n.as_ref()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use chrono::Local;
+
+    #[test]
+    fn skips_excluded_and_snapshot_subvolumes() {
+        let backend = MockBackend::leak();
+        let base = backend.scratch_dir();
+        for p in [
+            "home",
+            "home/.cache",
+            "docker",
+            "docker/abc",
+            "swap",
+            "snapshots",
+            "snapshots/home-1",
+        ] {
+            backend.add(&base.join(p), Local::now());
+        }
+        let exclude: Vec<_> = ["*/.cache", "docker/*", "swap"]
+            .iter()
+            .map(|p| glob::Pattern::new(p).unwrap())
+            .collect();
+
+        let found = discover(backend, &base, &base.join("snapshots"), &exclude).unwrap();
+
+        assert_eq!(found.subvols, [base.join("docker"), base.join("home")]);
+        let excluded: Vec<_> = found.excluded.iter().map(|(p, _)| p.clone()).collect();
+        assert_eq!(
+            excluded,
+            [
+                base.join("docker/abc"),
+                base.join("home/.cache"),
+                base.join("swap")
+            ]
+        );
+    }
+}
@@ -0,0 +1,92 @@
+//! Recognizing the subvolume layouts distributions install by default
+
+use crate::mounts::{self, Mount};
+use log::debug;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layout {
+    /// `@` and `@home` at the top level (Ubuntu, Debian, Arch, Mint)
+    At,
+    /// `/` booted from a snapper snapshot below `@/.snapshots` (openSUSE)
+    Snapper,
+    /// `root` and `home` at the top level (Fedora)
+    Fedora,
+    /// `/` on btrfs, in a layout not known here
+    Other,
+    /// `/` is not on btrfs
+    NotBtrfs,
+}
+
+impl Layout {
+    /// Layout of the running system, `Other` if the mounts cannot be read
+    pub fn current() -> Layout {
+        match mounts::read() {
+            Ok(mounts) => detect(&mounts),
+            Err(e) => {
+                debug!("{:#}", e);
+                Layout::Other
+            }
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Layout::At => "@/@home layout (Ubuntu, Debian, Arch)",
+            Layout::Snapper => "snapper layout (openSUSE)",
+            Layout::Fedora => "root/home layout (Fedora)",
+            Layout::Other => "unrecognized btrfs layout",
+            Layout::NotBtrfs => "root filesystem not on btrfs",
+        }
+    }
+}
+
+/// Layout given the mount table, judged by the subvolume mounted at `/`
+pub fn detect(mounts: &[Mount]) -> Layout {
+    // The last mount on a point hides the earlier ones
+    let Some(root) = mounts.iter().rev().find(|m| m.point == Path::new("/")) else {
+        return Layout::NotBtrfs;
+    };
+    if root.fstype != "btrfs" {
+        return Layout::NotBtrfs;
+    }
+    if root.root.starts_with("/@/.snapshots") {
+        Layout::Snapper
+    } else if root.root == Path::new("/@") {
+        Layout::At
+    } else if root.root == Path::new("/root") {
+        Layout::Fedora
+    } else {
+        Layout::Other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_layout_from_root_mount() {
+        let layout = |mountinfo: &str| detect(&mounts::parse(mountinfo.as_bytes()));
+        assert_eq!(
+            layout("22 1 0:21 /@ / rw - btrfs /dev/sda2 rw,subvol=/@\n"),
+            Layout::At
+        );
+        assert_eq!(
+            layout("22 1 0:21 /@/.snapshots/1/snapshot / rw - btrfs /dev/sda2 rw\n"),
+            Layout::Snapper
+        );
+        assert_eq!(
+            layout("22 1 0:21 /root / rw - btrfs /dev/sda2 rw,subvol=/root\n"),
+            Layout::Fedora
+        );
+        assert_eq!(
+            layout(
+                "22 1 8:2 / / rw - ext4 /dev/sda2 rw\n\
+                    23 22 0:21 /@home /home rw - btrfs /dev/sdb1 rw\n"
+            ),
+            Layout::NotBtrfs
+        );
+        assert_eq!(layout(""), Layout::NotBtrfs);
+    }
+}
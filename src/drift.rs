@@ -0,0 +1,201 @@
+use crate::backend::SnapshotBackend;
+use crate::config::Config;
+use crate::manifest::{self, Manifest};
+use crate::{convert, notify, os_path, run};
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// Most file names listed in a drift summary
+const MAX_LISTED: usize = 50;
+
+/// Files added, removed and changed between two trees, relative to them
+#[derive(Debug, Default)]
+pub struct Diff {
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub changed: Vec<PathBuf>,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} added, {} removed, {} changed",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len()
+        )?;
+        let lines = [
+            ('+', &self.added),
+            ('-', &self.removed),
+            ('~', &self.changed),
+        ]
+        .into_iter()
+        .flat_map(|(mark, paths)| paths.iter().map(move |p| (mark, p)));
+        for (mark, path) in lines.clone().take(MAX_LISTED) {
+            writeln!(f, "{} {}", mark, path.display())?;
+        }
+        if lines.count() > MAX_LISTED {
+            writeln!(f, "...")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare two trees by file type, size, mode, owner and modification time
+pub fn diff(old: &Path, new: &Path) -> Result<Diff> {
+    let (old, new) = (files(old)?, files(new)?);
+    let mut diff = Diff::default();
+    for (path, meta) in &new {
+        match old.get(path) {
+            None => diff.added.push(path.clone()),
+            Some(old) if old != meta => diff.changed.push(path.clone()),
+            Some(_) => {}
+        }
+    }
+    diff.removed = old
+        .keys()
+        .filter(|p| !new.contains_key(*p))
+        .cloned()
+        .collect();
+    Ok(diff)
+}
+
+/// What [`diff`] compares of a file
+type Stat = (u32, u64, u32, u32, i64, i64);
+
+/// Everything below `root` except directories, keyed by relative path.
+/// Directories only count through what they contain.
+fn files(root: &Path) -> Result<BTreeMap<PathBuf, Stat>> {
+    let mut files = BTreeMap::new();
+    let mut todo = vec![root.to_path_buf()];
+    while let Some(dir) = todo.pop() {
+        let entries = fs::read_dir(&dir).context(format!("Failed to read {}", dir.display()))?;
+        for entry in entries {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+            let path = entry.path();
+            if meta.is_dir() {
+                todo.push(path);
+                continue;
+            }
+            let rel = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+            // Snapshots carry an .ignore marker the live tree does not have
+            if rel == Path::new(".ignore") {
+                continue;
+            }
+            let stat = (
+                meta.mode(),
+                meta.len(),
+                meta.uid(),
+                meta.gid(),
+                meta.mtime(),
+                meta.mtime_nsec(),
+            );
+            files.insert(rel, stat);
+        }
+    }
+    Ok(files)
+}
+
+/// Snapshot a tree that is not a subvolume: a new subvolume at `snap_path`
+/// holding a reflink copy of `path`, recorded like a snapshot of it
+pub fn copy(
+    backend: &dyn SnapshotBackend,
+    path: &Path,
+    snap_path: &Path,
+    trigger: &str,
+) -> Result<()> {
+    backend.create(snap_path).context(format!(
+        "Failed to create subvolume {}",
+        snap_path.display()
+    ))?;
+    if let Err(e) = convert::copy_contents(path, snap_path) {
+        let _ = backend.delete(snap_path);
+        return Err(e);
+    }
+    let info = backend.info(snap_path).context(format!(
+        "Failed to get info for snapshot {}",
+        snap_path.display()
+    ))?;
+    if let (Some(snap_dir), Some(name)) = (snap_path.parent(), snap_path.file_name()) {
+        let mut entry = manifest::Entry::new(path, &info);
+        entry.trigger = Some(trigger.to_string());
+        entry.created_by_run = Some(run::id().to_string());
+        Manifest::record(snap_dir, &os_path::name(name), entry)?;
+    }
+    Ok(())
+}
+
+/// Print what changed in `path` between the `previous` and `snapshot`, and
+/// mail it if `drift` is in `notify.email.on`
+pub fn report(config: &Config, path: &Path, previous: &Path, snapshot: &Path) -> Result<()> {
+    let diff = diff(previous, snapshot)?;
+    if diff.is_empty() {
+        return Ok(());
+    }
+    let message = format!(
+        "Changes in {} since {}: {}",
+        path.display(),
+        previous.display(),
+        diff
+    );
+    print!("{}", message);
+    let subject = format!(
+        "btrsnap: {} changed on {}",
+        path.display(),
+        notify::hostname()
+    );
+    notify::event(config, "drift", &subject, &message);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+
+    #[test]
+    fn lists_added_removed_and_changed_files() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let (old, new) = (dir.join("old"), dir.join("new"));
+        for tree in [&old, &new] {
+            fs::create_dir_all(tree.join("ssh")).unwrap();
+            fs::write(tree.join("hosts"), "127.0.0.1 localhost\n").unwrap();
+        }
+        fs::write(old.join("ssh/sshd_config"), "PermitRootLogin no\n").unwrap();
+        fs::write(new.join("ssh/sshd_config"), "PermitRootLogin yes\n").unwrap();
+        fs::write(old.join("motd"), "hi\n").unwrap();
+        fs::write(new.join("fstab"), "\n").unwrap();
+        fs::write(new.join(".ignore"), "").unwrap();
+        // Same content and times, only created separately
+        let mtime = fs::metadata(old.join("hosts")).unwrap().modified().unwrap();
+        fs::File::options()
+            .write(true)
+            .open(new.join("hosts"))
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+
+        let diff = diff(&old, &new).unwrap();
+
+        assert_eq!(diff.added, [PathBuf::from("fstab")]);
+        assert_eq!(diff.removed, [PathBuf::from("motd")]);
+        assert_eq!(diff.changed, [PathBuf::from("ssh/sshd_config")]);
+        assert!(
+            diff.to_string()
+                .starts_with("1 added, 1 removed, 1 changed\n")
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use crate::backend::SnapshotBackend;
+use crate::config::Config;
+use crate::{create, utils};
+use anyhow::{Context, Result};
+use chrono::Local;
+use humantime::Duration as HumanDuration;
+use log::debug;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(clap::Parser)]
+pub struct Exists {
+    /// Subvolume name as in snapshot names (e.g. home), or its path
+    #[arg(short = 'v', long)]
+    pub subvol: String,
+    /// Only count snapshots younger than this (e.g. 24h)
+    #[arg(long)]
+    pub newer_than: Option<HumanDuration>,
+    /// Snapshot dir to scan
+    #[arg(short = 'd', long, value_parser = utils::parse_path)]
+    pub snap_dir: Option<PathBuf>,
+}
+
+/// No snapshot matched; `main` exits with status 1 without an error message
+/// for it unless it ends a chain of commands
+#[derive(Debug)]
+pub struct NoMatch(String);
+
+impl fmt::Display for NoMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "No matching snapshot of {}", self.0)
+    }
+}
+
+impl std::error::Error for NoMatch {}
+
+impl Exists {
+    /// Fails with [`NoMatch`] if there is no matching snapshot, so it can be
+    /// used in shell conditionals
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        let snap_dir =
+            utils::resolve_snap_dir(backend, self.snap_dir.clone(), config.snap_dir.clone())?;
+        let name = create::name_arg(&self.subvol, config.name_parents);
+        if !self.matching(backend, &snap_dir, &name)? {
+            return Err(NoMatch(name).into());
+        }
+        Ok(())
+    }
+
+    fn matching(&self, backend: &dyn SnapshotBackend, snap_dir: &Path, name: &str) -> Result<bool> {
+        let snapshots = backend.list(snap_dir).context(format!(
+            "Failed to list subvolumes in {}",
+            snap_dir.display()
+        ))?;
+        let Some(newest) = create::newest_snapshot(&snapshots, name) else {
+            debug!("No snapshot of {}", name);
+            return Ok(false);
+        };
+        debug!("Newest snapshot of {}: {}", name, newest.path.display());
+        match self.newer_than {
+            Some(age) => Ok(Local::now() - newest.otime < chrono::Duration::from_std(age.into())?),
+            None => Ok(true),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use std::time::Duration;
+
+    #[test]
+    fn matches_by_name_and_age() {
+        let backend = MockBackend::leak();
+        let snap_dir = backend.scratch_dir();
+        backend.add(
+            &snap_dir.join("home-1"),
+            Local::now() - Duration::from_secs(7200),
+        );
+        let exists = |subvol: &str, newer_than: Option<&str>| Exists {
+            subvol: subvol.to_string(),
+            newer_than: newer_than.map(|s| s.parse().unwrap()),
+            snap_dir: None,
+        };
+
+        let check = |e: Exists| e.matching(backend, &snap_dir, &e.subvol).unwrap();
+        assert!(check(exists("home", None)));
+        assert!(check(exists("home", Some("3h"))));
+        assert!(!check(exists("home", Some("1h"))));
+        assert!(!check(exists("var", None)));
+
+        let missing = Exists {
+            snap_dir: Some(snap_dir.clone()),
+            ..exists("var", None)
+        };
+        let e = missing.execute(backend, Config::default()).unwrap_err();
+        assert!(e.is::<NoMatch>());
+    }
+}
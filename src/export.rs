@@ -0,0 +1,301 @@
+//! Read-only bind mounts of the newest snapshots of a subvolume below a
+//! stable directory, e.g. for an NFS export of its history. Exports are
+//! kept in the state dir of the snapshot dir, so `create`, `delete` and
+//! `cleanup` move the mounts along as snapshots rotate, and release a
+//! snapshot's mount before deleting it.
+
+use crate::backend::{SnapshotBackend, SubvolInfo};
+use crate::config::Config;
+use crate::warnings::warning;
+use crate::{create, mounts, state, utils};
+use anyhow::{Context, Result};
+use chrono::DateTime;
+use log::{debug, info};
+use nix::mount::{MntFlags, MsFlags, mount, umount2};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const FILE: &str = "exports.json";
+
+#[derive(clap::Parser)]
+pub struct Export {
+    /// Subvolume to export, by path or snapshot name prefix
+    pub subvol: String,
+    /// Directory for the mounts, each in `<dir>/<name>/<UTC time>`
+    #[arg(long)]
+    pub to: PathBuf,
+    /// Number of snapshots to keep mounted
+    #[arg(long, default_value_t = 10)]
+    pub keep: usize,
+    /// Unmount the snapshots and stop exporting
+    #[arg(long, conflicts_with = "keep")]
+    pub remove: bool,
+    #[arg(short = 'd', long, value_parser = utils::parse_path)]
+    pub snap_dir: Option<PathBuf>,
+}
+
+/// An exported subvolume and what is mounted for it
+#[derive(Deserialize, Serialize)]
+struct Exported {
+    name: String,
+    to: PathBuf,
+    keep: usize,
+    /// Snapshot names by mount directory below `<to>/<name>`
+    #[serde(default)]
+    mounted: BTreeMap<String, String>,
+}
+
+impl Exported {
+    fn dir(&self) -> PathBuf {
+        self.to.join(&self.name)
+    }
+}
+
+impl Export {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        let snap_dir = utils::resolve_snap_dir(backend, self.snap_dir, config.snap_dir)?;
+        let name = create::name_arg(&self.subvol, config.name_parents);
+        let to = match self.to.canonicalize() {
+            Ok(to) => to,
+            Err(_) => self.to.clone(),
+        };
+        let mut exports = load(&snap_dir)?;
+        let pos = exports.iter().position(|e| e.name == name && e.to == to);
+        if self.remove {
+            let Some(pos) = pos else {
+                println!("{} is not exported to {}", name, to.display());
+                return Ok(());
+            };
+            let mut export = exports.remove(pos);
+            for dir in std::mem::take(&mut export.mounted).into_keys() {
+                unmount(&export.dir().join(dir))?;
+            }
+            let _ = fs::remove_dir(export.dir());
+            save(&snap_dir, &exports)?;
+            println!("Stopped exporting {} to {}", name, to.display());
+            return Ok(());
+        }
+        let pos = pos.unwrap_or_else(|| {
+            exports.push(Exported {
+                name: name.clone(),
+                to,
+                keep: self.keep,
+                mounted: BTreeMap::new(),
+            });
+            exports.len() - 1
+        });
+        exports[pos].keep = self.keep;
+        let result = sync(backend, &snap_dir, &mut exports[pos]);
+        // Saved either way, the mounts that worked must be released later
+        save(&snap_dir, &exports)?;
+        result?;
+        let export = &exports[pos];
+        println!(
+            "Exported {} snapshots of {} in {}",
+            export.mounted.len(),
+            name,
+            export.dir().display()
+        );
+        Ok(())
+    }
+}
+
+/// Update the exports of the subvolume called `name` to its newest
+/// snapshots, after snapshots were created or deleted. Failures are
+/// warnings.
+pub fn refresh(backend: &dyn SnapshotBackend, snap_dir: &Path, name: &str) {
+    let result = load(snap_dir).and_then(|mut exports| {
+        if !exports.iter().any(|e| e.name == name) {
+            return Ok(());
+        }
+        for export in exports.iter_mut().filter(|e| e.name == name) {
+            if let Err(e) = sync(backend, snap_dir, export) {
+                warning!(
+                    "Failed to update export to {}: {:#}",
+                    export.to.display(),
+                    e
+                );
+            }
+        }
+        save(snap_dir, &exports)
+    });
+    if let Err(e) = result {
+        warning!("Failed to update exports of {}: {:#}", name, e);
+    }
+}
+
+/// Unmount the exports of `snapshot` so it can be deleted
+pub fn release(snapshot: &Path) -> Result<()> {
+    let (Some(snap_dir), Some(name)) = (snapshot.parent(), utils::file_name(snapshot)) else {
+        return Ok(());
+    };
+    let mut exports = load(snap_dir)?;
+    let mut changed = false;
+    for export in &mut exports {
+        let dirs: Vec<String> = export
+            .mounted
+            .iter()
+            .filter(|(_, s)| **s == name)
+            .map(|(dir, _)| dir.clone())
+            .collect();
+        for dir in dirs {
+            unmount(&export.dir().join(&dir))?;
+            export.mounted.remove(&dir);
+            changed = true;
+        }
+    }
+    if changed {
+        save(snap_dir, &exports)?;
+    }
+    Ok(())
+}
+
+/// Mount the newest `keep` snapshots of the export, unmounting the others
+fn sync(backend: &dyn SnapshotBackend, snap_dir: &Path, export: &mut Exported) -> Result<()> {
+    let snapshots = backend.list(snap_dir).context(format!(
+        "Failed to list subvolumes in {}",
+        snap_dir.display()
+    ))?;
+    let wanted = plan(&snapshots, &export.name, export.keep);
+    let points: BTreeSet<PathBuf> = mounts::read()?.into_iter().map(|m| m.point).collect();
+    let dir = export.dir();
+    for (mount_dir, snapshot) in export.mounted.clone() {
+        if wanted.get(&mount_dir) != Some(&snapshot) {
+            unmount(&dir.join(&mount_dir))?;
+            export.mounted.remove(&mount_dir);
+        }
+    }
+    for (mount_dir, snapshot) in wanted {
+        let point = dir.join(&mount_dir);
+        if export.mounted.get(&mount_dir) == Some(&snapshot) && points.contains(&point) {
+            continue;
+        }
+        fs::create_dir_all(&point).context(format!("Failed to create {}", point.display()))?;
+        bind_read_only(&snap_dir.join(&snapshot), &point)?;
+        export.mounted.insert(mount_dir, snapshot);
+    }
+    Ok(())
+}
+
+/// Mount directories for the newest `keep` snapshots of `name`, named
+/// after their UTC creation time so they sort and never collide across
+/// DST changes
+fn plan(snapshots: &[SubvolInfo], name: &str, keep: usize) -> BTreeMap<String, String> {
+    let mut matching: Vec<&SubvolInfo> = snapshots
+        .iter()
+        .filter(|s| {
+            utils::parse_snapshot_name(&utils::snapshot_name(s)).is_some_and(|(n, _)| n == name)
+        })
+        .collect();
+    matching.sort_by_key(|s| std::cmp::Reverse(s.otime));
+    matching
+        .into_iter()
+        .take(keep)
+        .filter_map(|s| {
+            let snapshot = utils::snapshot_name(s);
+            let (_, ts) = utils::parse_snapshot_name(&snapshot)?;
+            let mut dir = DateTime::from_timestamp(ts, 0)?
+                .format("%Y-%m-%d_%H-%M-%SZ")
+                .to_string();
+            // The counter of snapshots taken within the same second
+            if let Some((_, n)) = snapshot
+                .rsplit_once('-')
+                .and_then(|(_, t)| t.split_once('.'))
+            {
+                dir = format!("{}.{}", dir, n);
+            }
+            Some((dir, snapshot))
+        })
+        .collect()
+}
+
+fn bind_read_only(snapshot: &Path, point: &Path) -> Result<()> {
+    info!("Mounting {} on {}", snapshot.display(), point.display());
+    let none = None::<&str>;
+    mount(Some(snapshot), point, none, MsFlags::MS_BIND, none).context(format!(
+        "Failed to mount {} on {}",
+        snapshot.display(),
+        point.display()
+    ))?;
+    // Bind mounts take the read-only flag only on a remount
+    let flags = MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY;
+    if let Err(e) = mount(none, point, none, flags, none) {
+        let _ = umount2(point, MntFlags::MNT_DETACH);
+        return Err(e).context(format!("Failed to make {} read-only", point.display()));
+    }
+    Ok(())
+}
+
+fn unmount(point: &Path) -> Result<()> {
+    debug!("Unmounting {}", point.display());
+    match umount2(point, MntFlags::MNT_DETACH) {
+        // Not mounted (any more), e.g. after a reboot
+        Ok(()) | Err(nix::errno::Errno::EINVAL) | Err(nix::errno::Errno::ENOENT) => {}
+        Err(e) => return Err(e).context(format!("Failed to unmount {}", point.display())),
+    }
+    let _ = fs::remove_dir(point);
+    Ok(())
+}
+
+fn load(snap_dir: &Path) -> Result<Vec<Exported>> {
+    let path = state::dir(snap_dir).join(FILE);
+    match fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content)
+            .context(format!("Invalid exports file {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e).context(format!("Failed to read {}", path.display())),
+    }
+}
+
+fn save(snap_dir: &Path, exports: &[Exported]) -> Result<()> {
+    let dir = state::dir(snap_dir);
+    fs::create_dir_all(&dir).context(format!("Failed to create {}", dir.display()))?;
+    let path = dir.join(FILE);
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(exports)?)
+        .context(format!("Failed to write {}", tmp.display()))?;
+    fs::rename(&tmp, &path).context(format!("Failed to replace {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use chrono::Local;
+
+    #[test]
+    fn plans_mounts_of_the_newest_snapshots() {
+        let backend = MockBackend::leak();
+        let snap_dir = backend.scratch_dir();
+        let ts = 1700000000;
+        for (n, name) in [
+            "home-1699990000",
+            "home-1700000000",
+            "home-1700000000.1",
+            "var-1",
+        ]
+        .iter()
+        .enumerate()
+        {
+            let otime = Local::now() + chrono::Duration::seconds(n as i64);
+            backend.add(&snap_dir.join(name), otime);
+        }
+        let snapshots = backend.list(&snap_dir).unwrap();
+
+        let planned = plan(&snapshots, "home", 2);
+        assert_eq!(
+            planned.into_iter().collect::<Vec<_>>(),
+            [
+                ("2023-11-14_22-13-20Z".to_string(), format!("home-{}", ts)),
+                (
+                    "2023-11-14_22-13-20Z.1".to_string(),
+                    format!("home-{}.1", ts)
+                ),
+            ]
+        );
+        assert!(plan(&snapshots, "srv", 2).is_empty());
+        assert!(release(&snap_dir.join("home-1699990000")).is_ok());
+    }
+}
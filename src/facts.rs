@@ -0,0 +1,125 @@
+use crate::notify;
+use anyhow::{Result, bail};
+use log::debug;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+/// Placeholders a description template may use
+const FACTS: &[&str] = &[
+    "subvol", "hostname", "kernel", "uptime", "packages", "version",
+];
+
+/// Package managers tried for `{packages}`, the first one found is counted
+const PACKAGE_QUERIES: &[&[&str]] = &[
+    &["rpm", "-qa"],
+    &["dpkg-query", "-W", "-f", ".\n"],
+    &["pacman", "-Qq"],
+    &["apk", "info"],
+    &["xbps-query", "-l"],
+];
+
+/// Check that `template` only uses known placeholders
+pub fn parse_template(template: &str) -> Result<String> {
+    for name in placeholders(template) {
+        if !FACTS.contains(&name) {
+            bail!(
+                "Unknown placeholder {{{}}}, expected one of {}",
+                name,
+                FACTS.join(", ")
+            );
+        }
+    }
+    Ok(template.to_string())
+}
+
+/// `{name}` placeholders in `template`
+fn placeholders(template: &str) -> impl Iterator<Item = &str> {
+    template
+        .split('{')
+        .skip(1)
+        .filter_map(|s| s.split_once('}').map(|(name, _)| name))
+}
+
+/// System facts captured once per run for the placeholders a template uses
+pub struct Facts {
+    values: BTreeMap<&'static str, String>,
+}
+
+impl Facts {
+    pub fn gather(template: &str) -> Self {
+        let used: Vec<&str> = placeholders(template).collect();
+        let mut values = BTreeMap::new();
+        for &name in FACTS.iter().filter(|f| used.contains(f)) {
+            let value = match name {
+                "hostname" => notify::hostname(),
+                "kernel" => kernel(),
+                "uptime" => uptime(),
+                "packages" => packages(),
+                "version" => env!("CARGO_PKG_VERSION").to_string(),
+                _ => continue,
+            };
+            debug!("Fact {} = {}", name, value);
+            values.insert(name, value);
+        }
+        Facts { values }
+    }
+
+    /// `template` with its placeholders replaced, for a snapshot of `subvol`
+    pub fn render(&self, template: &str, subvol: &Path) -> String {
+        let mut out = template.replace("{subvol}", &subvol.display().to_string());
+        for (name, value) in &self.values {
+            out = out.replace(&format!("{{{}}}", name), value);
+        }
+        out
+    }
+}
+
+fn kernel() -> String {
+    fs::read_to_string("/proc/sys/kernel/osrelease")
+        .map(|s| s.trim().to_string())
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+fn uptime() -> String {
+    fs::read_to_string("/proc/uptime")
+        .ok()
+        .and_then(|s| s.split_whitespace().next()?.parse::<f64>().ok())
+        .map(|secs| humantime::format_duration(Duration::from_secs(secs as u64)).to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn packages() -> String {
+    for query in PACKAGE_QUERIES {
+        if let Ok(output) = Command::new(query[0]).args(&query[1..]).output()
+            && output.status.success()
+        {
+            let count = output
+                .stdout
+                .split(|&b| b == b'\n')
+                .filter(|l| !l.is_empty())
+                .count();
+            return format!("{} ({})", count, query[0]);
+        }
+    }
+    "unknown".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_only_known_placeholders() {
+        assert!(parse_template("{subvol} on {kernel}").is_ok());
+        assert!(parse_template("{kernal}").is_err());
+
+        let facts = Facts::gather("{subvol} by btrsnap {version}");
+        assert_eq!(
+            facts.render("{subvol} by btrsnap {version}", Path::new("/home")),
+            format!("/home by btrsnap {}", env!("CARGO_PKG_VERSION"))
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use crate::backend::{SnapshotBackend, SubvolInfo};
+use crate::config::Config;
+use crate::{create, utils};
+use anyhow::{Context, Result};
+use glob::Pattern;
+use log::debug;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// Files are searched in pieces of this size
+const CHUNK: usize = 1 << 20;
+
+#[derive(clap::Parser)]
+pub struct Find {
+    /// File name glob, e.g. '*.kdbx'
+    #[arg(long)]
+    pub name: Pattern,
+    /// Only search snapshots of this subvolume (name as in snapshot names,
+    /// or its path)
+    #[arg(short = 'v', long)]
+    pub subvol: Option<String>,
+    /// Only list files containing this text
+    #[arg(long)]
+    pub content: Option<String>,
+    /// Snapshot dir to search
+    #[arg(short = 'd', long, value_parser = utils::parse_path)]
+    pub snap_dir: Option<PathBuf>,
+}
+
+/// What identifies a version of a file or directory across snapshots:
+/// snapshots keep inode numbers, and any change updates the mtime
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Version {
+    ino: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+    size: u64,
+}
+
+impl Version {
+    fn of(meta: &fs::Metadata) -> Self {
+        Version {
+            ino: meta.ino(),
+            mtime: meta.mtime(),
+            mtime_nsec: meta.mtime_nsec(),
+            size: meta.size(),
+        }
+    }
+}
+
+/// A name in a directory listing. Files can change in place without
+/// touching their directory, so their version is looked up when needed.
+#[derive(Clone)]
+struct DirEntry {
+    name: OsString,
+    is_dir: bool,
+}
+
+/// Listings and content checks of earlier snapshots, reused where a
+/// directory or file is unchanged
+#[derive(Default)]
+struct Cache {
+    dirs: HashMap<PathBuf, (Version, Vec<DirEntry>)>,
+    contents: HashMap<Version, bool>,
+    reused: usize,
+}
+
+impl Find {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        let snap_dir =
+            utils::resolve_snap_dir(backend, self.snap_dir.clone(), config.snap_dir.clone())?;
+        let subvol = self
+            .subvol
+            .as_ref()
+            .map(|s| create::name_arg(s, config.name_parents));
+        let mut snapshots: Vec<SubvolInfo> = backend
+            .list(&snap_dir)
+            .context(format!(
+                "Failed to list subvolumes in {}",
+                snap_dir.display()
+            ))?
+            .into_iter()
+            .filter(|s| match &subvol {
+                Some(subvol) => utils::parse_snapshot_name(&utils::snapshot_name(s))
+                    .is_some_and(|(prefix, _)| prefix == **subvol),
+                None => true,
+            })
+            .collect();
+        snapshots.sort_by_key(|s| s.otime);
+
+        let mut cache = Cache::default();
+        let mut found = 0;
+        for snapshot in &snapshots {
+            let name = utils::snapshot_name(snapshot);
+            for path in self.search(&snapshot.path, &mut cache)? {
+                println!("{}\t/{}", name, path.display());
+                found += 1;
+            }
+        }
+        debug!("Reused {} unchanged listings", cache.reused);
+        println!("{} matches in {} snapshots", found, snapshots.len());
+        Ok(())
+    }
+
+    /// Matching files in the snapshot at `root`, relative to it
+    fn search(&self, root: &Path, cache: &mut Cache) -> Result<Vec<PathBuf>> {
+        let mut matches = vec![];
+        let mut todo = vec![PathBuf::new()];
+        while let Some(rel) = todo.pop() {
+            let dir = root.join(&rel);
+            let entries = listing(&dir, &rel, cache)?;
+            for entry in entries {
+                let path = rel.join(&entry.name);
+                if entry.is_dir {
+                    todo.push(path);
+                    continue;
+                }
+                if !self.name.matches_path(Path::new(&entry.name)) {
+                    continue;
+                }
+                let hit = match &self.content {
+                    Some(text) => {
+                        let file = root.join(&path);
+                        match fs::symlink_metadata(&file) {
+                            Ok(meta) => *cache
+                                .contents
+                                .entry(Version::of(&meta))
+                                .or_insert_with(|| contains(&file, text.as_bytes())),
+                            Err(e) => {
+                                debug!("Skipping {}: {}", file.display(), e);
+                                false
+                            }
+                        }
+                    }
+                    None => true,
+                };
+                if hit {
+                    matches.push(path);
+                }
+            }
+        }
+        matches.sort();
+        Ok(matches)
+    }
+}
+
+/// Entries of `dir`, from the cache if the directory is unchanged since an
+/// earlier snapshot
+fn listing(dir: &Path, rel: &Path, cache: &mut Cache) -> Result<Vec<DirEntry>> {
+    let version = Version::of(&fs::symlink_metadata(dir)?);
+    if let Some((cached, entries)) = cache.dirs.get(rel)
+        && *cached == version
+    {
+        cache.reused += 1;
+        return Ok(entries.clone());
+    }
+    let mut entries = vec![];
+    for entry in fs::read_dir(dir).context(format!("Failed to read {}", dir.display()))? {
+        let entry = entry?;
+        entries.push(DirEntry {
+            name: entry.file_name(),
+            is_dir: entry.file_type()?.is_dir(),
+        });
+    }
+    cache
+        .dirs
+        .insert(rel.to_path_buf(), (version, entries.clone()));
+    Ok(entries)
+}
+
+fn contains(path: &Path, text: &[u8]) -> bool {
+    match File::open(path).and_then(|file| scan(file, text)) {
+        Ok(found) => found,
+        Err(e) => {
+            debug!("Skipping {}: {}", path.display(), e);
+            false
+        }
+    }
+}
+
+/// Whether `reader` yields `text`, read a chunk at a time. The end of each
+/// chunk is kept for the next one, to find matches across the border.
+fn scan(mut reader: impl Read, text: &[u8]) -> io::Result<bool> {
+    if text.is_empty() {
+        return Ok(true);
+    }
+    let overlap = text.len() - 1;
+    let mut buf = vec![0; CHUNK + overlap];
+    let mut kept = 0;
+    loop {
+        let filled = match reader.read(&mut buf[kept..])? {
+            0 => return Ok(false),
+            n => kept + n,
+        };
+        if buf[..filled].windows(text.len()).any(|w| w == text) {
+            return Ok(true);
+        }
+        kept = overlap.min(filled);
+        buf.copy_within(filled - kept..filled, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+
+    #[test]
+    fn finds_files_by_name_and_content() {
+        let backend = MockBackend::leak();
+        let snap_dir = backend.scratch_dir();
+        let (old, new) = (snap_dir.join("home-1"), snap_dir.join("home-2"));
+        for (snapshot, files) in [
+            (
+                &old,
+                &[("docs/a.kdbx", "secret"), ("docs/b.kdbx", "other")][..],
+            ),
+            (&new, &[("docs/b.kdbx", "other")][..]),
+        ] {
+            backend.add(snapshot, chrono::Local::now());
+            for (file, content) in files {
+                let path = snapshot.join(file);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, content).unwrap();
+            }
+        }
+        let find = |content: Option<&str>| Find {
+            name: Pattern::new("*.kdbx").unwrap(),
+            subvol: Some("home".to_string()),
+            content: content.map(String::from),
+            snap_dir: None,
+        };
+
+        let mut cache = Cache::default();
+        let by_name = find(None);
+        assert_eq!(
+            by_name.search(&old, &mut cache).unwrap(),
+            [PathBuf::from("docs/a.kdbx"), PathBuf::from("docs/b.kdbx")]
+        );
+        assert_eq!(
+            by_name.search(&new, &mut cache).unwrap(),
+            [PathBuf::from("docs/b.kdbx")]
+        );
+        let by_content = find(Some("secret"));
+        assert_eq!(
+            by_content.search(&old, &mut Cache::default()).unwrap(),
+            [PathBuf::from("docs/a.kdbx")]
+        );
+    }
+
+    #[test]
+    fn rechecks_files_changed_in_place() {
+        let backend = MockBackend::leak();
+        let snapshot = backend.scratch_dir().join("home-1");
+        fs::create_dir_all(snapshot.join("docs")).unwrap();
+        fs::write(snapshot.join("docs/a.kdbx"), "secret").unwrap();
+        let find = Find {
+            name: Pattern::new("*.kdbx").unwrap(),
+            subvol: None,
+            content: Some("secret".to_string()),
+            snap_dir: None,
+        };
+
+        let mut cache = Cache::default();
+        assert_eq!(find.search(&snapshot, &mut cache).unwrap().len(), 1);
+        // Leaves the directory's mtime alone, so its listing is reused
+        fs::write(snapshot.join("docs/a.kdbx"), "public").unwrap();
+        assert!(find.search(&snapshot, &mut cache).unwrap().is_empty());
+        assert_eq!(cache.reused, 2);
+    }
+
+    #[test]
+    fn scans_across_chunk_borders() {
+        // The first read ends within "secret"
+        let mut content = vec![b'x'; CHUNK + 2];
+        content.extend_from_slice(b"secret and more");
+        assert!(scan(&content[..], b"secret").unwrap());
+        assert!(!scan(&content[..], b"secrets").unwrap());
+    }
+}
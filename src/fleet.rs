@@ -0,0 +1,172 @@
+use anyhow::{Context, Result, bail};
+use log::{debug, info};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::thread;
+use std::time::Instant;
+
+#[derive(clap::Parser)]
+pub struct Fleet {
+    /// Hosts file (TOML) listing the machines to run on
+    #[arg(long)]
+    pub hosts: PathBuf,
+    /// Print the aggregated report as JSON
+    #[arg(long)]
+    pub json: bool,
+    /// btrsnap arguments to run on every host (e.g. `list`)
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+    pub args: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct HostsFile {
+    host: Vec<Host>,
+}
+
+#[derive(Deserialize)]
+struct Host {
+    name: String,
+    /// SSH destination, defaults to `name`
+    ssh: Option<String>,
+    port: Option<u16>,
+    /// Remote command, e.g. `sudo btrsnap`
+    command: Option<String>,
+}
+
+/// Outcome on one host, printed by `fleet --json`
+#[derive(JsonSchema, Serialize)]
+pub struct HostResult {
+    host: String,
+    success: bool,
+    exit_code: Option<i32>,
+    seconds: f64,
+    stdout: String,
+    stderr: String,
+}
+
+impl Fleet {
+    pub fn execute(self) -> Result<()> {
+        let content = fs::read_to_string(&self.hosts).context(format!(
+            "Failed to read hosts file: {}",
+            self.hosts.display()
+        ))?;
+        let hosts: HostsFile = toml::from_str(&content)
+            .context(format!("Invalid hosts file: {}", self.hosts.display()))?;
+
+        let request = quote(&self.args)?;
+        info!("Running `{}` on {} hosts", request, hosts.host.len());
+        let results: Vec<HostResult> = thread::scope(|s| {
+            let handles: Vec<_> = hosts
+                .host
+                .iter()
+                .map(|host| s.spawn(|| run_on_host(host, &request)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("host thread panicked"))
+                .collect()
+        });
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&results)?);
+        } else {
+            print_table(&results);
+        }
+
+        let failed = results.iter().filter(|r| !r.success).count();
+        if failed > 0 {
+            bail!("{} of {} hosts failed", failed, results.len());
+        }
+        Ok(())
+    }
+}
+
+/// `args` as one shell command line, each quoted to reach the remote
+/// btrsnap as given: ssh hands the remote shell a single string
+fn quote(args: &[String]) -> Result<String> {
+    let quoted: Vec<_> = args
+        .iter()
+        .map(|arg| shlex::try_quote(arg).context(format!("Can't pass {:?} over SSH", arg)))
+        .collect::<Result<_>>()?;
+    Ok(quoted.join(" "))
+}
+
+/// Run `request`, quoted btrsnap arguments, with the host's remote command
+fn run_on_host(host: &Host, request: &str) -> HostResult {
+    let dest = host.ssh.as_deref().unwrap_or(&host.name);
+    let remote = host.command.as_deref().unwrap_or("btrsnap");
+    let mut cmd = Command::new("ssh");
+    cmd.args(["-o", "BatchMode=yes"]);
+    if let Some(port) = host.port {
+        cmd.arg("-p").arg(port.to_string());
+    }
+    cmd.arg(dest).arg(format!("{} {}", remote, request));
+    debug!("Running {:?}", cmd);
+
+    let start = Instant::now();
+    let output = cmd.output();
+    let seconds = start.elapsed().as_secs_f64();
+    match output {
+        Ok(out) => HostResult {
+            host: host.name.clone(),
+            success: out.status.success(),
+            exit_code: out.status.code(),
+            seconds,
+            stdout: String::from_utf8_lossy(&out.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&out.stderr).into_owned(),
+        },
+        Err(e) => HostResult {
+            host: host.name.clone(),
+            success: false,
+            exit_code: None,
+            seconds,
+            stdout: String::new(),
+            stderr: format!("Failed to run ssh: {}", e),
+        },
+    }
+}
+
+fn print_table(results: &[HostResult]) {
+    let width = results
+        .iter()
+        .map(|r| r.host.len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    println!("{:<width$}  {:<6}  {:>7}  OUTPUT", "HOST", "RESULT", "TIME");
+    for r in results {
+        let status = if r.success { "ok" } else { "failed" };
+        // Show the last line of output, which is the most telling for errors
+        let text = if r.success { &r.stdout } else { &r.stderr };
+        let summary = text.lines().rfind(|l| !l.trim().is_empty()).unwrap_or("");
+        println!(
+            "{:<width$}  {:<6}  {:>6.1}s  {}",
+            r.host, status, r.seconds, summary
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_each_argument_for_the_remote_shell() {
+        let args = [
+            "create",
+            "--description",
+            "before upgrade; rm -rf /",
+            "-v",
+            "/srv/a b",
+        ];
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        assert_eq!(
+            quote(&args).unwrap(),
+            "create --description 'before upgrade; rm -rf /' -v '/srv/a b'"
+        );
+        assert!(quote(&["a\0b".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+use crate::backend::{SnapshotBackend, SubvolInfo};
+use crate::config::Config;
+use crate::manifest::{Entry, Manifest, STATE_DIR};
+use crate::warnings::warning;
+use crate::{create, utils};
+use anyhow::{Context, Result};
+use log::{debug, info};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(clap::Parser)]
+pub struct Gc {
+    /// Snapshot dir to reconcile
+    #[arg(short = 'd', long, value_parser = utils::parse_path)]
+    pub snap_dir: Option<PathBuf>,
+    /// Record unknown snapshots of configured subvolumes in the manifest
+    #[arg(long)]
+    pub adopt: bool,
+    /// Only report what would change
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+}
+
+impl Gc {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        let snap_dir = utils::resolve_snap_dir(backend, self.snap_dir, config.snap_dir)?;
+        info!("Reconciling {}", snap_dir.display());
+        let on_disk: BTreeMap<String, SubvolInfo> = backend
+            .list(&snap_dir)
+            .context(format!(
+                "Failed to list subvolumes in {}",
+                snap_dir.display()
+            ))?
+            .into_iter()
+            .map(|info| (utils::snapshot_name(&info), info))
+            .collect();
+        let _lock = (!self.dry_run)
+            .then(|| Manifest::lock(&snap_dir))
+            .transpose()?;
+        let mut manifest = Manifest::load(&snap_dir)?.unwrap_or_default();
+        let removed = if self.dry_run {
+            "Would remove"
+        } else {
+            "Removed"
+        };
+
+        // Entries whose snapshot is gone, or was replaced by another one
+        // under the same name
+        let dangling: Vec<String> = manifest
+            .snapshots
+            .iter()
+            .filter(|(name, entry)| on_disk.get(*name).is_none_or(|info| !entry.describes(info)))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in &dangling {
+            println!("{} manifest entry {} (snapshot gone)", removed, name);
+            manifest.snapshots.remove(name);
+        }
+
+        let mut adopted = 0;
+        for (name, info) in &on_disk {
+            if manifest.snapshots.contains_key(name) {
+                continue;
+            }
+            let source = utils::parse_snapshot_name(name).and_then(|(prefix, _)| {
+                config
+                    .subvols
+                    .iter()
+                    .find(|sv| create::subvol_name(sv, config.name_parents) == prefix)
+            });
+            match source {
+                Some(source) if self.adopt => {
+                    let verb = if self.dry_run {
+                        "Would adopt"
+                    } else {
+                        "Adopted"
+                    };
+                    println!("{} {} as a snapshot of {}", verb, name, source.display());
+                    manifest
+                        .snapshots
+                        .insert(name.clone(), Entry::new(source, info));
+                    adopted += 1;
+                }
+                _ => println!("Unknown snapshot {} (not in the manifest)", name),
+            }
+        }
+
+        if !self.dry_run && (!dangling.is_empty() || adopted > 0) {
+            manifest.save(&snap_dir)?;
+        }
+        let sandboxes = remove_stale_sandboxes(backend, &snap_dir, self.dry_run)?;
+        println!(
+            "{} dangling entries, {} adopted, {} stale sandbox clones",
+            dangling.len(),
+            adopted,
+            sandboxes
+        );
+        Ok(())
+    }
+}
+
+/// Delete `sandbox-<pid>` clones left behind by sandbox runs that died.
+/// Clones kept with `sandbox --keep` are named differently and stay.
+fn remove_stale_sandboxes(
+    backend: &dyn SnapshotBackend,
+    snap_dir: &Path,
+    dry_run: bool,
+) -> Result<usize> {
+    let state_dir = snap_dir.join(STATE_DIR);
+    if !state_dir.exists() {
+        return Ok(0);
+    }
+    let mut removed = 0;
+    for info in backend.list(&state_dir).unwrap_or_default() {
+        let name = utils::snapshot_name(&info);
+        let Some(pid) = name
+            .strip_prefix("sandbox-")
+            .filter(|pid| pid.parse::<u32>().is_ok())
+        else {
+            continue;
+        };
+        if Path::new("/proc").join(pid).exists() {
+            debug!("Sandbox {} is still running", pid);
+            continue;
+        }
+        if dry_run {
+            println!("Would delete stale sandbox clone {}", info.path.display());
+        } else if let Err(e) = backend.delete(&info.path) {
+            warning!("Failed to delete {}: {}", info.path.display(), e);
+            continue;
+        } else {
+            println!("Deleted stale sandbox clone {}", info.path.display());
+        }
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use chrono::Local;
+    use std::fs;
+
+    #[test]
+    fn drops_dangling_entries_and_adopts_known_subvolumes() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let (source, snap_dir) = (dir.join("home"), dir.join("snapshots"));
+        backend.add(&source, Local::now());
+        fs::create_dir(&snap_dir).unwrap();
+        let (gone, kept) = (snap_dir.join("home-1"), snap_dir.join("home-2"));
+        for snap in [&gone, &kept] {
+            backend.add(snap, Local::now());
+            let entry = Entry::new(&source, &backend.info(snap).unwrap());
+            Manifest::record(&snap_dir, &utils::file_name(snap).unwrap(), entry).unwrap();
+        }
+        backend.delete(&gone).unwrap();
+        backend.add(&snap_dir.join("home-3"), Local::now());
+        backend.add(&snap_dir.join("other-4"), Local::now());
+
+        let config = Config {
+            snap_dir: Some(snap_dir.clone()),
+            subvols: vec![source],
+            ..Default::default()
+        };
+        Gc {
+            snap_dir: None,
+            adopt: true,
+            dry_run: false,
+        }
+        .execute(backend, config)
+        .unwrap();
+
+        let manifest = Manifest::load(&snap_dir).unwrap().unwrap();
+        let names: Vec<&str> = manifest.snapshots.keys().map(|k| k.as_str()).collect();
+        assert_eq!(names, ["home-2", "home-3"]);
+    }
+
+    #[test]
+    fn keeps_kept_sandbox_clones() {
+        let backend = MockBackend::leak();
+        let snap_dir = backend.scratch_dir();
+        let state_dir = snap_dir.join(STATE_DIR);
+        // No process has a pid this high
+        let (stale, kept) = (
+            state_dir.join("sandbox-4294967295"),
+            state_dir.join(format!("{}4294967295", crate::sandbox::KEPT_PREFIX)),
+        );
+        backend.add(&stale, Local::now());
+        backend.add(&kept, Local::now());
+
+        assert_eq!(
+            remove_stale_sandboxes(backend, &snap_dir, false).unwrap(),
+            1
+        );
+        assert!(!backend.exists(&stale));
+        assert!(backend.exists(&kept));
+    }
+}
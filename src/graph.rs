@@ -0,0 +1,175 @@
+use crate::backend::{SnapshotBackend, SubvolInfo};
+use crate::config::Config;
+use crate::utils;
+use anyhow::{Context, Result};
+use log::debug;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum Format {
+    /// Graphviz, e.g. `btrsnap graph | dot -Tsvg > graph.svg`
+    Dot,
+    /// Mermaid flowchart, for Markdown renderers
+    Mermaid,
+}
+
+#[derive(clap::Parser)]
+pub struct Graph {
+    /// Snapshot dir to scan
+    #[arg(short = 'd', long, value_parser = utils::parse_path)]
+    pub snap_dir: Option<PathBuf>,
+    /// Output format
+    #[arg(long, value_enum, default_value = "dot")]
+    pub format: Format,
+}
+
+/// How a subvolume came from another one
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Link {
+    /// Snapshot taken of it
+    Snapshot,
+    /// Received from a snapshot sent from it
+    Received,
+}
+
+/// Subvolumes by UUID with their labels, and the links between them
+#[derive(Default)]
+struct Lineage {
+    nodes: BTreeMap<Uuid, String>,
+    edges: Vec<(Uuid, Uuid, Link)>,
+}
+
+impl Lineage {
+    fn add(&mut self, info: &SubvolInfo, label: String) {
+        self.nodes.insert(info.uuid, label);
+        if let Some(parent) = info.parent_uuid {
+            self.edges.push((parent, info.uuid, Link::Snapshot));
+        }
+        if let Some(received) = info.received_uuid {
+            self.edges.push((received, info.uuid, Link::Received));
+        }
+    }
+
+    /// Label of `uuid`, for subvolumes only known as a link's end, e.g.
+    /// sent from another host or deleted
+    fn label(&self, uuid: &Uuid) -> String {
+        self.nodes
+            .get(uuid)
+            .cloned()
+            .unwrap_or_else(|| uuid.to_string())
+    }
+
+    /// Every UUID, also those only known from links
+    fn uuids(&self) -> Vec<Uuid> {
+        let mut uuids: Vec<Uuid> = self.nodes.keys().copied().collect();
+        for (from, to, _) in &self.edges {
+            uuids.extend([*from, *to]);
+        }
+        uuids.sort();
+        uuids.dedup();
+        uuids
+    }
+
+    fn dot(&self) -> String {
+        let mut out = String::from("digraph btrsnap {\n    rankdir=LR;\n");
+        for uuid in self.uuids() {
+            let _ = writeln!(
+                out,
+                "    \"{}\" [label=\"{}\"];",
+                uuid,
+                self.label(&uuid).replace('"', "\\\"")
+            );
+        }
+        for (from, to, link) in &self.edges {
+            let style = match link {
+                Link::Snapshot => "",
+                Link::Received => " [style=dashed, label=\"received\"]",
+            };
+            let _ = writeln!(out, "    \"{}\" -> \"{}\"{};", from, to, style);
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn mermaid(&self) -> String {
+        let id = |uuid: &Uuid| format!("n{}", uuid.to_simple());
+        let mut out = String::from("flowchart LR\n");
+        for uuid in self.uuids() {
+            let label = self.label(&uuid).replace('"', "#quot;");
+            let _ = writeln!(out, "    {}[\"{}\"]", id(&uuid), label);
+        }
+        for (from, to, link) in &self.edges {
+            let arrow = match link {
+                Link::Snapshot => "-->",
+                Link::Received => "-. received .->",
+            };
+            let _ = writeln!(out, "    {} {} {}", id(from), arrow, id(to));
+        }
+        out
+    }
+}
+
+impl Graph {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        let snap_dir = utils::resolve_snap_dir(backend, self.snap_dir, config.snap_dir)?;
+        let mut lineage = Lineage::default();
+        for sv in &config.subvols {
+            match backend.info(sv) {
+                Ok(info) => lineage.add(&info, sv.display().to_string()),
+                Err(e) => debug!("Failed to get subvolume {}: {}", sv.display(), e),
+            }
+        }
+        let snapshots = backend.list(&snap_dir).context(format!(
+            "Failed to list subvolumes in {}",
+            snap_dir.display()
+        ))?;
+        for info in &snapshots {
+            lineage.add(info, utils::snapshot_name(info));
+        }
+        match self.format {
+            Format::Dot => print!("{}", lineage.dot()),
+            Format::Mermaid => print!("{}", lineage.mermaid()),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use chrono::Local;
+
+    #[test]
+    fn links_snapshots_to_their_parents() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let (source, snap) = (dir.join("home"), dir.join("home-1"));
+        backend.add(&source, Local::now());
+        backend.snapshot(&source, &snap, true).unwrap();
+        let (source_info, snap_info) =
+            (backend.info(&source).unwrap(), backend.info(&snap).unwrap());
+
+        let mut lineage = Lineage::default();
+        lineage.add(&source_info, "home".to_string());
+        lineage.add(&snap_info, "home-1".to_string());
+
+        assert_eq!(
+            lineage.edges,
+            [(source_info.uuid, snap_info.uuid, Link::Snapshot)]
+        );
+        assert!(lineage.dot().contains(&format!(
+            "\"{}\" -> \"{}\";",
+            source_info.uuid, snap_info.uuid
+        )));
+        let (from, to) = (source_info.uuid.to_simple(), snap_info.uuid.to_simple());
+        assert!(
+            lineage
+                .mermaid()
+                .contains(&format!("n{} --> n{}", from, to))
+        );
+    }
+}
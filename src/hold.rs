@@ -0,0 +1,223 @@
+use crate::backend::SnapshotBackend;
+use crate::config::Config;
+use crate::manifest::{Hold, Manifest};
+use crate::{selector, utils};
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::{Local, NaiveDate};
+use nix::unistd::{Uid, User};
+use std::env;
+use std::path::{Path, PathBuf};
+
+#[derive(clap::Parser)]
+pub struct HoldCommand {
+    #[command(subcommand)]
+    action: Action,
+}
+
+#[derive(clap::Subcommand)]
+enum Action {
+    /// Keep a snapshot from being deleted, even with --force, until the
+    /// hold expires or is removed
+    Add {
+        /// Snapshot to hold, or a selector like @home:latest
+        #[arg(value_parser = selector::parse_snapshot)]
+        snapshot: PathBuf,
+        /// Why the snapshot is held, e.g. "legal case 123"
+        #[arg(long)]
+        reason: String,
+        /// Last day of the hold (YYYY-MM-DD), held until removed if unset
+        #[arg(long)]
+        until: Option<NaiveDate>,
+        /// Name to remove the hold by, hold-<n> if unset
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Remove a hold from a snapshot
+    Remove {
+        #[arg(value_parser = selector::parse_snapshot)]
+        snapshot: PathBuf,
+        name: String,
+    },
+    /// List the holds on the snapshots in a snapshot dir
+    List {
+        #[arg(short = 'd', long, value_parser = utils::parse_path)]
+        snap_dir: Option<PathBuf>,
+        /// Include expired holds
+        #[arg(short, long)]
+        all: bool,
+    },
+}
+
+impl HoldCommand {
+    /// Listing works unprivileged, changing holds writes the manifest
+    pub fn read_only(&self) -> bool {
+        matches!(self.action, Action::List { .. })
+    }
+
+    pub fn snapshot_mut(&mut self) -> Option<&mut PathBuf> {
+        match &mut self.action {
+            Action::Add { snapshot, .. } | Action::Remove { snapshot, .. } => Some(snapshot),
+            Action::List { .. } => None,
+        }
+    }
+
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        match self.action {
+            Action::Add {
+                snapshot,
+                reason,
+                until,
+                name,
+            } => add(backend, &snapshot, reason, until, name),
+            Action::Remove { snapshot, name } => remove(&snapshot, &name),
+            Action::List { snap_dir, all } => list(
+                &utils::resolve_snap_dir(backend, snap_dir, config.snap_dir)?,
+                all,
+            ),
+        }
+    }
+}
+
+fn add(
+    backend: &dyn SnapshotBackend,
+    snapshot: &Path,
+    reason: String,
+    until: Option<NaiveDate>,
+    name: Option<String>,
+) -> Result<()> {
+    backend
+        .info(snapshot)
+        .context(format!("{} is not a subvolume", snapshot.display()))?;
+    if let Some(until) = until
+        && until < Local::now().date_naive()
+    {
+        bail!("--until {} is in the past", until);
+    }
+    let (snap_dir, key) = split(snapshot)?;
+    let _lock = Manifest::lock(snap_dir)?;
+    let mut manifest = Manifest::load(snap_dir)?.unwrap_or_default();
+    let entry = manifest.snapshots.get_mut(&key).ok_or_else(|| {
+        anyhow!(
+            "{} is not in the manifest, record it with `gc --adopt` first",
+            snapshot.display()
+        )
+    })?;
+    let name = name.unwrap_or_else(|| {
+        (1..)
+            .map(|n| format!("hold-{}", n))
+            .find(|n| entry.holds.iter().all(|h| &h.name != n))
+            .unwrap()
+    });
+    if entry.holds.iter().any(|h| h.name == name) {
+        bail!("{} already has a hold named {}", snapshot.display(), name);
+    }
+    entry.holds.push(Hold {
+        name: name.clone(),
+        reason,
+        until,
+        created: Local::now(),
+        by: user(),
+    });
+    manifest.save(snap_dir)?;
+    match until {
+        Some(until) => println!("Holding {} until {} ({})", snapshot.display(), until, name),
+        None => println!("Holding {} until removed ({})", snapshot.display(), name),
+    }
+    Ok(())
+}
+
+fn remove(snapshot: &Path, name: &str) -> Result<()> {
+    let (snap_dir, key) = split(snapshot)?;
+    let _lock = Manifest::lock(snap_dir)?;
+    let mut manifest = Manifest::load(snap_dir)?.unwrap_or_default();
+    let holds = &mut manifest
+        .snapshots
+        .get_mut(&key)
+        .ok_or_else(|| anyhow!("{} has no holds", snapshot.display()))?
+        .holds;
+    let before = holds.len();
+    holds.retain(|h| h.name != name);
+    if holds.len() == before {
+        bail!("{} has no hold named {}", snapshot.display(), name);
+    }
+    manifest.save(snap_dir)?;
+    println!("Removed hold {} from {}", name, snapshot.display());
+    Ok(())
+}
+
+fn list(snap_dir: &Path, all: bool) -> Result<()> {
+    let manifest = Manifest::load(snap_dir)?.unwrap_or_default();
+    for (name, entry) in &manifest.snapshots {
+        for hold in entry.holds.iter().filter(|h| all || h.active()) {
+            let until = hold
+                .until
+                .map_or("until removed".to_string(), |d| format!("until {}", d));
+            let expired = if hold.active() { "" } else { " (expired)" };
+            println!(
+                "{}  {}  {}{}  {}  (by {}, {})",
+                name,
+                hold.name,
+                until,
+                expired,
+                hold.reason,
+                hold.by,
+                hold.created.format("%Y-%m-%d")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Snapshot dir and manifest key of `snapshot`
+fn split(snapshot: &Path) -> Result<(&Path, String)> {
+    match (snapshot.parent(), utils::file_name(snapshot)) {
+        (Some(snap_dir), Some(name)) => Ok((snap_dir, name)),
+        _ => bail!("{} is not a snapshot", snapshot.display()),
+    }
+}
+
+/// Who placed a hold, the user behind sudo if any
+pub fn user() -> String {
+    env::var("SUDO_USER").ok().unwrap_or_else(|| {
+        User::from_uid(Uid::current())
+            .ok()
+            .flatten()
+            .map_or_else(|| Uid::current().to_string(), |u| u.name)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use crate::manifest::Entry;
+    use crate::protect::{Guard, Policy};
+    use chrono::Days;
+
+    #[test]
+    fn holds_block_forced_deletion_until_removed_or_expired() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let (source, snapshot) = (dir.join("home"), dir.join("snapshots/home-1"));
+        backend.add(&source, Local::now());
+        backend.snapshot(&source, &snapshot, true).unwrap();
+        let info = backend.info(&snapshot).unwrap();
+        Manifest::record(&dir.join("snapshots"), "home-1", Entry::new(&source, &info)).unwrap();
+        // A guard reads the manifest once, as for a single cleanup run
+        let guard = || Guard::new(backend, Policy::default(), true);
+
+        let today = Local::now().date_naive();
+        add(backend, &snapshot, "case 1".into(), Some(today), None).unwrap();
+        add(backend, &snapshot, "case 2".into(), None, None).unwrap();
+        assert!(add(backend, &snapshot, "x".into(), None, Some("hold-1".into())).is_err());
+        assert!(guard().check(&info).is_err());
+
+        remove(&snapshot, "hold-2").unwrap();
+        assert!(guard().check(&info).is_err());
+        Manifest::update(&dir.join("snapshots"), "home-1", |e| {
+            e.holds[0].until = today.checked_sub_days(Days::new(1));
+        })
+        .unwrap();
+        assert!(guard().check(&info).is_ok());
+    }
+}
@@ -0,0 +1,135 @@
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource, FluentValue};
+use log::warn;
+use std::env;
+use std::sync::OnceLock;
+use unic_langid::LanguageIdentifier;
+
+/// Message catalogs by language, English first as the fallback
+const CATALOGS: &[(&str, &str)] = &[
+    ("en", include_str!("../i18n/en.ftl")),
+    ("de", include_str!("../i18n/de.ftl")),
+];
+
+/// English and, if it has a catalog, the user's language
+struct Bundles {
+    fallback: FluentBundle<FluentResource>,
+    local: Option<FluentBundle<FluentResource>>,
+}
+
+static BUNDLES: OnceLock<Bundles> = OnceLock::new();
+
+/// Localized message `id`, e.g. `tr!("aborted-unchanged", path = p)`
+macro_rules! tr {
+    ($id:literal) => {
+        $crate::i18n::message($id, &[])
+    };
+    ($id:literal, $($name:ident = $value:expr),+ $(,)?) => {
+        $crate::i18n::message(
+            $id,
+            &[$((stringify!($name), fluent_bundle::FluentValue::from($value))),+],
+        )
+    };
+}
+pub(crate) use tr;
+
+pub fn message(id: &str, args: &[(&str, FluentValue)]) -> String {
+    let bundles = BUNDLES.get_or_init(|| {
+        let local = language(|name| env::var(name).ok())
+            .filter(|lang| lang != "en")
+            .and_then(|lang| CATALOGS.iter().find(|(l, _)| *l == lang))
+            .map(|(lang, ftl)| bundle(lang, ftl));
+        Bundles {
+            fallback: bundle(CATALOGS[0].0, CATALOGS[0].1),
+            local,
+        }
+    });
+    let mut fargs = FluentArgs::new();
+    for (name, value) in args {
+        fargs.set(*name, value.clone());
+    }
+    bundles
+        .local
+        .iter()
+        .chain([&bundles.fallback])
+        .find_map(|bundle| {
+            let pattern = bundle.get_message(id)?.value()?;
+            let mut errors = vec![];
+            let text = bundle.format_pattern(pattern, Some(&fargs), &mut errors);
+            if !errors.is_empty() {
+                warn!("Errors formatting message {}: {:?}", id, errors);
+            }
+            Some(text.into_owned())
+        })
+        .unwrap_or_else(|| id.to_string())
+}
+
+/// Language of the first set locale variable, by POSIX precedence
+fn language(var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| var(name))
+        .find(|v| !v.is_empty())?;
+    // de_DE.UTF-8@euro -> de-DE
+    let tag = locale.split(['.', '@']).next()?.replace('_', "-");
+    let langid: LanguageIdentifier = tag.parse().ok()?;
+    Some(langid.language.as_str().to_string())
+}
+
+fn bundle(lang: &str, ftl: &str) -> FluentBundle<FluentResource> {
+    let langid: LanguageIdentifier = lang.parse().expect("valid catalog language");
+    let mut bundle = FluentBundle::new_concurrent(vec![langid]);
+    // Isolation marks around arguments garble terminal output
+    bundle.set_use_isolating(false);
+    let resource = FluentResource::try_new(ftl.to_string()).expect("valid catalog");
+    bundle
+        .add_resource(resource)
+        .expect("no duplicate messages in a catalog");
+    bundle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catalogs_have_every_message() {
+        for (lang, ftl) in CATALOGS {
+            bundle(lang, ftl);
+        }
+        // Messages start unindented, comments with `#`
+        let ids = |ftl: &str| -> Vec<String> {
+            let mut ids: Vec<String> = ftl
+                .lines()
+                .filter(|l| l.starts_with(|c: char| c.is_ascii_lowercase()))
+                .filter_map(|l| Some(l.split_once('=')?.0.trim().to_string()))
+                .collect();
+            ids.sort();
+            ids
+        };
+        let english = ids(CATALOGS[0].1);
+        for (lang, ftl) in &CATALOGS[1..] {
+            assert_eq!(ids(ftl), english, "catalog {}", lang);
+        }
+    }
+
+    #[test]
+    fn picks_language_by_precedence() {
+        let env = |vars: &'static [(&str, &str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, v)| v.to_string())
+            }
+        };
+        assert_eq!(
+            language(env(&[("LANG", "de_DE.UTF-8")])).as_deref(),
+            Some("de")
+        );
+        assert_eq!(
+            language(env(&[("LANG", "de_DE.UTF-8"), ("LC_ALL", "C")])),
+            None
+        );
+        assert_eq!(language(env(&[])), None);
+    }
+}
@@ -0,0 +1,185 @@
+//! Finding what keeps a subvolume busy: processes with files, a working
+//! directory or their root (e.g. a container's rootfs) inside it, and mounts
+//! on or below it, wherever else the subvolume is mounted
+
+use crate::mounts::{self, Mount};
+use crate::warnings::warning;
+use anyhow::{Result, bail};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Reasons `path` is in use, one line each, empty if none were found.
+/// Fails if the mounts can't be read, as nothing could be checked then.
+pub fn blockers(path: &Path) -> Result<Vec<String>> {
+    let mounts = mounts::read()?;
+    let views = views(path, &mounts);
+    let mut found = processes(Path::new("/proc"), &views, std::process::id());
+    for mount in mounts {
+        if views.iter().any(|view| mount.point.starts_with(view)) {
+            found.push(format!("{} is a mount point", mount.point.display()));
+        }
+    }
+    Ok(found)
+}
+
+/// Fail if `path` is in use or that can't be checked, only warn if `force`
+pub fn refuse_busy(path: &Path, force: bool) -> Result<()> {
+    let blockers = match blockers(path) {
+        Ok(blockers) => blockers,
+        Err(e) if force => {
+            warning!("Not checking whether {} is in use: {:#}", path.display(), e);
+            return Ok(());
+        }
+        Err(e) => {
+            return Err(e.context(format!(
+                "Failed to check whether {} is in use, pass --force to go ahead anyway",
+                path.display()
+            )));
+        }
+    };
+    if blockers.is_empty() {
+        return Ok(());
+    }
+    let list = blockers.join("\n  ");
+    if !force {
+        bail!(
+            "{} is in use, stop these first or pass --force:\n  {}",
+            path.display(),
+            list
+        );
+    }
+    warning!("{} is in use:\n  {}", path.display(), list);
+    Ok(())
+}
+
+/// Every directory showing the contents of `path`: `path` itself, and the
+/// other mounts of its filesystem that show it or a part of it, e.g.
+/// `/var/lib/postgresql` with `subvol=/@pg` for `/mnt/top/@pg`
+fn views(path: &Path, mounts: &[Mount]) -> Vec<PathBuf> {
+    let mut views = vec![path.to_path_buf()];
+    // The mount `path` is on, the last one mounted wins
+    let Some(on) = mounts
+        .iter()
+        .filter(|m| path.starts_with(&m.point))
+        .max_by_key(|m| m.point.components().count())
+    else {
+        return views;
+    };
+    let Ok(below) = path.strip_prefix(&on.point) else {
+        return views;
+    };
+    let in_fs = on.root.join(below);
+    for mount in mounts {
+        if mount.source != on.source || mount.fstype != on.fstype {
+            continue;
+        }
+        let view = if mount.root.starts_with(&in_fs) {
+            mount.point.clone()
+        } else if let Ok(rest) = in_fs.strip_prefix(&mount.root) {
+            mount.point.join(rest)
+        } else {
+            continue;
+        };
+        if !views.contains(&view) {
+            views.push(view);
+        }
+    }
+    views
+}
+
+/// Processes in `proc` other than `own_pid` using something inside one of
+/// `views`
+fn processes(proc: &Path, views: &[PathBuf], own_pid: u32) -> Vec<String> {
+    let inside = |target: &Path| views.iter().any(|view| target.starts_with(view));
+    let Ok(entries) = fs::read_dir(proc) else {
+        return vec![];
+    };
+    let mut found = BTreeMap::new();
+    for entry in entries.flatten() {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|p| p.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        if pid == own_pid {
+            continue;
+        }
+        let dir = entry.path();
+        let mut uses = vec![];
+        for link in ["root", "cwd"] {
+            if let Ok(target) = fs::read_link(dir.join(link))
+                && inside(&target)
+            {
+                uses.push(format!("{} {}", link, target.display()));
+            }
+        }
+        // Only readable for our own processes unless running as root
+        let fds = fs::read_dir(dir.join("fd")).into_iter().flatten().flatten();
+        let open: Vec<PathBuf> = fds
+            .filter_map(|fd| fs::read_link(fd.path()).ok())
+            .filter(|target| inside(target))
+            .collect();
+        if let Some(first) = open.first() {
+            match open.len() {
+                1 => uses.push(format!("has {} open", first.display())),
+                n => uses.push(format!("has {} and {} more open", first.display(), n - 1)),
+            }
+        }
+        if !uses.is_empty() {
+            let comm = fs::read_to_string(dir.join("comm")).unwrap_or_default();
+            found.insert(
+                pid,
+                format!("process {} ({}): {}", pid, comm.trim(), uses.join(", ")),
+            );
+        }
+    }
+    found.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+
+    #[test]
+    fn finds_processes_with_open_files() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let _open = fs::File::create(dir.join("db")).unwrap();
+
+        // Without excluding this process, it shows up holding the file
+        let views = [dir];
+        let found = processes(Path::new("/proc"), &views, 0);
+        assert!(
+            found
+                .iter()
+                .any(|b| b.starts_with(&format!("process {} ", std::process::id()))),
+            "{:?}",
+            found
+        );
+        assert!(processes(Path::new("/proc"), &views, std::process::id()).is_empty());
+    }
+
+    #[test]
+    fn finds_other_mounts_of_the_subvolume() {
+        let mounts = mounts::parse(
+            "22 1 0:21 /@ / rw - btrfs /dev/sda2 rw,subvol=/@\n\
+             30 22 0:21 /@pg /var/lib/postgresql rw - btrfs /dev/sda2 rw,subvol=/@pg\n\
+             31 22 0:21 /@pg/main /srv/main rw - btrfs /dev/sda2 rw\n\
+             32 22 0:21 / /mnt/top rw - btrfs /dev/sda2 rw,subvolid=5\n\
+             33 22 0:30 /@pg /mnt/other rw - btrfs /dev/sdb1 rw,subvol=/@pg\n"
+                .as_bytes(),
+        );
+        assert_eq!(
+            views(Path::new("/mnt/top/@pg"), &mounts),
+            [
+                PathBuf::from("/mnt/top/@pg"),
+                PathBuf::from("/var/lib/postgresql"),
+                PathBuf::from("/srv/main"),
+            ]
+        );
+    }
+}
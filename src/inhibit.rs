@@ -0,0 +1,49 @@
+use crate::warnings::warning;
+use log::debug;
+use std::io::ErrorKind;
+use std::process::{Child, Command, Stdio};
+
+/// A logind inhibitor lock blocking sleep and shutdown, released on drop
+pub struct Inhibitor {
+    child: Child,
+}
+
+/// Block sleep and shutdown while `why` is in progress. Returns `None` if
+/// no lock could be taken (no systemd-inhibit), the operation runs anyway.
+pub fn take(why: &str) -> Option<Inhibitor> {
+    // systemd-inhibit holds the lock for as long as `cat` waits on our pipe
+    let child = Command::new("systemd-inhibit")
+        .args([
+            "--what=sleep:shutdown",
+            "--who=btrsnap",
+            &format!("--why={}", why),
+            "--mode=block",
+            "cat",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn();
+    match child {
+        Ok(child) => {
+            debug!("Inhibiting sleep and shutdown: {}", why);
+            Some(Inhibitor { child })
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            debug!("systemd-inhibit not found, not inhibiting sleep");
+            None
+        }
+        Err(e) => {
+            warning!("Failed to take an inhibitor lock: {}", e);
+            None
+        }
+    }
+}
+
+impl Drop for Inhibitor {
+    fn drop(&mut self) {
+        // Closing the pipe ends `cat` and with it the lock
+        drop(self.child.stdin.take());
+        let _ = self.child.wait();
+        debug!("Released inhibitor lock");
+    }
+}
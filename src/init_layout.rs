@@ -0,0 +1,202 @@
+use crate::backend::{FS_TREE_ID, SnapshotBackend};
+use crate::i18n::tr;
+use crate::utils;
+use anyhow::{Context, Result, anyhow, bail};
+use log::{debug, info};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+#[derive(clap::Parser)]
+pub struct InitLayout {
+    /// Mount point of the top-level subvolume (subvolid=5)
+    #[arg(value_parser = utils::parse_path)]
+    pub mount: PathBuf,
+    /// Subvolume to create and snapshot (repeatable)
+    #[arg(short = 'n', long = "name", default_values = ["@", "@home"])]
+    pub names: Vec<String>,
+    /// Subvolume holding the snapshots
+    #[arg(long, default_value = "@snapshots")]
+    pub snap_name: String,
+    /// Append mount entries for the layout to the fstab file
+    #[arg(long)]
+    pub write_fstab: bool,
+    /// fstab file to update
+    #[arg(long, default_value = "/etc/fstab")]
+    pub fstab: PathBuf,
+    /// Write the generated config to this file instead of stdout
+    #[arg(short = 'o', long)]
+    pub config_out: Option<PathBuf>,
+    /// Do not ask for confirmation
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+impl InitLayout {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend) -> Result<()> {
+        let top = backend
+            .info(&self.mount)
+            .context(format!("{} is not a BTRFS subvolume", self.mount.display()))?;
+        if top.id != FS_TREE_ID {
+            bail!(
+                "{} is not the top-level subvolume, mount it with subvolid=5",
+                self.mount.display()
+            );
+        }
+
+        // Needs /dev/disk/by-uuid, fail before anything is created
+        let entries = fstab_entries(&self.mount, &self.names, &self.snap_name)?;
+
+        info!("Creating subvolume layout in {}", self.mount.display());
+        for name in self.names.iter().chain([&self.snap_name]) {
+            create_subvol(backend, &self.mount.join(name))?;
+        }
+
+        if self.write_fstab {
+            self.update_fstab(&entries)?;
+        } else {
+            eprintln!("Add these entries to {}:", self.fstab.display());
+            for entry in &entries {
+                eprintln!("{}", entry);
+            }
+        }
+
+        let config = layout_config(&self.mount, &self.names, &self.snap_name)?;
+        match self.config_out {
+            Some(path) => {
+                fs::write(&path, config)
+                    .context(format!("Failed to write config file: {}", path.display()))?;
+                println!("Wrote config: {}", path.display());
+            }
+            None => print!("{}", config),
+        }
+        Ok(())
+    }
+
+    fn update_fstab(&self, entries: &[String]) -> Result<()> {
+        let existing = fs::read_to_string(&self.fstab).unwrap_or_default();
+        let mounted: Vec<&str> = existing
+            .lines()
+            .filter(|l| !l.trim_start().starts_with('#'))
+            .filter_map(|l| l.split_whitespace().nth(1))
+            .collect();
+        let new: Vec<&String> = entries
+            .iter()
+            .filter(|e| {
+                let target = e.split_whitespace().nth(1).unwrap_or_default();
+                if mounted.contains(&target) {
+                    println!("Skipping {}, already in {}", target, self.fstab.display());
+                    return false;
+                }
+                true
+            })
+            .collect();
+        if new.is_empty() {
+            return Ok(());
+        }
+
+        for entry in &new {
+            println!("{}", entry);
+        }
+        let path = self.fstab.display().to_string();
+        let prompt = tr!("confirm-append-fstab", path = path.as_str());
+        if !self.yes && !utils::confirm(&prompt)? {
+            bail!(tr!("aborted-unchanged", path = path));
+        }
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.fstab)
+            .context(format!("Failed to open {}", self.fstab.display()))?;
+        for entry in new {
+            writeln!(file, "{}", entry)?;
+        }
+        println!("Updated: {}", self.fstab.display());
+        Ok(())
+    }
+}
+
+fn create_subvol(backend: &dyn SnapshotBackend, path: &Path) -> Result<()> {
+    if path.exists() {
+        println!("Exists: {}", path.display());
+        return Ok(());
+    }
+    backend
+        .create(path)
+        .context(format!("Failed to create subvolume {}", path.display()))?;
+    println!("Created subvolume: {}", path.display());
+    Ok(())
+}
+
+/// Conventional mount point for a subvolume name, e.g. `@home` -> `/home`
+fn mount_point(name: &str, snap_name: &str) -> String {
+    if name == snap_name {
+        return "/.snapshots".to_string();
+    }
+    format!("/{}", name.trim_start_matches('@'))
+}
+
+/// Entries mounting each subvolume at its conventional mount point, and the
+/// top-level subvolume at `mount`, where the generated config finds them
+fn fstab_entries(mount: &Path, names: &[String], snap_name: &str) -> Result<Vec<String>> {
+    let uuid = fs_uuid(mount)?;
+    let mut entries: Vec<String> = names
+        .iter()
+        .chain([&snap_name.to_string()])
+        .map(|name| {
+            format!(
+                "UUID={} {} btrfs subvol=/{},defaults 0 0",
+                uuid,
+                mount_point(name, snap_name),
+                name
+            )
+        })
+        .collect();
+    entries.push(format!(
+        "UUID={} {} btrfs subvolid={},defaults 0 0",
+        uuid,
+        mount.display(),
+        FS_TREE_ID
+    ));
+    Ok(entries)
+}
+
+/// Filesystem UUID of the device mounted at `mount`
+fn fs_uuid(mount: &Path) -> Result<String> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")
+        .context("Failed to read /proc/self/mountinfo")?;
+    let source = mountinfo
+        .lines()
+        .find(|l| l.split_whitespace().nth(4) == mount.to_str())
+        .and_then(|l| l.split(" - ").nth(1))
+        .and_then(|l| l.split_whitespace().nth(1))
+        .ok_or_else(|| anyhow!("{} is not a mount point", mount.display()))?;
+    debug!("{} is mounted from {}", mount.display(), source);
+    let device = Path::new(source).canonicalize()?;
+
+    for entry in fs::read_dir("/dev/disk/by-uuid").context("Failed to read /dev/disk/by-uuid")? {
+        let entry = entry?;
+        if entry.path().canonicalize().ok().as_ref() == Some(&device) {
+            return Ok(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    Err(anyhow!("Failed to find the UUID of {}", device.display()))
+}
+
+fn layout_config(mount: &Path, names: &[String], snap_name: &str) -> Result<String> {
+    let mut config = Table::new();
+    config.insert(
+        "snap-dir".to_string(),
+        Value::String(mount.join(snap_name).display().to_string()),
+    );
+    config.insert(
+        "subvol-base".to_string(),
+        Value::String(mount.display().to_string()),
+    );
+    config.insert(
+        "subvol-names".to_string(),
+        Value::Array(names.iter().cloned().map(Value::String).collect()),
+    );
+    toml::to_string(&config).context("Failed to generate config")
+}
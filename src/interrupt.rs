@@ -0,0 +1,29 @@
+use anyhow::Result;
+use nix::libc::c_int;
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle(_: c_int) {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Catch SIGINT and SIGTERM so a run stops between items instead of in the
+/// middle of a btrfs operation. A second signal terminates immediately.
+pub fn install() -> Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(handle),
+        SaFlags::SA_RESETHAND,
+        SigSet::empty(),
+    );
+    for sig in [Signal::SIGINT, Signal::SIGTERM] {
+        unsafe { signal::sigaction(sig, &action) }?;
+    }
+    Ok(())
+}
+
+/// Whether the run should stop before the next item
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,123 @@
+//! Kernels inside snapshots of `/`, and whether the boot loader of the
+//! running system has an entry for them
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+/// Where kernel modules live, one directory per kernel version
+const MODULE_DIRS: &[&str] = &["usr/lib/modules", "lib/modules"];
+/// Files naming the kernels of boot entries, relative to `/boot`
+const GRUB_CONFIGS: &[&str] = &["grub/grub.cfg", "grub2/grub.cfg"];
+
+/// Versions of the kernels installed below `root`, empty if it is not a
+/// system root or cannot be read
+pub fn installed(root: &Path) -> BTreeSet<String> {
+    MODULE_DIRS
+        .iter()
+        .filter_map(|dir| fs::read_dir(root.join(dir)).ok())
+        .flatten()
+        .flatten()
+        // Leftovers of removed kernels keep only a few files, e.g. from DKMS
+        .filter(|entry| entry.path().join("modules.dep").exists())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect()
+}
+
+/// What the boot loader of the running system can boot: the words of its
+/// configuration and the names of kernel images, searched for kernel
+/// versions
+pub struct BootEntries {
+    words: BTreeSet<String>,
+}
+
+impl BootEntries {
+    /// Boot entries in `boot` (usually `/boot`): Boot Loader Specification
+    /// entries, GRUB configs and the names of the kernel images
+    pub fn load(boot: &Path) -> Self {
+        let mut text = String::new();
+        if let Ok(entries) = fs::read_dir(boot.join("loader/entries")) {
+            for entry in entries.flatten() {
+                text += &fs::read_to_string(entry.path()).unwrap_or_default();
+            }
+        }
+        for config in GRUB_CONFIGS {
+            text += &fs::read_to_string(boot.join(config)).unwrap_or_default();
+        }
+        if let Ok(entries) = fs::read_dir(boot) {
+            for entry in entries.flatten() {
+                text += &entry.file_name().to_string_lossy();
+                text.push('\n');
+            }
+        }
+        let words = text
+            .split(|c: char| c.is_whitespace() || "/'\",".contains(c))
+            .filter(|w| !w.is_empty())
+            .map(str::to_string)
+            .collect();
+        BootEntries { words }
+    }
+
+    /// Whether an entry boots the kernel `version`: a word is the version,
+    /// e.g. `version 6.8.0-31-generic`, or ends in `-<version>`, e.g.
+    /// `vmlinuz-6.8.0-31-generic`, so `6.8.0-3` matches neither
+    pub fn has(&self, version: &str) -> bool {
+        self.words.iter().any(|word| {
+            word.strip_suffix(version)
+                .is_some_and(|rest| rest.is_empty() || rest.ends_with('-'))
+        })
+    }
+}
+
+/// `6.8.0-31 (boot entry), 6.5.0-9 (no boot entry)` for the listing
+pub fn describe(kernels: &BTreeSet<String>, boot: &BootEntries) -> String {
+    kernels
+        .iter()
+        .map(|version| {
+            let entry = if boot.has(version) {
+                "boot entry"
+            } else {
+                "no boot entry"
+            };
+            format!("{} ({})", version, entry)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+
+    #[test]
+    fn finds_kernels_and_their_boot_entries() {
+        let dir = MockBackend::leak().scratch_dir();
+        let (snapshot, boot) = (dir.join("root-1"), dir.join("boot"));
+        for version in ["6.8.0-31-generic", "6.5.0-9-generic"] {
+            let modules = snapshot.join("usr/lib/modules").join(version);
+            fs::create_dir_all(&modules).unwrap();
+            fs::write(modules.join("modules.dep"), "").unwrap();
+        }
+        fs::create_dir_all(snapshot.join("usr/lib/modules/6.1.0-dkms")).unwrap();
+        fs::create_dir_all(boot.join("loader/entries")).unwrap();
+        fs::write(
+            boot.join("loader/entries/fedora.conf"),
+            "linux /vmlinuz-6.8.0-31-generic\n",
+        )
+        .unwrap();
+
+        let kernels = installed(&snapshot);
+        assert_eq!(kernels.len(), 2);
+        assert_eq!(
+            describe(&kernels, &BootEntries::load(&boot)),
+            "6.5.0-9-generic (no boot entry), 6.8.0-31-generic (boot entry)"
+        );
+        assert!(installed(&dir).is_empty());
+
+        let boot = BootEntries::load(&boot);
+        assert!(!boot.has("6.8.0-3"));
+        assert!(!boot.has("8.0-31-generic"));
+        assert!(!boot.has("6.8.0-31"));
+    }
+}
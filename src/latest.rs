@@ -0,0 +1,92 @@
+//! `<name>-latest` symlinks in the snapshot dir, pointing at the newest
+//! snapshot of each subvolume (`latest-links`), for backup scripts and
+//! other tools that want a stable path to it
+
+use crate::backend::SnapshotBackend;
+use crate::utils;
+use crate::warnings::warning;
+use anyhow::{Context, Result};
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+/// Point `<snap_dir>/<name>-latest` at the newest snapshot of `name`, or
+/// remove it if none is left. Failures are warnings: the snapshots
+/// themselves are fine.
+pub fn update(backend: &dyn SnapshotBackend, snap_dir: &Path, name: &str) {
+    if let Err(e) = try_update(backend, snap_dir, name) {
+        warning!("Failed to update {}-latest: {:#}", name, e);
+    }
+}
+
+fn try_update(backend: &dyn SnapshotBackend, snap_dir: &Path, name: &str) -> Result<()> {
+    let link = snap_dir.join(format!("{}-latest", name));
+    let newest = backend
+        .list(snap_dir)
+        .context(format!(
+            "Failed to list subvolumes in {}",
+            snap_dir.display()
+        ))?
+        .into_iter()
+        .filter_map(|info| {
+            let snapshot = utils::snapshot_name(&info);
+            let (subvol, ts) = utils::parse_snapshot_name(&snapshot)?;
+            // Counters of snapshots taken within the same second sort by otime
+            (subvol == name).then_some(((ts, info.otime), info.path))
+        })
+        .max_by_key(|(key, _)| *key)
+        .and_then(|(_, path)| path.file_name().map(|n| n.to_os_string()));
+    let Some(target) = newest else {
+        return match fs::remove_file(&link) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).context(format!("Failed to remove {}", link.display()))
+            }
+            _ => Ok(()),
+        };
+    };
+    if fs::read_link(&link).is_ok_and(|current| current == target) {
+        return Ok(());
+    }
+    // Relative, so the link survives the snapshot dir being mounted elsewhere
+    let tmp = snap_dir.join(format!(".{}-latest.tmp", name));
+    let _ = fs::remove_file(&tmp);
+    symlink(&target, &tmp).context(format!("Failed to create {}", tmp.display()))?;
+    fs::rename(&tmp, &link).context(format!("Failed to replace {}", link.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use chrono::Local;
+
+    #[test]
+    fn follows_the_newest_snapshot() {
+        let backend = MockBackend::leak();
+        let snap_dir = backend.scratch_dir();
+        for (n, name) in ["home-100", "home-200", "home-200.1", "var-300"]
+            .iter()
+            .enumerate()
+        {
+            backend.add(
+                &snap_dir.join(name),
+                Local::now() + chrono::Duration::seconds(n as i64),
+            );
+        }
+        let link = snap_dir.join("home-latest");
+
+        update(backend, &snap_dir, "home");
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("home-200.1"));
+        for name in ["home-200.1", "home-200"] {
+            backend.delete_any(&snap_dir.join(name)).unwrap();
+        }
+        update(backend, &snap_dir, "home");
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("home-100"));
+        assert!(link.join(".").is_dir());
+
+        backend.delete_any(&snap_dir.join("home-100")).unwrap();
+        update(backend, &snap_dir, "home");
+        assert!(link.symlink_metadata().is_err());
+        assert!(!snap_dir.join("var-latest").exists());
+    }
+}
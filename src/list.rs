@@ -1,40 +1,354 @@
-use crate::utils;
-use anyhow::Result;
-use btrfsutil::subvolume::Subvolume;
+use crate::backend::{SnapshotBackend, SubvolInfo};
+use crate::config::Config;
+use crate::kernels::{self, BootEntries};
+use crate::manifest::Manifest;
+use crate::porcelain::{self, Porcelain};
+use crate::{os_path, tombstone, utils};
+use anyhow::{Context, Result, bail};
 use log::{debug, info};
-use std::path::PathBuf;
+use nix::unistd::Uid;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Options of `List` a fleet controller may set through `agent`, by clap id
+pub const AGENT_OPTIONS: &[&str] = &["long", "porcelain", "from_uuid", "deleted"];
 
 #[derive(clap::Parser)]
 pub struct List {
     /// Snapshot dir to scan
     #[arg(short = 'd', long, value_parser = utils::parse_path)]
     pub snap_dir: Option<PathBuf>,
+    /// Also show the packages recorded by package-manager hook snapshots,
+    /// and the kernels in snapshots of / with whether /boot has an entry
+    /// for them
+    #[arg(short = 'l', long)]
+    pub long: bool,
+    /// Print in a stable format for scripts
+    #[arg(long, value_enum, value_name = "VERSION", conflicts_with = "long")]
+    pub porcelain: Option<Porcelain>,
+    /// Only list snapshots descending from the subvolume or snapshot with
+    /// this UUID, directly or through received copies
+    #[arg(long, value_name = "UUID")]
+    pub from_uuid: Option<Uuid>,
+    /// List the tombstones of deleted snapshots instead
+    #[arg(long, conflicts_with_all = ["porcelain", "from_uuid"])]
+    pub deleted: bool,
+    /// Only list snapshots whose top directory this user owns, set in
+    /// self-service mode
+    #[arg(skip)]
+    pub owner: Option<Uid>,
+}
+
+/// Where snapshots came from, followed through their parent and received
+/// UUIDs rather than their names, which renames and migrations change
+struct Origins {
+    /// Configured subvolumes
+    sources: HashMap<Uuid, PathBuf>,
+    /// Snapshots in the snapshot dir, which other snapshots may come from
+    snapshots: HashMap<Uuid, SubvolInfo>,
+    /// Sources recorded in the manifest, by snapshot UUID
+    recorded: HashMap<String, PathBuf>,
+}
+
+impl Origins {
+    fn new(
+        backend: &dyn SnapshotBackend,
+        config: &Config,
+        snap_dir: &Path,
+        manifest: Option<&Manifest>,
+    ) -> Result<Self> {
+        let sources = config
+            .subvols
+            .iter()
+            .filter_map(|sv| match backend.info(sv) {
+                Ok(info) => Some((info.uuid, sv.clone())),
+                Err(e) => {
+                    debug!("Failed to get subvolume {}: {}", sv.display(), e);
+                    None
+                }
+            })
+            .collect();
+        let snapshots = backend
+            .list(snap_dir)
+            .context(format!(
+                "Failed to list subvolumes in {}",
+                snap_dir.display()
+            ))?
+            .into_iter()
+            .map(|info| (info.uuid, info))
+            .collect();
+        let recorded = manifest
+            .iter()
+            .flat_map(|m| m.snapshots.values())
+            .map(|e| (e.uuid.clone(), e.source.clone()))
+            .collect();
+        Ok(Origins {
+            sources,
+            snapshots,
+            recorded,
+        })
+    }
+
+    /// UUIDs `info` descends from, nearest first, and the source subvolume
+    /// at the end of the chain if known. A received snapshot comes from the
+    /// one it was sent from, otherwise from the one it was taken of.
+    fn trace(&self, info: &SubvolInfo) -> (Vec<Uuid>, Option<PathBuf>) {
+        let mut chain = vec![];
+        let mut current = info;
+        loop {
+            if let Some(source) = self.recorded.get(&current.uuid.to_string()) {
+                return (chain, Some(source.clone()));
+            }
+            let Some(next) = current.received_uuid.or(current.parent_uuid) else {
+                break;
+            };
+            if next == info.uuid || chain.contains(&next) {
+                break;
+            }
+            chain.push(next);
+            if let Some(source) = self.sources.get(&next) {
+                return (chain, Some(source.clone()));
+            }
+            match self.snapshots.get(&next) {
+                Some(snapshot) => current = snapshot,
+                None => break,
+            }
+        }
+        (chain, None)
+    }
+
+    /// Whether `info` should be listed under `--from-uuid`
+    fn matches(&self, info: &SubvolInfo, from: Option<Uuid>) -> bool {
+        from.is_none_or(|from| self.trace(info).0.contains(&from))
+    }
+
+    /// ` source=...` for the listing, the UUID the chain ends at if the
+    /// source is unknown
+    fn note(&self, info: &SubvolInfo) -> String {
+        match self.trace(info) {
+            (_, Some(source)) => format!(", source={}", source.display()),
+            (chain, None) => chain
+                .last()
+                .map_or(String::new(), |uuid| format!(", source=uuid:{}", uuid)),
+        }
+    }
 }
 
 impl List {
-    pub fn execute(self, snap_dir: Option<PathBuf>) -> Result<()> {
-        let snap_dir = utils::resolve_snap_dir(self.snap_dir, snap_dir)?;
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        let snap_dir =
+            utils::resolve_snap_dir(backend, self.snap_dir.clone(), config.snap_dir.clone())?;
         info!("Listing snapshots in {}", snap_dir.display());
-        utils::scan_snapshots(&snap_dir, list_snapshot)?;
+        if self.deleted {
+            return list_deleted(&snap_dir, self.owner.is_some());
+        }
+        let manifest = Manifest::load(&snap_dir)?;
+        let origins = Origins::new(backend, &config, &snap_dir, manifest.as_ref())?;
+        if let Some(Porcelain::V1) = self.porcelain {
+            return list_porcelain_v1(
+                backend,
+                &snap_dir,
+                manifest.as_ref(),
+                |info| self.shows(&origins, info),
+                !self.filtered(),
+            );
+        }
+        let boot = self.long.then(|| BootEntries::load(Path::new("/boot")));
+        let kernels = |info: &SubvolInfo| {
+            if let Some(boot) = &boot {
+                let kernels = kernels::installed(&info.path);
+                if !kernels.is_empty() {
+                    println!("    kernels: {}", kernels::describe(&kernels, boot));
+                }
+            }
+        };
+        let Some(manifest) = manifest else {
+            return utils::scan_snapshots(backend, &snap_dir, |info| {
+                if self.shows(&origins, &info) {
+                    list_snapshot(&info, &origins.note(&info))?;
+                    kernels(&info);
+                }
+                Ok(())
+            });
+        };
+
+        // Flag any mismatch between the manifest and the disk
+        let mut seen = HashSet::new();
+        utils::scan_snapshots(backend, &snap_dir, |info| {
+            let name = utils::snapshot_name(&info);
+            if !self.shows(&origins, &info) {
+                seen.insert(name);
+                return Ok(());
+            }
+            if let Some(known) = manifest.snapshots.get(&name) {
+                let mut note = origins.note(&info);
+                note += &known
+                    .trigger
+                    .as_ref()
+                    .map_or(String::new(), |t| format!(" ({})", t));
+                if let Some(description) = &known.description {
+                    note.push_str(&format!(" \"{}\"", description));
+                }
+                if let Some(reason) = &known.pinned {
+                    note.push_str(&format!(" [pinned: {}]", reason));
+                }
+                for hold in known.holds.iter().filter(|h| h.active()) {
+                    let until = hold
+                        .until
+                        .map_or(String::new(), |d| format!(" until {}", d));
+                    note.push_str(&format!(" [hold {}: {}{}]", hold.name, hold.reason, until));
+                }
+                seen.insert(name);
+                list_snapshot(&info, &note)?;
+                if self.long && !known.packages.is_empty() {
+                    println!("    packages: {}", known.packages.join(", "));
+                }
+                kernels(&info);
+                Ok(())
+            } else {
+                list_snapshot(&info, &(origins.note(&info) + " (not in manifest)"))?;
+                kernels(&info);
+                Ok(())
+            }
+        })?;
+        if self.filtered() {
+            return Ok(());
+        }
+        for name in manifest.snapshots.keys().filter(|n| !seen.contains(*n)) {
+            println!(
+                "{}: missing (in manifest only)",
+                snap_dir.join(name).display()
+            );
+        }
         Ok(())
     }
+
+    /// Whether the filters let `info` through
+    fn shows(&self, origins: &Origins, info: &SubvolInfo) -> bool {
+        origins.matches(info, self.from_uuid)
+            && self.owner.is_none_or(|owner| {
+                fs::metadata(&info.path).is_ok_and(|m| m.uid() == owner.as_raw())
+            })
+    }
+
+    /// Whether snapshots are filtered, then those missing from the disk are
+    /// left out as nothing is known about them
+    fn filtered(&self) -> bool {
+        self.from_uuid.is_some() || self.owner.is_some()
+    }
 }
 
-fn list_snapshot(entry: walkdir::DirEntry) -> Result<()> {
-    debug!("Checking path: {}", entry.path().display());
-    let subvol = match Subvolume::get(entry.path()) {
-        Ok(subvol) => subvol,
-        Err(_) => {
-            debug!("Path {} is not a subvolume", entry.path().display());
-            return Ok(());
+/// Tombstones of deleted snapshots, with their sources unless `private`
+fn list_deleted(snap_dir: &Path, private: bool) -> Result<()> {
+    if private {
+        bail!("Deleted snapshots are only listed for root");
+    }
+    for t in tombstone::load(snap_dir)? {
+        let mut line = format!(
+            "{}: created={}, deleted={}, rule={}",
+            t.name,
+            t.created.format("%Y-%m-%d %H:%M:%S"),
+            t.deleted.format("%Y-%m-%d %H:%M:%S"),
+            t.rule
+        );
+        if let Some(source) = &t.source {
+            line += &format!(", source={}", source.display());
+        }
+        if let Some(exclusive) = t.exclusive {
+            line += &format!(", exclusive={}", utils::format_size(exclusive));
         }
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+fn list_porcelain_v1(
+    backend: &dyn SnapshotBackend,
+    snap_dir: &Path,
+    manifest: Option<&Manifest>,
+    shows: impl Fn(&SubvolInfo) -> bool,
+    list_missing: bool,
+) -> Result<()> {
+    let record = |path: &Path, name: &str, otime: i64, generation: u64, state: &str| {
+        let subvol = utils::parse_snapshot_name(name).map_or(String::new(), |(s, _)| s.into());
+        let (otime, generation) = (otime.to_string(), generation.to_string());
+        let path = path.display().to_string();
+        porcelain::record(&["snapshot", &path, &subvol, &otime, &generation, state]);
     };
-    let subvol_info = subvol.info()?;
+    let mut seen = HashSet::new();
+    utils::scan_snapshots(backend, snap_dir, |info| {
+        let name = utils::snapshot_name(&info);
+        if !shows(&info) {
+            seen.insert(name);
+            return Ok(());
+        }
+        let managed = manifest.is_some_and(|m| m.snapshots.contains_key(&name));
+        let state = if managed { "managed" } else { "unmanaged" };
+        record(
+            &info.path,
+            &name,
+            info.otime.timestamp(),
+            info.generation,
+            state,
+        );
+        seen.insert(name);
+        Ok(())
+    })?;
+    for (name, entry) in manifest.iter().flat_map(|m| &m.snapshots) {
+        if list_missing && !seen.contains(name) {
+            let path = snap_dir.join(os_path::from_name(name));
+            let otime = entry.created.timestamp();
+            record(&path, name, otime, entry.generation, "missing");
+        }
+    }
+    Ok(())
+}
+
+fn list_snapshot(info: &SubvolInfo, note: &str) -> Result<()> {
     println!(
-        "{}: gen={}, otime={}",
-        entry.path().display(),
-        subvol_info.generation,
-        subvol_info.otransid
+        "{}: gen={}, otime={}{}",
+        info.path.display(),
+        info.generation,
+        info.otransid,
+        note
     );
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use chrono::Local;
+
+    #[test]
+    fn traces_sources_through_snapshots_of_snapshots() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let (source, snap_dir) = (dir.join("home"), dir.join("snapshots"));
+        backend.add(&source, Local::now());
+        let (first, copy) = (snap_dir.join("home-1"), snap_dir.join("renamed-2"));
+        backend.snapshot(&source, &first, true).unwrap();
+        backend.snapshot(&first, &copy, true).unwrap();
+        let config = Config {
+            subvols: vec![source.clone()],
+            ..Default::default()
+        };
+
+        let origins = Origins::new(backend, &config, &snap_dir, None).unwrap();
+        let (source_info, first_info) = (
+            backend.info(&source).unwrap(),
+            backend.info(&first).unwrap(),
+        );
+        let copy_info = backend.info(&copy).unwrap();
+        assert_eq!(
+            origins.trace(&copy_info),
+            (vec![first_info.uuid, source_info.uuid], Some(source))
+        );
+        assert!(origins.matches(&copy_info, Some(first_info.uuid)));
+        assert!(!origins.matches(&first_info, Some(copy_info.uuid)));
+    }
+}
@@ -0,0 +1,263 @@
+//! systemd-nspawn machines and Incus/LXD instances as snapshot subjects:
+//! `create --machines` finds their root subvolumes in /var/lib/machines
+//! and btrfs storage pools and snapshots each machine on its own, named
+//! after it so `[subvol."<machine>"]` sets its retention. With `--freeze`
+//! a running machine is frozen just while its snapshot is taken.
+
+use crate::backend::SnapshotBackend;
+use crate::warnings::warning;
+use anyhow::{Context, Result, bail};
+use log::{debug, info};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Where `machinectl` keeps machine images
+const NSPAWN_DIR: &str = "/var/lib/machines";
+
+/// Storage pool dirs by the client managing them: Incus, and LXD from
+/// distribution packages and from the snap
+const POOLS: &[(&str, &str)] = &[
+    ("incus", "/var/lib/incus/storage-pools"),
+    ("lxc", "/var/lib/lxd/storage-pools"),
+    ("lxc", "/var/snap/lxd/common/lxd/storage-pools"),
+];
+
+/// Dirs of a btrfs pool holding one subvolume per instance
+const INSTANCE_DIRS: &[&str] = &["containers", "virtual-machines"];
+
+#[derive(Debug, PartialEq)]
+pub enum Manager {
+    Nspawn,
+    /// An Incus or LXD instance, managed with this client
+    Incus {
+        client: &'static str,
+        project: String,
+    },
+}
+
+/// A machine and the subvolume holding its root
+#[derive(Debug)]
+pub struct Machine {
+    pub name: String,
+    pub manager: Manager,
+    pub subvol: PathBuf,
+}
+
+/// The machines called `names`, or all of them if `names` is empty
+pub fn select(backend: &dyn SnapshotBackend, names: &[String]) -> Result<Vec<Machine>> {
+    let pools: Vec<(&str, &Path)> = POOLS.iter().map(|(c, p)| (*c, Path::new(p))).collect();
+    let mut machines = find(backend, Path::new(NSPAWN_DIR), &pools);
+    if names.is_empty() {
+        if machines.is_empty() {
+            bail!(
+                "No machines found in {} or Incus/LXD btrfs storage pools",
+                NSPAWN_DIR
+            );
+        }
+        return Ok(machines);
+    }
+    if let Some(missing) = names
+        .iter()
+        .find(|n| !machines.iter().any(|m| m.name == **n))
+    {
+        bail!("No machine {} on btrfs", missing);
+    }
+    machines.retain(|m| names.contains(&m.name));
+    Ok(machines)
+}
+
+/// Machines whose roots are subvolumes in `nspawn_dir` or in the instance
+/// dirs of `pools`
+fn find(
+    backend: &dyn SnapshotBackend,
+    nspawn_dir: &Path,
+    pools: &[(&'static str, &Path)],
+) -> Vec<Machine> {
+    let mut machines: Vec<Machine> = subvols_in(backend, nspawn_dir)
+        .into_iter()
+        .map(|(name, subvol)| Machine {
+            name,
+            manager: Manager::Nspawn,
+            subvol,
+        })
+        .collect();
+    for (client, pools_dir) in pools {
+        let Ok(entries) = fs::read_dir(pools_dir) else {
+            continue;
+        };
+        for pool in entries.flatten() {
+            for dir in INSTANCE_DIRS {
+                for (dir_name, subvol) in subvols_in(backend, &pool.path().join(dir)) {
+                    // Instances of other projects than the default one are
+                    // stored as `<project>_<name>`, names have no `_`
+                    let project = dir_name.split_once('_').map_or("default", |(p, _)| p);
+                    debug!(
+                        "Found {} instance {} in {}",
+                        client,
+                        dir_name,
+                        subvol.display()
+                    );
+                    machines.push(Machine {
+                        manager: Manager::Incus {
+                            client,
+                            project: project.to_string(),
+                        },
+                        // Unique across projects and becomes the snapshot name
+                        name: dir_name,
+                        subvol,
+                    });
+                }
+            }
+        }
+    }
+    machines
+}
+
+/// Subvolumes directly in `dir` by name, skipping hidden ones such as
+/// machinectl's temporary `.#<name>` images
+fn subvols_in(backend: &dyn SnapshotBackend, dir: &Path) -> Vec<(String, PathBuf)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut found: Vec<(String, PathBuf)> = entries
+        .flatten()
+        .filter_map(|e| Some((e.file_name().into_string().ok()?, e.path())))
+        .filter(|(name, path)| !name.starts_with('.') && backend.is_subvolume(path))
+        .collect();
+    found.sort();
+    found
+}
+
+impl Machine {
+    /// Freeze the machine if it is running, until the returned guard is
+    /// dropped
+    pub fn freeze(&self) -> Result<Option<Frozen<'_>>> {
+        match &self.manager {
+            Manager::Nspawn => {
+                // Only running machines are registered with machined
+                let Ok(unit) = output(
+                    "machinectl",
+                    &["show", "--property=Unit", "--value", &self.name],
+                ) else {
+                    return Ok(None);
+                };
+                info!("Freezing machine {}", self.name);
+                output("systemctl", &["freeze", unit.trim()])?;
+            }
+            Manager::Incus { client, project } => {
+                let state = output(
+                    client,
+                    &[
+                        "query",
+                        &format!(
+                            "/1.0/instances/{}/state?project={}",
+                            self.instance(),
+                            project
+                        ),
+                    ],
+                )?;
+                let state: Value = serde_json::from_str(&state)
+                    .context(format!("Invalid state of instance {}", self.name))?;
+                if state["status"].as_str() != Some("Running") {
+                    return Ok(None);
+                }
+                info!("Freezing instance {}", self.name);
+                output(client, &["pause", "--project", project, self.instance()])?;
+            }
+        }
+        Ok(Some(Frozen(self)))
+    }
+
+    fn thaw(&self) -> Result<()> {
+        match &self.manager {
+            Manager::Nspawn => {
+                let unit = output(
+                    "machinectl",
+                    &["show", "--property=Unit", "--value", &self.name],
+                )?;
+                output("systemctl", &["thaw", unit.trim()])?;
+            }
+            // Starting a frozen instance resumes it
+            Manager::Incus { client, project } => {
+                output(client, &["start", "--project", project, self.instance()])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The name Incus knows the instance by, without its project
+    fn instance(&self) -> &str {
+        self.name
+            .split_once('_')
+            .map_or(self.name.as_str(), |(_, name)| name)
+    }
+}
+
+/// A frozen machine, thawed when dropped, also on errors
+pub struct Frozen<'a>(&'a Machine);
+
+impl Drop for Frozen<'_> {
+    fn drop(&mut self) {
+        info!("Thawing machine {}", self.0.name);
+        if let Err(e) = self.0.thaw() {
+            warning!("{:#}, thaw machine {} by hand", e, self.0.name);
+        }
+    }
+}
+
+/// Stdout of `program` with `args`, an error with its stderr if it fails
+fn output(program: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .context(format!("Failed to run {}", program))?;
+    if !output.status.success() {
+        bail!(
+            "{} {} failed: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use chrono::Local;
+
+    #[test]
+    fn finds_nspawn_machines_and_incus_instances() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let (machines, pools) = (dir.join("machines"), dir.join("storage-pools"));
+        for path in [
+            machines.join("web"),
+            machines.join(".#web.tmp"),
+            pools.join("default/containers/db"),
+            pools.join("default/containers/staging_db"),
+            pools.join("default/virtual-machines/win"),
+        ] {
+            backend.add(&path, Local::now());
+        }
+        fs::create_dir_all(machines.join("plain-dir")).unwrap();
+
+        let found = find(backend, &machines, &[("incus", &pools)]);
+        let names: Vec<&str> = found.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["web", "db", "staging_db", "win"]);
+        assert_eq!(found[0].manager, Manager::Nspawn);
+        assert_eq!(
+            found[2].manager,
+            Manager::Incus {
+                client: "incus",
+                project: "staging".to_string()
+            }
+        );
+        assert_eq!(found[2].instance(), "db");
+        assert_eq!(found[3].subvol, pools.join("default/virtual-machines/win"));
+    }
+}
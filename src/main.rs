@@ -1,37 +1,115 @@
-use anyhow::{Result, bail};
-use clap::{CommandFactory, Parser, Subcommand};
-use color_print::cstr;
+use anyhow::{Context, Result, bail};
+use backend::SnapshotBackend;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use color_print::cformat;
+use config::Config;
+use i18n::tr;
 use log::info;
 use nix::unistd::Uid;
 use std::env;
+use std::ffi::OsString;
+use std::io::Write;
 use std::path::PathBuf;
 
+mod agent;
+mod alias;
+mod backend;
+mod bench;
+mod boot;
+mod cache;
 mod cleanup;
+mod clone;
+mod compliance;
 pub mod config;
+mod config_cmd;
+mod container;
+mod convert;
 mod create;
+mod default_subvol;
+mod defaults;
 mod delete;
+mod diff_packages;
+mod discover;
+mod distro;
+mod drift;
+mod exists;
+mod export;
+mod facts;
+mod find;
+mod fleet;
+mod gc;
+mod graph;
+mod hold;
+mod i18n;
+mod in_use;
+mod inhibit;
+mod init_layout;
+mod interrupt;
+mod kernels;
+mod latest;
 mod list;
+mod machine;
+mod maintenance;
+mod manifest;
+mod migrate;
+mod mounts;
+mod notify;
+mod notify_history;
+mod os_path;
+mod permissions;
+mod porcelain;
+mod preset;
+mod protect;
+mod ransomware;
+mod remote;
+mod report;
+mod report_template;
+mod restore;
+mod restore_file;
+mod restore_plan;
+mod retry;
+mod run;
+mod samba;
+mod sandbox;
+mod schema;
+mod selector;
+mod self_service;
+mod set_ro;
+mod signing;
+mod state;
+mod summary;
+mod suspend;
+mod timeout;
+mod tombstone;
+mod top;
+mod tui;
 pub mod utils;
+mod warnings;
+mod watch;
 
-const AFTER_HELP: &str = cstr!(
-    r#"
-<bold><underline>ENVIRONMENT VARIABLES:</underline></bold>
-  <bold>BTRSNAP_CONFIG</bold>
-      Path to the TOML configuration file (e.g., /etc/btrsnap.toml).
-      If set, allows running commands like `btrsnap create` without --config.
-"#
-);
+/// Help epilogue, in the user's language
+fn after_help() -> String {
+    let config: Vec<String> = tr!("after-help-config")
+        .lines()
+        .map(|l| format!("      {}", l))
+        .collect();
+    cformat!(
+        "\n<bold><underline>{}</underline></bold>\n  <bold>BTRSNAP_CONFIG</bold>\n{}\n",
+        tr!("after-help-env-heading"),
+        config.join("\n")
+    )
+}
 
 #[derive(Parser)]
-#[command(
-    about,
-    version,
-    after_help = AFTER_HELP
-)]
+#[command(about, version)]
 struct Cli {
-    /// Path to configuration file (TOML)
+    /// Path or https:// URL of a configuration file (TOML), repeatable:
+    /// later files override earlier ones
     #[arg(short = 'c', long)]
-    config: Option<PathBuf>,
+    config: Vec<PathBuf>,
+    /// Fail the run if it had warnings
+    #[arg(long, global = true)]
+    warnings_as_errors: bool,
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -46,31 +124,257 @@ enum Commands {
     List(list::List),
     /// Cleanup snapshots older than duration (e.g., 7d)
     Cleanup(cleanup::Cleanup),
+    /// Convert an existing directory into a subvolume
+    ConvertToSubvol(convert::ConvertToSubvol),
+    /// Create a standard subvolume layout on a fresh filesystem
+    InitLayout(init_layout::InitLayout),
+    /// Run a btrsnap command on several hosts over SSH
+    Fleet(fleet::Fleet),
+    /// Serve requests from a fleet controller (SSH forced command)
+    Agent(agent::Agent),
+    /// Summarize snapshot counts and space usage
+    Summary(summary::Summary),
+    /// Measure snapshot create, list and delete latency
+    Bench(bench::Bench),
+    /// Snapshot subvolumes when files change ([[watch]] rules)
+    Watch(watch::Watch),
+    /// Roll a subvolume back to a snapshot, saving its current state first
+    Restore(restore::Restore),
+    /// Restore selected files from a snapshot into the live subvolume
+    RestoreFile(restore_file::RestoreFile),
+    /// Plan recovering a subvolume to a point in time, run it with --execute
+    RestorePlan(restore_plan::RestorePlan),
+    /// Show or set the default subvolume
+    DefaultSubvol(default_subvol::DefaultSubvol),
+    /// Create a writable clone of a snapshot, e.g. for testing on a copy
+    Clone(clone::CloneSnapshot),
+    /// Run a command on a throwaway clone of the latest snapshot
+    Sandbox(sandbox::Sandbox),
+    /// Reconcile the manifest with the snapshots actually on disk
+    Gc(gc::Gc),
+    /// Move existing snapshots into btrsnap's layout and naming
+    MigrateLayout(migrate::MigrateLayout),
+    /// Make a snapshot read-only or writable
+    SetRo(set_ro::SetRo),
+    /// Place, remove and list named holds that block deleting snapshots
+    Hold(hold::HoldCommand),
+    /// Pause all deletions from a snapshot dir, e.g. during an incident
+    Maintenance(maintenance::MaintenanceCommand),
+    /// Stop snapshotting a subvolume for a while, without editing the config
+    Disable(suspend::Disable),
+    /// Snapshot a subvolume again after `disable`
+    Enable(suspend::Enable),
+    /// Print the lineage of subvolumes and snapshots for graphviz or Mermaid
+    Graph(graph::Graph),
+    /// Search file names, optionally contents, across snapshots
+    Find(find::Find),
+    /// Compare the installed packages of two snapshots of /, or of one
+    /// and the running system
+    DiffPackages(diff_packages::DiffPackages),
+    /// Keep read-only mounts of the newest snapshots, e.g. for NFS
+    Export(export::Export),
+    /// Print an smb.conf share that shows snapshots as Previous Versions
+    SambaConfig(samba::SambaConfig),
+    /// Exit 0 if a matching snapshot exists, 1 otherwise (for scripts)
+    Exists(exists::Exists),
+    /// Watch the space held by snapshots, per subvolume (needs quotas)
+    Top(top::Top),
+    /// Browse and manage snapshots in a full-screen interface
+    Tui(tui::Tui),
+    /// Print the JSON Schema of a machine-readable output
+    Schema(schema::Schema),
+    /// Check the configuration file
+    Config(config_cmd::ConfigCommand),
+    /// An `[alias]` from the config
+    #[command(external_subcommand)]
+    External(Vec<OsString>),
 }
 
 impl Commands {
-    fn execute(
-        self,
-        snap_dir: Option<PathBuf>,
-        subvols: Vec<PathBuf>,
-        keep_duration: Option<humantime::Duration>,
-    ) -> Result<()> {
+    /// Whether the command changes subvolumes, read-only commands work
+    /// unprivileged on kernels with the unprivileged subvolume ioctls (4.18+)
+    fn needs_root(&self) -> bool {
+        // The agent runs each request as a separate btrsnap process that
+        // checks for itself
+        !matches!(
+            self,
+            Commands::List(_)
+                | Commands::Summary(_)
+                | Commands::Fleet(_)
+                | Commands::Agent(_)
+                | Commands::Config(_)
+                | Commands::Exists(_)
+                | Commands::Find(_)
+                | Commands::DiffPackages(_)
+                | Commands::SambaConfig(_)
+                | Commands::Graph(_)
+                | Commands::Schema(_)
+                // Checked for each step of the alias
+                | Commands::External(_)
+        ) && !matches!(self, Commands::Hold(cmd) if cmd.read_only())
+            && !matches!(self, Commands::Maintenance(cmd) if cmd.read_only())
+            && !matches!(self, Commands::RestorePlan(cmd) if !cmd.execute)
+    }
+
+    /// Whether the command uses the config. Signing must work on configs
+    /// that do not load yet because they are not signed.
+    fn needs_config(&self) -> bool {
         match self {
-            Commands::Create(cmd) => cmd.execute(snap_dir, subvols),
-            Commands::Delete(cmd) => cmd.execute(),
-            Commands::List(cmd) => cmd.execute(snap_dir),
-            Commands::Cleanup(cmd) => cmd.execute(snap_dir, keep_duration),
+            Commands::Config(cmd) => cmd.needs_config(),
+            _ => true,
+        }
+    }
+
+    /// Replace snapshot selectors such as `@home:latest` in the arguments
+    /// with the snapshots they select
+    fn resolve_selectors(&mut self, config: &Config, backend: &dyn SnapshotBackend) -> Result<()> {
+        let snapshots: Vec<&mut PathBuf> = match self {
+            Commands::Delete(cmd) => cmd.snapshot.iter_mut().collect(),
+            Commands::Restore(cmd) => cmd.snapshot.iter_mut().collect(),
+            Commands::RestoreFile(cmd) => vec![&mut cmd.snapshot],
+            Commands::Clone(cmd) => vec![&mut cmd.snapshot],
+            Commands::SetRo(cmd) => vec![&mut cmd.snapshot],
+            Commands::Hold(cmd) => cmd.snapshot_mut().into_iter().collect(),
+            Commands::DiffPackages(cmd) => {
+                std::iter::once(&mut cmd.old).chain(&mut cmd.new).collect()
+            }
+            _ => vec![],
+        };
+        for snapshot in snapshots {
+            *snapshot = selector::resolve(backend, config, snapshot)?;
         }
+        Ok(())
+    }
+
+    fn execute(self, config: Config, backend: &'static dyn SnapshotBackend) -> Result<()> {
+        match self {
+            Commands::Create(cmd) => cmd.execute(backend, config),
+            Commands::Delete(cmd) => cmd.execute(backend, config),
+            Commands::List(cmd) => cmd.execute(backend, config),
+            Commands::Cleanup(cmd) => cmd.execute(backend, config),
+            Commands::ConvertToSubvol(cmd) => cmd.execute(backend),
+            Commands::InitLayout(cmd) => cmd.execute(backend),
+            Commands::Fleet(cmd) => cmd.execute(),
+            Commands::Agent(cmd) => cmd.execute(config.paths),
+            Commands::Summary(cmd) => cmd.execute(backend, config),
+            Commands::Bench(cmd) => cmd.execute(backend),
+            Commands::Watch(cmd) => cmd.execute(backend, config),
+            Commands::Restore(cmd) => cmd.execute(backend, config),
+            Commands::RestoreFile(cmd) => cmd.execute(backend, config),
+            Commands::RestorePlan(cmd) => cmd.execute(backend, config),
+            Commands::DefaultSubvol(cmd) => cmd.execute(backend, config),
+            Commands::Clone(cmd) => cmd.execute(backend, config.snap_dir),
+            Commands::Sandbox(cmd) => cmd.execute(backend, config),
+            Commands::Gc(cmd) => cmd.execute(backend, config),
+            Commands::MigrateLayout(cmd) => cmd.execute(backend, config),
+            Commands::Config(cmd) => cmd.execute(backend, config),
+            Commands::Exists(cmd) => cmd.execute(backend, config),
+            Commands::Find(cmd) => cmd.execute(backend, config),
+            Commands::DiffPackages(cmd) => cmd.execute(backend, config),
+            Commands::SambaConfig(cmd) => cmd.execute(backend, config),
+            Commands::Export(cmd) => cmd.execute(backend, config),
+            Commands::Graph(cmd) => cmd.execute(backend, config),
+            Commands::SetRo(cmd) => cmd.execute(backend),
+            Commands::Hold(cmd) => cmd.execute(backend, config),
+            Commands::Maintenance(cmd) => cmd.execute(backend, config),
+            Commands::Disable(cmd) => cmd.execute(backend, config),
+            Commands::Enable(cmd) => cmd.execute(backend, config),
+            Commands::Schema(cmd) => cmd.execute(),
+            Commands::Tui(cmd) => cmd.execute(backend, config),
+            Commands::Top(cmd) => cmd.execute(backend, config.snap_dir),
+            Commands::External(_) => unreachable!("aliases are expanded in main"),
+        }
+    }
+}
+
+/// Split the command line at `--then` into the first command's arguments
+/// and the words of each command chained after it. Arguments after `--`
+/// belong to the command before it.
+fn split_chain(args: Vec<OsString>) -> (Vec<OsString>, Vec<Vec<OsString>>) {
+    let mut commands = vec![vec![]];
+    let mut verbatim = false;
+    for arg in args {
+        if arg == "--then" && !verbatim {
+            commands.push(vec![]);
+            continue;
+        }
+        verbatim |= arg == "--";
+        commands.last_mut().unwrap().push(arg);
+    }
+    let first = commands.remove(0);
+    (first, commands)
+}
+
+/// Parse `args` as a full command line, failing unless it names a btrsnap
+/// command
+fn parse(cli_command: &mut clap::Command, args: &[OsString]) -> Result<(Commands, ArgMatches)> {
+    let matches = cli_command.try_get_matches_from_mut(args)?;
+    match Cli::from_arg_matches(&matches)?.command {
+        Some(Commands::External(words)) => {
+            bail!("Unknown command '{}'", words[0].to_string_lossy())
+        }
+        Some(command) => Ok((command, matches)),
+        None => bail!("No command given"),
+    }
+}
+
+/// Run one command parsed from `args`, after applying the config's defaults
+/// for it
+fn run(
+    cli_command: &mut clap::Command,
+    args: &[OsString],
+    matches: &ArgMatches,
+    mut command: Commands,
+    config: &Config,
+    caller: Option<Uid>,
+) -> Result<()> {
+    // Parse again with the config's defaults for the subcommand
+    if let Some(args) = defaults::apply(cli_command, matches, args, &config.command_defaults)? {
+        let section = matches.subcommand_name().unwrap_or_default();
+        command = parse(cli_command, &args)
+            .context(format!("Invalid defaults in [{}] of the config", section))?
+            .0;
     }
+    if let (Some(compliance), Some(snap_dir)) = (&config.compliance, &config.snap_dir)
+        && command.needs_root()
+    {
+        compliance::arm(backend::get(), snap_dir, compliance)?;
+    }
+    command.resolve_selectors(config, backend::get())?;
+    let command = match caller {
+        Some(user) => self_service::restrict(command, config, backend::get(), user)?,
+        None => command,
+    };
+    command.execute(config.clone(), backend::get())
 }
 
 fn main() -> Result<()> {
-    env_logger::init();
+    if self_service::caller().is_some() {
+        self_service::clear_env();
+    }
+    env_logger::Builder::from_default_env()
+        .format(|buf, record| {
+            writeln!(
+                buf,
+                "[{} {:<5} {} run={}] {}",
+                buf.timestamp(),
+                record.level(),
+                record.target(),
+                run::id(),
+                record.args()
+            )
+        })
+        .init();
     info!("Starting btrsnap");
 
     // Parse CLI arguments, handling errors explicitly
-    let cli = match Cli::try_parse() {
-        Ok(cli) => cli,
+    let mut cli_command = Cli::command().after_help(after_help());
+    let (args, chain) = split_chain(env::args_os().collect());
+    let (cli, matches) = match cli_command
+        .try_get_matches_from_mut(&args)
+        .and_then(|matches| Ok((Cli::from_arg_matches(&matches)?, matches)))
+    {
+        Ok(parsed) => parsed,
         Err(e) => {
             // Print any parsing errors and exit
             e.print()?;
@@ -79,24 +383,132 @@ fn main() -> Result<()> {
     };
 
     // If no subcommand is provided, explicitly print help and exit
-    if cli.command.is_none() {
-        Cli::command().print_help()?;
+    let Some(command) = cli.command else {
+        cli_command.print_help()?;
         return Ok(());
-    }
+    };
 
-    // Check for root privileges only if a subcommand is provided
-    if !Uid::effective().is_root() {
-        bail!("Error: Must run with sudo or as root for BTRFS operations");
+    if command.needs_root() && !Uid::effective().is_root() {
+        bail!(tr!("need-root"));
     }
 
-    let config_path = cli.config.or_else(|| {
-        env::var("BTRSNAP_CONFIG")
-            .ok()
-            .and_then(|s| PathBuf::from(s).canonicalize().ok())
-    });
+    // Running setuid root for a user, who must not pick the config
+    let caller = self_service::caller();
+    let config_paths = match caller {
+        Some(_) => vec![self_service::system_config()?],
+        None if cli.config.is_empty() => {
+            let list = env::var("BTRSNAP_CONFIG").unwrap_or_default();
+            let mut paths = vec![];
+            for source in remote::split_sources(&list) {
+                if remote::is_url(&source) {
+                    paths.push(remote::fetch(&source)?);
+                } else if let Ok(path) = PathBuf::from(source).canonicalize() {
+                    paths.push(path);
+                }
+            }
+            paths
+        }
+        None => cli
+            .config
+            .into_iter()
+            .map(|path| match path.to_str().filter(|s| remote::is_url(s)) {
+                Some(url) => remote::fetch(url),
+                None => Ok(path),
+            })
+            .collect::<Result<_>>()?,
+    };
+
+    let config = if command.needs_config() {
+        config::load(&config_paths, backend::get())?
+    } else {
+        Config::default()
+    };
+    state::init(
+        config
+            .state_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(state::DEFAULT_ROOT)),
+    );
 
-    let (snap_dir, toml_subvols, toml_keep) = config::load(config_path)?;
-    cli.command
-        .unwrap()
-        .execute(snap_dir, toml_subvols, toml_keep)
+    // An alias runs its steps in order, each parsed like a command line,
+    // followed by the commands chained with --then
+    let mut steps = match command {
+        Commands::External(words) => {
+            let mut steps = vec![];
+            for step in alias::expand(&config.aliases, &words)? {
+                let args: Vec<OsString> = args[..1]
+                    .iter()
+                    .cloned()
+                    .chain(step.into_iter().map(OsString::from))
+                    .collect();
+                let (command, matches) = parse(&mut cli_command, &args).context(format!(
+                    "Invalid step in alias '{}'",
+                    words[0].to_string_lossy()
+                ))?;
+                steps.push((args, matches, command));
+            }
+            steps
+        }
+        command => vec![(args.clone(), matches, command)],
+    };
+    for words in chain {
+        let args: Vec<OsString> = args[..1].iter().cloned().chain(words).collect();
+        let (command, matches) =
+            parse(&mut cli_command, &args).context("Invalid command after --then")?;
+        steps.push((args, matches, command));
+    }
+
+    interrupt::install()?;
+    let mut result = Ok(());
+    let total = steps.len();
+    if total > 1 {
+        report::chain();
+    }
+    for (n, (args, matches, command)) in steps.into_iter().enumerate() {
+        if total > 1 {
+            let words: Vec<_> = args[1..].iter().map(|a| a.to_string_lossy()).collect();
+            eprintln!(
+                "{}",
+                tr!(
+                    "chain-step",
+                    step = n + 1,
+                    steps = total,
+                    command = words.join(" ")
+                )
+            );
+        }
+        // Also for a one-step alias, which skipped the check above
+        if command.needs_root() && !Uid::effective().is_root() {
+            result = Err(anyhow::anyhow!(tr!("need-root")));
+            break;
+        }
+        result = run(&mut cli_command, &args, &matches, command, &config, caller);
+        if total > 1 {
+            result = result.context(tr!("chain-failed", step = n + 1, steps = total));
+        }
+        if result.is_err() || interrupt::requested() {
+            break;
+        }
+    }
+    report::print_chained();
+    warnings::print();
+    if result.is_ok() && cli.warnings_as_errors && warnings::count() > 0 {
+        result = Err(anyhow::anyhow!(tr!(
+            "warnings-as-errors",
+            count = warnings::count()
+        )));
+    }
+    // A lone `exists` answers through its exit status only
+    if total == 1
+        && let Err(e) = &result
+        && e.is::<exists::NoMatch>()
+    {
+        std::process::exit(1);
+    }
+    let command = env::args_os()
+        .map(|a| a.to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join(" ");
+    notify::finished(&config, &command, &result);
+    result
 }
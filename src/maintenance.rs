@@ -0,0 +1,160 @@
+//! Maintenance mode: deletions from a snapshot dir are paused, even with
+//! `--force`, while snapshots are still created. The flag lives in the
+//! state dir so it holds across runs and timers until switched off.
+
+use crate::backend::SnapshotBackend;
+use crate::config::Config;
+use crate::{hold, state, utils};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const FILE: &str = "maintenance.json";
+
+#[derive(clap::Parser)]
+pub struct MaintenanceCommand {
+    #[command(subcommand)]
+    action: Action,
+    #[arg(short = 'd', long, value_parser = utils::parse_path, global = true)]
+    snap_dir: Option<PathBuf>,
+}
+
+#[derive(clap::Subcommand)]
+enum Action {
+    /// Pause cleanup and every other deletion from the snapshot dir
+    On {
+        /// Why, e.g. "incident 42", shown wherever deletions are refused
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Resume deletions
+    Off,
+    /// Print whether maintenance mode is on
+    Status,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Maintenance {
+    pub since: DateTime<Local>,
+    pub by: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl std::fmt::Display for Maintenance {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "since {} by {}",
+            self.since.format("%Y-%m-%d %H:%M"),
+            self.by
+        )?;
+        if let Some(reason) = &self.reason {
+            write!(f, ": {}", reason)?;
+        }
+        Ok(())
+    }
+}
+
+impl MaintenanceCommand {
+    /// Status works unprivileged, switching writes the state dir
+    pub fn read_only(&self) -> bool {
+        matches!(self.action, Action::Status)
+    }
+
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        let snap_dir = utils::resolve_snap_dir(backend, self.snap_dir, config.snap_dir)?;
+        match self.action {
+            Action::On { reason } => {
+                let maintenance = Maintenance {
+                    since: Local::now(),
+                    by: hold::user(),
+                    reason,
+                };
+                set(&snap_dir, Some(&maintenance))?;
+                println!(
+                    "Maintenance mode on for {}, deletions are paused",
+                    snap_dir.display()
+                );
+            }
+            Action::Off => {
+                set(&snap_dir, None)?;
+                println!("Maintenance mode off for {}", snap_dir.display());
+            }
+            Action::Status => match active(&snap_dir)? {
+                Some(m) => println!("Maintenance mode on for {} {}", snap_dir.display(), m),
+                None => println!("Maintenance mode off for {}", snap_dir.display()),
+            },
+        }
+        Ok(())
+    }
+}
+
+/// Maintenance mode of `snap_dir`, if on
+pub fn active(snap_dir: &Path) -> Result<Option<Maintenance>> {
+    let path = state::dir(snap_dir).join(FILE);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context(format!("Failed to read {}", path.display())),
+    };
+    // Like the compliance lock, a broken file keeps deletions paused
+    let maintenance = serde_json::from_str(&content)
+        .context(format!("Invalid maintenance file {}", path.display()))?;
+    Ok(Some(maintenance))
+}
+
+/// Switch maintenance mode of `snap_dir` on, or off with `None`
+fn set(snap_dir: &Path, maintenance: Option<&Maintenance>) -> Result<()> {
+    let dir = state::dir(snap_dir);
+    let path = dir.join(FILE);
+    let Some(maintenance) = maintenance else {
+        return match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).context(format!("Failed to remove {}", path.display()))
+            }
+            _ => Ok(()),
+        };
+    };
+    fs::create_dir_all(&dir).context(format!("Failed to create {}", dir.display()))?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(maintenance)?)
+        .context(format!("Failed to write {}", tmp.display()))?;
+    fs::rename(&tmp, &path).context(format!("Failed to replace {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use crate::protect::{Guard, Policy};
+
+    #[test]
+    fn pauses_even_forced_deletions_until_switched_off() {
+        let backend = MockBackend::leak();
+        let snap_dir = backend.scratch_dir();
+        let snapshot = snap_dir.join("home-1");
+        backend.add(&snapshot, Local::now());
+        let info = backend.info(&snapshot).unwrap();
+        let guard = Guard::new(backend, Policy::default(), true);
+
+        set(
+            &snap_dir,
+            Some(&Maintenance {
+                since: Local::now(),
+                by: "root".into(),
+                reason: Some("incident 42".into()),
+            }),
+        )
+        .unwrap();
+        let err = guard.check(&info).unwrap_err().to_string();
+        assert!(err.contains("incident 42"), "{}", err);
+
+        set(&snap_dir, None).unwrap();
+        set(&snap_dir, None).unwrap();
+        assert!(active(&snap_dir).unwrap().is_none());
+        assert!(guard.check(&info).is_ok());
+    }
+}
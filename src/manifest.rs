@@ -0,0 +1,229 @@
+use crate::backend::SubvolInfo;
+use crate::state;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDate};
+use log::debug;
+use nix::fcntl::{Flock, FlockArg};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+/// Directory inside the snapshot dir holding btrsnap's own subvolumes
+/// (sandbox clones), and its files before they moved to [`crate::state::dir`]
+pub const STATE_DIR: &str = ".btrsnap";
+const MANIFEST_FILE: &str = "manifest.json";
+/// Locked while the manifest is changed; the manifest itself is replaced
+/// on every save, so it cannot carry the lock
+const LOCK_FILE: &str = "manifest.lock";
+
+/// Inventory of the snapshots btrsnap created in a snapshot dir
+#[derive(Default, Deserialize, JsonSchema, Serialize)]
+pub struct Manifest {
+    /// Snapshots keyed by directory name
+    pub snapshots: BTreeMap<String, Entry>,
+}
+
+#[derive(Clone, Deserialize, JsonSchema, Serialize)]
+pub struct Entry {
+    #[serde(with = "crate::os_path")]
+    #[schemars(with = "crate::os_path::JsonPath")]
+    pub source: PathBuf,
+    pub created: DateTime<Local>,
+    pub uuid: String,
+    pub generation: u64,
+    pub otransid: u64,
+    /// What caused the snapshot, e.g. `watch:/etc`, unset for `create`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trigger: Option<String>,
+    /// For `pre-rollback` snapshots, the snapshot that was restored over them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replaced_by: Option<String>,
+    /// Rendered `description` template, with the system facts at creation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Packages of the transaction a package-manager hook snapshot was taken
+    /// before
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<String>,
+    /// Run that created the snapshot, see [`crate::run::id`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_by_run: Option<String>,
+    /// Why cleanup keeps the snapshot until deleted with `--force`, e.g.
+    /// the last known-good snapshot before a ransomware alert
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pinned: Option<String>,
+    /// Read-only flag last set with `set-ro`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_only: Option<bool>,
+    /// When `set-ro` last changed the flag
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_only_changed: Option<DateTime<Local>>,
+    /// Holds placed with `hold add`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub holds: Vec<Hold>,
+}
+
+/// A named reason to keep a snapshot, blocking its deletion until it expires
+#[derive(Clone, Deserialize, JsonSchema, Serialize)]
+pub struct Hold {
+    pub name: String,
+    pub reason: String,
+    /// Last day the hold applies, held until removed if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<NaiveDate>,
+    pub created: DateTime<Local>,
+    /// User who placed the hold
+    pub by: String,
+}
+
+impl Hold {
+    pub fn active(&self) -> bool {
+        self.until
+            .is_none_or(|until| Local::now().date_naive() <= until)
+    }
+}
+
+impl Entry {
+    pub fn new(source: &Path, info: &SubvolInfo) -> Self {
+        Entry {
+            source: source.to_path_buf(),
+            created: info.otime,
+            uuid: info.uuid.to_string(),
+            generation: info.generation,
+            otransid: info.otransid,
+            trigger: None,
+            replaced_by: None,
+            description: None,
+            packages: vec![],
+            created_by_run: None,
+            pinned: None,
+            read_only: None,
+            read_only_changed: None,
+            holds: vec![],
+        }
+    }
+
+    /// Whether this entry is about `info`, or `info` was received from the
+    /// snapshot it is about
+    pub fn describes(&self, info: &SubvolInfo) -> bool {
+        self.uuid == info.uuid.to_string()
+            || info
+                .received_uuid
+                .is_some_and(|uuid| self.uuid == uuid.to_string())
+    }
+}
+
+impl Manifest {
+    fn path(snap_dir: &Path) -> PathBuf {
+        state::dir(snap_dir).join(MANIFEST_FILE)
+    }
+
+    /// Load the manifest of `snap_dir`, `None` if it has none yet
+    pub fn load(snap_dir: &Path) -> Result<Option<Self>> {
+        let path = Self::path(snap_dir);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)
+            .context(format!("Failed to read manifest: {}", path.display()))?;
+        let manifest = serde_json::from_str(&content)
+            .context(format!("Invalid manifest: {}", path.display()))?;
+        Ok(Some(manifest))
+    }
+
+    pub fn save(&self, snap_dir: &Path) -> Result<()> {
+        let path = Self::path(snap_dir);
+        let dir = state::dir(snap_dir);
+        fs::create_dir_all(&dir).context(format!("Failed to create {}", dir.display()))?;
+        // Write to a temporary file first so readers never see a partial manifest
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)
+            .context(format!("Failed to write manifest: {}", tmp.display()))?;
+        fs::rename(&tmp, &path).context(format!("Failed to replace manifest: {}", path.display()))
+    }
+
+    /// Lock the manifest of `snap_dir` until the returned guard is dropped.
+    /// Take it before loading a manifest to change and save it, so runs at
+    /// the same time don't drop each other's changes.
+    pub fn lock(snap_dir: &Path) -> Result<Flock<File>> {
+        let dir = state::dir(snap_dir);
+        fs::create_dir_all(&dir).context(format!("Failed to create {}", dir.display()))?;
+        let path = dir.join(LOCK_FILE);
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .context(format!("Failed to open {}", path.display()))?;
+        Flock::lock(file, FlockArg::LockExclusive)
+            .map_err(|(_, errno)| errno)
+            .context(format!("Failed to lock {}", path.display()))
+    }
+
+    /// Add a new snapshot to the manifest of `snap_dir`
+    pub fn record(snap_dir: &Path, name: &str, entry: Entry) -> Result<()> {
+        debug!("Recording {} in manifest", name);
+        let _lock = Self::lock(snap_dir)?;
+        let mut manifest = Self::load(snap_dir)?.unwrap_or_default();
+        manifest.snapshots.insert(name.to_string(), entry);
+        manifest.save(snap_dir)
+    }
+
+    /// Change the entry of `name` in the manifest of `snap_dir`, if present
+    pub fn update(snap_dir: &Path, name: &str, f: impl FnOnce(&mut Entry)) -> Result<()> {
+        if !Self::path(snap_dir).exists() {
+            return Ok(());
+        }
+        let _lock = Self::lock(snap_dir)?;
+        if let Some(mut manifest) = Self::load(snap_dir)?
+            && let Some(entry) = manifest.snapshots.get_mut(name)
+        {
+            f(entry);
+            manifest.save(snap_dir)?;
+        }
+        Ok(())
+    }
+
+    /// Drop a deleted snapshot from the manifest of `snap_dir`, if there is one
+    pub fn forget(snap_dir: &Path, name: &str) -> Result<()> {
+        if !Self::path(snap_dir).exists() {
+            return Ok(());
+        }
+        let _lock = Self::lock(snap_dir)?;
+        if let Some(mut manifest) = Self::load(snap_dir)?
+            && manifest.snapshots.remove(name).is_some()
+        {
+            debug!("Removing {} from manifest", name);
+            manifest.save(snap_dir)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::SnapshotBackend;
+    use crate::backend::mock::MockBackend;
+    use std::thread;
+
+    #[test]
+    fn concurrent_records_are_all_kept() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let (source, snap_dir) = (dir.join("home"), dir.join("snapshots"));
+        backend.add(&source, Local::now());
+        let info = backend.info(&source).unwrap();
+
+        thread::scope(|s| {
+            for n in 0..8 {
+                let (snap_dir, entry) = (&snap_dir, Entry::new(&source, &info));
+                s.spawn(move || Manifest::record(snap_dir, &format!("home-{}", n), entry));
+            }
+        });
+        let manifest = Manifest::load(&snap_dir).unwrap().unwrap();
+        assert_eq!(manifest.snapshots.len(), 8);
+    }
+}
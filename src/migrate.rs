@@ -0,0 +1,326 @@
+use crate::backend::{SnapshotBackend, SubvolInfo};
+use crate::config::Config;
+use crate::manifest::{Entry, Manifest, STATE_DIR};
+use crate::warnings::warning;
+use crate::{create, utils};
+use anyhow::{Context, Result, anyhow, bail};
+use log::{debug, info};
+use std::collections::BTreeSet;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Layout {
+    /// `<snap-dir>/<subvol>-<timestamp>`, the layout btrsnap manages
+    Flat,
+    /// `<snap-dir>/<subvol>/<snapshot>`, as left by other tools
+    PerSubvol,
+}
+
+#[derive(clap::Parser)]
+pub struct MigrateLayout {
+    /// Snapshot dir to migrate
+    #[arg(short = 'd', long, value_parser = utils::parse_path)]
+    pub snap_dir: Option<PathBuf>,
+    /// Layout the snapshots are in now
+    #[arg(long, value_enum, default_value = "flat")]
+    pub from: Layout,
+    /// Layout to move them to
+    #[arg(long, value_enum, default_value = "flat")]
+    pub to: Layout,
+    /// Rename snapshots of subvolume OLD to NEW, e.g. after renaming the
+    /// subvolume (OLD=NEW, repeatable)
+    #[arg(long, value_parser = parse_rename)]
+    pub rename: Vec<(String, String)>,
+    /// Only print the moves
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+}
+
+/// A snapshot to move to `<snap-dir>/<name>`
+struct Move {
+    info: SubvolInfo,
+    name: String,
+}
+
+impl MigrateLayout {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        if self.to != Layout::Flat {
+            bail!(
+                "btrsnap only manages the flat layout, cleanup would not see snapshots in subdirectories"
+            );
+        }
+        let snap_dir =
+            utils::resolve_snap_dir(backend, self.snap_dir.clone(), config.snap_dir.clone())?;
+        let moves = match self.from {
+            Layout::Flat => self.flat_moves(backend, &snap_dir)?,
+            Layout::PerSubvol => self.per_subvol_moves(backend, &snap_dir)?,
+        };
+        if moves.is_empty() {
+            println!("Nothing to migrate");
+            return Ok(());
+        }
+
+        let _lock = (!self.dry_run)
+            .then(|| Manifest::lock(&snap_dir))
+            .transpose()?;
+        let mut manifest = Manifest::load(&snap_dir)?.unwrap_or_default();
+        let mut moved = 0;
+        let mut emptied = BTreeSet::new();
+        for Move { info, name } in moves {
+            let dest = snap_dir.join(&name);
+            if dest.symlink_metadata().is_ok() {
+                warning!(
+                    "{} already exists, not moving {}",
+                    dest.display(),
+                    info.path.display()
+                );
+                continue;
+            }
+            println!("{} -> {}", info.path.display(), dest.display());
+            moved += 1;
+            if self.dry_run {
+                continue;
+            }
+            fs::rename(&info.path, &dest).context(format!(
+                "Failed to move {} to {}",
+                info.path.display(),
+                dest.display()
+            ))?;
+            if let Some(parent) = info.path.parent()
+                && parent != snap_dir
+            {
+                emptied.insert(parent.to_path_buf());
+            }
+
+            // Only snapshots that were directly in the snapshot dir can have
+            // an entry under their old name
+            let old_entry = match (info.path.parent(), utils::file_name(&info.path)) {
+                (Some(parent), Some(old)) if parent == snap_dir => manifest.snapshots.remove(&old),
+                _ => None,
+            };
+            let source = utils::parse_snapshot_name(&name).and_then(|(prefix, _)| {
+                config
+                    .subvols
+                    .iter()
+                    .find(|sv| create::subvol_name(sv, config.name_parents) == prefix)
+            });
+            let entry = match (old_entry, source) {
+                (Some(mut entry), Some(source)) => {
+                    entry.source = source.clone();
+                    Some(entry)
+                }
+                (Some(entry), None) => Some(entry),
+                (None, Some(source)) => Some(Entry::new(source, &info)),
+                (None, None) => None,
+            };
+            if let Some(entry) = entry {
+                manifest.snapshots.insert(name, entry);
+            }
+        }
+
+        if self.dry_run {
+            println!("{} snapshots would be moved", moved);
+            return Ok(());
+        }
+        manifest.save(&snap_dir)?;
+        for dir in emptied {
+            // Fails unless the migration emptied it, then it is left alone
+            if fs::remove_dir(&dir).is_ok() {
+                info!("Removed empty directory {}", dir.display());
+            }
+        }
+        println!("Moved {} snapshots", moved);
+        Ok(())
+    }
+
+    /// New prefix for snapshots of `prefix`, if it is renamed
+    fn renamed(&self, prefix: &str) -> Option<&str> {
+        self.rename
+            .iter()
+            .find(|(old, _)| crate::create::canonical_name(old) == prefix)
+            .map(|(_, new)| new.as_str())
+    }
+
+    /// Flat snapshots whose prefix is renamed
+    fn flat_moves(&self, backend: &dyn SnapshotBackend, snap_dir: &Path) -> Result<Vec<Move>> {
+        if self.rename.is_empty() {
+            bail!("Nothing to do from the flat layout without --rename");
+        }
+        let mut moves = vec![];
+        utils::scan_snapshots(backend, snap_dir, |info| {
+            let name = utils::snapshot_name(&info);
+            if let Some((prefix, _)) = utils::parse_snapshot_name(&name)
+                && let Some(new) = self.renamed(&prefix)
+                && let Some((_, stamp)) = name.rsplit_once('-')
+            {
+                // The timestamp with its same-second counter, if any
+                let name = format!("{}-{}", new, stamp);
+                moves.push(Move { info, name });
+            }
+            Ok(())
+        })?;
+        Ok(moves)
+    }
+
+    /// Snapshots in per-subvolume directories, named after the directory
+    /// and their creation time, counting up within the same second as
+    /// `create` does
+    fn per_subvol_moves(
+        &self,
+        backend: &dyn SnapshotBackend,
+        snap_dir: &Path,
+    ) -> Result<Vec<Move>> {
+        let mut moves = vec![];
+        let mut taken = BTreeSet::new();
+        let entries =
+            fs::read_dir(snap_dir).context(format!("Failed to read {}", snap_dir.display()))?;
+        for entry in entries {
+            let dir = entry?.path();
+            if !dir.is_dir() || backend.is_subvolume(&dir) || dir.ends_with(STATE_DIR) {
+                continue;
+            }
+            let Some(prefix) = dir.file_name().map(|n| create::escape_name(n.as_bytes())) else {
+                continue;
+            };
+            let prefix = self.renamed(&prefix).unwrap_or(&prefix).to_string();
+            debug!("Collecting snapshots of {} in {}", prefix, dir.display());
+            utils::scan_snapshots(backend, &dir, |info| {
+                let base = format!("{}-{}", prefix, info.otime.timestamp());
+                let name = (0..)
+                    .map(|n| match n {
+                        0 => base.clone(),
+                        n => format!("{}.{}", base, n),
+                    })
+                    .find(|name| {
+                        !taken.contains(name) && snap_dir.join(name).symlink_metadata().is_err()
+                    })
+                    .unwrap();
+                taken.insert(name.clone());
+                moves.push(Move { info, name });
+                Ok(())
+            })?;
+        }
+        Ok(moves)
+    }
+}
+
+/// `OLD=NEW`, with NEW escaped as snapshot names are, given escaped or not
+fn parse_rename(s: &str) -> Result<(String, String)> {
+    let (old, new) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Expected OLD=NEW, got {}", s))?;
+    if old.is_empty() || new.is_empty() || new.contains('/') {
+        bail!("Invalid rename {}", s);
+    }
+    let new = create::escape_name(&create::unescape_name(new));
+    Ok((old.to_string(), new))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use chrono::{Local, TimeZone};
+
+    #[test]
+    fn moves_per_subvol_snapshots_into_flat_names() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let (source, snap_dir) = (dir.join("home"), dir.join("snapshots"));
+        backend.add(&source, Local::now());
+        let otime = Local.timestamp_opt(1_700_000_000, 0).unwrap();
+        backend.add(&snap_dir.join("@home").join("1"), otime);
+
+        MigrateLayout {
+            snap_dir: Some(snap_dir.clone()),
+            from: Layout::PerSubvol,
+            to: Layout::Flat,
+            rename: vec![("@home".to_string(), "home".to_string())],
+            dry_run: false,
+        }
+        .execute(
+            backend,
+            Config {
+                subvols: vec![source.clone()],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert!(snap_dir.join("home-1700000000").is_dir());
+        assert!(!snap_dir.join("@home").exists());
+        let manifest = Manifest::load(&snap_dir).unwrap().unwrap();
+        assert_eq!(manifest.snapshots["home-1700000000"].source, source);
+    }
+
+    #[test]
+    fn keeps_same_second_counters() {
+        let backend = MockBackend::leak();
+        let snap_dir = backend.scratch_dir();
+        let otime = Local.timestamp_opt(100, 0).unwrap();
+        for name in ["home-100", "home-100.1", "@old/a", "@old/b"] {
+            backend.add(&snap_dir.join(name), otime);
+        }
+
+        let migrate = |from| MigrateLayout {
+            snap_dir: Some(snap_dir.clone()),
+            from,
+            to: Layout::Flat,
+            rename: vec![("home".to_string(), "data".to_string())],
+            dry_run: false,
+        };
+        migrate(Layout::Flat)
+            .execute(backend, Config::default())
+            .unwrap();
+        migrate(Layout::PerSubvol)
+            .execute(backend, Config::default())
+            .unwrap();
+
+        for name in ["data-100", "data-100.1", "@old-100", "@old-100.1"] {
+            assert!(snap_dir.join(name).is_dir(), "{} missing", name);
+        }
+        assert!(!snap_dir.join("home-100.1").exists());
+    }
+
+    #[test]
+    fn escapes_directory_names_and_new_names() {
+        use std::ffi::OsStr;
+
+        let backend = MockBackend::leak();
+        let snap_dir = backend.scratch_dir();
+        let otime = Local.timestamp_opt(100, 0).unwrap();
+        let dir = snap_dir.join(OsStr::from_bytes(b"old-data \xff"));
+        backend.add(&dir.join("a"), otime);
+        backend.add(&snap_dir.join("my-home").join("b"), otime);
+
+        MigrateLayout {
+            snap_dir: Some(snap_dir.clone()),
+            from: Layout::PerSubvol,
+            to: Layout::Flat,
+            rename: vec![parse_rename("my-home=new home").unwrap()],
+            dry_run: false,
+        }
+        .execute(backend, Config::default())
+        .unwrap();
+
+        for name in ["old-data%20%FF-100", "new%20home-100"] {
+            assert!(snap_dir.join(name).is_dir(), "{} missing", name);
+        }
+    }
+
+    #[test]
+    fn rejects_per_subvol_target_and_bad_renames() {
+        assert!(parse_rename("@home").is_err());
+        let backend = MockBackend::leak();
+        let migrate = MigrateLayout {
+            snap_dir: Some(backend.scratch_dir()),
+            from: Layout::Flat,
+            to: Layout::PerSubvol,
+            rename: vec![],
+            dry_run: true,
+        };
+        assert!(migrate.execute(backend, Config::default()).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+//! The mount table, from `/proc/self/mountinfo`
+
+use anyhow::{Context, Result};
+use std::ffi::OsString;
+use std::fs;
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+
+/// One mounted filesystem
+#[derive(Debug, PartialEq)]
+pub struct Mount {
+    /// Directory of the filesystem mounted, e.g. `/@home` for a subvolume
+    pub root: PathBuf,
+    pub point: PathBuf,
+    pub fstype: String,
+    pub source: String,
+    /// Filesystem-specific options, e.g. `subvol=/@home`
+    pub options: String,
+}
+
+/// Current mounts of this process
+pub fn read() -> Result<Vec<Mount>> {
+    // Mount points need not be UTF-8
+    let mountinfo =
+        fs::read("/proc/self/mountinfo").context("Failed to read /proc/self/mountinfo")?;
+    Ok(parse(&mountinfo))
+}
+
+pub fn parse(mountinfo: &[u8]) -> Vec<Mount> {
+    let text = |field: &[u8]| String::from_utf8_lossy(field).into_owned();
+    let path = |field: &[u8]| PathBuf::from(OsString::from_vec(unescape(field)));
+    mountinfo
+        .split(|&b| b == b'\n')
+        .filter_map(|line| {
+            let sep = line.windows(3).position(|w| w == b" - ")?;
+            let mount: Vec<&[u8]> = line[..sep].split(|&b| b == b' ').collect();
+            let fs: Vec<&[u8]> = line[sep + 3..].split(|&b| b == b' ').collect();
+            Some(Mount {
+                root: path(mount.get(3)?),
+                point: path(mount.get(4)?),
+                fstype: text(fs.first()?),
+                source: text(&unescape(fs.get(1)?)),
+                options: fs.get(2).map_or(String::new(), |o| text(o)),
+            })
+        })
+        .collect()
+}
+
+/// Undo the octal escapes mountinfo uses for spaces, tabs and such
+fn unescape(field: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(field.len());
+    let mut i = 0;
+    while i < field.len() {
+        let code = field
+            .get(i + 1..i + 4)
+            .and_then(|c| std::str::from_utf8(c).ok())
+            .and_then(|c| u8::from_str_radix(c, 8).ok());
+        match code {
+            Some(byte) if field[i] == b'\\' => {
+                out.push(byte);
+                i += 4;
+            }
+            _ => {
+                out.push(field[i]);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::ffi::OsStrExt;
+
+    #[test]
+    fn parses_mountinfo_lines() {
+        let mountinfo = "22 1 0:21 /@ / rw shared:1 - btrfs /dev/sda2 rw,subvol=/@\n\
+                         30 22 0:21 /@data /srv/my\\040data rw - btrfs /dev/sda2 rw\n";
+        let mounts = parse(mountinfo.as_bytes());
+        assert_eq!(mounts.len(), 2);
+        assert_eq!(mounts[0].root, PathBuf::from("/@"));
+        assert_eq!(mounts[0].fstype, "btrfs");
+        assert_eq!(mounts[0].options, "rw,subvol=/@");
+        assert_eq!(mounts[1].point, PathBuf::from("/srv/my data"));
+    }
+
+    #[test]
+    fn keeps_mount_points_that_are_not_utf8() {
+        let mountinfo = b"40 22 0:21 /@x /mnt/\xff rw - btrfs /dev/sda2 rw\n\
+                          41 22 0:21 /@y /mnt/caf\\351 rw - btrfs /dev/sda2 rw\n";
+        let mounts = parse(mountinfo);
+        assert_eq!(mounts[0].point.as_os_str().as_bytes(), b"/mnt/\xff");
+        assert_eq!(mounts[1].point.as_os_str().as_bytes(), b"/mnt/caf\xe9");
+    }
+}
@@ -0,0 +1,433 @@
+use crate::config::{ChatConfig, ChatService, Config, EmailConfig};
+use crate::notify_history::{self, History};
+use crate::warnings::warning;
+use crate::{report, run, state};
+use anyhow::{Context, Result, anyhow};
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use log::info;
+use nix::unistd::gethostname;
+use serde_json::json;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// How urgent an event is, for `min-severity` of chat backends
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+    Critical,
+}
+
+impl Severity {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "warning" => Some(Severity::Warning),
+            "error" => Some(Severity::Error),
+            "critical" => Some(Severity::Critical),
+            _ => None,
+        }
+    }
+
+    /// Severity of the events in `notify.email.on`
+    fn of(event: &str) -> Self {
+        match event {
+            "ransomware" => Severity::Critical,
+            "failure" | "recovery" => Severity::Error,
+            _ => Severity::Warning,
+        }
+    }
+}
+
+/// Notify the outcome of a run: a failure unless it repeats one notified
+/// less than `repeat-after` ago, subvolumes that recovered with
+/// `recovery`, and the digest if one is due
+pub fn finished(config: &Config, command: &str, result: &Result<()>) {
+    let policy = &config.notify;
+    let mut history = history_path()
+        .filter(|_| tracks_failures(config) || policy.digest.is_some())
+        .map(|path| (History::load(&path), path));
+    let now = chrono::Local::now();
+    let (mut notify, mut repeats, mut recovered) = (result.is_err(), 0, vec![]);
+    if let Some((history, _)) = history.as_mut().filter(|_| tracks_failures(config)) {
+        // Subvolumes fail and recover on their own, other failures count
+        // for the whole command
+        let mut outcomes = report::outcomes();
+        if outcomes.iter().all(|(_, error)| error.is_none()) {
+            let error = result.as_ref().err().map(|e| format!("{:#}", e));
+            outcomes.push((command.to_string(), error));
+        }
+        notify = false;
+        for (key, error) in outcomes {
+            match error {
+                Some(error) => {
+                    if let Some(suppressed) = history.failed(&key, &error, now, policy.repeat_after)
+                    {
+                        notify = true;
+                        repeats += suppressed;
+                    }
+                }
+                None => {
+                    if let Some(failure) = history.succeeded(&key) {
+                        recovered.push(format!(
+                            "{}, failing since {}",
+                            key,
+                            failure.since.format("%Y-%m-%d %H:%M")
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    let mut queue = history.as_mut().map(|(history, _)| history);
+    if let Err(error) = result
+        && notify
+    {
+        let subject = format!("btrsnap failed on {}: {}", hostname(), command);
+        let mut body = format!("`{}` failed (run {}):\n\n{:?}\n", command, run::id(), error);
+        if repeats > 0 {
+            body += &format!("\n{} repeats of this failure were not notified.\n", repeats);
+        }
+        deliver(config, queue.as_deref_mut(), "failure", &subject, &body);
+    }
+    if policy.recovery && !recovered.is_empty() {
+        let subject = format!("btrsnap recovered on {}", hostname());
+        let body = format!("Succeeding again:\n\n{}\n", recovered.join("\n"));
+        deliver(config, queue, "recovery", &subject, &body);
+    }
+    if let Some((history, path)) = history.as_mut() {
+        if let Some(every) = policy.digest {
+            send_digest(config, history.take_digest(now, every));
+        }
+        save(history, path);
+    }
+}
+
+/// Notify `event` through the mail and chat backends subscribed to it, or
+/// queue it for the next digest
+pub fn event(config: &Config, event: &str, subject: &str, body: &str) {
+    let path = history_path().filter(|_| config.notify.digest.is_some());
+    let mut history = path.as_ref().map(|path| History::load(path));
+    deliver(config, history.as_mut(), event, subject, body);
+    if let (Some(history), Some(path)) = (&history, &path) {
+        save(history, path);
+    }
+}
+
+/// Whether failures are remembered across runs
+fn tracks_failures(config: &Config) -> bool {
+    config.notify.repeat_after.is_some() || config.notify.recovery
+}
+
+fn history_path() -> Option<PathBuf> {
+    state::root().map(|root| root.join(notify_history::FILE))
+}
+
+fn save(history: &History, path: &Path) {
+    // Expected to fail for unprivileged runs, which then notify every time
+    if let Err(e) = history.save(path) {
+        log::debug!("{:#}", e);
+    }
+}
+
+/// Send `subject` and `body` about `event` now, or add them to `digest`
+/// if digests are on and the event is no ransomware alert
+fn deliver(config: &Config, digest: Option<&mut History>, event: &str, subject: &str, body: &str) {
+    if let Some(history) = digest.filter(|_| config.notify.digest.is_some())
+        && Severity::of(event) < Severity::Critical
+    {
+        if subscribed(config, event) {
+            history.queue(event, subject, body, chrono::Local::now());
+        }
+        return;
+    }
+    // Never let a notification problem hide the original error
+    if let Some(email) = mail_to(config, event)
+        && let Err(e) = send(email, subject, body)
+    {
+        warning!("Failed to send {} notification: {:#}", event, e);
+    }
+    chat(&config.chat, event, subject, body);
+}
+
+/// One message per backend with the queued notifications it takes
+fn send_digest(config: &Config, queued: Vec<notify_history::Queued>) {
+    if queued.is_empty() {
+        return;
+    }
+    let subject = format!("btrsnap digest for {}", hostname());
+    let body = |items: Vec<&notify_history::Queued>| {
+        items
+            .iter()
+            .map(|q| {
+                format!(
+                    "{} {}\n\n{}\n",
+                    q.at.format("%Y-%m-%d %H:%M"),
+                    q.subject,
+                    q.body.trim_end()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    if let Some(email) = &config.email {
+        let items: Vec<_> = queued
+            .iter()
+            .filter(|q| mail_to(config, &q.event).is_some())
+            .collect();
+        if !items.is_empty()
+            && let Err(e) = send(email, &subject, &body(items))
+        {
+            warning!("Failed to send notification digest: {:#}", e);
+        }
+    }
+    for chat in &config.chat {
+        let items: Vec<_> = queued
+            .iter()
+            .filter(|q| Severity::of(&q.event) >= chat.min_severity)
+            .collect();
+        if !items.is_empty()
+            && let Err(e) = post(&chat.service, &subject, &body(items))
+        {
+            warning!("Failed to post notification digest: {:#}", e);
+        }
+    }
+}
+
+/// The mail settings if `event` is in `notify.email.on`; recoveries go
+/// wherever failures do
+fn mail_to<'a>(config: &'a Config, event: &str) -> Option<&'a EmailConfig> {
+    let event = if event == "recovery" {
+        "failure"
+    } else {
+        event
+    };
+    config
+        .email
+        .as_ref()
+        .filter(|e| e.on.iter().any(|o| o == event))
+}
+
+fn subscribed(config: &Config, event: &str) -> bool {
+    mail_to(config, event).is_some()
+        || config
+            .chat
+            .iter()
+            .any(|c| Severity::of(event) >= c.min_severity)
+}
+
+/// Post `subject` and `body` about `event` to the chat backends whose
+/// `min-severity` it reaches. Failures are warnings.
+fn chat(chats: &[ChatConfig], event: &str, subject: &str, body: &str) {
+    for chat in chats
+        .iter()
+        .filter(|c| Severity::of(event) >= c.min_severity)
+    {
+        match post(&chat.service, subject, body) {
+            Ok(()) => info!("Posted {} notification", event),
+            Err(e) => warning!("Failed to post {} notification: {:#}", event, e),
+        }
+    }
+}
+
+/// A chat API call: method, URL, bearer token and JSON payload
+struct Request {
+    method: &'static str,
+    url: String,
+    token: Option<String>,
+    payload: serde_json::Value,
+}
+
+fn request(service: &ChatService, subject: &str, body: &str) -> Result<Request> {
+    let text = format!("{}\n\n{}", subject, body.trim_end());
+    Ok(match service {
+        ChatService::Webhook { url_file } => Request {
+            method: "POST",
+            url: secret(url_file)?,
+            token: None,
+            payload: json!({ "text": text }),
+        },
+        ChatService::Matrix {
+            homeserver,
+            room,
+            token_file,
+        } => Request {
+            // The transaction id makes retries of the same message idempotent
+            method: "PUT",
+            url: format!(
+                "{}/_matrix/client/v3/rooms/{}/send/m.room.message/btrsnap-{}-{}",
+                homeserver,
+                encode(room),
+                run::id(),
+                chrono::Local::now().timestamp_micros()
+            ),
+            token: Some(secret(token_file)?),
+            payload: json!({ "msgtype": "m.text", "body": text }),
+        },
+        ChatService::Telegram {
+            token_file,
+            chat_id,
+        } => Request {
+            method: "POST",
+            url: format!(
+                "https://api.telegram.org/bot{}/sendMessage",
+                secret(token_file)?
+            ),
+            token: None,
+            payload: json!({ "chat_id": chat_id, "text": text }),
+        },
+    })
+}
+
+fn post(service: &ChatService, subject: &str, body: &str) -> Result<()> {
+    let request = request(service, subject, body)?;
+    // URL and token go through stdin, they would show in `ps` as arguments
+    let mut curl_config = format!("url = {}\n", quote(&request.url));
+    if let Some(token) = &request.token {
+        let header = format!("Authorization: Bearer {}", token);
+        curl_config += &format!("header = {}\n", quote(&header));
+    }
+    let mut child = Command::new("curl")
+        .args([
+            "--fail",
+            "--silent",
+            "--show-error",
+            "--output",
+            "/dev/null",
+        ])
+        .args(["--proto", "=https", "--max-time", "30"])
+        .args(["--request", request.method])
+        .args(["--header", "Content-Type: application/json"])
+        .args(["--data-binary", &request.payload.to_string()])
+        .args(["--config", "-"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run curl")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(curl_config.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+fn secret(path: &Path) -> Result<String> {
+    Ok(fs::read_to_string(path)
+        .context(format!("Failed to read {}", path.display()))?
+        .trim()
+        .to_string())
+}
+
+/// `s` percent-encoded for a URL path segment, e.g. a `!room:server` id
+fn encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// `s` as a quoted curl config value
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+pub fn send(email: &EmailConfig, subject: &str, body: &str) -> Result<()> {
+    let from: Mailbox = email
+        .from
+        .parse()
+        .context(format!("Invalid sender address: {}", email.from))?;
+    let mut builder = Message::builder().from(from).subject(subject);
+    for to in &email.to {
+        builder = builder.to(to
+            .parse()
+            .context(format!("Invalid recipient address: {}", to))?);
+    }
+    let message = builder
+        .body(body.to_string())
+        .context("Failed to build message")?;
+
+    let mut transport = match email.smtp_tls.as_str() {
+        "tls" => SmtpTransport::relay(&email.smtp_server)?,
+        "none" => SmtpTransport::builder_dangerous(&email.smtp_server),
+        _ => SmtpTransport::starttls_relay(&email.smtp_server)?,
+    };
+    if let Some(port) = email.smtp_port {
+        transport = transport.port(port);
+    }
+    if let Some(user) = &email.smtp_user {
+        let password = match &email.smtp_password_file {
+            Some(path) => fs::read_to_string(path)
+                .context(format!("Failed to read password file: {}", path.display()))?
+                .trim()
+                .to_string(),
+            None => String::new(),
+        };
+        transport = transport.credentials(Credentials::new(user.clone(), password));
+    }
+
+    transport
+        .build()
+        .send(&message)
+        .context(format!("Failed to send mail via {}", email.smtp_server))?;
+    info!("Sent notification to {}", email.to.join(", "));
+    Ok(())
+}
+
+pub fn hostname() -> String {
+    gethostname()
+        .map(|h| h.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+
+    #[test]
+    fn builds_chat_requests_with_secrets_from_files() {
+        let dir = MockBackend::leak().scratch_dir();
+        let token_file = dir.join("token");
+        fs::write(&token_file, "s3cret\n").unwrap();
+        let matrix = ChatService::Matrix {
+            homeserver: "https://matrix.example".into(),
+            room: "!ops:example".into(),
+            token_file: token_file.clone(),
+        };
+        let telegram = ChatService::Telegram {
+            token_file,
+            chat_id: "-100123".into(),
+        };
+
+        let call = request(&matrix, "btrsnap failed", "details\n").unwrap();
+        assert_eq!(call.method, "PUT");
+        assert!(
+            call.url.starts_with(
+                "https://matrix.example/_matrix/client/v3/rooms/%21ops%3Aexample/send/"
+            ),
+            "{}",
+            call.url
+        );
+        assert_eq!(call.token.as_deref(), Some("s3cret"));
+        assert_eq!(call.payload["body"], "btrsnap failed\n\ndetails");
+        let call = request(&telegram, "s", "b").unwrap();
+        assert_eq!(call.url, "https://api.telegram.org/bots3cret/sendMessage");
+        assert_eq!(call.payload["chat_id"], "-100123");
+        assert!(Severity::of("ransomware") >= Severity::Error);
+        assert!(Severity::of("drift") < Severity::Error);
+        assert_eq!(quote(r#"a"b\c"#), r#""a\"b\\c""#);
+    }
+}
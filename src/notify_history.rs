@@ -0,0 +1,186 @@
+//! What was notified before, so repeated failures collapse into one
+//! message per `repeat-after`, recoveries are noticed and digests
+//! collected. Kept in `notify.json` below the state root.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+pub const FILE: &str = "notify.json";
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct History {
+    /// Failing subvolumes and commands, by key
+    #[serde(default)]
+    pub failing: BTreeMap<String, Failure>,
+    /// Notifications held back for the next digest
+    #[serde(default)]
+    pub queued: Vec<Queued>,
+    /// When the last digest went out, or collecting began
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest_sent: Option<DateTime<Local>>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Failure {
+    /// The error, to tell a repeated failure from a new one
+    pub error: String,
+    pub since: DateTime<Local>,
+    pub notified: DateTime<Local>,
+    /// Repeats not notified since `notified`
+    pub suppressed: u32,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Queued {
+    pub at: DateTime<Local>,
+    pub event: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl History {
+    /// The history in `path`, empty if there is none or it is unreadable
+    pub fn load(path: &Path) -> Self {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    debug!("Failed to read {}: {}", path.display(), e);
+                }
+                return History::default();
+            }
+        };
+        serde_json::from_str(&content).unwrap_or_else(|e| {
+            debug!("Ignoring invalid {}: {}", path.display(), e);
+            History::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context(format!("Failed to create {}", dir.display()))?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)
+            .context(format!("Failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, path).context(format!("Failed to replace {}", path.display()))
+    }
+
+    /// Record that `key` failed with `error`. Returns the number of
+    /// repeats that were not notified if this one should be, `None` if it
+    /// repeats the last failure within `repeat_after`.
+    pub fn failed(
+        &mut self,
+        key: &str,
+        error: &str,
+        now: DateTime<Local>,
+        repeat_after: Option<Duration>,
+    ) -> Option<u32> {
+        let error = fingerprint(error);
+        match self.failing.get_mut(key) {
+            Some(failure) if failure.error == error => {
+                let since = (now - failure.notified).to_std().unwrap_or_default();
+                if repeat_after.is_some_and(|after| since < after) {
+                    failure.suppressed += 1;
+                    return None;
+                }
+                failure.notified = now;
+                Some(std::mem::take(&mut failure.suppressed))
+            }
+            _ => {
+                let failure = Failure {
+                    error,
+                    since: now,
+                    notified: now,
+                    suppressed: 0,
+                };
+                self.failing.insert(key.to_string(), failure);
+                Some(0)
+            }
+        }
+    }
+
+    /// Record that `key` succeeded, returning its failure if it had one
+    pub fn succeeded(&mut self, key: &str) -> Option<Failure> {
+        self.failing.remove(key)
+    }
+
+    pub fn queue(&mut self, event: &str, subject: &str, body: &str, now: DateTime<Local>) {
+        self.digest_sent.get_or_insert(now);
+        self.queued.push(Queued {
+            at: now,
+            event: event.to_string(),
+            subject: subject.to_string(),
+            body: body.to_string(),
+        });
+    }
+
+    /// The queued notifications if a digest is due `every` this long
+    pub fn take_digest(&mut self, now: DateTime<Local>, every: Duration) -> Vec<Queued> {
+        let since = self.digest_sent.get_or_insert(now);
+        if self.queued.is_empty() || (now - *since).to_std().unwrap_or_default() < every {
+            return vec![];
+        }
+        self.digest_sent = Some(now);
+        std::mem::take(&mut self.queued)
+    }
+}
+
+/// `error` with numbers left out, so failures naming a new snapshot
+/// each time still count as the same
+fn fingerprint(error: &str) -> String {
+    let mut fingerprint = String::new();
+    for c in error.chars() {
+        if !c.is_ascii_digit() {
+            fingerprint.push(c);
+        } else if !fingerprint.ends_with('#') {
+            fingerprint.push('#');
+        }
+    }
+    fingerprint
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+
+    #[test]
+    fn collapses_repeats_and_notices_recovery() {
+        let path = MockBackend::leak().scratch_dir().join(FILE);
+        let mut history = History::load(&path);
+        let start = Local::now();
+        let hours = |h| start + chrono::Duration::hours(h);
+        let every = Some(Duration::from_secs(6 * 3600));
+
+        assert_eq!(history.failed("home", "disk full", start, every), Some(0));
+        assert_eq!(history.failed("home", "disk full", hours(1), every), None);
+        assert_eq!(history.failed("home", "disk full", hours(2), every), None);
+        assert_eq!(history.failed("home", "busy", hours(3), every), Some(0));
+        assert_eq!(history.failed("home", "busy", hours(4), every), None);
+        assert_eq!(history.failed("home", "busy", hours(9), every), Some(1));
+        let error = |ts| format!("Failed to create /snapshots/var-{}", ts);
+        assert_eq!(
+            history.failed("var", &error(20240101), start, every),
+            Some(0)
+        );
+        assert_eq!(history.failed("var", &error(20240102), start, every), None);
+        history.save(&path).unwrap();
+
+        let mut history = History::load(&path);
+        assert_eq!(history.succeeded("home").unwrap().since, hours(3));
+        assert!(history.succeeded("home").is_none());
+
+        let day = Duration::from_secs(24 * 3600);
+        history.queue("drift", "changed", "details", hours(10));
+        assert!(history.take_digest(hours(20), day).is_empty());
+        assert_eq!(history.take_digest(hours(34), day).len(), 1);
+        assert!(history.queued.is_empty());
+    }
+}
@@ -0,0 +1,124 @@
+use crate::create::{escape_name, unescape_name};
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+/// Marks names escaped by [`name`]. Snapshot names never start with it,
+/// [`escape_name`] only writes `%` before two hex digits.
+const ESCAPED: &str = "%%";
+
+/// `name` as a string without losing bytes: as it is if valid UTF-8, else
+/// escaped with [`escape_name`] behind [`ESCAPED`]. Valid names starting
+/// with the marker are escaped too, so no two names give the same string.
+pub fn name(name: &OsStr) -> String {
+    match name.to_str() {
+        Some(name) if !name.starts_with(ESCAPED) => name.to_string(),
+        _ => format!("{}{}", ESCAPED, escape_name(name.as_bytes())),
+    }
+}
+
+/// The file name [`name`] made `name` from
+pub fn from_name(name: &str) -> OsString {
+    match name.strip_prefix(ESCAPED) {
+        Some(escaped) => OsString::from_vec(unescape_name(escaped)),
+        None => OsString::from(name),
+    }
+}
+
+/// A path in JSON: a string if valid UTF-8, else its raw bytes
+#[derive(Deserialize, JsonSchema, Serialize)]
+#[serde(untagged)]
+pub enum JsonPath {
+    Utf8(String),
+    Bytes { bytes: Vec<u8> },
+}
+
+impl From<&Path> for JsonPath {
+    fn from(path: &Path) -> Self {
+        match path.to_str() {
+            Some(path) => JsonPath::Utf8(path.to_string()),
+            None => JsonPath::Bytes {
+                bytes: path.as_os_str().as_bytes().to_vec(),
+            },
+        }
+    }
+}
+
+impl From<JsonPath> for PathBuf {
+    fn from(path: JsonPath) -> Self {
+        match path {
+            JsonPath::Utf8(path) => PathBuf::from(path),
+            JsonPath::Bytes { bytes } => PathBuf::from(OsString::from_vec(bytes)),
+        }
+    }
+}
+
+/// For `#[serde(with = "os_path")]` on `PathBuf` fields that may not be
+/// UTF-8
+pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    JsonPath::from(path).serialize(serializer)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+    JsonPath::deserialize(deserializer).map(PathBuf::from)
+}
+
+/// [`serialize`] and [`deserialize`] for `Option<PathBuf>`
+pub mod option {
+    use super::JsonPath;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::path::PathBuf;
+
+    pub fn serialize<S: Serializer>(
+        path: &Option<PathBuf>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        path.as_deref().map(JsonPath::from).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<PathBuf>, D::Error> {
+        Option::<JsonPath>::deserialize(deserializer).map(|p| p.map(PathBuf::from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::SnapshotBackend;
+    use crate::backend::mock::MockBackend;
+    use crate::create::subvol_name;
+    use crate::manifest::{Entry, Manifest};
+    use crate::utils;
+    use chrono::Local;
+
+    #[test]
+    fn keeps_non_utf8_names_and_paths() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let source = dir.join(OsStr::from_bytes(b"caf\xe9"));
+        let snap_dir = dir.join("snapshots");
+        let snapshot = snap_dir.join(format!("{}-1", subvol_name(&source, 0)));
+        backend.add(&source, Local::now());
+        backend.snapshot(&source, &snapshot, true).unwrap();
+        assert_eq!(utils::file_name(&snapshot).unwrap(), "caf%E9-1");
+
+        let info = backend.info(&snapshot).unwrap();
+        Manifest::record(&snap_dir, "caf%E9-1", Entry::new(&source, &info)).unwrap();
+        let manifest = Manifest::load(&snap_dir).unwrap().unwrap();
+        assert_eq!(manifest.snapshots["caf%E9-1"].source, source);
+
+        let raw = OsStr::from_bytes(b"x\xff%41");
+        assert_eq!(from_name(&name(raw)), raw);
+        assert_eq!(from_name("100%41"), OsStr::new("100%41"));
+        let (valid, raw) = (OsStr::new("x%FF"), OsStr::from_bytes(b"x\xff"));
+        assert_ne!(name(valid), name(raw));
+        assert_eq!(from_name(&name(valid)), valid);
+        assert_eq!(from_name(&name(raw)), raw);
+        let marked = OsStr::new("%%x");
+        assert_eq!(from_name(&name(marked)), marked);
+    }
+}
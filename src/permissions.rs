@@ -0,0 +1,111 @@
+use anyhow::{Context, Result, anyhow, bail};
+use nix::unistd::{Gid, Group, Uid, User, chown};
+use std::fs::{self, Permissions};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+/// Owner, group and mode to give a directory, each left alone if unset
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Access {
+    pub mode: Option<u32>,
+    pub owner: Option<Uid>,
+    pub group: Option<Gid>,
+}
+
+/// `[permissions]` settings, so unprivileged users can reach their files in
+/// snapshots without being able to list everything
+#[derive(Clone, Copy, Default)]
+pub struct SnapshotAccess {
+    /// For the snapshot dir (`snap-dir-mode`, `snap-dir-owner`)
+    pub snap_dir: Access,
+    /// For the top directory of each new snapshot (`snapshot-mode`,
+    /// `snapshot-owner`)
+    pub snapshots: Access,
+}
+
+impl Access {
+    pub fn is_set(&self) -> bool {
+        *self != Access::default()
+    }
+
+    pub fn apply(&self, path: &Path) -> Result<()> {
+        if self.owner.is_some() || self.group.is_some() {
+            chown(path, self.owner, self.group)
+                .context(format!("Failed to change the owner of {}", path.display()))?;
+        }
+        if let Some(mode) = self.mode {
+            fs::set_permissions(path, Permissions::from_mode(mode))
+                .context(format!("Failed to change the mode of {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Octal mode such as `0711`
+pub fn parse_mode(s: &str) -> Result<u32> {
+    let mode = u32::from_str_radix(s, 8).map_err(|_| anyhow!("Invalid mode {}", s))?;
+    if mode > 0o7777 {
+        bail!("Invalid mode {}", s);
+    }
+    Ok(mode)
+}
+
+/// `user`, `user:group` or `:group`, by name or numeric ID
+pub fn parse_owner(s: &str) -> Result<(Option<Uid>, Option<Gid>)> {
+    let (user, group) = s.split_once(':').unwrap_or((s, ""));
+    let uid = match user {
+        "" => None,
+        user => Some(match user.parse() {
+            Ok(id) => Uid::from_raw(id),
+            Err(_) => {
+                User::from_name(user)?
+                    .ok_or_else(|| anyhow!("Unknown user {}", user))?
+                    .uid
+            }
+        }),
+    };
+    let gid = match group {
+        "" => None,
+        group => Some(match group.parse() {
+            Ok(id) => Gid::from_raw(id),
+            Err(_) => {
+                Group::from_name(group)?
+                    .ok_or_else(|| anyhow!("Unknown group {}", group))?
+                    .gid
+            }
+        }),
+    };
+    Ok((uid, gid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use std::os::unix::fs::MetadataExt;
+
+    #[test]
+    fn applies_mode_and_owner() {
+        let dir = MockBackend::leak().scratch_dir();
+        let (uid, gid) = (Uid::current(), Gid::current());
+        let access = Access {
+            mode: Some(parse_mode("0711").unwrap()),
+            ..Access::default()
+        };
+        access.apply(&dir).unwrap();
+        let (owner, group) = parse_owner(&format!("{}:{}", uid, gid)).unwrap();
+        Access {
+            owner,
+            group,
+            ..access
+        }
+        .apply(&dir)
+        .unwrap();
+
+        let meta = fs::metadata(&dir).unwrap();
+        assert_eq!(meta.mode() & 0o7777, 0o711);
+        assert_eq!((meta.uid(), meta.gid()), (uid.as_raw(), gid.as_raw()));
+        assert_eq!(parse_owner(":0").unwrap(), (None, Some(Gid::from_raw(0))));
+        assert!(parse_mode("0999").is_err());
+    }
+}
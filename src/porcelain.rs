@@ -0,0 +1,47 @@
+//! Plumbing output for scripts, selected with `--porcelain=<version>`.
+//!
+//! A version's format never changes once released; new fields or records
+//! mean a new version. Each line is one record, fields are separated by
+//! tabs, the first field names the record type, and tabs, newlines and
+//! backslashes inside fields are escaped as `\t`, `\n` and `\\`. Missing
+//! values are `-`.
+//!
+//! v1 records:
+//! - `list`: `snapshot <path> <subvol> <otime> <generation> <state>`, with
+//!   `<otime>` in Unix seconds and `<state>` one of `managed`, `unmanaged`
+//!   (not in the manifest) or `missing` (only in the manifest)
+//! - run reports: `subvol <name> <created> <deleted> <retries> <errors>`,
+//!   then `error <name> <message>` per error and `interrupted` if the run
+//!   was interrupted
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Porcelain {
+    V1,
+}
+
+/// Print one record of tab-separated, escaped fields
+pub fn record(fields: &[&str]) {
+    let fields: Vec<String> = fields.iter().map(|f| escape(f)).collect();
+    println!("{}", fields.join("\t"));
+}
+
+fn escape(field: &str) -> String {
+    if field.is_empty() {
+        return "-".to_string();
+    }
+    field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_separators() {
+        assert_eq!(escape("a\tb\\c\nd"), "a\\tb\\\\c\\nd");
+        assert_eq!(escape(""), "-");
+    }
+}
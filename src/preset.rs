@@ -0,0 +1,147 @@
+//! Built-in subvolume sets for `create --preset`
+
+use crate::backend::SnapshotBackend;
+use crate::distro::Layout;
+use crate::mounts::{self, Mount};
+use crate::warnings::warning;
+use anyhow::{Context, Result, bail};
+use log::{debug, info};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// Where the presets keep snapshots unless `snap-dir` is set
+const SNAP_DIR: &str = "/.snapshots";
+
+/// Mount points whose subvolumes hold nothing worth rolling back: caches,
+/// temporary files and logs, swap, container and VM storage, and removable
+/// or runtime mounts
+const VOLATILE: &[&str] = &[
+    "/tmp",
+    "/var/tmp",
+    "/var/cache",
+    "/var/log",
+    "/var/crash",
+    "/var/spool",
+    "/var/lib/docker",
+    "/var/lib/containers",
+    "/var/lib/machines",
+    "/var/lib/libvirt/images",
+    "/boot/grub2",
+    "/swap",
+    "/mnt",
+    "/media",
+    "/run",
+];
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum Preset {
+    /// `/`, `/home` and the other mounted btrfs subvolumes, except caches,
+    /// temporary files, logs and container storage
+    System,
+}
+
+impl Preset {
+    /// Subvolumes to snapshot
+    pub fn subvols(self, backend: &dyn SnapshotBackend, snap_dir: &Path) -> Result<Vec<PathBuf>> {
+        let found = match self {
+            Preset::System => system(&mounts::read()?, snap_dir, |p| backend.is_subvolume(p)),
+        };
+        if found.is_empty() {
+            bail!("No mounted btrfs subvolumes found for the preset");
+        }
+        Ok(found)
+    }
+
+    /// Snapshot dir when none is configured, created as a subvolume if
+    /// missing so restoring `/` does not take the snapshots along
+    pub fn snap_dir(self, backend: &dyn SnapshotBackend) -> Result<PathBuf> {
+        let snap_dir = PathBuf::from(SNAP_DIR);
+        let layout = Layout::current();
+        debug!("Detected {}", layout.description());
+        match layout {
+            Layout::NotBtrfs => bail!(
+                "/ is not on btrfs, set 'snap-dir' or pass --snap-dir to a \
+                 directory on the filesystem of the subvolumes"
+            ),
+            // snapper keeps numbered snapshots there, mixing them up would
+            // confuse both tools
+            Layout::Snapper => bail!(
+                "{} belongs to snapper on this {}, set 'snap-dir' or pass --snap-dir",
+                snap_dir.display(),
+                layout.description()
+            ),
+            _ if Path::new("/etc/snapper/configs/root").exists() => bail!(
+                "{} is managed by snapper, set 'snap-dir' or pass --snap-dir",
+                snap_dir.display()
+            ),
+            _ => {}
+        }
+        if !snap_dir.exists() {
+            info!("Creating subvolume {}", snap_dir.display());
+            backend
+                .create(&snap_dir)
+                .context(format!("Failed to create subvolume {}", snap_dir.display()))?;
+        } else if !backend.is_subvolume(&snap_dir) {
+            warning!(
+                "{} is a plain directory, snapshots of / will carry it and \
+                 restoring / will swap the snapshots out",
+                snap_dir.display()
+            );
+        }
+        Ok(snap_dir)
+    }
+}
+
+/// Mounted btrfs subvolumes outside the volatile places and the snapshot
+/// dir, each once even if bind-mounted elsewhere
+fn system(mounts: &[Mount], snap_dir: &Path, is_subvolume: impl Fn(&Path) -> bool) -> Vec<PathBuf> {
+    let mut seen = BTreeSet::new();
+    let mut found = vec![];
+    for mount in mounts.iter().filter(|m| m.fstype == "btrfs") {
+        let point = &mount.point;
+        if let Some(volatile) = VOLATILE.iter().find(|v| point.starts_with(v)) {
+            debug!("Skipping {} (below {})", point.display(), volatile);
+            continue;
+        }
+        if point.starts_with(snap_dir) || !is_subvolume(point) {
+            continue;
+        }
+        if seen.insert((&mount.source, &mount.root)) {
+            found.push(point.clone());
+        }
+    }
+    found.sort();
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn system_skips_volatile_and_bind_mounts() {
+        let mount = |root: &str, point: &str, fstype: &str| Mount {
+            root: PathBuf::from(root),
+            point: PathBuf::from(point),
+            fstype: fstype.to_string(),
+            source: "/dev/sda2".to_string(),
+            options: String::new(),
+        };
+        let mounts = [
+            mount("/@", "/", "btrfs"),
+            mount("/@home", "/home", "btrfs"),
+            mount("/@log", "/var/log", "btrfs"),
+            mount("/@cache", "/var/cache/pacman/pkg", "btrfs"),
+            mount("/@snapshots", "/.snapshots", "btrfs"),
+            mount("/@home", "/srv/home", "btrfs"),
+            mount("/@/data", "/data", "btrfs"),
+            mount("/", "/boot", "vfat"),
+        ];
+
+        let found = system(&mounts, Path::new("/.snapshots"), |p| {
+            p != Path::new("/data")
+        });
+
+        assert_eq!(found, [PathBuf::from("/"), PathBuf::from("/home")]);
+    }
+}
@@ -0,0 +1,225 @@
+use crate::backend::{SnapshotBackend, SubvolInfo};
+use crate::manifest::Manifest;
+use crate::{compliance, maintenance, utils};
+use anyhow::{Result, bail};
+use chrono::Local;
+use log::debug;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Deletion safety settings derived from the config
+#[derive(Clone, Default)]
+pub struct Policy {
+    /// Refuse to delete snapshots younger than this (`min-age-before-delete`)
+    pub min_age: Option<Duration>,
+    /// Configured source subvolumes and the snapshot dir, never deleted
+    pub protected: Vec<PathBuf>,
+}
+
+/// Safety checks every deletion path goes through
+pub struct Guard<'a> {
+    backend: &'a dyn SnapshotBackend,
+    policy: Policy,
+    protected_uuids: Vec<String>,
+    /// Override `min_age`, protected subvolumes stay protected
+    force: bool,
+    /// Snapshot dirs checked so far, each read on its first check only
+    dirs: RefCell<BTreeMap<PathBuf, Rc<Dir>>>,
+}
+
+/// The compliance lock and manifest of a snapshot dir
+struct Dir {
+    lock: Option<compliance::Lock>,
+    manifest: Manifest,
+}
+
+impl<'a> Guard<'a> {
+    pub fn new(backend: &'a dyn SnapshotBackend, policy: Policy, force: bool) -> Self {
+        // Match by UUID too, so other paths to the same subvolume are caught
+        let protected_uuids = policy
+            .protected
+            .iter()
+            .filter_map(|p| backend.info(p).ok())
+            .map(|info| info.uuid.to_string())
+            .collect();
+        Guard {
+            backend,
+            policy,
+            protected_uuids,
+            force,
+            dirs: RefCell::default(),
+        }
+    }
+
+    /// The compliance lock and manifest of `snap_dir`, read once per guard
+    /// rather than for every snapshot in it
+    fn dir(&self, snap_dir: &Path) -> Result<Rc<Dir>> {
+        if let Some(dir) = self.dirs.borrow().get(snap_dir) {
+            return Ok(dir.clone());
+        }
+        let dir = Rc::new(Dir {
+            lock: compliance::active(self.backend, snap_dir)?,
+            manifest: Manifest::load(snap_dir)?.unwrap_or_default(),
+        });
+        self.dirs
+            .borrow_mut()
+            .insert(snap_dir.to_path_buf(), dir.clone());
+        Ok(dir)
+    }
+
+    /// Fail if `subvol` must not be deleted
+    pub fn check(&self, info: &SubvolInfo) -> Result<()> {
+        let path = &info.path;
+        debug!(
+            "Checking {} ({}) before deletion",
+            path.display(),
+            info.uuid
+        );
+        if self.policy.protected.iter().any(|p| p == path)
+            || self.protected_uuids.contains(&info.uuid.to_string())
+        {
+            bail!(
+                "{} is a configured source subvolume and is never deleted",
+                path.display()
+            );
+        }
+
+        // The running root and the next boot's, e.g. the copy `restore
+        // --root` leaves in the snapshot dir, whatever its name or age
+        if self
+            .backend
+            .default_subvol(path)
+            .is_ok_and(|id| id == info.id)
+        {
+            bail!(
+                "{} is the default subvolume, mounted as / on the next boot",
+                path.display()
+            );
+        }
+        if self
+            .backend
+            .info(Path::new("/"))
+            .is_ok_and(|root| root.uuid == info.uuid)
+        {
+            bail!("{} is mounted as /", path.display());
+        }
+
+        let dir = match path.parent() {
+            Some(snap_dir) => Some(self.dir(snap_dir)?),
+            None => None,
+        };
+
+        // Compliance holds against --force too
+        if let Some(lock) = dir.as_ref().and_then(|d| d.lock.as_ref())
+            && Local::now() - info.otime < chrono::Duration::from_std(lock.min_retention())?
+        {
+            bail!(
+                "{} is younger than the compliance min-retention ({}), locked until {}",
+                path.display(),
+                humantime::format_duration(lock.min_retention()),
+                lock.until.format("%Y-%m-%d %H:%M")
+            );
+        }
+
+        if let Some(snap_dir) = path.parent()
+            && let Some(maintenance) = maintenance::active(snap_dir)?
+        {
+            bail!(
+                "{} is in maintenance mode ({}), deletions resume after `maintenance off`",
+                snap_dir.display(),
+                maintenance
+            );
+        }
+
+        let name = utils::file_name(path);
+        let entry = dir
+            .as_ref()
+            .zip(name)
+            .and_then(|(d, name)| d.manifest.snapshots.get(&name));
+        if let Some(hold) = entry.iter().flat_map(|e| &e.holds).find(|h| h.active()) {
+            bail!(
+                "{} is on hold '{}' ({}), release it with `hold remove`",
+                path.display(),
+                hold.name,
+                hold.reason
+            );
+        }
+
+        if self.force {
+            return Ok(());
+        }
+        if let Some(reason) = entry.and_then(|e| e.pinned.as_ref()) {
+            bail!(
+                "{} is pinned ({}), use --force to delete it",
+                path.display(),
+                reason
+            );
+        }
+        if let Some(min_age) = self.policy.min_age
+            && Local::now() - info.otime < chrono::Duration::from_std(min_age)?
+        {
+            bail!(
+                "{} is younger than min-age-before-delete ({}), use --force to delete it",
+                path.display(),
+                humantime::format_duration(min_age)
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+
+    #[test]
+    fn min_age_is_overridden_by_force_only() {
+        let backend = MockBackend::leak();
+        let young = backend.scratch_dir().join("home-1");
+        backend.add(&young, Local::now());
+        let info = backend.info(&young).unwrap();
+        let policy = Policy {
+            min_age: Some(Duration::from_secs(3600)),
+            protected: vec![],
+        };
+
+        assert!(
+            Guard::new(backend, policy.clone(), false)
+                .check(&info)
+                .is_err()
+        );
+        assert!(Guard::new(backend, policy, true).check(&info).is_ok());
+    }
+
+    #[test]
+    fn protected_subvolumes_are_matched_by_uuid() {
+        let backend = MockBackend::leak();
+        let source = backend.scratch_dir().join("home");
+        backend.add(&source, Local::now());
+        let mut info = backend.info(&source).unwrap();
+        info.path = PathBuf::from("/other/mount/home");
+        let policy = Policy {
+            min_age: None,
+            protected: vec![source],
+        };
+
+        assert!(Guard::new(backend, policy, true).check(&info).is_err());
+    }
+
+    #[test]
+    fn default_subvolume_is_never_deleted() {
+        let backend = MockBackend::leak();
+        let restored = backend.scratch_dir().join("root-1.restored");
+        backend.add(&restored, Local::now() - chrono::Duration::days(365));
+        let info = backend.info(&restored).unwrap();
+        let guard = Guard::new(backend, Policy::default(), true);
+
+        assert!(guard.check(&info).is_ok());
+        backend.set_default(&restored).unwrap();
+        assert!(guard.check(&info).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//! Ransomware heuristics, run by `create` after each new snapshot: if far
+//! more files changed since the previous snapshot than usual, or many
+//! changed files got extensions encryption malware uses, raise an alert
+//! and pin the previous snapshot as the last known-good one.
+
+use crate::backend::SubvolInfo;
+use crate::config::{Config, RansomwareConfig};
+use crate::manifest::{Entry, Manifest};
+use crate::warnings::warning;
+use crate::{notify, utils};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use log::{debug, info};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// Extensions used by common ransomware families for encrypted files
+const DEFAULT_EXTENSIONS: &[&str] = &[
+    "encrypted",
+    "enc",
+    "crypt",
+    "crypted",
+    "locked",
+    "locky",
+    "lockbit",
+    "cerber",
+    "wncry",
+    "ryk",
+    "conti",
+    "akira",
+    "basta",
+    "blackcat",
+];
+
+/// Files in a snapshot that changed since a point in time
+#[derive(Debug, Default)]
+pub struct Churn {
+    pub files: usize,
+    pub changed: usize,
+    /// Changed files with a ransomware extension
+    pub suspicious: usize,
+}
+
+impl Churn {
+    /// Why this churn looks like ransomware, if it does
+    pub fn alarm(&self, rules: &RansomwareConfig) -> Option<String> {
+        if self.files < rules.min_files {
+            return None;
+        }
+        let fraction = self.changed as f64 / self.files as f64;
+        if fraction > rules.max_changed {
+            Some(format!(
+                "{} of {} files ({:.0}%) changed",
+                self.changed,
+                self.files,
+                fraction * 100.0
+            ))
+        } else if self.suspicious >= rules.suspicious_files {
+            Some(format!(
+                "{} changed files have ransomware extensions",
+                self.suspicious
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+/// Count the regular files below `snapshot` and those changed after
+/// `since`. Nested subvolumes show up as empty directories and add nothing.
+pub fn measure(snapshot: &Path, since: DateTime<Local>, extensions: &[String]) -> Result<Churn> {
+    let since = since.timestamp();
+    let mut churn = Churn::default();
+    let mut todo = vec![snapshot.to_path_buf()];
+    while let Some(dir) = todo.pop() {
+        let entries = fs::read_dir(&dir).context(format!("Failed to read {}", dir.display()))?;
+        for entry in entries {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+            if meta.is_dir() {
+                todo.push(entry.path());
+                continue;
+            }
+            if !meta.is_file() {
+                continue;
+            }
+            churn.files += 1;
+            if meta.mtime() > since || meta.ctime() > since {
+                churn.changed += 1;
+                let path = entry.path();
+                if path
+                    .extension()
+                    .is_some_and(|ext| extensions.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+                {
+                    churn.suspicious += 1;
+                }
+            }
+        }
+    }
+    Ok(churn)
+}
+
+/// Compare `snapshot` of `source` with the snapshot before it; on an alarm
+/// warn, mail if `ransomware` is in `notify.email.on`, and pin `previous`
+pub fn check(
+    config: &Config,
+    rules: &RansomwareConfig,
+    source: &Path,
+    previous: &SubvolInfo,
+    snapshot: &Path,
+) -> Result<()> {
+    let churn = measure(snapshot, previous.otime, &rules.extensions)?;
+    debug!(
+        "Churn of {} since {}: {:?}",
+        snapshot.display(),
+        previous.path.display(),
+        churn
+    );
+    let Some(alarm) = churn.alarm(rules) else {
+        return Ok(());
+    };
+    let message = format!(
+        "Possible ransomware activity in {} since {}: {}",
+        source.display(),
+        previous.path.display(),
+        alarm
+    );
+    warning!("{}", message);
+    let subject = format!("btrsnap ransomware alert on {}", notify::hostname());
+    notify::event(config, "ransomware", &subject, &format!("{}\n", message));
+    if rules.pin {
+        pin(source, previous, &alarm)?;
+    }
+    Ok(())
+}
+
+/// Mark `previous` as the last known-good snapshot; cleanup keeps it until
+/// deleted with `--force`
+fn pin(source: &Path, previous: &SubvolInfo, alarm: &str) -> Result<()> {
+    let (Some(snap_dir), Some(name)) = (previous.path.parent(), utils::file_name(&previous.path))
+    else {
+        return Ok(());
+    };
+    let _lock = Manifest::lock(snap_dir)?;
+    let mut manifest = Manifest::load(snap_dir)?.unwrap_or_default();
+    let entry = manifest
+        .snapshots
+        .entry(name)
+        .or_insert_with(|| Entry::new(source, previous));
+    entry.pinned = Some(format!(
+        "last known-good before a ransomware alert: {}",
+        alarm
+    ));
+    manifest.save(snap_dir)?;
+    info!("Pinned {}", previous.path.display());
+    Ok(())
+}
+
+/// Default for `[ransomware] extensions`
+pub fn default_extensions() -> Vec<String> {
+    DEFAULT_EXTENSIONS.iter().map(|e| e.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+
+    #[test]
+    fn alarms_on_mass_changes_and_ransom_extensions() {
+        let dir = MockBackend::leak().scratch_dir();
+        fs::create_dir(dir.join("docs")).unwrap();
+        for i in 0..4 {
+            fs::write(dir.join("docs").join(format!("{}.pdf.locked", i)), "x").unwrap();
+        }
+        let rules = RansomwareConfig {
+            max_changed: 0.5,
+            extensions: default_extensions(),
+            suspicious_files: 3,
+            min_files: 2,
+            pin: true,
+        };
+
+        let before = Local::now() - chrono::Duration::hours(1);
+        let churn = measure(&dir, before, &rules.extensions).unwrap();
+        assert_eq!((churn.files, churn.changed, churn.suspicious), (4, 4, 4));
+        assert!(churn.alarm(&rules).unwrap().contains("(100%) changed"));
+
+        let after = Local::now() + chrono::Duration::hours(1);
+        let churn = measure(&dir, after, &rules.extensions).unwrap();
+        assert_eq!(churn.alarm(&rules), None);
+
+        let churn = Churn {
+            files: 100,
+            changed: 10,
+            suspicious: 3,
+        };
+        assert!(
+            churn
+                .alarm(&rules)
+                .unwrap()
+                .contains("ransomware extensions")
+        );
+    }
+}
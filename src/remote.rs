@@ -0,0 +1,194 @@
+//! Configs fetched over HTTPS, for fleets whose policy is managed
+//! centrally. Each run fetches the config again; the last good copy is
+//! kept to fall back on when the server cannot be reached.
+
+use crate::warnings::warning;
+use crate::{signing, state, utils};
+use anyhow::{Context, Result, anyhow, bail};
+use log::{debug, info};
+use ring::digest::{SHA256, digest};
+use std::fs::{self, DirBuilder};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Below the default state root: `state-dir` comes from the config itself
+const CACHE_DIR: &str = "remote-configs";
+
+pub fn is_url(source: &str) -> bool {
+    source.starts_with("https://")
+}
+
+/// Config sources in a `BTRSNAP_CONFIG` list, which is separated by colons
+/// except for those in URLs: after `https` and before a port
+pub fn split_sources(list: &str) -> Vec<String> {
+    let mut sources: Vec<String> = vec![];
+    for part in list.split(':') {
+        let port = part.split('/').next().unwrap_or_default();
+        let joins = match sources.last() {
+            Some(last) if last == "https" => part.starts_with("//"),
+            Some(last) if is_url(last) && !last[8..].contains([':', '/']) => {
+                !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit())
+            }
+            _ => false,
+        };
+        match sources.last_mut() {
+            Some(last) if joins => {
+                last.push(':');
+                last.push_str(part);
+            }
+            _ => sources.push(part.to_string()),
+        }
+    }
+    sources.retain(|s| !s.is_empty());
+    sources
+}
+
+/// Local copy of the config at `url`. A `#sha256=<hex>` fragment pins the
+/// content, anything else fails. If the server cannot be reached, the copy
+/// from the last successful fetch is used, with its signature.
+pub fn fetch(url: &str) -> Result<PathBuf> {
+    let (location, pin) = match url.split_once("#sha256=") {
+        Some((location, pin)) => (location, Some(pin.to_ascii_lowercase())),
+        None => (url, None),
+    };
+    let cache = PathBuf::from(state::DEFAULT_ROOT)
+        .join(CACHE_DIR)
+        .join(format!(
+            "{}.toml",
+            utils::hex(digest(&SHA256, location.as_bytes()).as_ref())
+        ));
+
+    let fetched = download(location).and_then(|content| {
+        check_pin(location, &content, pin.as_deref())?;
+        let text =
+            String::from_utf8(content).context(format!("Config from {} is not UTF-8", location))?;
+        toml::from_str::<toml::Value>(&text)
+            .context(format!("Invalid TOML in config from {}", location))?;
+        // Served next to the config, needed once configs must be signed
+        let signature = match download(&format!("{}.sig", location)) {
+            Ok(signature) => Some(signature),
+            Err(e) => {
+                debug!("No signature: {:#}", e);
+                None
+            }
+        };
+        replace(&cache, &text, signature)
+            .context(format!("Failed to cache the config from {}", location))
+    });
+    match fetched {
+        Ok(()) => {
+            debug!("Cached config from {} in {}", location, cache.display());
+            Ok(cache)
+        }
+        // The cached copy keeps the signature it was verified with
+        Err(e) if cache.exists() => {
+            warning!(
+                "Using the cached config for {}, fetching failed: {:#}",
+                location,
+                e
+            );
+            let content =
+                fs::read(&cache).context(format!("Failed to read {}", cache.display()))?;
+            check_pin(location, &content, pin.as_deref())?;
+            Ok(cache)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn check_pin(location: &str, content: &[u8], pin: Option<&str>) -> Result<()> {
+    let Some(pin) = pin else {
+        return Ok(());
+    };
+    let actual = utils::hex(digest(&SHA256, content).as_ref());
+    if actual != pin {
+        bail!(
+            "Config from {} has SHA-256 {}, expected {}",
+            location,
+            actual,
+            pin
+        );
+    }
+    Ok(())
+}
+
+/// Replace the cached config and its signature with `text` and
+/// `signature`, written to temporary files first and, if signatures are
+/// required, swapped in only once they verify. A config is never paired
+/// with the signature of another version.
+fn replace(cache: &Path, text: &str, signature: Option<Vec<u8>>) -> Result<()> {
+    if let Some(dir) = cache.parent() {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .context(format!("Failed to create {}", dir.display()))?;
+    }
+    let tmp = cache.with_extension("toml.tmp");
+    let (sig, tmp_sig) = (
+        signing::signature_path(cache),
+        signing::signature_path(&tmp),
+    );
+    let _ = fs::remove_file(&tmp_sig);
+    fs::write(&tmp, text).context(format!("Failed to write {}", tmp.display()))?;
+    if let Some(signature) = signature {
+        fs::write(&tmp_sig, signature).context(format!("Failed to write {}", tmp_sig.display()))?;
+    }
+    if let Some(keys) = signing::trusted_keys()?
+        && let Err(e) = signing::verify(&tmp, &keys)
+    {
+        let _ = fs::remove_file(&tmp);
+        let _ = fs::remove_file(&tmp_sig);
+        return Err(e);
+    }
+    // Each replaced in one step so a crash cannot truncate it
+    if tmp_sig.exists() {
+        fs::rename(&tmp_sig, &sig).context(format!("Failed to replace {}", sig.display()))?;
+    } else {
+        let _ = fs::remove_file(&sig);
+    }
+    fs::rename(&tmp, cache).context(format!("Failed to replace {}", cache.display()))
+}
+
+fn download(url: &str) -> Result<Vec<u8>> {
+    info!("Fetching config from {}", url);
+    let output = Command::new("curl")
+        .args(["--fail", "--silent", "--show-error", "--location"])
+        .args(["--proto", "=https", "--max-time", "30", url])
+        .output()
+        .context("Failed to run curl")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .context(format!("Failed to fetch {}", url));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_lists_with_urls() {
+        assert_eq!(
+            split_sources("/etc/base.toml:https://cfg.example/h.toml#sha256=ab::local.toml"),
+            [
+                "/etc/base.toml",
+                "https://cfg.example/h.toml#sha256=ab",
+                "local.toml"
+            ]
+        );
+        assert_eq!(
+            split_sources("https://cfg.example:8443/h.toml:8443.toml"),
+            ["https://cfg.example:8443/h.toml", "8443.toml"]
+        );
+        assert_eq!(
+            utils::hex(digest(&SHA256, b"").as_ref()),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}
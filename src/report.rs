@@ -0,0 +1,349 @@
+use crate::i18n::tr;
+use crate::porcelain::{self, Porcelain};
+use crate::report_template::Template;
+use crate::{run, warnings};
+use anyhow::{Result, bail};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::sync::Mutex;
+
+/// Reports of the steps of a chained run, printed as one at the end
+static CHAIN: Mutex<Option<Chained>> = Mutex::new(None);
+
+/// Subvolumes that failed or succeeded in this process, see [`outcomes`]
+static OUTCOMES: Mutex<Vec<(String, Option<String>)>> = Mutex::new(vec![]);
+
+struct Chained {
+    report: Report,
+    json: bool,
+    porcelain: Option<Porcelain>,
+    template: Option<Template>,
+}
+
+/// Collect the reports of the following commands into one, printed by
+/// [`print_chained`]
+pub fn chain() {
+    *CHAIN.lock().unwrap() = Some(Chained {
+        report: Report::default(),
+        json: false,
+        porcelain: None,
+        template: None,
+    });
+}
+
+/// Print the report collected since [`chain`], if any command made one
+pub fn print_chained() {
+    if let Some(chained) = CHAIN.lock().unwrap().take()
+        && !chained.report.subvols.is_empty()
+    {
+        chained
+            .report
+            .print(chained.json, chained.porcelain, chained.template);
+    }
+}
+
+/// Subvolumes of the reports finished so far, with their first error or
+/// `None` if they succeeded. Skipped ones are left out.
+pub fn outcomes() -> Vec<(String, Option<String>)> {
+    std::mem::take(&mut *OUTCOMES.lock().unwrap())
+}
+
+/// Outcome of creating a snapshot of one subvolume
+#[derive(Clone, Copy, JsonSchema, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Created {
+    Yes,
+    No,
+    /// Not due yet because of `min-interval`
+    Skipped,
+    /// Found by `create --all` but matching an `exclude` pattern
+    Excluded,
+    /// Turned off with `enabled = false` or `disable`
+    Disabled,
+}
+
+impl Created {
+    /// Name in JSON and porcelain output
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Created::Yes => "yes",
+            Created::No => "no",
+            Created::Skipped => "skipped",
+            Created::Excluded => "excluded",
+            Created::Disabled => "disabled",
+        }
+    }
+
+    /// Localized name for the report table
+    pub fn label(self) -> String {
+        match self {
+            Created::Yes => tr!("created-yes"),
+            Created::No => tr!("created-no"),
+            Created::Skipped => tr!("created-skipped"),
+            Created::Excluded => tr!("created-excluded"),
+            Created::Disabled => tr!("created-disabled"),
+        }
+    }
+}
+
+/// What cleanup did with a snapshot
+#[derive(Clone, Copy, JsonSchema, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Deleted,
+    Kept,
+}
+
+/// A cleanup decision and the rule behind it
+#[derive(JsonSchema, Serialize)]
+pub struct Decision {
+    pub snapshot: String,
+    pub action: Action,
+    /// E.g. `older than keep=7d`
+    pub reason: String,
+}
+
+/// Per-subvolume results of a run
+#[derive(JsonSchema, Serialize)]
+pub struct SubvolReport {
+    pub subvol: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<Created>,
+    pub deleted: usize,
+    /// Retries made after transient btrfs errors
+    pub retries: u32,
+    pub errors: Vec<String>,
+    /// Cleanup decisions per snapshot
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub decisions: Vec<Decision>,
+}
+
+impl SubvolReport {
+    /// Record the decision about `snapshot`, replacing an earlier one
+    pub fn decide(&mut self, snapshot: &str, action: Action, reason: String) {
+        self.decisions.retain(|d| d.snapshot != snapshot);
+        self.decisions.push(Decision {
+            snapshot: snapshot.to_string(),
+            action,
+            reason,
+        });
+    }
+}
+
+/// Results of a multi-item operation, printed once at the end
+#[derive(JsonSchema, Serialize)]
+pub struct Report {
+    /// Identifier of the run, also in its log lines and manifest entries
+    pub run: String,
+    pub subvols: Vec<SubvolReport>,
+    /// The run stopped early on SIGINT/SIGTERM
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub interrupted: bool,
+    /// Non-fatal problems, e.g. a snapshot cleanup refused to delete
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+impl Default for Report {
+    fn default() -> Self {
+        Report {
+            run: run::id().to_string(),
+            subvols: vec![],
+            interrupted: false,
+            warnings: vec![],
+        }
+    }
+}
+
+impl Report {
+    /// Entry for `subvol`, added on first use
+    pub fn subvol(&mut self, subvol: &str) -> &mut SubvolReport {
+        let pos = match self.subvols.iter().position(|s| s.subvol == subvol) {
+            Some(pos) => pos,
+            None => {
+                self.subvols.push(SubvolReport {
+                    subvol: subvol.to_string(),
+                    created: None,
+                    deleted: 0,
+                    retries: 0,
+                    errors: vec![],
+                    decisions: vec![],
+                });
+                self.subvols.len() - 1
+            }
+        };
+        &mut self.subvols[pos]
+    }
+
+    /// Print the report, or add it to the chained one, then fail if any
+    /// item had errors or the run was interrupted
+    pub fn finish(
+        self,
+        json: bool,
+        porcelain: Option<Porcelain>,
+        template: Option<Template>,
+    ) -> Result<()> {
+        let result = self.result();
+        let mut outcomes = OUTCOMES.lock().unwrap();
+        for s in &self.subvols {
+            let skipped = matches!(
+                s.created,
+                Some(Created::Skipped | Created::Excluded | Created::Disabled)
+            );
+            if !skipped {
+                // The latest outcome counts, e.g. across runs of `watch`
+                outcomes.retain(|(subvol, _)| *subvol != s.subvol);
+                outcomes.push((s.subvol.clone(), s.errors.first().cloned()));
+            }
+        }
+        drop(outcomes);
+        match CHAIN.lock().unwrap().as_mut() {
+            Some(chained) => {
+                chained.json |= json;
+                chained.porcelain = chained.porcelain.or(porcelain);
+                chained.template = chained.template.take().or(template);
+                chained.report.merge(self);
+            }
+            None => self.print(json, porcelain, template),
+        }
+        result
+    }
+
+    /// Add the results of `other`, rows of the same subvolume combined
+    fn merge(&mut self, other: Report) {
+        self.interrupted |= other.interrupted;
+        for s in other.subvols {
+            let item = self.subvol(&s.subvol);
+            item.created = s.created.or(item.created);
+            item.deleted += s.deleted;
+            item.retries += s.retries;
+            item.errors.extend(s.errors);
+            item.decisions.extend(s.decisions);
+        }
+    }
+
+    fn print(mut self, json: bool, porcelain: Option<Porcelain>, template: Option<Template>) {
+        if let Some(template) = template {
+            self.warnings = warnings::take();
+            match serde_json::to_value(&self) {
+                Ok(report) => print!("{}", template.render(&report)),
+                Err(e) => eprintln!("Failed to print the report: {}", e),
+            }
+        } else if json {
+            // Shown here instead of on stderr at the end
+            self.warnings = warnings::take();
+            match serde_json::to_string_pretty(&self) {
+                Ok(json) => println!("{}", json),
+                Err(e) => eprintln!("Failed to print the report: {}", e),
+            }
+        } else if let Some(Porcelain::V1) = porcelain {
+            self.print_porcelain_v1();
+        } else {
+            self.print_table();
+        }
+    }
+
+    fn result(&self) -> Result<()> {
+        if self.interrupted {
+            bail!(tr!("report-interrupted"));
+        }
+        let failed = self.subvols.iter().filter(|s| !s.errors.is_empty()).count();
+        if failed > 0 {
+            bail!(tr!(
+                "report-failed",
+                failed = failed,
+                total = self.subvols.len()
+            ));
+        }
+        Ok(())
+    }
+
+    fn print_porcelain_v1(&self) {
+        for s in &self.subvols {
+            let created = s.created.map_or("", Created::as_str);
+            let (deleted, retries) = (s.deleted.to_string(), s.retries.to_string());
+            let errors = s.errors.len().to_string();
+            porcelain::record(&["subvol", &s.subvol, created, &deleted, &retries, &errors]);
+            for e in &s.errors {
+                porcelain::record(&["error", &s.subvol, e]);
+            }
+        }
+        if self.interrupted {
+            porcelain::record(&["interrupted"]);
+        }
+    }
+
+    fn print_table(&self) {
+        if self.subvols.is_empty() {
+            return;
+        }
+        let headers = [
+            tr!("report-subvolume"),
+            tr!("report-created"),
+            tr!("report-deleted"),
+            tr!("report-retries"),
+            tr!("report-errors"),
+        ];
+        let rows: Vec<[String; 5]> = self
+            .subvols
+            .iter()
+            .map(|s| {
+                [
+                    s.subvol.clone(),
+                    s.created.map_or("-".to_string(), Created::label),
+                    s.deleted.to_string(),
+                    s.retries.to_string(),
+                    s.errors.len().to_string(),
+                ]
+            })
+            .collect();
+        let mut widths = [9, 8, 7, 7, 6];
+        for row in rows.iter().chain([&headers]) {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        println!();
+        for row in [&headers].into_iter().chain(&rows) {
+            println!(
+                "{:<w0$}  {:<w1$}  {:>w2$}  {:>w3$}  {:>w4$}",
+                row[0],
+                row[1],
+                row[2],
+                row[3],
+                row[4],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2],
+                w3 = widths[3],
+                w4 = widths[4],
+            );
+        }
+        for s in &self.subvols {
+            for e in &s.errors {
+                eprintln!("{}: {}", s.subvol, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_combines_rows_of_the_same_subvolume() {
+        let mut first = Report::default();
+        first.subvol("home").created = Some(Created::Yes);
+        let mut second = Report::default();
+        second.subvol("home").deleted = 2;
+        second.subvol("var").errors.push("failed".to_string());
+
+        first.merge(second);
+
+        assert_eq!(first.subvols.len(), 2);
+        assert!(matches!(first.subvols[0].created, Some(Created::Yes)));
+        assert_eq!(first.subvols[0].deleted, 2);
+        assert!(first.result().is_err());
+    }
+}
@@ -0,0 +1,474 @@
+//! Rendering the run report through a user-provided template, e.g. into a
+//! mail, a chat message or an HTML page. Templates use the common subset
+//! of Jinja syntax: `{{ subvols | length }}`, `{% for s in subvols %}` with
+//! `{% else %}` and `loop.index`, `{% if %}` with `elif`/`else`, `==` and
+//! `!=`, `{# comments #}` and `-` to trim whitespace. The context is the
+//! report as printed by `--json`.
+
+use anyhow::{Context, Result, bail};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// Filters usable after `|`
+const FILTERS: &[&str] = &["length", "upper", "lower", "escape", "e", "join", "default"];
+
+/// A parsed report template
+#[derive(Clone, Debug)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Text(String),
+    Print(Expr),
+    For {
+        var: String,
+        list: Expr,
+        body: Vec<Node>,
+        empty: Vec<Node>,
+    },
+    If {
+        branches: Vec<(Cond, Vec<Node>)>,
+        otherwise: Vec<Node>,
+    },
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    Path(Vec<String>),
+    Literal(Value),
+}
+
+/// A value with filters, e.g. `s.errors | join(", ")`
+#[derive(Clone, Debug)]
+struct Expr {
+    operand: Operand,
+    filters: Vec<(String, Option<Value>)>,
+}
+
+/// `[not] expr [(==|!=) expr]`
+#[derive(Clone, Debug)]
+struct Cond {
+    negate: bool,
+    left: Expr,
+    compare: Option<(bool, Expr)>,
+}
+
+enum Token {
+    Text(String),
+    Print(String),
+    Tag(String),
+}
+
+/// Read and parse the template at `path`, for `--report-template`. Only
+/// once the command runs, not while arguments are parsed, so files are not
+/// read with privileges the caller does not have.
+pub fn load(path: &Path) -> Result<Template> {
+    let source = fs::read_to_string(path)
+        .context(format!("Failed to read report template {}", path.display()))?;
+    Template::parse(&source).context(format!("Invalid report template {}", path.display()))
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self> {
+        let tokens = lex(source)?;
+        let (nodes, _) = block(&tokens, &mut 0, &[])?;
+        Ok(Template { nodes })
+    }
+
+    /// The template filled in from `context`. Missing values are empty.
+    pub fn render(&self, context: &Value) -> String {
+        let mut out = String::new();
+        render(&self.nodes, context, &mut vec![], &mut out);
+        out
+    }
+}
+
+fn lex(source: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut rest = source;
+    let mut trim_next = false;
+    while !rest.is_empty() {
+        let start = ["{{", "{%", "{#"].iter().filter_map(|o| rest.find(o)).min();
+        let (text, tail) = rest.split_at(start.unwrap_or(rest.len()));
+        let mut text = if trim_next { text.trim_start() } else { text };
+        trim_next = false;
+        if tail.is_empty() {
+            if !text.is_empty() {
+                tokens.push(Token::Text(text.to_string()));
+            }
+            break;
+        }
+        let (open, close) = match &tail[..2] {
+            "{{" => ("{{", "}}"),
+            "{%" => ("{%", "%}"),
+            _ => ("{#", "#}"),
+        };
+        let Some(end) = tail[2..].find(close).map(|i| i + 2) else {
+            bail!("{} is never closed with {}", open, close);
+        };
+        let mut inner = &tail[2..end];
+        if let Some(trimmed) = inner.strip_prefix('-') {
+            text = text.trim_end();
+            inner = trimmed;
+        }
+        if let Some(trimmed) = inner.strip_suffix('-') {
+            trim_next = true;
+            inner = trimmed;
+        }
+        if !text.is_empty() {
+            tokens.push(Token::Text(text.to_string()));
+        }
+        match open {
+            "{{" => tokens.push(Token::Print(inner.trim().to_string())),
+            "{%" => tokens.push(Token::Tag(inner.trim().to_string())),
+            _ => {}
+        }
+        rest = &tail[end + 2..];
+    }
+    Ok(tokens)
+}
+
+/// Nodes up to one of the tags in `ends`, returned with that tag
+fn block(tokens: &[Token], pos: &mut usize, ends: &[&str]) -> Result<(Vec<Node>, String)> {
+    let mut nodes = vec![];
+    while let Some(token) = tokens.get(*pos) {
+        *pos += 1;
+        let tag = match token {
+            Token::Text(text) => {
+                nodes.push(Node::Text(text.clone()));
+                continue;
+            }
+            Token::Print(expr) => {
+                nodes.push(Node::Print(whole_expr(expr)?));
+                continue;
+            }
+            Token::Tag(tag) => tag,
+        };
+        let keyword = tag.split_whitespace().next().unwrap_or_default();
+        let rest = &tag[keyword.len()..];
+        if ends.contains(&keyword) {
+            return Ok((nodes, tag.clone()));
+        }
+        match keyword {
+            "for" => {
+                let words: Vec<&str> = rest.split_whitespace().collect();
+                let [var, "in", ..] = words[..] else {
+                    bail!("Expected {{% for <name> in <list> %}}, got {{% {} %}}", tag);
+                };
+                let list = whole_expr(&words[2..].join(" "))?;
+                let (body, end) = block(tokens, pos, &["else", "endfor"])?;
+                let empty = match end.as_str() {
+                    "else" => block(tokens, pos, &["endfor"])?.0,
+                    _ => vec![],
+                };
+                nodes.push(Node::For {
+                    var: var.to_string(),
+                    list,
+                    body,
+                    empty,
+                });
+            }
+            "if" => {
+                let mut branches = vec![];
+                let mut cond = condition(rest)?;
+                let otherwise = loop {
+                    let (body, end) = block(tokens, pos, &["elif", "else", "endif"])?;
+                    branches.push((cond, body));
+                    match end.split_whitespace().next() {
+                        Some("elif") => cond = condition(&end[4..])?,
+                        Some("else") => break block(tokens, pos, &["endif"])?.0,
+                        _ => break vec![],
+                    }
+                };
+                nodes.push(Node::If {
+                    branches,
+                    otherwise,
+                });
+            }
+            _ => bail!("Unknown tag {{% {} %}}", tag),
+        }
+    }
+    if let Some(end) = ends.last() {
+        bail!("Missing {{% {} %}}", end);
+    }
+    Ok((nodes, String::new()))
+}
+
+/// Split an expression into names, string literals and operators
+fn words(s: &str) -> Result<Vec<String>> {
+    let mut words = vec![];
+    let mut chars = s.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '"' | '\'' => {
+                let Some(end) = s[i + 1..].find(c) else {
+                    bail!("Unterminated string in {}", s);
+                };
+                words.push(s[i..i + end + 2].to_string());
+                while chars.next_if(|&(j, _)| j <= i + end + 1).is_some() {}
+            }
+            '|' | '(' | ')' => words.push(c.to_string()),
+            '=' | '!' if chars.next_if(|&(_, c)| c == '=').is_some() => {
+                words.push(format!("{}=", c));
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '.' || c == '-' => {
+                let mut end = i + c.len_utf8();
+                while let Some((j, c)) =
+                    chars.next_if(|&(_, c)| c.is_alphanumeric() || c == '_' || c == '.' || c == '-')
+                {
+                    end = j + c.len_utf8();
+                }
+                words.push(s[i..end].to_string());
+            }
+            _ => bail!("Unexpected {} in {}", c, s),
+        }
+    }
+    Ok(words)
+}
+
+fn literal(word: &str) -> Option<Value> {
+    if let Some(quote) = word.chars().next().filter(|c| *c == '"' || *c == '\'') {
+        return Some(Value::String(word.trim_matches(quote).to_string()));
+    }
+    match word {
+        "true" => Some(Value::Bool(true)),
+        "false" => Some(Value::Bool(false)),
+        "none" => Some(Value::Null),
+        _ => serde_json::from_str::<serde_json::Number>(word)
+            .ok()
+            .map(Value::Number),
+    }
+}
+
+fn expr(words: &[String], pos: &mut usize) -> Result<Expr> {
+    let Some(first) = words.get(*pos) else {
+        bail!("Missing value");
+    };
+    *pos += 1;
+    let operand = match literal(first) {
+        Some(value) => Operand::Literal(value),
+        None if first.split('.').all(|s| !s.is_empty()) => {
+            Operand::Path(first.split('.').map(str::to_string).collect())
+        }
+        None => bail!("Invalid name {}", first),
+    };
+    let mut filters = vec![];
+    while words.get(*pos).is_some_and(|w| w == "|") {
+        let Some(name) = words
+            .get(*pos + 1)
+            .filter(|w| FILTERS.contains(&w.as_str()))
+        else {
+            bail!("Unknown filter, expected one of {}", FILTERS.join(", "));
+        };
+        *pos += 2;
+        let mut arg = None;
+        if words.get(*pos).is_some_and(|w| w == "(") {
+            let (Some(value), Some(")")) = (
+                words.get(*pos + 1).and_then(|w| literal(w)),
+                words.get(*pos + 2).map(String::as_str),
+            ) else {
+                bail!("Expected a literal argument to {}", name);
+            };
+            arg = Some(value);
+            *pos += 3;
+        }
+        filters.push((name.clone(), arg));
+    }
+    Ok(Expr { operand, filters })
+}
+
+/// An expression filling all of `s`
+fn whole_expr(s: &str) -> Result<Expr> {
+    let words = words(s)?;
+    let mut pos = 0;
+    let expr = expr(&words, &mut pos)?;
+    if let Some(word) = words.get(pos) {
+        bail!("Unexpected {} in {}", word, s);
+    }
+    Ok(expr)
+}
+
+fn condition(s: &str) -> Result<Cond> {
+    let words = words(s)?;
+    let mut pos = 0;
+    let negate = words.first().is_some_and(|w| w == "not");
+    if negate {
+        pos += 1;
+    }
+    let left = expr(&words, &mut pos)?;
+    let mut compare = None;
+    if let Some(op) = words.get(pos).filter(|w| *w == "==" || *w == "!=") {
+        pos += 1;
+        compare = Some((op == "==", expr(&words, &mut pos)?));
+    }
+    if let Some(word) = words.get(pos) {
+        bail!("Unexpected {} in {}", word, s);
+    }
+    Ok(Cond {
+        negate,
+        left,
+        compare,
+    })
+}
+
+fn render(nodes: &[Node], context: &Value, vars: &mut Vec<(String, Value)>, out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Print(expr) => out.push_str(&display(&eval(expr, context, vars))),
+            Node::For {
+                var,
+                list,
+                body,
+                empty,
+            } => {
+                let items = match eval(list, context, vars) {
+                    Value::Array(items) if !items.is_empty() => items,
+                    _ => {
+                        render(empty, context, vars, out);
+                        continue;
+                    }
+                };
+                let length = items.len();
+                for (i, item) in items.into_iter().enumerate() {
+                    let state = serde_json::json!({
+                        "index": i + 1,
+                        "index0": i,
+                        "first": i == 0,
+                        "last": i + 1 == length,
+                        "length": length,
+                    });
+                    vars.push(("loop".to_string(), state));
+                    vars.push((var.clone(), item));
+                    render(body, context, vars, out);
+                    vars.truncate(vars.len() - 2);
+                }
+            }
+            Node::If {
+                branches,
+                otherwise,
+            } => {
+                let body = branches
+                    .iter()
+                    .find(|(cond, _)| holds(cond, context, vars))
+                    .map_or(otherwise, |(_, body)| body);
+                render(body, context, vars, out);
+            }
+        }
+    }
+}
+
+fn eval(expr: &Expr, context: &Value, vars: &[(String, Value)]) -> Value {
+    let mut value = match &expr.operand {
+        Operand::Literal(value) => value.clone(),
+        Operand::Path(path) => {
+            let root = vars
+                .iter()
+                .rev()
+                .find(|(name, _)| *name == path[0])
+                .map_or_else(|| &context[&path[0]], |(_, value)| value);
+            let found = path[1..].iter().try_fold(root, |value, key| match value {
+                Value::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+                _ => value.get(key),
+            });
+            found.cloned().unwrap_or(Value::Null)
+        }
+    };
+    for (name, arg) in &expr.filters {
+        value = match name.as_str() {
+            "length" => Value::from(match &value {
+                Value::Array(items) => items.len(),
+                Value::Object(fields) => fields.len(),
+                Value::String(s) => s.chars().count(),
+                _ => 0,
+            }),
+            "upper" => Value::String(display(&value).to_uppercase()),
+            "lower" => Value::String(display(&value).to_lowercase()),
+            "join" => {
+                let sep = arg.as_ref().map(display).unwrap_or_default();
+                let items = value.as_array().map(Vec::as_slice).unwrap_or_default();
+                Value::String(items.iter().map(display).collect::<Vec<_>>().join(&sep))
+            }
+            "default" if value.is_null() => arg.clone().unwrap_or(Value::Null),
+            "default" => value,
+            _ => Value::String(escape(&display(&value))),
+        };
+    }
+    value
+}
+
+fn holds(cond: &Cond, context: &Value, vars: &[(String, Value)]) -> bool {
+    let left = eval(&cond.left, context, vars);
+    let result = match &cond.compare {
+        Some((equal, right)) => (left == eval(right, context, vars)) == *equal,
+        None => match &left {
+            Value::Null => false,
+            Value::Bool(b) => *b,
+            Value::Number(n) => n.as_f64() != Some(0.0),
+            Value::String(s) => !s.is_empty(),
+            Value::Array(items) => !items.is_empty(),
+            Value::Object(fields) => !fields.is_empty(),
+        },
+    };
+    result != cond.negate
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// HTML-escape `s` for the `escape` filter
+fn escape(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&#x27;".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn renders_loops_conditions_and_filters() {
+        let template = Template::parse(concat!(
+            "Run {{ run }}: {{ subvols | length }} subvolumes\n",
+            "{% for s in subvols -%}\n",
+            "{{ loop.index }}. {{ s.subvol | upper }} {# name #}",
+            "{% if s.errors %}failed: {{ s.errors | join('; ') | e }}",
+            "{% elif s.created == 'yes' %}created{% else %}{{ s.created | default('-') }}{% endif %}\n",
+            "{% endfor -%}\n",
+            "{% if not interrupted %}done{% endif %}",
+        ))
+        .unwrap();
+        let report = json!({
+            "run": "r1",
+            "subvols": [
+                {"subvol": "home", "created": "yes", "errors": []},
+                {"subvol": "var", "errors": ["<busy>", "full"]},
+            ],
+        });
+
+        assert_eq!(
+            template.render(&report),
+            "Run r1: 2 subvolumes\n1. HOME created\n2. VAR failed: &lt;busy&gt;; full\ndone"
+        );
+        assert!(Template::parse("{% for s in subvols %}").is_err());
+        assert!(Template::parse("{{ run | shout }}").is_err());
+    }
+}
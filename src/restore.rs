@@ -0,0 +1,329 @@
+use crate::backend::SnapshotBackend;
+use crate::config::Config;
+use crate::i18n::tr;
+use crate::manifest::Manifest;
+use crate::warnings::warning;
+use crate::{create, default_subvol, in_use, inhibit, os_path, selector, utils};
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::Local;
+use log::info;
+use nix::fcntl::{AT_FDCWD, RenameFlags, renameat2};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Trigger recorded for the safety snapshot taken before a restore
+pub const PRE_ROLLBACK: &str = "pre-rollback";
+
+#[derive(clap::Parser)]
+pub struct Restore {
+    /// Snapshot to restore, or a selector like @home:latest-1
+    #[arg(value_parser = selector::parse_snapshot, required_unless_present = "undo")]
+    pub snapshot: Option<PathBuf>,
+    /// Live subvolume to replace (default: the snapshot's source)
+    #[arg(long, value_parser = utils::parse_path, conflicts_with = "root")]
+    pub to: Option<PathBuf>,
+    /// Roll back `/`: boot into a writable copy of the snapshot, set as the
+    /// default subvolume, after the next reboot
+    #[arg(long)]
+    pub root: bool,
+    /// Revert the last restore of --to or --root from its pre-rollback snapshot
+    #[arg(long, conflicts_with = "snapshot")]
+    pub undo: bool,
+    /// Do not ask for confirmation
+    #[arg(short, long)]
+    pub yes: bool,
+    /// Replace the subvolume even if processes or mounts are using it
+    #[arg(long)]
+    pub force: bool,
+}
+
+impl Restore {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        let snap_dir = utils::resolve_snap_dir(backend, None, config.snap_dir.clone())?;
+        let manifest = Manifest::load(&snap_dir)?.unwrap_or_default();
+        let to = if self.root {
+            Some(PathBuf::from("/"))
+        } else {
+            self.to
+        };
+        let (snapshot, live) = if self.undo {
+            let live = to.ok_or_else(|| anyhow!("--undo needs --to or --root"))?;
+            // The newest safety snapshot of `live` holds its state before the last restore
+            let (name, _) = manifest
+                .snapshots
+                .iter()
+                .filter(|(_, e)| e.trigger.as_deref() == Some(PRE_ROLLBACK) && e.source == live)
+                .max_by_key(|(_, e)| e.created)
+                .ok_or_else(|| anyhow!("No pre-rollback snapshot of {}", live.display()))?;
+            (snap_dir.join(os_path::from_name(name)), live)
+        } else {
+            let snapshot = self.snapshot.ok_or_else(|| anyhow!("No snapshot given"))?;
+            let live = match to {
+                Some(to) => to,
+                None => utils::file_name(&snapshot)
+                    .and_then(|name| manifest.snapshots.get(&name))
+                    .map(|e| e.source.clone())
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "{} is not in the manifest, pass the subvolume to replace with --to",
+                            snapshot.display()
+                        )
+                    })?,
+            };
+            (snapshot, live)
+        };
+
+        for p in [&snapshot, &live] {
+            if !backend.is_subvolume(p) {
+                bail!("{} is not a subvolume", p.display());
+            }
+        }
+        if self.root {
+            default_subvol::check_layout(&snapshot)?;
+        } else {
+            // The running root is only replaced on the next boot
+            in_use::refuse_busy(&live, self.force)?;
+        }
+        let shown = snapshot.display().to_string();
+        let prompt = if self.root {
+            tr!("confirm-boot-root", snapshot = shown)
+        } else {
+            let live = live.display().to_string();
+            tr!("confirm-replace", live = live, snapshot = shown)
+        };
+        if !self.yes && !utils::confirm(&prompt)? {
+            bail!(tr!("aborted"));
+        }
+        if self.root {
+            restore_root(backend, &config, &snap_dir, &snapshot)?;
+        } else {
+            restore(backend, &config, &snap_dir, &snapshot, &live)?;
+        }
+        Ok(())
+    }
+}
+
+/// Snapshot `live` as `pre-rollback`, noting `snapshot` as what replaces it
+fn save_state(
+    backend: &'static dyn SnapshotBackend,
+    config: &Config,
+    snap_dir: &Path,
+    live: &Path,
+    snapshot: &Path,
+) -> Result<PathBuf> {
+    info!("Saving the current state of {}", live.display());
+    let safety = create::snapshot_path(
+        snap_dir,
+        live,
+        config.name_parents,
+        Local::now().timestamp(),
+    );
+    let mut retries = 0;
+    let safety = create::create_snapshot(
+        backend,
+        live,
+        &safety,
+        config.retry,
+        config.timeouts,
+        Some(PRE_ROLLBACK),
+        &mut retries,
+    )
+    .context("Failed to take the pre-rollback snapshot, nothing was changed")?;
+    if let Some(name) = utils::file_name(&safety) {
+        let restored = utils::file_name(snapshot);
+        Manifest::update(snap_dir, &name, |entry| entry.replaced_by = restored)?;
+    }
+    Ok(safety)
+}
+
+/// Replace `live` with a writable snapshot of `snapshot`, after saving the
+/// current state of `live` as a pre-rollback snapshot
+pub fn restore(
+    backend: &'static dyn SnapshotBackend,
+    config: &Config,
+    snap_dir: &Path,
+    snapshot: &Path,
+    live: &Path,
+) -> Result<PathBuf> {
+    let staging = utils::sibling(live, "btrsnap-restore")?;
+    if staging.exists() {
+        bail!("{} already exists, remove it first", staging.display());
+    }
+
+    // A shutdown between the pre-rollback snapshot and the swap would leave
+    // a half-done restore
+    let _inhibitor = inhibit::take(&format!("Restoring {}", live.display()));
+    let safety = save_state(backend, config, snap_dir, live, snapshot)?;
+    backend
+        .snapshot(snapshot, &staging, false)
+        .context(format!(
+            "Failed to create {} from {}",
+            staging.display(),
+            snapshot.display()
+        ))?;
+    // Swap both entries in one step so `live` never disappears
+    if let Err(e) = renameat2(
+        AT_FDCWD,
+        &staging,
+        AT_FDCWD,
+        live,
+        RenameFlags::RENAME_EXCHANGE,
+    ) {
+        let _ = backend.delete(&staging);
+        return Err(e).context(format!("Failed to swap {} into place", live.display()));
+    }
+    // `staging` now holds the replaced state, which the safety snapshot keeps
+    if let Err(e) = backend.delete(&staging) {
+        warning!(
+            "Failed to delete the replaced subvolume, it is kept at {}: {}",
+            staging.display(),
+            e
+        );
+    }
+
+    println!("Restored {} from {}", live.display(), snapshot.display());
+    println!("Previous state saved as {}", safety.display());
+    println!("Undo with: btrsnap restore --undo --to {}", live.display());
+    Ok(safety)
+}
+
+/// Make a writable copy of `snapshot` the default subvolume so it is
+/// mounted as `/` on the next boot. The running root cannot be swapped out.
+pub fn restore_root(
+    backend: &'static dyn SnapshotBackend,
+    config: &Config,
+    snap_dir: &Path,
+    snapshot: &Path,
+) -> Result<PathBuf> {
+    // Until the new root is the default subvolume and the bootloader knows
+    let _inhibitor = inhibit::take("Rolling back /");
+    let safety = save_state(backend, config, snap_dir, Path::new("/"), snapshot)?;
+    // The snapshot dir is the one place known to be on the root filesystem
+    let new_root = utils::sibling(&safety, "restored")?;
+    backend
+        .snapshot(snapshot, &new_root, false)
+        .context(format!(
+            "Failed to create {} from {}",
+            new_root.display(),
+            snapshot.display()
+        ))?;
+
+    let fstab_path = new_root.join("etc/fstab");
+    if let Ok(fstab) = fs::read_to_string(&fstab_path)
+        && let Some(unpinned) = unpin_root(&fstab)
+    {
+        if config.rollback.fstab {
+            info!("Removing subvol= from / in {}", fstab_path.display());
+            fs::write(&fstab_path, unpinned)
+                .context(format!("Failed to update {}", fstab_path.display()))?;
+        } else {
+            warning!(
+                "{} mounts / with subvol=, which overrides the default subvolume. \
+                 Edit it or set rollback.fstab = true",
+                fstab_path.display()
+            );
+        }
+    }
+
+    default_subvol::set(backend, &new_root, false)?;
+
+    if let Some(cmd) = &config.rollback.bootloader_command {
+        info!("Running bootloader command: {}", cmd);
+        match Command::new("sh")
+            .args(["-c", cmd])
+            .env("BTRSNAP_NEW_ROOT", &new_root)
+            .status()
+        {
+            Ok(status) if status.success() => {}
+            Ok(status) => warning!("Bootloader command failed ({}), update it by hand", status),
+            Err(e) => warning!("Failed to run the bootloader command: {}", e),
+        }
+    }
+    if fs::read_to_string("/proc/cmdline").is_ok_and(|c| c.contains("subvol")) {
+        warning!(
+            "The kernel command line selects a subvolume, the bootloader must stop passing it"
+        );
+    }
+
+    println!(
+        "{} is now the default subvolume, reboot to use it as /",
+        new_root.display()
+    );
+    println!("Previous state saved as {}", safety.display());
+    println!("Undo with: btrsnap restore --root --undo");
+    Ok(new_root)
+}
+
+/// `fstab` with `subvol=` and `subvolid=` dropped from the btrfs `/` entry,
+/// `None` if that entry does not pin a subvolume
+fn unpin_root(fstab: &str) -> Option<String> {
+    let pinned = |o: &str| o.starts_with("subvol=") || o.starts_with("subvolid=");
+    let mut changed = false;
+    let mut out = String::new();
+    for line in fstab.lines() {
+        let mut fields: Vec<&str> = line.split_whitespace().collect();
+        if line.trim_start().starts_with('#')
+            || fields.len() < 4
+            || fields[1] != "/"
+            || fields[2] != "btrfs"
+            || !fields[3].split(',').any(pinned)
+        {
+            out.push_str(line);
+            out.push('\n');
+            continue;
+        }
+        let opts: Vec<&str> = fields[3].split(',').filter(|o| !pinned(o)).collect();
+        let opts = if opts.is_empty() {
+            "defaults".to_string()
+        } else {
+            opts.join(",")
+        };
+        fields[3] = &opts;
+        out.push_str(&fields.join("\t"));
+        out.push('\n');
+        changed = true;
+    }
+    changed.then_some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+
+    #[test]
+    fn restore_keeps_a_pre_rollback_snapshot() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let (live, snap_dir) = (dir.join("home"), dir.join("snapshots"));
+        backend.add(&live, Local::now());
+        std::fs::create_dir(&snap_dir).unwrap();
+        let old = snap_dir.join("home-1000");
+        backend.add(&old, Local::now());
+
+        let safety = restore(backend, &Config::default(), &snap_dir, &old, &live).unwrap();
+
+        assert!(backend.exists(&live));
+        assert!(backend.exists(&safety));
+        assert!(!utils::sibling(&live, "btrsnap-restore").unwrap().exists());
+        let manifest = Manifest::load(&snap_dir).unwrap().unwrap();
+        let entry = &manifest.snapshots[&utils::file_name(&safety).unwrap()];
+        assert_eq!(entry.trigger.as_deref(), Some(PRE_ROLLBACK));
+        assert_eq!(entry.replaced_by.as_deref(), Some("home-1000"));
+    }
+
+    #[test]
+    fn unpins_only_the_root_entry() {
+        let fstab = "# /etc/fstab\n\
+                     UUID=abc / btrfs subvol=@,compress=zstd 0 0\n\
+                     UUID=abc /home btrfs subvol=@home 0 0\n";
+        assert_eq!(
+            unpin_root(fstab).unwrap(),
+            "# /etc/fstab\n\
+             UUID=abc\t/\tbtrfs\tcompress=zstd\t0\t0\n\
+             UUID=abc /home btrfs subvol=@home 0 0\n"
+        );
+        assert!(unpin_root("UUID=abc / btrfs defaults 0 0\n").is_none());
+    }
+}
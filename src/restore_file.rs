@@ -0,0 +1,458 @@
+use crate::backend::SnapshotBackend;
+use crate::config::Config;
+use crate::manifest::Manifest;
+use crate::warnings::warning;
+use crate::{selector, utils};
+use anyhow::{Context, Result, anyhow, bail};
+use glob::{MatchOptions, Pattern};
+use log::debug;
+use nix::errno::Errno;
+use nix::fcntl::{AtFlags, OFlag, open, openat, readlinkat, renameat};
+use nix::sys::stat::{Mode, SFlag, fstatat, mkdirat};
+use nix::unistd::{Gid, Uid, UnlinkatFlags, fchownat, symlinkat, unlinkat};
+use std::ffi::OsStr;
+use std::fs::{self, File, FileTimes};
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::fs::{MetadataExt, fchown};
+use std::path::{Path, PathBuf};
+
+/// `*` stays within one directory, `**` crosses them
+const MATCH: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Files are compared in pieces of this size
+const CHUNK: usize = 1 << 20;
+
+nix::ioctl_write_int!(ficlone, 0x94, 9);
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum OnConflict {
+    /// Replace the live file
+    Overwrite,
+    /// Keep the live file
+    Skip,
+    /// Move the live file to `<name>.btrsnap-backup`, then restore
+    Backup,
+}
+
+#[derive(clap::Parser)]
+pub struct RestoreFile {
+    /// Snapshot to restore from, or a selector like @home:latest
+    #[arg(value_parser = selector::parse_snapshot)]
+    pub snapshot: PathBuf,
+    /// File or directory to restore, a glob relative to the snapshot root
+    /// (repeatable)
+    #[arg(short = 'p', long = "path", required = true)]
+    pub paths: Vec<Pattern>,
+    /// Leave out matching files and directories (repeatable)
+    #[arg(long)]
+    pub exclude: Vec<Pattern>,
+    /// Directory to restore into (default: the snapshot's source)
+    #[arg(long, value_parser = utils::parse_path)]
+    pub to: Option<PathBuf>,
+    /// What to do with live files that differ from the snapshot
+    #[arg(long, value_enum, default_value = "skip")]
+    pub on_conflict: OnConflict,
+    /// Only print what would be restored
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+}
+
+/// Counts for the closing summary
+#[derive(Default)]
+struct Summary {
+    restored: usize,
+    unchanged: usize,
+    conflicted: usize,
+    backed_up: usize,
+}
+
+impl RestoreFile {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        if !backend.is_subvolume(&self.snapshot) {
+            bail!("{} is not a subvolume", self.snapshot.display());
+        }
+        let dest = match &self.to {
+            Some(to) => to.clone(),
+            None => {
+                let snap_dir = utils::resolve_snap_dir(backend, None, config.snap_dir)?;
+                let manifest = Manifest::load(&snap_dir)?.unwrap_or_default();
+                utils::file_name(&self.snapshot)
+                    .and_then(|name| manifest.snapshots.get(&name))
+                    .map(|e| e.source.clone())
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "{} is not in the manifest, pass the directory to restore into with --to",
+                            self.snapshot.display()
+                        )
+                    })?
+            }
+        };
+        let mut summary = Summary::default();
+        self.walk(&self.snapshot, &dest, Path::new(""), &mut summary)?;
+        println!(
+            "{} {} files, {} unchanged, {} conflicts{}, {} backed up",
+            if self.dry_run {
+                "Would restore"
+            } else {
+                "Restored"
+            },
+            summary.restored,
+            summary.unchanged,
+            summary.conflicted,
+            match self.on_conflict {
+                OnConflict::Skip => " skipped",
+                _ => "",
+            },
+            summary.backed_up
+        );
+        Ok(())
+    }
+
+    /// Whether `rel` or one of its parents matches any of `patterns`
+    fn matches(patterns: &[Pattern], rel: &Path) -> bool {
+        rel.ancestors()
+            .filter(|p| !p.as_os_str().is_empty())
+            .any(|p| patterns.iter().any(|pat| pat.matches_path_with(p, MATCH)))
+    }
+
+    fn walk(
+        &self,
+        src_dir: &Path,
+        dest: &Path,
+        rel_dir: &Path,
+        summary: &mut Summary,
+    ) -> Result<()> {
+        let entries =
+            fs::read_dir(src_dir).context(format!("Failed to read {}", src_dir.display()))?;
+        for entry in entries {
+            let entry = entry?;
+            let rel = rel_dir.join(entry.file_name());
+            if Self::matches(&self.exclude, &rel) {
+                debug!("Excluded {}", rel.display());
+                continue;
+            }
+            let src = entry.path();
+            let meta = entry.metadata()?;
+            let selected = Self::matches(&self.paths, &rel);
+            if meta.is_dir() {
+                if selected && !self.dry_run {
+                    make_dir(dest, &rel, &meta)
+                        .context(format!("Failed to create {}", dest.join(&rel).display()))?;
+                }
+                // Patterns like `*/config` can match below unselected dirs
+                self.walk(&src, dest, &rel, summary)?;
+            } else if selected {
+                if meta.is_file() || meta.is_symlink() {
+                    self.restore_entry(&src, dest, &rel, &meta, summary)?;
+                } else {
+                    warning!("Skipping {}, not a file or symlink", rel.display());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn restore_entry(
+        &self,
+        src: &Path,
+        dest: &Path,
+        rel: &Path,
+        meta: &fs::Metadata,
+        summary: &mut Summary,
+    ) -> Result<()> {
+        let target = dest.join(rel);
+        let name = rel
+            .file_name()
+            .ok_or_else(|| anyhow!("Invalid path {}", rel.display()))?;
+        // Everything below goes through the parent's fd, never through a path
+        // the owner of the live tree could swap for a symlink
+        let dir = open_dir(dest, rel.parent().unwrap_or(Path::new("")), !self.dry_run)?;
+        let existing = dir
+            .as_ref()
+            .and_then(|dir| fstatat(dir, name, AtFlags::AT_SYMLINK_NOFOLLOW).ok());
+        if let (Some(dir), Some(stat)) = (&dir, existing) {
+            if same_content(src, meta, dir, name, &stat)? {
+                summary.unchanged += 1;
+                return Ok(());
+            }
+            match self.on_conflict {
+                OnConflict::Skip => {
+                    println!("conflict {} (kept)", rel.display());
+                    summary.conflicted += 1;
+                    return Ok(());
+                }
+                OnConflict::Overwrite => {
+                    summary.conflicted += 1;
+                }
+                OnConflict::Backup => {
+                    let backup = utils::sibling(Path::new(name), "btrsnap-backup")?;
+                    println!(
+                        "backup {} -> {}",
+                        rel.display(),
+                        target.with_file_name(&backup).display()
+                    );
+                    if !self.dry_run {
+                        renameat(dir, name, dir, &backup)
+                            .context(format!("Failed to back up {}", target.display()))?;
+                    }
+                    summary.conflicted += 1;
+                    summary.backed_up += 1;
+                }
+            }
+        }
+        println!("restore {}", rel.display());
+        summary.restored += 1;
+        if let Some(dir) = &dir
+            && !self.dry_run
+        {
+            copy(src, meta, dir, name)
+                .context(format!("Failed to restore {}", target.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Open `rel` below `dest` one component at a time without following
+/// symlinks. Missing directories are created if `create`, otherwise `None`
+/// is returned for them.
+fn open_dir(dest: &Path, rel: &Path, create: bool) -> Result<Option<OwnedFd>> {
+    let flags = OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC;
+    if create {
+        fs::create_dir_all(dest).context(format!("Failed to create {}", dest.display()))?;
+    }
+    let mut dir = match open(dest, OFlag::O_DIRECTORY | OFlag::O_CLOEXEC, Mode::empty()) {
+        Ok(fd) => fd,
+        Err(Errno::ENOENT) if !create => return Ok(None),
+        Err(e) => return Err(e).context(format!("Failed to open {}", dest.display())),
+    };
+    let mut path = dest.to_path_buf();
+    for component in rel.components() {
+        let name = component.as_os_str();
+        path.push(name);
+        let mut next = openat(&dir, name, flags, Mode::empty());
+        if matches!(next, Err(Errno::ENOENT)) && create {
+            match mkdirat(&dir, name, Mode::from_bits_truncate(0o777)) {
+                Ok(()) | Err(Errno::EEXIST) => {}
+                Err(e) => return Err(e).context(format!("Failed to create {}", path.display())),
+            }
+            next = openat(&dir, name, flags, Mode::empty());
+        }
+        dir = match next {
+            Ok(fd) => fd,
+            Err(Errno::ENOENT) => return Ok(None),
+            Err(Errno::ELOOP | Errno::ENOTDIR) => {
+                bail!(
+                    "{} is not a directory, not restoring through it",
+                    path.display()
+                )
+            }
+            Err(e) => return Err(e).context(format!("Failed to open {}", path.display())),
+        };
+    }
+    Ok(Some(dir))
+}
+
+/// Create the directory `rel` below `dest` with the snapshot's mode, unless
+/// it already exists
+fn make_dir(dest: &Path, rel: &Path, meta: &fs::Metadata) -> Result<()> {
+    let name = rel
+        .file_name()
+        .ok_or_else(|| anyhow!("Invalid path {}", rel.display()))?;
+    let Some(parent) = open_dir(dest, rel.parent().unwrap_or(Path::new("")), true)? else {
+        bail!("{} disappeared", dest.display());
+    };
+    match mkdirat(&parent, name, Mode::from_bits_truncate(0o700)) {
+        Ok(()) => {}
+        Err(Errno::EEXIST) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    }
+    let dir = File::from(openat(
+        &parent,
+        name,
+        OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?);
+    dir.set_permissions(meta.permissions())?;
+    Ok(())
+}
+
+/// Whether `name` in `dir`, with `stat`, already holds what `src` would
+/// restore
+fn same_content(
+    src: &Path,
+    meta: &fs::Metadata,
+    dir: &OwnedFd,
+    name: &OsStr,
+    stat: &nix::sys::stat::FileStat,
+) -> Result<bool> {
+    let kind = SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT;
+    if meta.is_symlink() || kind == SFlag::S_IFLNK {
+        return Ok(meta.is_symlink()
+            && kind == SFlag::S_IFLNK
+            && fs::read_link(src)?.as_os_str() == readlinkat(dir, name)?);
+    }
+    if kind != SFlag::S_IFREG || meta.len() != stat.st_size as u64 {
+        return Ok(false);
+    }
+    let target = File::from(openat(
+        dir,
+        name,
+        OFlag::O_RDONLY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?);
+    Ok(same_bytes(File::open(src)?, target)?)
+}
+
+/// Compare two readers a chunk at a time, so large files are never held whole
+fn same_bytes(mut a: impl Read, mut b: impl Read) -> io::Result<bool> {
+    let (mut buf_a, mut buf_b) = (vec![0; CHUNK], vec![0; CHUNK]);
+    loop {
+        let n = read_full(&mut a, &mut buf_a)?;
+        if n != read_full(&mut b, &mut buf_b)? || buf_a[..n] != buf_b[..n] {
+            return Ok(false);
+        }
+        if n == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Fill `buf` unless the reader ends first, returning how much was read
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+/// Copy `src` over `name` in `dir` with its mode, owner and mtime, sharing
+/// extents where possible. The copy is staged next to `name` and renamed over
+/// it.
+fn copy(src: &Path, meta: &fs::Metadata, dir: &OwnedFd, name: &OsStr) -> Result<()> {
+    let staging = utils::sibling(Path::new(name), "btrsnap-restore")?;
+    let _ = unlinkat(dir, &staging, UnlinkatFlags::NoRemoveDir);
+    let (uid, gid) = (Uid::from_raw(meta.uid()), Gid::from_raw(meta.gid()));
+    if meta.is_symlink() {
+        symlinkat(&fs::read_link(src)?, dir, &staging)?;
+        fchownat(
+            dir,
+            &staging,
+            Some(uid),
+            Some(gid),
+            AtFlags::AT_SYMLINK_NOFOLLOW,
+        )?;
+    } else {
+        let mut from = File::open(src)?;
+        // O_EXCL fails on anything planted under the staging name, links
+        // included, instead of writing through it
+        let mut to = File::from(openat(
+            dir,
+            &staging,
+            OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_EXCL | OFlag::O_CLOEXEC,
+            Mode::from_bits_truncate(0o600),
+        )?);
+        // A reflink shares the snapshot's extents, a plain copy works across
+        // filesystems
+        if unsafe { ficlone(to.as_raw_fd(), from.as_raw_fd() as _) }.is_err() {
+            io::copy(&mut from, &mut to)?;
+        }
+        // Owner first, chown clears setuid bits
+        fchown(&to, Some(meta.uid()), Some(meta.gid()))?;
+        to.set_permissions(meta.permissions())?;
+        to.set_times(FileTimes::new().set_modified(meta.modified()?))?;
+    }
+    renameat(dir, &staging, dir, name)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use chrono::Local;
+
+    #[test]
+    fn restores_filtered_subtree_with_backups() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let (snapshot, live) = (dir.join("home-1"), dir.join("home"));
+        backend.add(&snapshot, Local::now());
+        for (file, content) in [
+            ("a/x", "old x"),
+            ("a/y", "y"),
+            ("a/same", "s"),
+            ("b/z", "z"),
+        ] {
+            let path = snapshot.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        fs::create_dir_all(live.join("a")).unwrap();
+        fs::write(live.join("a/x"), "new x").unwrap();
+        fs::write(live.join("a/same"), "s").unwrap();
+
+        RestoreFile {
+            snapshot,
+            paths: vec![Pattern::new("a").unwrap()],
+            exclude: vec![Pattern::new("a/y").unwrap()],
+            to: Some(live.clone()),
+            on_conflict: OnConflict::Backup,
+            dry_run: false,
+        }
+        .execute(backend, Config::default())
+        .unwrap();
+
+        assert_eq!(fs::read_to_string(live.join("a/x")).unwrap(), "old x");
+        assert_eq!(
+            fs::read_to_string(live.join("a/x.btrsnap-backup")).unwrap(),
+            "new x"
+        );
+        assert!(!live.join("a/y").exists());
+        assert!(!live.join("b").exists());
+    }
+
+    #[test]
+    fn refuses_to_restore_through_symlinked_dirs() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let (snapshot, live, outside) = (dir.join("home-1"), dir.join("home"), dir.join("etc"));
+        backend.add(&snapshot, Local::now());
+        fs::create_dir_all(snapshot.join("docs")).unwrap();
+        fs::write(snapshot.join("docs/passwd"), "restored").unwrap();
+        fs::create_dir_all(&live).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, live.join("docs")).unwrap();
+
+        let result = RestoreFile {
+            snapshot,
+            paths: vec![Pattern::new("docs").unwrap()],
+            exclude: vec![],
+            to: Some(live),
+            on_conflict: OnConflict::Overwrite,
+            dry_run: false,
+        }
+        .execute(backend, Config::default());
+
+        assert!(result.is_err());
+        assert!(!outside.join("passwd").exists());
+    }
+
+    #[test]
+    fn compares_across_chunks() {
+        let a = vec![7u8; CHUNK + 3];
+        let mut b = a.clone();
+        assert!(same_bytes(&a[..], &b[..]).unwrap());
+        b[CHUNK + 1] = 0;
+        assert!(!same_bytes(&a[..], &b[..]).unwrap());
+        assert!(!same_bytes(&a[..], &a[..CHUNK]).unwrap());
+    }
+}
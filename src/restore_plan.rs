@@ -0,0 +1,161 @@
+use crate::backend::{SnapshotBackend, SubvolInfo};
+use crate::config::Config;
+use crate::restore::Restore;
+use crate::{create, utils};
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use std::path::{Path, PathBuf};
+
+#[derive(clap::Parser)]
+pub struct RestorePlan {
+    /// Subvolume to recover, by path or snapshot name prefix
+    #[arg(long)]
+    pub subvol: String,
+    /// Point in time to recover, e.g. "2024-05-01 12:00"
+    #[arg(long, value_parser = parse_time)]
+    pub at: DateTime<Local>,
+    /// Carry out the plan instead of only printing it
+    #[arg(long)]
+    pub execute: bool,
+    /// Do not ask for confirmation when executing
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+impl RestorePlan {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        let snap_dir = utils::resolve_snap_dir(backend, None, config.snap_dir.clone())?;
+        let name = create::name_arg(&self.subvol, config.name_parents);
+        let live = config
+            .subvols
+            .iter()
+            .find(|sv| create::subvol_name(sv, config.name_parents) == name)
+            .cloned()
+            .or_else(|| Some(PathBuf::from(&self.subvol)).filter(|p| p.is_absolute()))
+            .ok_or_else(|| anyhow!("{} is not a configured subvolume", self.subvol))?;
+        let snapshots: Vec<SubvolInfo> = backend
+            .list(&snap_dir)
+            .context(format!(
+                "Failed to list subvolumes in {}",
+                snap_dir.display()
+            ))?
+            .into_iter()
+            .filter(|s| {
+                utils::parse_snapshot_name(&utils::snapshot_name(s)).is_some_and(|(n, _)| n == name)
+            })
+            .collect();
+        let snapshot = closest(&snapshots, self.at)
+            .ok_or_else(|| anyhow!("{} has no snapshots in {}", name, snap_dir.display()))?;
+
+        print_plan(&live, snapshot, self.at);
+        if !self.execute {
+            println!("Run again with --execute to carry it out.");
+            return Ok(());
+        }
+        let root = live == Path::new("/");
+        Restore {
+            snapshot: Some(snapshot.path.clone()),
+            to: (!root).then_some(live),
+            root,
+            undo: false,
+            yes: self.yes,
+            force: false,
+        }
+        .execute(backend, config)
+    }
+}
+
+/// The newest snapshot taken at or before `at`, which holds the state at
+/// that time, else the oldest one after it
+fn closest(snapshots: &[SubvolInfo], at: DateTime<Local>) -> Option<&SubvolInfo> {
+    snapshots
+        .iter()
+        .filter(|s| s.otime <= at)
+        .max_by_key(|s| s.otime)
+        .or_else(|| snapshots.iter().min_by_key(|s| s.otime))
+}
+
+fn print_plan(live: &Path, snapshot: &SubvolInfo, at: DateTime<Local>) {
+    let offset = (at - snapshot.otime)
+        .abs()
+        .to_std()
+        .map(|d| humantime::format_duration(std::time::Duration::from_secs(d.as_secs())))
+        .map_or(String::new(), |d| d.to_string());
+    let when = match snapshot.otime.cmp(&at) {
+        std::cmp::Ordering::Equal => "exactly at the requested time".to_string(),
+        std::cmp::Ordering::Less => format!("{} before the requested time", offset),
+        std::cmp::Ordering::Greater => {
+            format!("{} after the requested time, no snapshot is older", offset)
+        }
+    };
+    println!(
+        "Plan to restore {} to its state at {}:",
+        live.display(),
+        at.format("%Y-%m-%d %H:%M")
+    );
+    println!(
+        "  Snapshot: {} (taken {}, {})",
+        snapshot.path.display(),
+        snapshot.otime.format("%Y-%m-%d %H:%M"),
+        when
+    );
+    println!(
+        "  1. Snapshot the current {} as pre-rollback",
+        live.display()
+    );
+    if live == Path::new("/") {
+        println!("  2. Make a writable copy of the snapshot the default subvolume");
+        println!("  3. Reboot into it");
+    } else {
+        println!(
+            "  2. Replace {} with a writable copy of the snapshot",
+            live.display()
+        );
+    }
+    println!("  Data to transfer: none, the snapshot is local");
+}
+
+/// `YYYY-MM-DD HH:MM[:SS]`, or a day meaning its end
+fn parse_time(s: &str) -> Result<DateTime<Local>> {
+    let time = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .ok()?
+                .and_hms_opt(23, 59, 59)
+        });
+    match time.map(|t| Local.from_local_datetime(&t).earliest()) {
+        Some(Some(time)) => Ok(time),
+        _ => bail!("Invalid time {}, expected YYYY-MM-DD [HH:MM[:SS]]", s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use chrono::Duration;
+
+    #[test]
+    fn picks_the_newest_snapshot_not_after_the_time() {
+        let backend = MockBackend::leak();
+        let snap_dir = backend.scratch_dir();
+        let at = parse_time("2024-05-01 12:00").unwrap();
+        for (name, hours) in [("home-1", -5), ("home-2", -1), ("home-3", 2)] {
+            backend.add(&snap_dir.join(name), at + Duration::hours(hours));
+        }
+        let snapshots = backend.list(&snap_dir).unwrap();
+
+        assert_eq!(
+            closest(&snapshots, at).unwrap().path,
+            snap_dir.join("home-2")
+        );
+        let early = at - Duration::days(1);
+        assert_eq!(
+            closest(&snapshots, early).unwrap().path,
+            snap_dir.join("home-1")
+        );
+        assert!(parse_time("May 1st").is_err());
+    }
+}
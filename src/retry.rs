@@ -0,0 +1,75 @@
+use crate::timeout;
+use log::warn;
+use nix::errno::Errno;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+/// How often and how patiently to retry transient btrfs errors
+#[derive(Clone, Copy)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    /// Delay before the first retry, doubled after every attempt
+    pub delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 3,
+            delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Errors that tend to succeed on a later attempt (e.g. during a commit)
+fn is_transient(errno: Errno) -> bool {
+    matches!(errno, Errno::EBUSY | Errno::ETXTBSY | Errno::EAGAIN)
+}
+
+/// Run a btrfs operation, retrying transient failures with backoff.
+/// `retries` is increased by the number of retries made.
+pub fn retry<T>(
+    policy: RetryPolicy,
+    what: &str,
+    retries: &mut u32,
+    mut op: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let mut delay = policy.delay;
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Ok(value) => return Ok(value),
+            Err(e) => {
+                let errno = Errno::from_raw(e.raw_os_error().unwrap_or(0));
+                if attempt >= policy.retries || !is_transient(errno) {
+                    return Err(e);
+                }
+                attempt += 1;
+                *retries += 1;
+                warn!(
+                    "{} failed ({}), retry {}/{} in {:?}",
+                    what, e, attempt, policy.retries, delay
+                );
+                thread::sleep(delay);
+                delay *= 2;
+            }
+        }
+    }
+}
+
+/// [`retry`] on a worker thread, giving up after `limit`
+pub fn retry_with_timeout<T: Send + 'static>(
+    policy: RetryPolicy,
+    limit: Option<Duration>,
+    what: &'static str,
+    retries: &mut u32,
+    op: impl FnMut() -> io::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    let (result, n) = timeout::run(limit, what, move || {
+        let mut n = 0;
+        (retry(policy, what, &mut n, op), n)
+    })?;
+    *retries += n;
+    Ok(result?)
+}
@@ -0,0 +1,19 @@
+//! Identifier of this invocation, tying together its log lines, report,
+//! notifications and the snapshots and tombstones it wrote
+
+use chrono::Local;
+use std::sync::OnceLock;
+
+static ID: OnceLock<String> = OnceLock::new();
+
+/// Start time and PID, e.g. `20240131T020000-3f2a`: sortable, and unique
+/// on a host since a PID is not reused within the same second
+pub fn id() -> &'static str {
+    ID.get_or_init(|| {
+        format!(
+            "{}-{:x}",
+            Local::now().format("%Y%m%dT%H%M%S"),
+            std::process::id()
+        )
+    })
+}
@@ -0,0 +1,89 @@
+//! smb.conf snippets for Samba's `shadow_copy2` module, so Windows clients
+//! see the snapshots of a share as "Previous Versions". The module reads
+//! btrsnap's `<name>-<unix time>` names directly with `shadow:sscanf`, so
+//! snapshots need no separate `@GMT-...` layout.
+
+use crate::backend::SnapshotBackend;
+use crate::config::Config;
+use crate::{create, utils};
+use anyhow::{Result, bail};
+use std::path::{Path, PathBuf};
+
+#[derive(clap::Parser)]
+pub struct SambaConfig {
+    /// Subvolume the share is in
+    #[arg(value_parser = utils::parse_path)]
+    pub subvol: PathBuf,
+    /// Share name, the subvolume's snapshot name if unset
+    #[arg(long)]
+    pub share: Option<String>,
+    /// Directory to share if not the whole subvolume
+    #[arg(long, value_parser = utils::parse_path)]
+    pub path: Option<PathBuf>,
+    #[arg(short = 'd', long, value_parser = utils::parse_path)]
+    pub snap_dir: Option<PathBuf>,
+}
+
+impl SambaConfig {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        let snap_dir = utils::resolve_snap_dir(backend, self.snap_dir, config.snap_dir)?;
+        if !backend.is_subvolume(&self.subvol) {
+            bail!("{} is not a subvolume", self.subvol.display());
+        }
+        let path = self.path.unwrap_or_else(|| self.subvol.clone());
+        if !path.starts_with(&self.subvol) {
+            bail!(
+                "{} is not inside {}, whose snapshots would not contain it",
+                path.display(),
+                self.subvol.display()
+            );
+        }
+        let name = create::subvol_name(&self.subvol, config.name_parents);
+        let share = self.share.unwrap_or_else(|| name.clone());
+        print!("{}", snippet(&share, &path, &self.subvol, &snap_dir, &name));
+        Ok(())
+    }
+}
+
+/// The share section for `path` inside `subvol`, whose snapshots named
+/// `name-<ts>` are in `snap_dir`
+fn snippet(share: &str, path: &Path, subvol: &Path, snap_dir: &Path, name: &str) -> String {
+    // Escaped bytes in snapshot names would read as conversions
+    let format = format!("{}-%lu", name.replace('%', "%%"));
+    format!(
+        "# Previous Versions from btrsnap snapshots of {subvol}\n\
+         [{share}]\n\
+         \tpath = {path}\n\
+         \tvfs objects = shadow_copy2\n\
+         \tshadow:mountpoint = {subvol}\n\
+         \tshadow:snapdir = {snap_dir}\n\
+         \tshadow:format = {format}\n\
+         \tshadow:sscanf = yes\n\
+         \tshadow:localtime = no\n\
+         \tshadow:sort = desc\n",
+        subvol = subvol.display(),
+        path = path.display(),
+        snap_dir = snap_dir.display(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_snapshot_names_by_unix_time() {
+        let text = snippet(
+            "projects",
+            Path::new("/srv/data/projects"),
+            Path::new("/srv/data"),
+            Path::new("/srv/.snapshots"),
+            "data%20set",
+        );
+
+        assert!(text.contains("[projects]\n\tpath = /srv/data/projects\n"));
+        assert!(text.contains("\tshadow:mountpoint = /srv/data\n"));
+        assert!(text.contains("\tshadow:snapdir = /srv/.snapshots\n"));
+        assert!(text.contains("\tshadow:format = data%%20set-%lu\n"));
+    }
+}
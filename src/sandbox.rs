@@ -0,0 +1,135 @@
+use crate::backend::SnapshotBackend;
+use crate::config::Config;
+use crate::manifest::STATE_DIR;
+use crate::warnings::warning;
+use crate::{create, utils};
+use anyhow::{Context, Result, anyhow};
+use log::{debug, info};
+use nix::mount::{MntFlags, MsFlags, mount, umount2};
+use nix::sched::{CloneFlags, unshare};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, ExitStatus};
+
+/// Prefix of clones kept with `--keep`, which `gc` leaves alone
+pub const KEPT_PREFIX: &str = "sandbox-kept-";
+
+#[derive(clap::Parser)]
+pub struct Sandbox {
+    /// Subvolume whose latest snapshot the command runs on
+    #[arg(value_parser = utils::parse_path)]
+    pub subvol: PathBuf,
+    /// Run the command chrooted into the clone instead of mounting the
+    /// clone over the subvolume
+    #[arg(long)]
+    pub chroot: bool,
+    /// Keep the clone afterwards to inspect what the command changed
+    #[arg(long)]
+    pub keep: bool,
+    /// Command to run, after `--`
+    #[arg(last = true, required = true)]
+    pub command: Vec<String>,
+}
+
+impl Sandbox {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        let snap_dir = utils::resolve_snap_dir(backend, None, config.snap_dir)?;
+        let snapshots = backend.list(&snap_dir).context(format!(
+            "Failed to list subvolumes in {}",
+            snap_dir.display()
+        ))?;
+        let name = create::subvol_name(&self.subvol, config.name_parents);
+        let latest = create::newest_snapshot(&snapshots, &name)
+            .ok_or_else(|| anyhow!("No snapshot of {} to run on", self.subvol.display()))?;
+
+        // Inside the state dir the clone is on the right filesystem but not
+        // taken for a snapshot
+        let state_dir = snap_dir.join(STATE_DIR);
+        fs::create_dir_all(&state_dir)
+            .context(format!("Failed to create {}", state_dir.display()))?;
+        let clone = state_dir.join(format!("sandbox-{}", process::id()));
+        backend
+            .snapshot(&latest.path, &clone, false)
+            .context(format!("Failed to clone {}", latest.path.display()))?;
+        info!(
+            "Running {:?} on a clone of {}",
+            self.command,
+            latest.path.display()
+        );
+
+        let status = run(&clone, &self.subvol, self.chroot, &self.command);
+        if self.keep {
+            // Renamed so `gc` does not take it for the clone of a dead run
+            let kept = state_dir.join(format!("{}{}", KEPT_PREFIX, process::id()));
+            match fs::rename(&clone, &kept) {
+                Ok(()) => println!("Clone kept at {}", kept.display()),
+                Err(e) => {
+                    warning!("Failed to rename the clone {}: {}", clone.display(), e);
+                    println!("Clone kept at {}", clone.display());
+                }
+            }
+        } else if let Err(e) = backend.delete(&clone) {
+            warning!("Failed to delete the clone {}: {}", clone.display(), e);
+        }
+        let status = status?;
+        if !status.success() {
+            process::exit(status.code().unwrap_or(1));
+        }
+        Ok(())
+    }
+}
+
+/// Run `command` seeing `clone` in place of `subvol`
+fn run(clone: &Path, subvol: &Path, chroot: bool, command: &[String]) -> Result<ExitStatus> {
+    if chroot {
+        return Command::new("chroot")
+            .arg(clone)
+            .args(command)
+            .status()
+            .context("Failed to run chroot");
+    }
+
+    // A private mount namespace keeps the bind mount away from everyone else
+    unshare(CloneFlags::CLONE_NEWNS).context("Failed to create a mount namespace")?;
+    mount(
+        None::<&str>,
+        "/",
+        None::<&str>,
+        MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+        None::<&str>,
+    )
+    .context("Failed to make mounts private")?;
+    mount(
+        Some(clone),
+        subvol,
+        None::<&str>,
+        MsFlags::MS_BIND,
+        None::<&str>,
+    )
+    .context(format!(
+        "Failed to mount the clone over {}",
+        subvol.display()
+    ))?;
+
+    let mut cmd = Command::new(&command[0]);
+    cmd.args(&command[1..]);
+    // Re-enter the working directory so it resolves through the bind mount
+    if let Ok(cwd) = env::current_dir()
+        && cwd.starts_with(subvol)
+    {
+        cmd.current_dir(cwd);
+    }
+    let status = cmd
+        .status()
+        .context(format!("Failed to run {}", command[0]));
+    debug!("Unmounting the clone from {}", subvol.display());
+    if let Err(e) = umount2(subvol, MntFlags::MNT_DETACH) {
+        warning!(
+            "Failed to unmount the clone from {}: {}",
+            subvol.display(),
+            e
+        );
+    }
+    status
+}
@@ -0,0 +1,61 @@
+use crate::fleet::HostResult;
+use crate::manifest::Manifest;
+use crate::report::Report;
+use anyhow::Result;
+use schemars::generate::SchemaSettings;
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum Output {
+    /// `create --json` and `cleanup --json`
+    Report,
+    /// `manifest.json` in the state dir of a snapshot dir
+    Manifest,
+    /// `fleet --json`
+    Fleet,
+}
+
+#[derive(clap::Parser)]
+pub struct Schema {
+    /// Output to print the JSON Schema of
+    #[arg(value_enum)]
+    pub output: Output,
+}
+
+impl Schema {
+    pub fn execute(self) -> Result<()> {
+        println!("{}", serde_json::to_string_pretty(&schema(self.output))?);
+        Ok(())
+    }
+}
+
+/// JSON Schema generated from the serde types of `output`, describing what
+/// btrsnap writes (fields skipped when empty are optional)
+pub fn schema(output: Output) -> schemars::Schema {
+    let generator = SchemaSettings::draft2020_12()
+        .for_serialize()
+        .into_generator();
+    match output {
+        Output::Report => generator.into_root_schema_for::<Report>(),
+        Output::Manifest => generator.into_root_schema_for::<Manifest>(),
+        Output::Fleet => generator.into_root_schema_for::<Vec<HostResult>>(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The published schemas in schemas/ must be regenerated with
+    /// `btrsnap schema <output>` whenever an output changes
+    #[test]
+    fn published_schemas_are_current() {
+        for (output, published) in [
+            (Output::Report, include_str!("../schemas/report.json")),
+            (Output::Manifest, include_str!("../schemas/manifest.json")),
+            (Output::Fleet, include_str!("../schemas/fleet.json")),
+        ] {
+            let current = serde_json::to_string_pretty(&schema(output)).unwrap();
+            assert_eq!(current.trim(), published.trim());
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Symbolic names for snapshots, resolved against the snapshot dir:
+//! `@<subvol>:latest`, `@<subvol>:oldest`, `@<subvol>:latest-<n>` (the
+//! `n`th before the latest) and `@<subvol>:oldest+<n>`
+
+use crate::backend::SnapshotBackend;
+use crate::config::Config;
+use crate::{create, utils};
+use anyhow::{Context, Result, anyhow, bail};
+use std::path::{Path, PathBuf};
+
+/// A parsed selector
+#[derive(Debug, PartialEq)]
+struct Selector {
+    /// Name as in snapshot names, or the subvolume's path
+    subvol: String,
+    /// Count from the newest snapshot instead of the oldest
+    newest: bool,
+    offset: usize,
+}
+
+/// Value parser for snapshot arguments: a path, or a selector kept as is
+/// until the snapshot dir is known
+pub fn parse_snapshot(s: &str) -> Result<PathBuf> {
+    match parse(s) {
+        Some(selector) => selector.map(|_| PathBuf::from(s)),
+        None => utils::parse_path(s),
+    }
+}
+
+/// The selector in `s`, `None` if it is not one
+fn parse(s: &str) -> Option<Result<Selector>> {
+    let (subvol, which) = s.strip_prefix('@')?.rsplit_once(':')?;
+    Some(parse_which(which).map(|(newest, offset)| Selector {
+        subvol: subvol.to_string(),
+        newest,
+        offset,
+    }))
+}
+
+fn parse_which(which: &str) -> Result<(bool, usize)> {
+    let offset = |n: &str| {
+        n.parse()
+            .map_err(|_| anyhow!("Invalid offset in snapshot selector: {}", n))
+    };
+    match which {
+        "latest" => Ok((true, 0)),
+        "oldest" => Ok((false, 0)),
+        _ => {
+            if let Some(n) = which.strip_prefix("latest-") {
+                Ok((true, offset(n)?))
+            } else if let Some(n) = which.strip_prefix("oldest+") {
+                Ok((false, offset(n)?))
+            } else {
+                bail!(
+                    "Unknown snapshot selector :{}, expected latest, oldest, latest-<n> or oldest+<n>",
+                    which
+                )
+            }
+        }
+    }
+}
+
+/// `path` itself, or the snapshot it selects if it is a selector
+pub fn resolve(backend: &dyn SnapshotBackend, config: &Config, path: &Path) -> Result<PathBuf> {
+    let Some(selector) = path.to_str().and_then(parse) else {
+        return Ok(path.to_path_buf());
+    };
+    let selector = selector?;
+    let snap_dir = utils::resolve_snap_dir(backend, None, config.snap_dir.clone())?;
+    let name = create::name_arg(&selector.subvol, config.name_parents);
+    let mut snapshots = backend.list(&snap_dir).context(format!(
+        "Failed to list subvolumes in {}",
+        snap_dir.display()
+    ))?;
+    snapshots.retain(|s| {
+        utils::parse_snapshot_name(&utils::snapshot_name(s)).is_some_and(|(n, _)| n == name)
+    });
+    snapshots.sort_by_key(|s| (s.otime, s.path.clone()));
+    if selector.newest {
+        snapshots.reverse();
+    }
+    let count = snapshots.len();
+    snapshots
+        .into_iter()
+        .nth(selector.offset)
+        .map(|s| s.path)
+        .ok_or_else(|| {
+            anyhow!(
+                "{} selects nothing, {} has {} snapshots in {}",
+                path.display(),
+                name,
+                count,
+                snap_dir.display()
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use chrono::{Duration, Local};
+
+    #[test]
+    fn selects_by_position() {
+        let backend = MockBackend::leak();
+        let snap_dir = backend.scratch_dir();
+        for (name, age) in [("home-1", 3), ("home-2", 2), ("home-3", 1), ("var-4", 0)] {
+            backend.add(&snap_dir.join(name), Local::now() - Duration::hours(age));
+        }
+        let config = Config {
+            snap_dir: Some(snap_dir.clone()),
+            ..Default::default()
+        };
+        let select = |s: &str| resolve(backend, &config, Path::new(s));
+
+        assert_eq!(select("@home:latest").unwrap(), snap_dir.join("home-3"));
+        assert_eq!(select("@home:latest-1").unwrap(), snap_dir.join("home-2"));
+        assert_eq!(select("@home:oldest").unwrap(), snap_dir.join("home-1"));
+        assert!(select("@home:oldest+3").is_err());
+        assert!(parse_snapshot("@home:newest").is_err());
+        assert_eq!(select("/tmp").unwrap(), PathBuf::from("/tmp"));
+    }
+}
@@ -0,0 +1,302 @@
+//! Self-service mode: with btrsnap installed setuid root, users without
+//! sudo can snapshot subvolumes they own, list those snapshots and restore
+//! files from them. The privileged side decides what is allowed: only the
+//! system config is read, and only if it enables `self-service`.
+
+use crate::Commands;
+use crate::backend::SnapshotBackend;
+use crate::config::Config;
+use crate::manifest::Manifest;
+use crate::utils;
+use anyhow::{Context, Result, bail};
+use nix::unistd::{Gid, Uid, setresgid, setresuid};
+use std::env;
+use std::fs::File;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// The only config read in self-service mode
+pub const SYSTEM_CONFIG: &str = "/etc/btrsnap.toml";
+
+/// `PATH` for the programs run as root on a caller's behalf
+const PATH: &str = "/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// The user running btrsnap, if it runs setuid root on their behalf
+pub fn caller() -> Option<Uid> {
+    let (real, effective) = (Uid::current(), Uid::effective());
+    (effective.is_root() && !real.is_root()).then_some(real)
+}
+
+/// Drop the caller's environment, so programs run as root (package
+/// managers, curl, hooks) are not looked up in their `PATH` nor
+/// influenced by their variables. Must run before any other thread starts.
+pub fn clear_env() {
+    for (key, _) in env::vars_os() {
+        // SAFETY: called first thing in main, while single-threaded
+        unsafe { env::remove_var(key) };
+    }
+    // SAFETY: as above
+    unsafe { env::set_var("PATH", PATH) };
+}
+
+/// The system config, if only root can change it
+pub fn system_config() -> Result<PathBuf> {
+    let path = PathBuf::from(SYSTEM_CONFIG);
+    utils::root_only(&path).context(format!(
+        "Self-service needs the system config {}",
+        path.display()
+    ))?;
+    Ok(path)
+}
+
+/// `command` narrowed to what `user` may do, or an error if it is not
+/// available to them
+pub fn restrict(
+    command: Commands,
+    config: &Config,
+    backend: &dyn SnapshotBackend,
+    user: Uid,
+) -> Result<Commands> {
+    if !config.self_service {
+        bail!(
+            "btrsnap runs setuid root, but 'self-service' is not enabled in {}",
+            SYSTEM_CONFIG
+        );
+    }
+    for path in &config.included {
+        utils::root_only(path).context("Self-service needs root-only configs")?;
+    }
+    match command {
+        Commands::Create(mut create) => {
+            if create.all
+                || create.snap_dir.is_some()
+                || create.subvol.is_empty()
+                || !create.container.is_empty()
+                || create.machines.is_some()
+            {
+                bail!(
+                    "Without root, create needs --subvol and does not take --all, --container, \
+                     --machines or --snap-dir"
+                );
+            }
+            // These run programs or read files as root on the user's behalf
+            if create.description.is_some()
+                || create.report_template.is_some()
+                || create.pkg_hook
+                || create.json
+                || create.porcelain.is_some()
+            {
+                bail!(
+                    "Without root, create does not take --description, --report-template, \
+                     --pkg-hook, --json or --porcelain"
+                );
+            }
+            for sv in &create.subvol {
+                let uuid = check_owner(backend, sv, user)?;
+                create.owned.insert(sv.clone(), uuid);
+            }
+            Ok(Commands::Create(create))
+        }
+        Commands::List(mut list) => {
+            if list.snap_dir.is_some() {
+                bail!("Without root, list does not take --snap-dir");
+            }
+            list.owner = Some(user);
+            Ok(Commands::List(list))
+        }
+        Commands::RestoreFile(restore) => {
+            check_owner(backend, &restore.snapshot, user)?;
+            // Copy with the user's own permissions, so only files they can
+            // read are restored and only where they can write
+            let gid = Gid::current();
+            setresgid(gid, gid, gid).context("Failed to drop privileges")?;
+            setresuid(user, user, user).context("Failed to drop privileges")?;
+            Ok(Commands::RestoreFile(restore))
+        }
+        _ => bail!("Without root, only create, list and restore-file are available"),
+    }
+}
+
+/// Fail unless `path` is a subvolume whose top directory `user` owns, and
+/// return its UUID. Both are read through one fd, so the caller can't swap
+/// a directory on the path between the two.
+fn check_owner(backend: &dyn SnapshotBackend, path: &Path, user: Uid) -> Result<Uuid> {
+    let dir = File::open(path).context(format!("Failed to open {}", path.display()))?;
+    let fd_path = PathBuf::from(format!("/proc/self/fd/{}", dir.as_raw_fd()));
+    if !backend.is_subvolume(&fd_path) {
+        bail!("{} is not a subvolume", path.display());
+    }
+    let meta = dir
+        .metadata()
+        .context(format!("Failed to stat {}", path.display()))?;
+    if meta.uid() != user.as_raw() {
+        bail!("{} is not owned by you", path.display());
+    }
+    let info = backend
+        .info(&fd_path)
+        .context(format!("Failed to get info for {}", path.display()))?;
+    Ok(info.uuid)
+}
+
+/// Delete `snapshot` unless it was taken of the subvolume with `uuid`, the
+/// one `check_owner` let through. The caller may have swapped the path in
+/// between.
+pub fn verify_snapshot(backend: &dyn SnapshotBackend, snapshot: &Path, uuid: Uuid) -> Result<()> {
+    let info = backend.info(snapshot).context(format!(
+        "Failed to get info for snapshot {}",
+        snapshot.display()
+    ))?;
+    if info.parent_uuid == Some(uuid) {
+        return Ok(());
+    }
+    backend
+        .delete(snapshot)
+        .context(format!("Failed to delete {}", snapshot.display()))?;
+    if let (Some(snap_dir), Some(name)) = (snapshot.parent(), utils::file_name(snapshot)) {
+        Manifest::forget(snap_dir, &name)?;
+    }
+    bail!(
+        "The subvolume changed after its owner was checked, deleted {}",
+        snapshot.display()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use crate::{create, gc};
+    use chrono::Local;
+
+    #[test]
+    fn allows_only_own_subvolumes_and_some_commands() {
+        let backend = MockBackend::leak();
+        let home = backend.scratch_dir().join("home");
+        backend.add(&home, Local::now());
+        let config = Config {
+            self_service: true,
+            ..Default::default()
+        };
+        let create = |subvol: &Path| create::Create {
+            subvol: vec![subvol.to_path_buf()],
+            all: false,
+            preset: None,
+            snap_dir: None,
+            description: None,
+            pkg_hook: false,
+            ignore_min_interval: false,
+            json: false,
+            porcelain: None,
+            report_template: None,
+            container: vec![],
+            no_pause: false,
+            machines: None,
+            freeze: false,
+            owned: Default::default(),
+        };
+        let (me, other) = (Uid::current(), Uid::from_raw(Uid::current().as_raw() + 1));
+
+        assert!(restrict(Commands::Create(create(&home)), &config, backend, me).is_ok());
+        assert!(restrict(Commands::Create(create(&home)), &config, backend, other).is_err());
+        let disabled = Config::default();
+        assert!(restrict(Commands::Create(create(&home)), &disabled, backend, me).is_err());
+        let mut described = create(&home);
+        described.description = Some("{packages}".to_string());
+        assert!(restrict(Commands::Create(described), &config, backend, me).is_err());
+        let gc = Commands::Gc(gc::Gc {
+            snap_dir: None,
+            adopt: false,
+            dry_run: true,
+        });
+        assert!(restrict(gc, &config, backend, me).is_err());
+    }
+
+    #[test]
+    fn deletes_snapshots_of_swapped_subvolumes() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let (home, other, snap_dir) = (dir.join("home"), dir.join("root"), dir.join("snapshots"));
+        backend.add(&home, Local::now());
+        backend.add(&other, Local::now());
+        std::fs::create_dir(&snap_dir).unwrap();
+        let config = Config {
+            self_service: true,
+            ..Default::default()
+        };
+        let create = create::Create {
+            subvol: vec![home.clone()],
+            all: false,
+            preset: None,
+            snap_dir: None,
+            description: None,
+            pkg_hook: false,
+            ignore_min_interval: false,
+            json: false,
+            porcelain: None,
+            report_template: None,
+            container: vec![],
+            no_pause: false,
+            machines: None,
+            freeze: false,
+            owned: Default::default(),
+        };
+        let mut create = match restrict(Commands::Create(create), &config, backend, Uid::current())
+        {
+            Ok(Commands::Create(create)) => create,
+            Ok(_) => panic!("create turned into another command"),
+            Err(e) => panic!("{:#}", e),
+        };
+        assert_eq!(create.owned[&home], backend.info(&home).unwrap().uuid);
+
+        // The checked path now leads somewhere else
+        create
+            .owned
+            .insert(home.clone(), backend.info(&other).unwrap().uuid);
+        create.snap_dir = Some(snap_dir.clone());
+        let _ = create.execute(backend, Config::default());
+        assert!(backend.list(&snap_dir).unwrap().is_empty());
+    }
+
+    #[test]
+    fn does_not_follow_a_planted_ignore_symlink() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let (home, snap_dir, target) =
+            (dir.join("home"), dir.join("snapshots"), dir.join("nologin"));
+        backend.add(&home, Local::now());
+        std::fs::create_dir(&snap_dir).unwrap();
+        std::os::unix::fs::symlink(&target, home.join(".ignore")).unwrap();
+        let config = Config {
+            self_service: true,
+            ..Default::default()
+        };
+        let create = create::Create {
+            subvol: vec![home.clone()],
+            all: false,
+            preset: None,
+            snap_dir: None,
+            description: None,
+            pkg_hook: false,
+            ignore_min_interval: false,
+            json: false,
+            porcelain: None,
+            report_template: None,
+            container: vec![],
+            no_pause: false,
+            machines: None,
+            freeze: false,
+            owned: Default::default(),
+        };
+        let mut create = match restrict(Commands::Create(create), &config, backend, Uid::current())
+        {
+            Ok(Commands::Create(create)) => create,
+            Ok(_) => panic!("create turned into another command"),
+            Err(e) => panic!("{:#}", e),
+        };
+        create.snap_dir = Some(snap_dir);
+        let _ = create.execute(backend, Config::default());
+        assert!(target.symlink_metadata().is_err());
+    }
+}
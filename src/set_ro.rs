@@ -0,0 +1,95 @@
+use crate::backend::SnapshotBackend;
+use crate::manifest::Manifest;
+use crate::warnings::warning;
+use crate::{selector, utils};
+use anyhow::{Context, Result};
+use chrono::Local;
+use clap::ArgAction;
+use std::path::PathBuf;
+
+#[derive(clap::Parser)]
+pub struct SetRo {
+    /// Snapshot to change, or a selector like @home:latest
+    #[arg(value_parser = selector::parse_snapshot)]
+    pub snapshot: PathBuf,
+    /// Make it read-only (true) or writable (false)
+    #[arg(action = ArgAction::Set, value_name = "true|false")]
+    pub read_only: bool,
+}
+
+impl SetRo {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend) -> Result<()> {
+        let info = backend
+            .info(&self.snapshot)
+            .context(format!("{} is not a subvolume", self.snapshot.display()))?;
+        let state = if self.read_only {
+            "read-only"
+        } else {
+            "writable"
+        };
+        if info.is_read_only() == self.read_only {
+            println!("{} is already {}", self.snapshot.display(), state);
+            return Ok(());
+        }
+        if !self.read_only {
+            warning!(
+                "A writable snapshot cannot be sent, and neither it nor its copies \
+                 received elsewhere can serve as the parent of incremental sends"
+            );
+            if info.received_uuid.is_some() {
+                warning!(
+                    "{} was received, making it writable drops its received UUID",
+                    self.snapshot.display()
+                );
+            }
+        }
+        backend
+            .set_read_only(&self.snapshot, self.read_only)
+            .context(format!(
+                "Failed to make {} {}",
+                self.snapshot.display(),
+                state
+            ))?;
+        if let (Some(snap_dir), Some(name)) =
+            (self.snapshot.parent(), utils::file_name(&self.snapshot))
+        {
+            Manifest::update(snap_dir, &name, |entry| {
+                entry.read_only = Some(self.read_only);
+                entry.read_only_changed = Some(Local::now());
+            })?;
+        }
+        println!("{} is now {}", self.snapshot.display(), state);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use crate::manifest::Entry;
+
+    #[test]
+    fn toggles_flag_and_records_it() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let (source, snap_dir) = (dir.join("home"), dir.join("snapshots"));
+        let snapshot = snap_dir.join("home-1");
+        backend.add(&source, Local::now());
+        backend.snapshot(&source, &snapshot, true).unwrap();
+        let entry = Entry::new(&source, &backend.info(&snapshot).unwrap());
+        Manifest::record(&snap_dir, "home-1", entry).unwrap();
+
+        let set_ro = |read_only| SetRo {
+            snapshot: snapshot.clone(),
+            read_only,
+        };
+        set_ro(false).execute(backend).unwrap();
+        assert!(!backend.info(&snapshot).unwrap().is_read_only());
+        let manifest = Manifest::load(&snap_dir).unwrap().unwrap();
+        assert_eq!(manifest.snapshots["home-1"].read_only, Some(false));
+
+        set_ro(true).execute(backend).unwrap();
+        assert!(backend.info(&snapshot).unwrap().is_read_only());
+    }
+}
@@ -0,0 +1,133 @@
+//! Config signatures. Once `/etc/btrsnap/trusted-keys` exists, every config
+//! file must come with a `<file>.sig` made by one of the Ed25519 keys listed
+//! there, so an attacker who can edit the config cannot quietly shorten
+//! retention to get rid of snapshots.
+
+use crate::utils;
+use anyhow::{Context, Result, anyhow, bail};
+use log::debug;
+use ring::rand::SystemRandom;
+use ring::signature::{ED25519, Ed25519KeyPair, KeyPair, UnparsedPublicKey};
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// Public keys trusted to sign configs, in hex, one per line
+pub const TRUST_FILE: &str = "/etc/btrsnap/trusted-keys";
+
+/// Signature file of `config`
+pub fn signature_path(config: &Path) -> PathBuf {
+    let mut path = config.as_os_str().to_owned();
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+/// Keys in the trust file, `None` if there is none and signatures are not
+/// required
+pub fn trusted_keys() -> Result<Option<Vec<Vec<u8>>>> {
+    load_keys(Path::new(TRUST_FILE))
+}
+
+fn load_keys(path: &Path) -> Result<Option<Vec<Vec<u8>>>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context(format!("Failed to read {}", path.display())),
+    };
+    // Whoever can change the trust file can sign anything
+    utils::root_only(path)?;
+    let keys = content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| utils::unhex(line).context(format!("Invalid key in {}", path.display())))
+        .collect::<Result<Vec<_>>>()?;
+    if keys.is_empty() {
+        bail!("No keys in {}", path.display());
+    }
+    Ok(Some(keys))
+}
+
+/// Fail unless `config` has a signature by one of `keys`
+pub fn verify(config: &Path, keys: &[Vec<u8>]) -> Result<()> {
+    let content = fs::read(config).context(format!("Failed to read {}", config.display()))?;
+    let sig_path = signature_path(config);
+    let signature =
+        fs::read_to_string(&sig_path).context(format!("{} is not signed", config.display()))?;
+    let signature = utils::unhex(signature.trim())
+        .context(format!("Invalid signature {}", sig_path.display()))?;
+    if !keys.iter().any(|key| {
+        UnparsedPublicKey::new(&ED25519, key)
+            .verify(&content, &signature)
+            .is_ok()
+    }) {
+        bail!(
+            "The signature of {} does not match, refusing a config that may have been tampered with",
+            config.display()
+        );
+    }
+    debug!("Verified the signature of {}", config.display());
+    Ok(())
+}
+
+/// Verify all of `configs` if signatures are required
+pub fn enforce<'a>(configs: impl IntoIterator<Item = &'a PathBuf>) -> Result<()> {
+    let Some(keys) = trusted_keys()? else {
+        return Ok(());
+    };
+    configs
+        .into_iter()
+        .try_for_each(|config| verify(config, &keys))
+}
+
+/// Write a new private key to `path`, readable only by the owner, and
+/// return the public key in hex
+pub fn generate(path: &Path) -> Result<String> {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+        .map_err(|_| anyhow!("Failed to generate a key"))?;
+    let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+        .map_err(|_| anyhow!("Failed to generate a key"))?;
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut file| file.write_all(pkcs8.as_ref()))
+        .context(format!("Failed to write {}", path.display()))?;
+    Ok(utils::hex(pair.public_key().as_ref()))
+}
+
+/// Sign `config` with the private key in `key`, writing `<config>.sig`
+pub fn sign(config: &Path, key: &Path) -> Result<PathBuf> {
+    let pkcs8 = fs::read(key).context(format!("Failed to read {}", key.display()))?;
+    let pair = Ed25519KeyPair::from_pkcs8(&pkcs8)
+        .map_err(|_| anyhow!("{} is not an Ed25519 private key", key.display()))?;
+    let content = fs::read(config).context(format!("Failed to read {}", config.display()))?;
+    let sig_path = signature_path(config);
+    fs::write(&sig_path, utils::hex(pair.sign(&content).as_ref()) + "\n")
+        .context(format!("Failed to write {}", sig_path.display()))?;
+    Ok(sig_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+
+    #[test]
+    fn rejects_edited_and_foreign_signed_configs() {
+        let dir = MockBackend::leak().scratch_dir();
+        let (config, key, other) = (dir.join("c.toml"), dir.join("key"), dir.join("other"));
+        fs::write(&config, "keep = \"30d\"\n").unwrap();
+        let public = utils::unhex(&generate(&key).unwrap()).unwrap();
+        let foreign = utils::unhex(&generate(&other).unwrap()).unwrap();
+
+        sign(&config, &key).unwrap();
+        verify(&config, std::slice::from_ref(&public)).unwrap();
+        assert!(verify(&config, &[foreign]).is_err());
+
+        fs::write(&config, "keep = \"1h\"\n").unwrap();
+        assert!(verify(&config, &[public]).is_err());
+    }
+}
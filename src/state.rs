@@ -0,0 +1,144 @@
+//! Where btrsnap keeps its files about a snapshot dir: the manifest, the
+//! tombstone journal and the info cache. They live below
+//! `/var/lib/btrsnap` (or `state-dir`) in a directory per snapshot dir, so
+//! read-only snapshot dirs such as receive targets work too.
+
+use crate::manifest::STATE_DIR;
+use crate::warnings::warning;
+use anyhow::{Context, Result};
+use log::{debug, info};
+use nix::unistd::Uid;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+pub const DEFAULT_ROOT: &str = "/var/lib/btrsnap";
+
+/// Files that used to live in `<snap-dir>/.btrsnap`
+const FILES: &[&str] = &["manifest.json", "tombstones.jsonl", "cache.json"];
+
+static ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+/// Use `root` for the state of all snapshot dirs. Until this is called,
+/// e.g. in tests, state stays in `<snap-dir>/.btrsnap`.
+pub fn init(root: PathBuf) {
+    let _ = ROOT.set(root);
+}
+
+/// Directory for state not tied to a snapshot dir, if [`init`] was called
+pub fn root() -> Option<&'static Path> {
+    ROOT.get().map(PathBuf::as_path)
+}
+
+/// State directory of `snap_dir`. Files still in the old location are
+/// moved over on first use; if that fails, e.g. for a user without write
+/// access, the old location is used.
+pub fn dir(snap_dir: &Path) -> PathBuf {
+    let legacy = snap_dir.join(STATE_DIR);
+    let Some(root) = ROOT.get() else {
+        return legacy;
+    };
+    let dir = root.join(key(snap_dir));
+    if dir.exists() || !FILES.iter().any(|f| legacy.join(f).exists()) {
+        return dir;
+    }
+    match migrate(&legacy, &dir) {
+        Ok(()) => dir,
+        Err(e) => {
+            // Unprivileged reads are expected to fail here
+            if Uid::effective().is_root() {
+                warning!(
+                    "State of {} stays in {}: {:#}",
+                    snap_dir.display(),
+                    legacy.display(),
+                    e
+                );
+            } else {
+                debug!("{:#}", e);
+            }
+            legacy
+        }
+    }
+}
+
+/// Move the state files from `legacy` to `dir`, copying since they may be
+/// on different filesystems
+fn migrate(legacy: &Path, dir: &Path) -> Result<()> {
+    info!(
+        "Moving state from {} to {}",
+        legacy.display(),
+        dir.display()
+    );
+    let staging = dir.with_extension("tmp");
+    fs::create_dir_all(&staging).context(format!("Failed to create {}", staging.display()))?;
+    for file in FILES.iter().map(|f| legacy.join(f)).filter(|f| f.exists()) {
+        let dest = staging.join(file.file_name().unwrap_or_default());
+        fs::copy(&file, &dest).context(format!("Failed to copy {}", file.display()))?;
+    }
+    fs::rename(&staging, dir).context(format!("Failed to create {}", dir.display()))?;
+    for file in FILES.iter().map(|f| legacy.join(f)).filter(|f| f.exists()) {
+        if let Err(e) = fs::remove_file(&file) {
+            debug!("Failed to remove {}: {}", file.display(), e);
+        }
+    }
+    Ok(())
+}
+
+/// Directory name for `snap_dir`, escaped like `systemd-escape --path`:
+/// `/mnt/snapshots` becomes `mnt-snapshots`. Bytes that are not UTF-8 are
+/// escaped as they are, so such dirs don't share a key.
+fn key(snap_dir: &Path) -> String {
+    let mut path = snap_dir.as_os_str().as_bytes();
+    while let [b'/', rest @ ..] = path {
+        path = rest;
+    }
+    while let [rest @ .., b'/'] = path {
+        path = rest;
+    }
+    if path.is_empty() {
+        return "-".to_string();
+    }
+    let mut key = String::new();
+    for (i, &byte) in path.iter().enumerate() {
+        match byte {
+            b'/' => key.push('-'),
+            b'.' if i == 0 => key.push_str("\\x2e"),
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b':' | b'_' | b'.' => key.push(byte as char),
+            _ => key.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+
+    #[test]
+    fn moves_legacy_state_into_escaped_dir() {
+        assert_eq!(key(Path::new("/mnt/snap-shots")), "mnt-snap\\x2dshots");
+        assert_eq!(key(Path::new("/")), "-");
+        let (a, b) = (
+            Path::new(std::ffi::OsStr::from_bytes(b"/mnt/\xff")),
+            Path::new(std::ffi::OsStr::from_bytes(b"/mnt/\xfe")),
+        );
+        assert_eq!(key(a), "mnt-\\xff");
+        assert_ne!(key(a), key(b));
+
+        let dir = MockBackend::leak().scratch_dir();
+        let legacy = dir.join("snapshots").join(STATE_DIR);
+        fs::create_dir_all(&legacy).unwrap();
+        fs::write(legacy.join("manifest.json"), "{}").unwrap();
+
+        let state = dir.join("state").join("snapshots");
+        migrate(&legacy, &state).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(state.join("manifest.json")).unwrap(),
+            "{}"
+        );
+        assert!(!legacy.join("manifest.json").exists());
+    }
+}
@@ -0,0 +1,295 @@
+use crate::backend::{QgroupUsage, SnapshotBackend};
+use crate::config::Config;
+use crate::i18n::tr;
+use crate::{create, maintenance, notify, tombstone, utils};
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::{Local, TimeZone};
+use log::info;
+use nix::sys::statvfs::statvfs;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(clap::Parser)]
+pub struct Summary {
+    /// Snapshot dir to scan
+    #[arg(short = 'd', long, value_parser = utils::parse_path)]
+    pub snap_dir: Option<PathBuf>,
+    /// Mail the summary (requires `weekly-summary` in `notify.email.on`)
+    #[arg(long)]
+    pub email: bool,
+    /// Fail if a subvolume trips its `warn-if-older-than` or
+    /// `warn-if-fewer-than` alarm
+    #[arg(long)]
+    pub check: bool,
+}
+
+#[derive(Default)]
+struct SubvolStats {
+    count: usize,
+    oldest: Option<i64>,
+    newest: Option<i64>,
+}
+
+impl Summary {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        let snap_dir = utils::resolve_snap_dir(backend, self.snap_dir, config.snap_dir.clone())?;
+        info!("Summarizing snapshots in {}", snap_dir.display());
+        let (body, alarms) = summarize(backend, &snap_dir, &config)?;
+        print!("{}", body);
+
+        if self.email {
+            let email = config
+                .email
+                .filter(|e| e.on.iter().any(|o| o == "weekly-summary"))
+                .ok_or_else(|| anyhow!("'weekly-summary' is not enabled in [notify.email]"))?;
+            let subject = format!("btrsnap summary for {}", notify::hostname());
+            notify::send(&email, &subject, &body)?;
+        }
+        if self.check && !alarms.is_empty() {
+            bail!("{} subvolumes tripped a snapshot alarm", alarms.len());
+        }
+        Ok(())
+    }
+}
+
+/// Subvolumes whose snapshots are fewer or older than their
+/// `[subvol."<name>"]` thresholds allow, as of `now`
+fn alarms(config: &Config, stats: &BTreeMap<String, SubvolStats>, now: i64) -> Vec<String> {
+    let none = SubvolStats::default();
+    let configured = config
+        .subvols
+        .iter()
+        .map(|sv| create::subvol_name(sv, config.name_parents));
+    let mut names: Vec<String> = stats.keys().cloned().chain(configured).collect();
+    names.sort();
+    names.dedup();
+    let mut alarms = vec![];
+    for name in names {
+        let Some(settings) = config.subvol_settings(&name) else {
+            continue;
+        };
+        let s = stats.get(&name).unwrap_or(&none);
+        if let Some(min) = settings.warn_if_fewer_than
+            && s.count < min
+        {
+            alarms.push(format!(
+                "{} has {} snapshots, fewer than warn-if-fewer-than ({})",
+                name, s.count, min
+            ));
+        }
+        if let Some(max_age) = settings.warn_if_older_than {
+            let limit = humantime::format_duration(max_age);
+            match s.newest {
+                Some(newest) if now - newest > max_age.as_secs() as i64 => alarms.push(format!(
+                    "The newest snapshot of {} is from {}, older than warn-if-older-than ({})",
+                    name,
+                    format_ts(Some(newest)),
+                    limit
+                )),
+                Some(_) => {}
+                None => alarms.push(format!(
+                    "{} has no snapshots, warn-if-older-than is {}",
+                    name, limit
+                )),
+            }
+        }
+    }
+    alarms
+}
+
+/// Space of the filesystem split by what holds it, from the qgroups
+#[derive(Debug, PartialEq)]
+struct Breakdown {
+    live: u64,
+    /// Exclusive space of the snapshots in the snapshot dir
+    snapshots: u64,
+    /// Exclusive space of snapshots btrsnap deleted that btrfs has not
+    /// freed yet
+    pending: u64,
+}
+
+impl Breakdown {
+    fn new(
+        qgroups: &BTreeMap<u64, QgroupUsage>,
+        snapshots: &[u64],
+        deleted: &[u64],
+        used: u64,
+    ) -> Self {
+        let exclusive = |ids: &[u64]| -> u64 {
+            ids.iter()
+                .filter_map(|id| qgroups.get(id))
+                .map(|q| q.exclusive)
+                .sum()
+        };
+        let (snapshots, pending) = (exclusive(snapshots), exclusive(deleted));
+        Breakdown {
+            live: used.saturating_sub(snapshots + pending),
+            snapshots,
+            pending,
+        }
+    }
+}
+
+/// The summary text and the alarms it lists
+fn summarize(
+    backend: &dyn SnapshotBackend,
+    snap_dir: &Path,
+    config: &Config,
+) -> Result<(String, Vec<String>)> {
+    let mut stats: BTreeMap<String, SubvolStats> = BTreeMap::new();
+    let mut ids = vec![];
+    utils::scan_snapshots(backend, snap_dir, |info| {
+        ids.push(info.id);
+        let name = utils::snapshot_name(&info);
+        let (subvol, ts) = match utils::parse_snapshot_name(&name) {
+            Some((subvol, ts)) => (subvol.to_string(), Some(ts)),
+            None => ("(other)".to_string(), None),
+        };
+        let s = stats.entry(subvol).or_default();
+        s.count += 1;
+        if let Some(ts) = ts {
+            s.oldest = Some(s.oldest.map_or(ts, |o| o.min(ts)));
+            s.newest = Some(s.newest.map_or(ts, |n| n.max(ts)));
+        }
+        Ok(())
+    })?;
+
+    let mut body = String::new();
+    let dir = snap_dir.display().to_string();
+    writeln!(body, "{}", tr!("summary-heading", dir = dir))?;
+    if let Some(maintenance) = maintenance::active(snap_dir)? {
+        let state = maintenance.to_string();
+        writeln!(body, "  {}", tr!("summary-maintenance", state = state))?;
+    }
+    if stats.is_empty() {
+        writeln!(body, "  {}", tr!("summary-none"))?;
+    }
+    for (subvol, s) in &stats {
+        let line = tr!(
+            "summary-subvol",
+            subvol = subvol.as_str(),
+            count = s.count,
+            oldest = format_ts(s.oldest),
+            newest = format_ts(s.newest),
+        );
+        writeln!(body, "  {}", line)?;
+    }
+    let (space, used) = space_usage(snap_dir)?;
+    writeln!(body, "{}", space)?;
+    // Without quotas there is no per-subvolume accounting to split by
+    if let Ok(qgroups) = backend.qgroups(snap_dir) {
+        let deleted: Vec<u64> = tombstone::load(snap_dir)?
+            .iter()
+            .filter_map(|t| t.id)
+            .collect();
+        let b = Breakdown::new(&qgroups, &ids, &deleted, used);
+        let line = tr!(
+            "summary-breakdown",
+            live = utils::format_size(b.live),
+            snapshots = utils::format_size(b.snapshots),
+            pending = utils::format_size(b.pending),
+        );
+        writeln!(body, "  {}", line)?;
+    }
+    let alarms = alarms(config, &stats, Local::now().timestamp());
+    for alarm in &alarms {
+        writeln!(body, "{}", tr!("summary-alarm", alarm = alarm.as_str()))?;
+    }
+    Ok((body, alarms))
+}
+
+fn format_ts(ts: Option<i64>) -> String {
+    ts.and_then(|ts| Local.timestamp_opt(ts, 0).single())
+        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// The filesystem line of the summary, and the bytes used
+fn space_usage(path: &Path) -> Result<(String, u64)> {
+    let st = statvfs(path).context(format!("Failed to stat filesystem of {}", path.display()))?;
+    let block = st.fragment_size();
+    let total = st.blocks() * block;
+    let used = (st.blocks() - st.blocks_free()) * block;
+    let available = st.blocks_available() * block;
+    if total == 0 {
+        bail!("Filesystem of {} reports no size", path.display());
+    }
+    let line = tr!(
+        "summary-space",
+        used = utils::format_size(used),
+        total = utils::format_size(total),
+        available = utils::format_size(available),
+    );
+    Ok((line, used))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SubvolSettings;
+
+    #[test]
+    fn alarms_per_subvolume() {
+        let mut config = Config {
+            subvols: vec![PathBuf::from("/home"), PathBuf::from("/srv")],
+            ..Default::default()
+        };
+        let settings = SubvolSettings {
+            warn_if_older_than: Some(std::time::Duration::from_secs(3600)),
+            warn_if_fewer_than: Some(2),
+            ..Default::default()
+        };
+        config.subvol_settings.insert("*".to_string(), settings);
+        let stats = BTreeMap::from([
+            (
+                "home".to_string(),
+                SubvolStats {
+                    count: 3,
+                    oldest: Some(0),
+                    newest: Some(9000),
+                },
+            ),
+            (
+                "var".to_string(),
+                SubvolStats {
+                    count: 1,
+                    oldest: Some(0),
+                    newest: Some(0),
+                },
+            ),
+        ]);
+
+        let alarms = alarms(&config, &stats, 10000);
+
+        // srv has no snapshots at all, var has one that is too old
+        assert_eq!(alarms.len(), 4);
+        assert!(alarms.iter().all(|a| !a.contains("home")));
+    }
+
+    #[test]
+    fn breakdown_splits_used_space() {
+        let usage = |exclusive| QgroupUsage {
+            referenced: exclusive,
+            exclusive,
+        };
+        // 256 is live, 257 and 258 snapshots, 259 deleted and 260 freed
+        let qgroups = BTreeMap::from([
+            (256, usage(1000)),
+            (257, usage(200)),
+            (258, usage(100)),
+            (259, usage(50)),
+        ]);
+
+        let b = Breakdown::new(&qgroups, &[257, 258], &[259, 260], 2000);
+
+        assert_eq!(
+            b,
+            Breakdown {
+                live: 1650,
+                snapshots: 300,
+                pending: 50
+            }
+        );
+    }
+}
@@ -0,0 +1,192 @@
+//! Suspending snapshots of a subvolume without touching the config, e.g.
+//! during a large migration. Suspensions live in the state dir of the
+//! snapshot dir, keyed by snapshot name prefix, and end on their own
+//! after `--until`.
+
+use crate::backend::SnapshotBackend;
+use crate::config::Config;
+use crate::{create, hold, state, utils};
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const FILE: &str = "suspended.json";
+
+#[derive(clap::Parser)]
+pub struct Disable {
+    /// Subvolume to stop snapshotting, by path or snapshot name prefix
+    pub subvol: String,
+    /// Resume snapshots after this long, e.g. 2d; until `enable` if unset
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub until: Option<Duration>,
+    #[arg(short = 'd', long, value_parser = utils::parse_path)]
+    pub snap_dir: Option<PathBuf>,
+}
+
+#[derive(clap::Parser)]
+pub struct Enable {
+    /// Subvolume to snapshot again, by path or snapshot name prefix
+    pub subvol: String,
+    #[arg(short = 'd', long, value_parser = utils::parse_path)]
+    pub snap_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Suspension {
+    pub since: DateTime<Local>,
+    pub by: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<DateTime<Local>>,
+}
+
+impl Suspension {
+    fn active(&self) -> bool {
+        self.until.is_none_or(|until| until > Local::now())
+    }
+}
+
+impl Disable {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        let snap_dir = utils::resolve_snap_dir(backend, self.snap_dir, config.snap_dir)?;
+        let name = create::name_arg(&self.subvol, config.name_parents);
+        let until = match self.until {
+            Some(until) => Some(Local::now() + chrono::Duration::from_std(until)?),
+            None => None,
+        };
+        let mut suspended = load(&snap_dir)?;
+        suspended.retain(|_, s| s.active());
+        suspended.insert(
+            name.clone(),
+            Suspension {
+                since: Local::now(),
+                by: hold::user(),
+                until,
+            },
+        );
+        save(&snap_dir, &suspended)?;
+        match until {
+            Some(until) => println!(
+                "Snapshots of {} disabled until {}",
+                name,
+                until.format("%Y-%m-%d %H:%M")
+            ),
+            None => println!("Snapshots of {} disabled until `enable`", name),
+        }
+        Ok(())
+    }
+}
+
+impl Enable {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        let snap_dir = utils::resolve_snap_dir(backend, self.snap_dir, config.snap_dir.clone())?;
+        let name = create::name_arg(&self.subvol, config.name_parents);
+        let mut suspended = load(&snap_dir)?;
+        let was = suspended.remove(&name).is_some_and(|s| s.active());
+        suspended.retain(|_, s| s.active());
+        save(&snap_dir, &suspended)?;
+        if config.subvol_settings(&name).and_then(|s| s.enabled) == Some(false) {
+            bail!(
+                "{} has enabled = false in [subvol.\"{}\"] of the config",
+                name,
+                name
+            );
+        }
+        if was {
+            println!("Snapshots of {} enabled", name);
+        } else {
+            println!("Snapshots of {} were not disabled", name);
+        }
+        Ok(())
+    }
+}
+
+/// Why snapshots of the subvolume named `name` are off, if they are:
+/// `enabled = false` in the config or an active `disable`
+pub fn disabled(config: &Config, snap_dir: &Path, name: &str) -> Result<Option<String>> {
+    if config.subvol_settings(name).and_then(|s| s.enabled) == Some(false) {
+        return Ok(Some("enabled = false in the config".to_string()));
+    }
+    let suspended = load(snap_dir)?;
+    Ok(suspended
+        .get(name)
+        .filter(|s| s.active())
+        .map(|s| match s.until {
+            Some(until) => format!(
+                "disabled by {} until {}",
+                s.by,
+                until.format("%Y-%m-%d %H:%M")
+            ),
+            None => format!("disabled by {} until `enable`", s.by),
+        }))
+}
+
+fn load(snap_dir: &Path) -> Result<BTreeMap<String, Suspension>> {
+    let path = state::dir(snap_dir).join(FILE);
+    match fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content)
+            .context(format!("Invalid suspensions file {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e).context(format!("Failed to read {}", path.display())),
+    }
+}
+
+fn save(snap_dir: &Path, suspended: &BTreeMap<String, Suspension>) -> Result<()> {
+    let dir = state::dir(snap_dir);
+    fs::create_dir_all(&dir).context(format!("Failed to create {}", dir.display()))?;
+    let path = dir.join(FILE);
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(suspended)?)
+        .context(format!("Failed to write {}", tmp.display()))?;
+    fs::rename(&tmp, &path).context(format!("Failed to replace {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use crate::config::SubvolSettings;
+
+    #[test]
+    fn disable_lasts_until_enabled_or_expired() {
+        let backend = MockBackend::leak();
+        let snap_dir = backend.scratch_dir();
+        let mut config = Config {
+            snap_dir: Some(snap_dir.clone()),
+            ..Default::default()
+        };
+        let disable = |until| Disable {
+            subvol: "home".into(),
+            until,
+            snap_dir: None,
+        };
+
+        disable(None).execute(backend, config.clone()).unwrap();
+        assert!(disabled(&config, &snap_dir, "home").unwrap().is_some());
+        assert!(disabled(&config, &snap_dir, "var").unwrap().is_none());
+        let enable = || Enable {
+            subvol: "home".into(),
+            snap_dir: None,
+        };
+        enable().execute(backend, config.clone()).unwrap();
+        assert!(disabled(&config, &snap_dir, "home").unwrap().is_none());
+
+        disable(Some(Duration::ZERO))
+            .execute(backend, config.clone())
+            .unwrap();
+        assert!(disabled(&config, &snap_dir, "home").unwrap().is_none());
+
+        config.subvol_settings.insert(
+            "home".into(),
+            SubvolSettings {
+                enabled: Some(false),
+                ..Default::default()
+            },
+        );
+        assert!(disabled(&config, &snap_dir, "home").unwrap().is_some());
+        assert!(enable().execute(backend, config).is_err());
+    }
+}
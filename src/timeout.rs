@@ -0,0 +1,38 @@
+use anyhow::{Result, anyhow};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// `[timeouts]` settings, unset means wait forever
+#[derive(Clone, Copy, Default)]
+pub struct Timeouts {
+    pub create: Option<Duration>,
+    pub delete: Option<Duration>,
+}
+
+/// Run `op` on a worker thread and give up waiting after `limit`.
+///
+/// A btrfs ioctl cannot be cancelled, so a timed out operation keeps running
+/// in the background while the caller moves on to the next item.
+pub fn run<T: Send + 'static>(
+    limit: Option<Duration>,
+    what: &str,
+    op: impl FnOnce() -> T + Send + 'static,
+) -> Result<T> {
+    let Some(limit) = limit else {
+        return Ok(op());
+    };
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        // The receiver is gone if we already timed out
+        let _ = tx.send(op());
+    });
+    rx.recv_timeout(limit).map_err(|e| match e {
+        RecvTimeoutError::Timeout => anyhow!(
+            "{} timed out after {}",
+            what,
+            humantime::format_duration(limit)
+        ),
+        RecvTimeoutError::Disconnected => anyhow!("{} failed unexpectedly", what),
+    })
+}
@@ -0,0 +1,136 @@
+use crate::backend::SubvolInfo;
+use crate::manifest::Manifest;
+use crate::warnings::warning;
+use crate::{run, state, utils};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+const TOMBSTONES_FILE: &str = "tombstones.jsonl";
+
+/// What was known about a snapshot when it was deleted, kept for
+/// postmortems in `tombstones.jsonl` in the state dir, one per line
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Tombstone {
+    pub name: String,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "crate::os_path::option"
+    )]
+    pub source: Option<PathBuf>,
+    pub uuid: String,
+    /// Subvolume ID, to find its qgroup while btrfs frees its space
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    pub created: DateTime<Local>,
+    pub deleted: DateTime<Local>,
+    /// Why it was deleted, e.g. `keep 7d`
+    pub rule: String,
+    /// Exclusive bytes from the qgroups, if quotas are enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclusive: Option<u64>,
+    /// Run that deleted it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run: Option<String>,
+}
+
+impl Tombstone {
+    /// For `info`, deleted now because of `rule`
+    pub fn new(
+        info: &SubvolInfo,
+        manifest: Option<&Manifest>,
+        rule: impl Into<String>,
+        exclusive: Option<u64>,
+    ) -> Self {
+        let name = utils::snapshot_name(info);
+        Tombstone {
+            source: manifest
+                .and_then(|m| m.snapshots.get(&name))
+                .map(|e| e.source.clone()),
+            name,
+            uuid: info.uuid.to_string(),
+            id: Some(info.id),
+            created: info.otime,
+            deleted: Local::now(),
+            rule: rule.into(),
+            exclusive,
+            run: Some(run::id().to_string()),
+        }
+    }
+}
+
+/// Append `tombstone` to the journal of `snap_dir`. A failure only costs
+/// the record, so it is logged rather than failing the deletion.
+pub fn record(snap_dir: &Path, tombstone: &Tombstone) {
+    let write = || -> Result<()> {
+        let dir = state::dir(snap_dir);
+        fs::create_dir_all(&dir)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(TOMBSTONES_FILE))?;
+        writeln!(file, "{}", serde_json::to_string(tombstone)?)?;
+        Ok(())
+    };
+    if let Err(e) = write() {
+        warning!(
+            "Failed to record the deletion of {}: {:#}",
+            tombstone.name,
+            e
+        );
+    }
+}
+
+/// Tombstones of `snap_dir`, oldest deletion first
+pub fn load(snap_dir: &Path) -> Result<Vec<Tombstone>> {
+    let path = state::dir(snap_dir).join(TOMBSTONES_FILE);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e).context(format!("Failed to read {}", path.display())),
+    };
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(i, line)| {
+            serde_json::from_str(line).context(format!(
+                "Invalid tombstone at {}:{}",
+                path.display(),
+                i + 1
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::SnapshotBackend;
+    use crate::backend::mock::MockBackend;
+
+    #[test]
+    fn appends_and_loads_tombstones() {
+        let backend = MockBackend::leak();
+        let snap_dir = backend.scratch_dir();
+        for name in ["home-1", "home-2"] {
+            let path = snap_dir.join(name);
+            backend.add(&path, Local::now());
+            let info = backend.info(&path).unwrap();
+            record(
+                &snap_dir,
+                &Tombstone::new(&info, None, "keep 1h", Some(4096)),
+            );
+        }
+
+        let tombstones = load(&snap_dir).unwrap();
+        let names: Vec<&str> = tombstones.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["home-1", "home-2"]);
+        assert_eq!(tombstones[0].rule, "keep 1h");
+        assert_eq!(tombstones[0].exclusive, Some(4096));
+    }
+}
@@ -0,0 +1,190 @@
+use crate::backend::SnapshotBackend;
+use crate::{interrupt, utils};
+use anyhow::{Context, Result};
+use chrono::Local;
+use humantime::Duration as HumanDuration;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Largest snapshots listed below the per-subvolume table
+const TOP_SNAPSHOTS: usize = 10;
+
+#[derive(clap::Parser)]
+pub struct Top {
+    /// Snapshot dir to watch
+    #[arg(short = 'd', long, value_parser = utils::parse_path)]
+    pub snap_dir: Option<PathBuf>,
+    /// Time between refreshes
+    #[arg(short = 'n', long, default_value = "5s")]
+    pub interval: HumanDuration,
+    /// Recount the qgroups first, slow on big filesystems
+    #[arg(long)]
+    pub rescan: bool,
+    /// Print one refresh and exit
+    #[arg(long)]
+    pub once: bool,
+}
+
+/// Space held by the snapshots of one subvolume
+struct Row {
+    subvol: String,
+    snapshots: usize,
+    exclusive: u64,
+}
+
+/// Snapshot usage at one point in time
+struct Usage {
+    /// Sorted by exclusive space, largest first
+    subvols: Vec<Row>,
+    /// Largest snapshots by exclusive space, with their name
+    largest: Vec<(String, u64)>,
+}
+
+impl Top {
+    pub fn execute(
+        self,
+        backend: &'static dyn SnapshotBackend,
+        snap_dir: Option<PathBuf>,
+    ) -> Result<()> {
+        let snap_dir = utils::resolve_snap_dir(backend, self.snap_dir, snap_dir)?;
+        if self.rescan {
+            println!("Rescanning qgroups...");
+            backend
+                .quota_rescan(&snap_dir)
+                .context("Failed to rescan qgroups")?;
+        }
+        let interval: Duration = self.interval.into();
+        let mut previous = None;
+        loop {
+            let usage = usage(backend, &snap_dir)?;
+            let screen = render(&usage, previous.as_ref(), interval);
+            if self.once {
+                print!("{}", screen);
+                return Ok(());
+            }
+            // Clear the screen and start at the top, like top(1)
+            print!("\x1b[2J\x1b[H{}", screen);
+            previous = Some(usage);
+
+            let start = Instant::now();
+            while start.elapsed() < interval {
+                if interrupt::requested() {
+                    return Ok(());
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+    }
+}
+
+fn usage(backend: &dyn SnapshotBackend, snap_dir: &Path) -> Result<Usage> {
+    let qgroups = utils::qgroups(backend, snap_dir)?;
+    let mut by_subvol: BTreeMap<String, Row> = BTreeMap::new();
+    let mut largest = vec![];
+    utils::scan_snapshots(backend, snap_dir, |info| {
+        let name = utils::snapshot_name(&info);
+        let exclusive = qgroups.get(&info.id).map_or(0, |q| q.exclusive);
+        let subvol = utils::parse_snapshot_name(&name)
+            .map_or_else(|| "(other)".to_string(), |(subvol, _)| subvol.into());
+        let row = by_subvol.entry(subvol.to_string()).or_insert_with(|| Row {
+            subvol: subvol.to_string(),
+            snapshots: 0,
+            exclusive: 0,
+        });
+        row.snapshots += 1;
+        row.exclusive += exclusive;
+        largest.push((name, exclusive));
+        Ok(())
+    })?;
+    let mut subvols: Vec<Row> = by_subvol.into_values().collect();
+    subvols.sort_by_key(|r| std::cmp::Reverse(r.exclusive));
+    largest.sort_by_key(|(_, exclusive)| std::cmp::Reverse(*exclusive));
+    largest.truncate(TOP_SNAPSHOTS);
+    Ok(Usage { subvols, largest })
+}
+
+/// The screen for `usage`, with churn as the change since `previous`
+fn render(usage: &Usage, previous: Option<&Usage>, interval: Duration) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "btrsnap top - {} - every {}\n",
+        Local::now().format("%H:%M:%S"),
+        humantime::format_duration(interval)
+    );
+    let _ = writeln!(
+        out,
+        "{:<20} {:>9} {:>12} {:>12}",
+        "SUBVOLUME", "SNAPSHOTS", "EXCLUSIVE", "CHURN"
+    );
+    for row in &usage.subvols {
+        let churn = previous
+            .and_then(|p| p.subvols.iter().find(|r| r.subvol == row.subvol))
+            .map_or("-".to_string(), |p| {
+                signed_size(row.exclusive as i64 - p.exclusive as i64)
+            });
+        let _ = writeln!(
+            out,
+            "{:<20} {:>9} {:>12} {:>12}",
+            row.subvol,
+            row.snapshots,
+            utils::format_size(row.exclusive),
+            churn
+        );
+    }
+    let _ = writeln!(out, "\n{:<33} {:>12}", "LARGEST SNAPSHOTS", "EXCLUSIVE");
+    for (name, exclusive) in &usage.largest {
+        let _ = writeln!(out, "{:<33} {:>12}", name, utils::format_size(*exclusive));
+    }
+    out
+}
+
+fn signed_size(delta: i64) -> String {
+    let sign = if delta < 0 { "-" } else { "+" };
+    format!("{}{}", sign, utils::format_size(delta.unsigned_abs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::QgroupUsage;
+    use crate::backend::mock::MockBackend;
+
+    #[test]
+    fn sorts_subvolumes_by_exclusive_space() {
+        let backend = MockBackend::leak();
+        let snap_dir = backend.scratch_dir();
+        for (name, exclusive) in [("home-1", 10), ("home-2", 20), ("var-1", 100)] {
+            let path = snap_dir.join(name);
+            backend.add(&path, Local::now());
+            let usage = QgroupUsage {
+                referenced: exclusive,
+                exclusive,
+            };
+            backend.usage.lock().unwrap().insert(path, usage);
+        }
+
+        let first = usage(backend, &snap_dir).unwrap();
+        let order: Vec<_> = first
+            .subvols
+            .iter()
+            .map(|r| (r.subvol.as_str(), r.exclusive))
+            .collect();
+        assert_eq!(order, [("var", 100), ("home", 30)]);
+        assert_eq!(first.largest[0].0, "var-1");
+
+        backend
+            .usage
+            .lock()
+            .unwrap()
+            .get_mut(&snap_dir.join("home-2"))
+            .unwrap()
+            .exclusive = 50;
+        let second = usage(backend, &snap_dir).unwrap();
+        let screen = render(&second, Some(&first), Duration::from_secs(5));
+        assert!(screen.contains("+30 B"), "{}", screen);
+    }
+}
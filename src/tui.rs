@@ -0,0 +1,310 @@
+use crate::backend::{SnapshotBackend, SubvolInfo};
+use crate::config::Config;
+use crate::protect::Guard;
+use crate::{boot, create, delete, in_use, restore, utils};
+use anyhow::{Context, Result, bail};
+use chrono::Local;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState};
+use ratatui::{DefaultTerminal, Frame};
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(clap::Parser)]
+pub struct Tui {
+    /// Snapshot dir to manage
+    #[arg(short = 'd', long, value_parser = utils::parse_path)]
+    pub snap_dir: Option<PathBuf>,
+}
+
+impl Tui {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        let snap_dir = utils::resolve_snap_dir(backend, self.snap_dir, config.snap_dir.clone())?;
+        if config.subvols.is_empty() {
+            bail!("No subvolumes configured");
+        }
+        let mut app = App::new(backend, config, snap_dir);
+        app.refresh()?;
+        let mut terminal = ratatui::try_init().context("Failed to set up the terminal")?;
+        let result = app.run(&mut terminal);
+        ratatui::restore();
+        result
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Pane {
+    Subvols,
+    Snapshots,
+}
+
+/// A change waiting for the user to confirm it
+enum Action {
+    Create(PathBuf),
+    Delete(PathBuf),
+    Restore { snapshot: PathBuf, live: PathBuf },
+}
+
+impl Action {
+    fn prompt(&self) -> String {
+        match self {
+            Action::Create(sv) => format!("Snapshot {}?", sv.display()),
+            Action::Delete(s) => format!("Delete {}?", s.display()),
+            Action::Restore { snapshot, live } => {
+                format!("Replace {} with {}?", live.display(), snapshot.display())
+            }
+        }
+    }
+}
+
+struct App {
+    backend: &'static dyn SnapshotBackend,
+    config: Config,
+    snap_dir: PathBuf,
+    /// Snapshots of the selected subvolume, newest first
+    snapshots: Vec<SubvolInfo>,
+    subvol_state: ListState,
+    snapshot_state: ListState,
+    focus: Pane,
+    pending: Option<Action>,
+    status: String,
+}
+
+impl App {
+    fn new(backend: &'static dyn SnapshotBackend, config: Config, snap_dir: PathBuf) -> Self {
+        App {
+            backend,
+            config,
+            snap_dir,
+            snapshots: vec![],
+            subvol_state: ListState::default().with_selected(Some(0)),
+            snapshot_state: ListState::default(),
+            focus: Pane::Subvols,
+            pending: None,
+            status: String::new(),
+        }
+    }
+
+    fn subvol(&self) -> &PathBuf {
+        &self.config.subvols[self.subvol_state.selected().unwrap_or(0)]
+    }
+
+    fn snapshot(&self) -> Option<&SubvolInfo> {
+        self.snapshots.get(self.snapshot_state.selected()?)
+    }
+
+    /// Reload the snapshots of the selected subvolume
+    fn refresh(&mut self) -> Result<()> {
+        let name = create::subvol_name(self.subvol(), self.config.name_parents);
+        let mut snapshots: Vec<SubvolInfo> = self
+            .backend
+            .list(&self.snap_dir)
+            .context(format!(
+                "Failed to list subvolumes in {}",
+                self.snap_dir.display()
+            ))?
+            .into_iter()
+            .filter(|s| {
+                utils::parse_snapshot_name(&utils::snapshot_name(s))
+                    .is_some_and(|(prefix, _)| prefix == name)
+            })
+            .collect();
+        snapshots.sort_by_key(|s| std::cmp::Reverse(s.otime));
+        let selected = self.snapshot_state.selected().unwrap_or(0);
+        self.snapshot_state
+            .select((!snapshots.is_empty()).then(|| selected.min(snapshots.len() - 1)));
+        self.snapshots = snapshots;
+        Ok(())
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if !event::poll(Duration::from_millis(500))? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if let Some(action) = self.pending.take() {
+                if matches!(key.code, KeyCode::Char('y') | KeyCode::Char('Y')) {
+                    self.status = match self.apply(action) {
+                        Ok(done) => done,
+                        Err(e) => format!("Error: {:#}", e),
+                    };
+                    // Commands print as they go, draw everything anew
+                    terminal.clear()?;
+                } else {
+                    self.status = "Cancelled".to_string();
+                }
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Tab | KeyCode::Left | KeyCode::Right => {
+                    self.focus = match self.focus {
+                        Pane::Subvols => Pane::Snapshots,
+                        Pane::Snapshots => Pane::Subvols,
+                    };
+                }
+                KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1)?,
+                KeyCode::Down | KeyCode::Char('j') => self.move_selection(1)?,
+                KeyCode::Char('c') => self.pending = Some(Action::Create(self.subvol().clone())),
+                KeyCode::Char('d') => {
+                    self.pending = self.snapshot().map(|s| Action::Delete(s.path.clone()))
+                }
+                KeyCode::Char('r') => {
+                    self.pending = self.snapshot().map(|s| Action::Restore {
+                        snapshot: s.path.clone(),
+                        live: self.subvol().clone(),
+                    })
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn move_selection(&mut self, by: isize) -> Result<()> {
+        self.status.clear();
+        let (state, len) = match self.focus {
+            Pane::Subvols => (&mut self.subvol_state, self.config.subvols.len()),
+            Pane::Snapshots => (&mut self.snapshot_state, self.snapshots.len()),
+        };
+        if len == 0 {
+            return Ok(());
+        }
+        let selected = state.selected().unwrap_or(0).saturating_add_signed(by);
+        state.select(Some(selected.min(len - 1)));
+        if self.focus == Pane::Subvols {
+            self.snapshot_state.select(Some(0));
+            self.refresh()?;
+        }
+        Ok(())
+    }
+
+    /// Carry out a confirmed action, returning what was done
+    fn apply(&mut self, action: Action) -> Result<String> {
+        let config = &self.config;
+        let done = match action {
+            Action::Create(sv) => {
+                let ts = Local::now().timestamp();
+                let path = create::snapshot_path(&self.snap_dir, &sv, config.name_parents, ts);
+                let mut retries = 0;
+                let path = create::create_snapshot(
+                    self.backend,
+                    &sv,
+                    &path,
+                    config.retry,
+                    config.timeouts,
+                    Some("tui"),
+                    &mut retries,
+                )?;
+                format!("Created {}", path.display())
+            }
+            Action::Delete(path) => {
+                let guard = Guard::new(self.backend, config.protect.clone(), false);
+                delete::delete_snapshot(self.backend, &path, &guard, config)?;
+                boot::regenerate(&config.boot);
+                format!("Deleted {}", path.display())
+            }
+            Action::Restore { snapshot, live } => {
+                // The status line has room for the first reason only
+                if let Some(blocker) = in_use::blockers(&live)?.first() {
+                    bail!("{} is in use: {}", live.display(), blocker);
+                }
+                restore::restore(self.backend, config, &self.snap_dir, &snapshot, &live)?;
+                format!("Restored {} from {}", live.display(), snapshot.display())
+            }
+        };
+        self.refresh()?;
+        Ok(done)
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(30), Constraint::Percentage(70)])
+                .areas(main);
+        let highlight = Style::default().add_modifier(Modifier::REVERSED);
+        let block = |title: &str, pane: Pane| {
+            let block = Block::bordered().title(title.to_string());
+            if self.focus == pane {
+                block.border_style(Style::default().add_modifier(Modifier::BOLD))
+            } else {
+                block
+            }
+        };
+
+        let subvols: Vec<ListItem> = self
+            .config
+            .subvols
+            .iter()
+            .map(|sv| ListItem::new(sv.display().to_string()))
+            .collect();
+        let subvols = List::new(subvols)
+            .block(block(" Subvolumes ", Pane::Subvols))
+            .highlight_style(highlight);
+        frame.render_stateful_widget(subvols, left, &mut self.subvol_state);
+
+        let now = Local::now();
+        let snapshots: Vec<ListItem> = self
+            .snapshots
+            .iter()
+            .map(|s| {
+                let age = (now - s.otime).to_std().unwrap_or_default();
+                let age = humantime::format_duration(Duration::from_secs(age.as_secs() / 60 * 60));
+                ListItem::new(format!("{}  {} ago", utils::snapshot_name(s), age))
+            })
+            .collect();
+        let snapshots = List::new(snapshots)
+            .block(block(" Snapshots ", Pane::Snapshots))
+            .highlight_style(highlight);
+        frame.render_stateful_widget(snapshots, right, &mut self.snapshot_state);
+
+        let line = match &self.pending {
+            Some(action) => format!("{} [y/N]", action.prompt()),
+            None if !self.status.is_empty() => self.status.clone(),
+            None => "c create  d delete  r restore  tab switch pane  q quit".to_string(),
+        };
+        frame.render_widget(Line::from(line), status);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use std::fs;
+
+    #[test]
+    fn lists_and_creates_snapshots_of_the_selected_subvolume() {
+        let backend = MockBackend::leak();
+        let dir = backend.scratch_dir();
+        let (home, var, snap_dir) = (dir.join("home"), dir.join("var"), dir.join("snapshots"));
+        backend.add(&home, Local::now());
+        backend.add(&var, Local::now());
+        fs::create_dir(&snap_dir).unwrap();
+        backend.add(&snap_dir.join("var-1"), Local::now());
+        let config = Config {
+            subvols: vec![home.clone(), var],
+            ..Default::default()
+        };
+
+        let mut app = App::new(backend, config, snap_dir);
+        app.refresh().unwrap();
+        assert!(app.snapshots.is_empty());
+        assert!(app.snapshot().is_none());
+
+        app.apply(Action::Create(home)).unwrap();
+        assert_eq!(app.snapshots.len(), 1);
+        app.move_selection(1).unwrap();
+        assert_eq!(utils::snapshot_name(app.snapshot().unwrap()), "var-1");
+    }
+}
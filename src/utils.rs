@@ -1,8 +1,16 @@
+use crate::backend::{QgroupUsage, SnapshotBackend, SubvolInfo};
+use crate::i18n::tr;
+use crate::mounts;
 use anyhow::{Context, Result, anyhow, bail};
-use std::path::PathBuf;
-use walkdir::{DirEntry, WalkDir};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, ErrorKind, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
 
 pub fn resolve_snap_dir(
+    backend: &dyn SnapshotBackend,
     cli_snap_dir: Option<PathBuf>,
     config_snap_dir: Option<PathBuf>,
 ) -> Result<PathBuf, anyhow::Error> {
@@ -12,26 +20,175 @@ pub fn resolve_snap_dir(
     if !snap_dir.exists() {
         bail!("Snapshot directory {} does not exist", snap_dir.display());
     }
-    snap_dir
+    let snap_dir = snap_dir
         .canonicalize()
-        .context("Failed to canonicalize snapshot directory")
+        .context("Failed to canonicalize snapshot directory")?;
+    if !backend.is_btrfs(&snap_dir) {
+        bail!("{}", not_btrfs(&snap_dir));
+    }
+    Ok(snap_dir)
+}
+
+/// Why a snap-dir outside btrfs cannot work, naming its filesystem
+pub fn not_btrfs(snap_dir: &Path) -> String {
+    let fstype = mounts::read()
+        .ok()
+        .and_then(|mounts| {
+            mounts
+                .into_iter()
+                .filter(|m| snap_dir.starts_with(&m.point))
+                .max_by_key(|m| m.point.components().count())
+        })
+        .map_or("another filesystem".to_string(), |m| m.fstype);
+    format!(
+        "Snapshot directory {} is on {}, not btrfs. Snapshots can only be taken \
+         into the btrfs filesystem of their subvolumes; to keep copies elsewhere, \
+         send them with `btrfs send -f`.",
+        snap_dir.display(),
+        fstype
+    )
 }
 
 pub fn parse_path(s: &str) -> Result<PathBuf, anyhow::Error> {
     PathBuf::from(s).canonicalize().context("Invalid path")
 }
 
-pub fn scan_snapshots<F>(snap_dir: &PathBuf, mut callback: F) -> Result<(), anyhow::Error>
+/// Call `callback` for every snapshot subvolume directly inside `snap_dir`
+pub fn scan_snapshots<F>(
+    backend: &dyn SnapshotBackend,
+    snap_dir: &Path,
+    mut callback: F,
+) -> Result<(), anyhow::Error>
 where
-    F: FnMut(DirEntry) -> Result<(), anyhow::Error>,
+    F: FnMut(SubvolInfo) -> Result<(), anyhow::Error>,
 {
-    for entry in WalkDir::new(snap_dir)
-        .max_depth(1)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_dir() && e.path() != snap_dir.as_path())
-    {
-        callback(entry)?;
+    let snapshots = backend.list(snap_dir).context(format!(
+        "Failed to list subvolumes in {}",
+        snap_dir.display()
+    ))?;
+    for info in snapshots {
+        callback(info)?;
+    }
+    Ok(())
+}
+
+/// Directory name of a snapshot
+pub fn snapshot_name(info: &SubvolInfo) -> String {
+    file_name(&info.path).unwrap_or_default()
+}
+
+/// Last component of `path` as a string, as manifest entries are keyed,
+/// see [`crate::os_path::name`]
+pub fn file_name(path: &Path) -> Option<String> {
+    path.file_name().map(crate::os_path::name)
+}
+
+/// `<path>.<suffix>` next to `path`, for staging and backup copies
+pub fn sibling(path: &Path, suffix: &str) -> Result<PathBuf, anyhow::Error> {
+    let mut name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("Invalid path {}", path.display()))?
+        .to_os_string();
+    name.push(format!(".{}", suffix));
+    Ok(path.with_file_name(name))
+}
+
+pub fn confirm(prompt: &str) -> Result<bool, anyhow::Error> {
+    print!("{} {} ", prompt, tr!("confirm-suffix"));
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    let answer = answer.trim().to_lowercase();
+    Ok(tr!("confirm-yes")
+        .split(',')
+        .any(|yes| yes.trim() == answer))
+}
+
+/// Split a snapshot name `<subvol>-<timestamp>[.<n>]` into its parts, the
+/// counter of snapshots taken within the same second left out and the
+/// subvolume part as [`crate::create::canonical_name`] gives it
+pub fn parse_snapshot_name(name: &str) -> Option<(Cow<'_, str>, i64)> {
+    let (subvol, ts) = name.rsplit_once('-')?;
+    let ts = match ts.split_once('.') {
+        Some((ts, n)) if n.parse::<u32>().is_ok() => ts,
+        Some(_) => return None,
+        None => ts,
+    };
+    Some((crate::create::canonical_name(subvol), ts.parse().ok()?))
+}
+
+/// Qgroup usage by subvolume id on the filesystem of `path`
+pub fn qgroups(backend: &dyn SnapshotBackend, path: &Path) -> Result<BTreeMap<u64, QgroupUsage>> {
+    backend.qgroups(path).map_err(|e| {
+        if e.kind() == ErrorKind::NotFound {
+            anyhow!(
+                "Quotas are not enabled on the filesystem of {}, enable them with `btrfs quota enable`",
+                path.display()
+            )
+        } else {
+            anyhow::Error::new(e).context("Failed to read qgroups")
+        }
+    })
+}
+
+/// Parse a size like `100G` or `1.5TiB` (binary units)
+pub fn parse_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number.parse().map_err(|_| anyhow!("Invalid size {}", s))?;
+    let shift = match unit.trim().trim_end_matches("iB").trim_end_matches('B') {
+        "" => 0,
+        "K" | "k" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => bail!("Invalid size unit in {}", s),
+    };
+    Ok((number * (1u64 << shift) as f64) as u64)
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Fail unless `path` is owned by root and writable by nobody else
+pub fn root_only(path: &Path) -> Result<()> {
+    let meta = fs::metadata(path).context(format!("Failed to stat {}", path.display()))?;
+    if meta.uid() != 0 || meta.mode() & 0o022 != 0 {
+        bail!(
+            "{} must be owned by root and writable only by root",
+            path.display()
+        );
     }
     Ok(())
 }
+
+/// Lowercase hex digits of `bytes`
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn unhex(s: &str) -> Result<Vec<u8>> {
+    let invalid = || anyhow!("Invalid hex string: {}", s);
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return Err(invalid());
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| invalid()))
+        .collect()
+}
@@ -0,0 +1,46 @@
+//! Non-fatal problems of a run. Besides going to the log they are kept,
+//! printed together at the end, added to the JSON report, and turned into
+//! a failure by `--warnings-as-errors`.
+
+use crate::i18n::tr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static PENDING: Mutex<Vec<String>> = Mutex::new(vec![]);
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Log a warning and keep it for the end of the run, e.g.
+/// `warning!("{} is in use", path.display())`
+macro_rules! warning {
+    ($($arg:tt)+) => {{
+        let message = format!($($arg)+);
+        log::warn!("{}", message);
+        $crate::warnings::add(message);
+    }};
+}
+pub(crate) use warning;
+
+pub fn add(message: String) {
+    COUNT.fetch_add(1, Ordering::Relaxed);
+    PENDING
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(message);
+}
+
+/// Warnings not yet shown, e.g. to put them in a report
+pub fn take() -> Vec<String> {
+    std::mem::take(&mut *PENDING.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// Warnings of the whole run, including those already shown
+pub fn count() -> usize {
+    COUNT.load(Ordering::Relaxed)
+}
+
+/// Show the warnings not yet shown on stderr
+pub fn print() {
+    for message in take() {
+        eprintln!("{}", tr!("warning", message = message));
+    }
+}
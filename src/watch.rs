@@ -0,0 +1,214 @@
+use crate::backend::SnapshotBackend;
+use crate::config::{Config, WatchRule};
+use crate::{create, drift, interrupt, suspend, utils};
+use anyhow::{Context, Result, bail};
+use chrono::Local;
+use log::{debug, error, info, warn};
+use nix::errno::Errno;
+use nix::poll::{PollFd, PollFlags, PollTimeout, poll};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
+use std::collections::HashMap;
+use std::fs;
+use std::os::fd::AsFd;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Changes that count as modifications of a watched tree
+const EVENTS: AddWatchFlags = AddWatchFlags::IN_CREATE
+    .union(AddWatchFlags::IN_DELETE)
+    .union(AddWatchFlags::IN_MODIFY)
+    .union(AddWatchFlags::IN_ATTRIB)
+    .union(AddWatchFlags::IN_MOVED_FROM)
+    .union(AddWatchFlags::IN_MOVED_TO);
+
+#[derive(clap::Parser)]
+pub struct Watch {}
+
+/// Debounce state of one `[[watch]]` rule
+struct RuleState {
+    rule: WatchRule,
+    last: Option<Instant>,
+    pending: bool,
+}
+
+impl Watch {
+    pub fn execute(self, backend: &'static dyn SnapshotBackend, config: Config) -> Result<()> {
+        let snap_dir = utils::resolve_snap_dir(backend, None, config.snap_dir.clone())?;
+        if config.watches.is_empty() {
+            bail!("No [[watch]] rules in the config file");
+        }
+
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)
+            .context("Failed to initialize inotify")?;
+        let mut dirs: HashMap<WatchDescriptor, (usize, PathBuf)> = HashMap::new();
+        let mut rules = vec![];
+        for (i, rule) in config.watches.iter().enumerate() {
+            if !backend.is_subvolume(&rule.subvol) {
+                if !rule.drift {
+                    bail!("{} is not a subvolume", rule.subvol.display());
+                }
+                info!(
+                    "{} is not a subvolume, its snapshots will be reflink copies",
+                    rule.subvol.display()
+                );
+            }
+            watch_tree(&inotify, &rule.path, i, &mut dirs)?;
+            info!(
+                "Watching {} to snapshot {} (at most every {})",
+                rule.path.display(),
+                rule.subvol.display(),
+                humantime::format_duration(rule.debounce)
+            );
+            rules.push(RuleState {
+                rule: rule.clone(),
+                last: None,
+                pending: false,
+            });
+        }
+
+        while !interrupt::requested() {
+            let mut fds = [PollFd::new(inotify.as_fd(), PollFlags::POLLIN)];
+            match poll(&mut fds, PollTimeout::from(1000u16)) {
+                Ok(_) | Err(Errno::EINTR) => {}
+                Err(e) => return Err(e).context("Failed to wait for inotify events"),
+            }
+            let events = match inotify.read_events() {
+                Ok(events) => events,
+                Err(Errno::EAGAIN) => vec![],
+                Err(e) => return Err(e).context("Failed to read inotify events"),
+            };
+            for event in events {
+                if event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+                    // Events were lost, assume every tree changed
+                    rules.iter_mut().for_each(|r| r.pending = true);
+                    continue;
+                }
+                let Some((i, dir)) = dirs.get(&event.wd).cloned() else {
+                    continue;
+                };
+                debug!("Change in {}: {:?}", dir.display(), event.name);
+                rules[i].pending = true;
+                // Follow directories created inside the watched tree
+                if event
+                    .mask
+                    .contains(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_ISDIR)
+                    && let Some(name) = event.name
+                    && let Err(e) = watch_tree(&inotify, &dir.join(name), i, &mut dirs)
+                {
+                    warn!("{:#}", e);
+                }
+            }
+
+            for state in rules.iter_mut().filter(|r| r.pending) {
+                if state
+                    .last
+                    .is_some_and(|t| t.elapsed() < state.rule.debounce)
+                {
+                    continue;
+                }
+                state.pending = false;
+                state.last = Some(Instant::now());
+                snapshot(backend, &config, &snap_dir, &state.rule);
+            }
+        }
+        info!("Stopped watching");
+        Ok(())
+    }
+}
+
+/// Add inotify watches for `root` and every directory below it
+fn watch_tree(
+    inotify: &Inotify,
+    root: &Path,
+    rule: usize,
+    dirs: &mut HashMap<WatchDescriptor, (usize, PathBuf)>,
+) -> Result<()> {
+    let mut todo = vec![root.to_path_buf()];
+    while let Some(dir) = todo.pop() {
+        let wd = inotify
+            .add_watch(&dir, EVENTS)
+            .context(format!("Failed to watch {}", dir.display()))?;
+        let entries = fs::read_dir(&dir).context(format!("Failed to read {}", dir.display()))?;
+        for entry in entries.flatten() {
+            // file_type() does not follow symlinks, so links are not entered
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                todo.push(entry.path());
+            }
+        }
+        dirs.insert(wd, (rule, dir));
+    }
+    Ok(())
+}
+
+/// Snapshot the rule's subvolume, errors are logged and watching goes on
+fn snapshot(
+    backend: &'static dyn SnapshotBackend,
+    config: &Config,
+    snap_dir: &Path,
+    rule: &WatchRule,
+) {
+    let name = create::subvol_name(&rule.subvol, config.name_parents);
+    match suspend::disabled(config, snap_dir, &name) {
+        Ok(None) => {}
+        Ok(Some(reason)) => {
+            info!("Not snapshotting {}, {}", name, reason);
+            return;
+        }
+        Err(e) => {
+            error!("{:#}", e);
+            return;
+        }
+    }
+    let previous = if rule.drift {
+        backend
+            .list(snap_dir)
+            .ok()
+            .and_then(|s| create::newest_snapshot(&s, &name).map(|s| s.path.clone()))
+    } else {
+        None
+    };
+    let snap_path = create::snapshot_path(
+        snap_dir,
+        &rule.subvol,
+        config.name_parents,
+        Local::now().timestamp(),
+    );
+    let trigger = format!("watch:{}", rule.path.display());
+    let mut retries = 0;
+    let result = if rule.drift && !backend.is_subvolume(&rule.subvol) {
+        drift::copy(backend, &rule.subvol, &snap_path, &trigger).map(|()| snap_path)
+    } else {
+        create::create_snapshot(
+            backend,
+            &rule.subvol,
+            &snap_path,
+            config.retry,
+            config.timeouts,
+            Some(&trigger),
+            &mut retries,
+        )
+    };
+    let snap_path = match result {
+        Ok(snap_path) => snap_path,
+        Err(e) => {
+            error!("{:#}", e);
+            return;
+        }
+    };
+    println!("Created snapshot: {} ({})", snap_path.display(), trigger);
+    // Only the watched part of the subvolume is compared
+    if let Some(previous) = previous {
+        let rel = rule
+            .path
+            .strip_prefix(&rule.subvol)
+            .unwrap_or(Path::new(""));
+        if let Err(e) = drift::report(
+            config,
+            &rule.path,
+            &previous.join(rel),
+            &snap_path.join(rel),
+        ) {
+            error!("Failed to compare with {}: {:#}", previous.display(), e);
+        }
+    }
+}